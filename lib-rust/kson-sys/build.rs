@@ -1,4 +1,4 @@
-use anyhow::{bail, Context};
+use anyhow::{Context, bail};
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -7,9 +7,7 @@ use std::process::{Command, Stdio};
 // [[kson-version-num]]
 static KSON_LIB_VERSION: &str = "0.3.0-dev";
 
-fn get_kson_artifacts(
-    out_dir: &Path,
-) -> anyhow::Result<()> {
+fn get_kson_artifacts(out_dir: &Path) -> anyhow::Result<()> {
     let kson_root_env_var = "KSON_ROOT_SOURCE_DIR";
     let kson_prebuild_env_var = "KSON_PREBUILT_BIN_DIR";
     if let Ok(kson_root) = env::var(kson_root_env_var) {
@@ -36,18 +34,32 @@ fn get_kson_artifacts(
     }
 }
 
-fn download_prebuilt_kson(
-    use_dynamic_linking: bool,
-    out_dir: &Path,
-) -> anyhow::Result<()> {
+fn download_prebuilt_kson(use_dynamic_linking: bool, out_dir: &Path) -> anyhow::Result<()> {
+    let artifact = prebuilt_artifact_name(use_dynamic_linking)?;
+    let url = format!(
+        "https://github.com/kson-org/kson-binaries/releases/download/kson-lib-{KSON_LIB_VERSION}/{artifact}"
+    );
+
+    let archive = ureq::get(url).call()?.body_mut().read_to_vec()?;
+
+    fs::create_dir_all(out_dir)?;
+    let decoder = flate2::read::GzDecoder::new(archive.as_slice());
+    let mut archive = tar::Archive::new(decoder);
+    archive.unpack(out_dir)?;
+
+    Ok(())
+}
+
+/// The name of the kson-binaries release artifact matching the target
+fn prebuilt_artifact_name(use_dynamic_linking: bool) -> anyhow::Result<String> {
     let cpu_arch = match env::var("CARGO_CFG_TARGET_ARCH")?.as_str() {
         "aarch64" => "arm64",
         "x86_64" => "amd64",
-        arch => panic!("unsupported CPU architecture: {arch}"),
+        arch => bail!("unsupported CPU architecture: {arch}"),
     };
     let os = env::var("CARGO_CFG_TARGET_OS")?;
     if !["windows", "macos", "linux"].contains(&os.as_str()) {
-        panic!("unsupported operating system: {os}");
+        bail!("unsupported operating system: {os}");
     }
 
     let shared_or_static = if use_dynamic_linking {
@@ -56,22 +68,12 @@ fn download_prebuilt_kson(
         "static"
     };
 
-    fs::create_dir_all(out_dir)?;
-    let url = format!(
-        "https://github.com/kson-org/kson-binaries/releases/download/kson-lib-{KSON_LIB_VERSION}/kson-lib-{shared_or_static}-{cpu_arch}-{os}.tar.gz"
-    );
-    let archive = ureq::get(url).call()?.body_mut().read_to_vec()?;
-    let decoder = flate2::read::GzDecoder::new(archive.as_slice());
-    let mut archive = tar::Archive::new(decoder);
-    archive.unpack(out_dir)?;
-
-    Ok(())
+    Ok(format!(
+        "kson-lib-{shared_or_static}-{cpu_arch}-{os}.tar.gz"
+    ))
 }
 
-fn build_kson_from_source(
-    kson_root: &Path,
-    out_dir: &Path,
-) -> anyhow::Result<()> {
+fn build_kson_from_source(kson_root: &Path, out_dir: &Path) -> anyhow::Result<()> {
     // Build kotlin-native artifacts
     let gradle_script = if cfg!(target_os = "windows") {
         kson_root.join("gradlew.bat")
@@ -116,12 +118,13 @@ fn main() -> anyhow::Result<()> {
 
     // Generate bindings
     let bindings = bindgen::Builder::default()
-        .header(
-            out_dir
-                .join("jni_simplified.h")
-                .display()
-                .to_string(),
-        )
+        .header(out_dir.join("jni_simplified.h").display().to_string())
+        // The JNI fixes the width of these types, but the header may spell them with C types whose width
+        // depends on the platform (e.g. `long` is 32-bit on Windows)
+        .blocklist_type("jint")
+        .blocklist_type("jlong")
+        .raw_line("pub type jint = i32;")
+        .raw_line("pub type jlong = i64;")
         .generate()
         .context("Unable to generate bindings")?;
 
@@ -129,22 +132,31 @@ fn main() -> anyhow::Result<()> {
         .write_to_file(out_dir.join("bindings.rs"))
         .context("Couldn't write bindings!")?;
 
-    // Tell the compiler where to find the dynamic library
+    // Build scripts run on the host, so the target is only known through Cargo's environment variables
+    let target_os = env::var("CARGO_CFG_TARGET_OS")?;
+    let target_env = env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default();
+
+    // Tell the compiler where to find the dynamic library (MSVC links through the `kson.lib` import
+    // library, while MinGW can also link to `kson.dll` directly)
     println!("cargo:rustc-link-search=native={}", out_dir.display());
     println!("cargo:rustc-link-lib=dylib=kson");
+    if target_env == "msvc" && !out_dir.join("kson.lib").is_file() {
+        bail!(
+            "the kson binaries lack the `kson.lib` import library, which MSVC needs to link to `kson.dll`"
+        );
+    }
 
-    #[cfg(not(target_os = "windows"))]
-    println!("cargo:rustc-link-lib=dylib=z");
+    if target_os != "windows" {
+        println!("cargo:rustc-link-lib=dylib=z");
+    }
 
     // Let users of the library know the path to the compiled binary
-    let shared_name = if cfg!(target_os = "windows") {
-        format!("kson.dll")
-    } else if cfg!(target_os = "macos") {
-        format!("libkson.dylib")
-    } else {
-        format!("libkson.so")
+    let shared_name = match target_os.as_str() {
+        "windows" => "kson.dll",
+        "macos" => "libkson.dylib",
+        _ => "libkson.so",
     };
-    let built_lib = out_dir.join(&shared_name);
+    let built_lib = out_dir.join(shared_name);
     println!("cargo:lib-binary={}", built_lib.display());
 
     // Copy the library to a specific directory, if requested
@@ -0,0 +1,19 @@
+#![no_main]
+
+use kson_rs::roundtrip::DocumentRecipe;
+use kson_rs::{Kson, transpile_options};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|recipe: DocumentRecipe| {
+    let document = recipe.render();
+    let json = match Kson::to_json(&document, transpile_options::Json::new(false)) {
+        Ok(success) => success.output(),
+        Err(_) => panic!("failed to convert a valid document:\n{document}"),
+    };
+    let parsed: serde_json::Value = match serde_json::from_str(&json) {
+        Ok(parsed) => parsed,
+        Err(error) => panic!("converted to invalid JSON ({error}):\n{json}"),
+    };
+    // Embed blocks become strings, like the lossy conversion does
+    assert_eq!(parsed, serde_json::Value::from(&recipe.value), "json:\n{json}");
+});
@@ -0,0 +1,24 @@
+#![no_main]
+
+use kson_rs::value::Value;
+use kson_rs::{FormatOptions, FormattingStyle, IndentType, Kson, indent_type};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    let Ok(value) = Value::parse(input) else {
+        return;
+    };
+    for style in [
+        FormattingStyle::Plain,
+        FormattingStyle::Delimited,
+        FormattingStyle::Compact,
+        FormattingStyle::Classic,
+    ] {
+        let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+        let formatted = Kson::format(input, FormatOptions::new(indent, style, &[]));
+        match Value::parse(&formatted) {
+            Ok(reformatted) => assert_eq!(reformatted, value, "formatted:\n{formatted}"),
+            Err(errors) => panic!("formatting broke the document: {errors}\nformatted:\n{formatted}"),
+        }
+    }
+});
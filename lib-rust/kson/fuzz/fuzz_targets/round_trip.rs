@@ -0,0 +1,14 @@
+#![no_main]
+
+use kson_rs::roundtrip::{DocumentRecipe, assert_round_trips};
+use kson_rs::value::Value;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|recipe: DocumentRecipe| {
+    let document = recipe.render();
+    match Value::parse(&document) {
+        Ok(value) => assert_eq!(value, recipe.value, "document:\n{document}"),
+        Err(errors) => panic!("rendered an invalid document: {errors}\ndocument:\n{document}"),
+    }
+    assert_round_trips(&document);
+});
@@ -0,0 +1,196 @@
+//! Syntax highlighting of KSON for ANSI terminals.
+//!
+//! [`Kson::to_ansi`] colors a document as written (format it first with [`Kson::format`] to
+//! pretty-print it), and [`ColorChoice`] decides whether to color at all, following the
+//! [`NO_COLOR`](https://no-color.org) convention and whether the output is a terminal:
+//!
+//! ```no_run
+//! use std::io::Write;
+//!
+//! use kson_rs::Kson;
+//! use kson_rs::ansi::{ColorChoice, Theme};
+//!
+//! let document = "# the answer\nkey: 42";
+//! let mut stdout = std::io::stdout();
+//! let output = if ColorChoice::Auto.enabled_for(&stdout) {
+//!     Kson::to_ansi(document, &Theme::default())
+//! } else {
+//!     document.to_string()
+//! };
+//! writeln!(stdout, "{output}").unwrap();
+//! ```
+
+use std::io::IsTerminal;
+use std::ops::Range;
+
+use crate::line_index::LineIndex;
+use crate::{Kson, TokenType};
+
+/// The styles of each kind of token, as the parameters of an ANSI "Select Graphic Rendition" sequence
+/// (e.g. `"1;34"` for bold blue). An empty style leaves the token unstyled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Theme {
+    /// Property keys, quoted or not
+    pub key: &'static str,
+    /// Strings other than keys
+    pub string: &'static str,
+    pub number: &'static str,
+    /// `true`, `false` and `null`
+    pub keyword: &'static str,
+    pub comment: &'static str,
+    /// Braces, brackets, colons, commas, list dashes and embed delimiters
+    pub punctuation: &'static str,
+    pub embed_tag: &'static str,
+    pub embed_content: &'static str,
+    /// Characters that aren't valid KSON
+    pub error: &'static str,
+}
+
+impl Theme {
+    /// Blue keys, green strings, cyan numbers, magenta keywords, dim comments and red errors, readable
+    /// on both dark and light backgrounds
+    pub const DEFAULT: Theme = Theme {
+        key: "34",
+        string: "32",
+        number: "36",
+        keyword: "35",
+        comment: "2",
+        punctuation: "",
+        embed_tag: "33",
+        embed_content: "32",
+        error: "1;31",
+    };
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Whether to color output, as chosen by a `--color` flag
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color if the output is a terminal and the `NO_COLOR` environment variable is unset or empty
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether output written to `stream` should be colored
+    pub fn enabled_for(self, stream: &impl IsTerminal) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && stream.is_terminal()
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl Kson {
+    /// Highlights the document with ANSI escape sequences, leaving its text otherwise unchanged. Documents
+    /// with errors are highlighted as far as they could be tokenized, with invalid characters in the
+    /// [error style](Theme::error).
+    pub fn to_ansi(input: &str, theme: &Theme) -> String {
+        let index = LineIndex::new(input);
+        let tokens: Vec<(TokenType, Range<usize>)> = Kson::analyze(input, None)
+            .tokens()
+            .into_iter()
+            .map(|token| {
+                let range = index.offset_of(&token.start())..index.offset_of(&token.end());
+                (token.token_type(), range)
+            })
+            .collect();
+
+        let mut output = String::with_capacity(input.len() * 2);
+        let mut written = 0;
+        let mut string_style = None;
+        for (position, (token_type, range)) in tokens.iter().enumerate() {
+            let style = match token_type {
+                TokenType::UnquotedString => {
+                    if is_key(&tokens[position + 1..]) {
+                        theme.key
+                    } else {
+                        theme.string
+                    }
+                }
+                // The delimiters and content of a quoted string share the style of the whole string
+                TokenType::StringOpenQuote => {
+                    let close = tokens[position..]
+                        .iter()
+                        .position(|(token_type, _)| {
+                            matches!(token_type, TokenType::StringCloseQuote)
+                        })
+                        .map_or(tokens.len(), |close| position + close + 1);
+                    let style = if is_key(&tokens[close..]) {
+                        theme.key
+                    } else {
+                        theme.string
+                    };
+                    string_style = Some(style);
+                    style
+                }
+                TokenType::StringContent => string_style.unwrap_or(theme.string),
+                TokenType::StringCloseQuote => string_style.take().unwrap_or(theme.string),
+                TokenType::Number => theme.number,
+                TokenType::True | TokenType::False | TokenType::Null => theme.keyword,
+                TokenType::Comment => theme.comment,
+                TokenType::EmbedTag => theme.embed_tag,
+                TokenType::EmbedContent => theme.embed_content,
+                TokenType::IllegalChar => theme.error,
+                TokenType::CurlyBraceL
+                | TokenType::CurlyBraceR
+                | TokenType::SquareBracketL
+                | TokenType::SquareBracketR
+                | TokenType::AngleBracketL
+                | TokenType::AngleBracketR
+                | TokenType::Colon
+                | TokenType::Dot
+                | TokenType::EndDash
+                | TokenType::Comma
+                | TokenType::ListDash
+                | TokenType::EmbedOpenDelim
+                | TokenType::EmbedCloseDelim => theme.punctuation,
+                TokenType::EmbedPreambleNewline | TokenType::Whitespace | TokenType::Eof => "",
+            };
+            if style.is_empty() || range.start < written || range.is_empty() {
+                continue;
+            }
+            output.push_str(&input[written..range.start]);
+            write_styled(&input[range.clone()], style, &mut output);
+            written = range.end;
+        }
+        output.push_str(&input[written..]);
+        output
+    }
+}
+
+/// Whether the string preceding these tokens is a property key, i.e. followed by a colon
+fn is_key(following: &[(TokenType, Range<usize>)]) -> bool {
+    following
+        .iter()
+        .find(|(token_type, _)| !matches!(token_type, TokenType::Whitespace | TokenType::Comment))
+        .is_some_and(|(token_type, _)| matches!(token_type, TokenType::Colon))
+}
+
+/// Writes the text in the given style, line by line, so that the style doesn't leak into the margin of
+/// terminals and pagers that reset it at line ends
+fn write_styled(text: &str, style: &str, out: &mut String) {
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+        if !line.is_empty() {
+            out.push_str("\x1b[");
+            out.push_str(style);
+            out.push('m');
+            out.push_str(line);
+            out.push_str("\x1b[0m");
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Values borrowing their strings from the source text.
+//!
+//! [`KsonValueRef::parse`] keeps every string (and property name) that appears verbatim in the input as a
+//! slice of it, located by its span, only unescaping (and allocating) strings whose source contains
+//! escapes. Read-only pipelines can hold on to the result without copying each scalar out of the
+//! document.
+
+use std::borrow::Cow;
+
+use crate::line_index::LineIndex;
+use crate::value::{Map, Value, sorted_property_keys};
+use crate::{Kson, KsonValue, Message, MessageSeverity, kson_value};
+
+/// A parsed value whose strings borrow from the input when possible
+#[derive(Clone, Debug, PartialEq)]
+pub enum KsonValueRef<'a> {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Decimal(f64),
+    String(Cow<'a, str>),
+    Embed {
+        tag: Option<Cow<'a, str>>,
+        content: Cow<'a, str>,
+    },
+    Array(Vec<KsonValueRef<'a>>),
+    /// The properties of an object, in document order
+    Object(Vec<(Cow<'a, str>, KsonValueRef<'a>)>),
+}
+
+impl<'a> KsonValueRef<'a> {
+    /// Parses the input, or returns the errors that prevented parsing it
+    pub fn parse(input: &'a str) -> Result<Self, Vec<Message>> {
+        let analysis = Kson::analyze(input, None);
+        match analysis.kson_value() {
+            Some(value) => Ok(Self::from_kson_value(&value, &LineIndex::new(input))),
+            None => Err(analysis
+                .errors()
+                .into_iter()
+                .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+                .collect()),
+        }
+    }
+
+    fn from_kson_value(value: &KsonValue, index: &LineIndex<'a>) -> Self {
+        match value {
+            KsonValue::KsonNull(_) => KsonValueRef::Null,
+            KsonValue::KsonBoolean(boolean) => KsonValueRef::Bool(boolean.value()),
+            KsonValue::KsonNumber(kson_value::KsonNumber::Integer(integer)) => {
+                KsonValueRef::Integer(integer.value())
+            }
+            KsonValue::KsonNumber(kson_value::KsonNumber::Decimal(decimal)) => {
+                KsonValueRef::Decimal(decimal.value())
+            }
+            KsonValue::KsonString(string) => {
+                KsonValueRef::String(borrow_string(string, index, || string.value()))
+            }
+            // Embed content is dedented, so it rarely appears verbatim in the source
+            KsonValue::KsonEmbed(embed) => KsonValueRef::Embed {
+                tag: embed.tag().map(Cow::Owned),
+                content: Cow::Owned(embed.content()),
+            },
+            KsonValue::KsonArray(array) => KsonValueRef::Array(
+                array
+                    .elements()
+                    .iter()
+                    .map(|element| Self::from_kson_value(element, index))
+                    .collect(),
+            ),
+            KsonValue::KsonObject(object) => {
+                let mut properties = object.properties();
+                KsonValueRef::Object(
+                    sorted_property_keys(object)
+                        .into_iter()
+                        .filter_map(|(name, key)| {
+                            let value = properties.remove(&name)?;
+                            Some((
+                                borrow_string(&key, index, || name),
+                                Self::from_kson_value(&value, index),
+                            ))
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            KsonValueRef::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the given property, if this is an object that has it
+    pub fn get(&self, key: &str) -> Option<&KsonValueRef<'a>> {
+        match self {
+            KsonValueRef::Object(properties) => {
+                properties.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether this string borrows from the input (always `false` for other values)
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self, KsonValueRef::String(Cow::Borrowed(_)))
+    }
+
+    /// Copies the borrowed strings, detaching the value from the input
+    pub fn into_owned(self) -> KsonValueRef<'static> {
+        let owned = |string: Cow<'_, str>| Cow::Owned(string.into_owned());
+        match self {
+            KsonValueRef::Null => KsonValueRef::Null,
+            KsonValueRef::Bool(boolean) => KsonValueRef::Bool(boolean),
+            KsonValueRef::Integer(integer) => KsonValueRef::Integer(integer),
+            KsonValueRef::Decimal(decimal) => KsonValueRef::Decimal(decimal),
+            KsonValueRef::String(string) => KsonValueRef::String(owned(string)),
+            KsonValueRef::Embed { tag, content } => KsonValueRef::Embed {
+                tag: tag.map(owned),
+                content: owned(content),
+            },
+            KsonValueRef::Array(elements) => {
+                KsonValueRef::Array(elements.into_iter().map(KsonValueRef::into_owned).collect())
+            }
+            KsonValueRef::Object(properties) => KsonValueRef::Object(
+                properties
+                    .into_iter()
+                    .map(|(key, value)| (owned(key), value.into_owned()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<KsonValueRef<'_>> for Value {
+    fn from(value: KsonValueRef<'_>) -> Self {
+        match value {
+            KsonValueRef::Null => Value::Null,
+            KsonValueRef::Bool(boolean) => Value::Bool(boolean),
+            KsonValueRef::Integer(integer) => Value::Integer(integer),
+            KsonValueRef::Decimal(decimal) => Value::Decimal(decimal),
+            KsonValueRef::String(string) => Value::String(string.into_owned()),
+            KsonValueRef::Embed { tag, content } => Value::Embed {
+                tag: tag.map(Cow::into_owned),
+                content: content.into_owned(),
+            },
+            KsonValueRef::Array(elements) => {
+                Value::Array(elements.into_iter().map(Value::from).collect())
+            }
+            KsonValueRef::Object(properties) => Value::Object(
+                properties
+                    .into_iter()
+                    .map(|(key, value)| (key.into_owned(), Value::from(value)))
+                    .collect::<Map>(),
+            ),
+        }
+    }
+}
+
+/// Returns the string as a slice of the source if it appears there verbatim, i.e. unquoted or quoted
+/// without escapes, or else its `unescaped` value
+fn borrow_string<'a>(
+    string: &kson_value::KsonString,
+    index: &LineIndex<'a>,
+    unescaped: impl FnOnce() -> String,
+) -> Cow<'a, str> {
+    let source = index.slice(&string.start(), &string.end());
+    let unquoted = match source.as_bytes() {
+        [b'"', .., b'"'] | [b'\'', .., b'\''] => &source[1..source.len() - 1],
+        _ => source,
+    };
+    if unquoted.contains('\\') {
+        Cow::Owned(unescaped())
+    } else {
+        Cow::Borrowed(unquoted)
+    }
+}
@@ -0,0 +1,329 @@
+//! A compact binary encoding of values, for build tools caching the parse results of large documents
+//! that didn't change between runs.
+//!
+//! The encoding is internal to this crate: it starts with a header holding a format version, and
+//! [`Value::from_cache_bytes`] rejects bytes written with any other version, which callers should treat
+//! as a cache miss. It is not meant for exchanging documents, since it can change from one release to the
+//! next.
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//! use kson_rs::value::Value;
+//!
+//! let parsed = Kson::analyze("key: [1, 2, 3]", None).kson_value().unwrap();
+//! let bytes = parsed.to_cache_bytes();
+//! assert_eq!(Value::from_cache_bytes(&bytes).unwrap(), parsed.to_value());
+//! ```
+
+use std::collections::HashSet;
+
+use crate::KsonValue;
+use crate::depth::{DepthError, MaxDepth};
+use crate::numbers::BigInteger;
+use crate::path::{KsonPath, PathSegment};
+use crate::value::{Map, Step, Value};
+
+/// The bytes every cache entry starts with
+const MAGIC: &[u8; 4] = b"KSNC";
+/// The version of the encoding, to bump whenever it changes
+pub const CACHE_FORMAT_VERSION: u8 = 2;
+
+const NULL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const INTEGER: u8 = 3;
+const DECIMAL: u8 = 4;
+const STRING: u8 = 5;
+const EMBED: u8 = 6;
+const TAGGED_EMBED: u8 = 7;
+const ARRAY: u8 = 8;
+const OBJECT: u8 = 9;
+const BIG_INTEGER: u8 = 10;
+
+/// The reason cached bytes couldn't be decoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CacheError {
+    /// The bytes don't start with the header of a cache entry
+    NotACacheEntry,
+    /// The bytes were written with another version of the encoding
+    UnsupportedVersion(u8),
+    /// The bytes end in the middle of a value
+    UnexpectedEnd,
+    /// The bytes are corrupted, e.g. with an unknown value type or invalid UTF-8
+    Corrupted { offset: usize },
+    /// There are bytes left after the value
+    TrailingBytes { offset: usize },
+    /// The value nests arrays and objects deeper than the limit, which no parsed document does with the
+    /// default one
+    TooDeep(DepthError),
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheError::NotACacheEntry => write!(f, "not a kson cache entry"),
+            CacheError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported cache format version {version}, expected {CACHE_FORMAT_VERSION}"
+            ),
+            CacheError::UnexpectedEnd => write!(f, "unexpected end of cache entry"),
+            CacheError::Corrupted { offset } => {
+                write!(f, "corrupted cache entry at byte {offset}")
+            }
+            CacheError::TrailingBytes { offset } => {
+                write!(f, "unexpected bytes after the value at byte {offset}")
+            }
+            CacheError::TooDeep(error) => write!(f, "corrupted cache entry: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl Value {
+    /// Encodes this value in the binary cache format (see the [module documentation](crate::cache))
+    pub fn to_cache_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(CACHE_FORMAT_VERSION);
+        encode(self, &mut bytes);
+        bytes
+    }
+
+    /// Decodes a value encoded by [`to_cache_bytes`](Self::to_cache_bytes), with the same version of
+    /// this crate, nested no deeper than [`MaxDepth::DEFAULT`]
+    pub fn from_cache_bytes(bytes: &[u8]) -> Result<Value, CacheError> {
+        Value::from_cache_bytes_with(bytes, MaxDepth::DEFAULT)
+    }
+
+    /// Like [`from_cache_bytes`](Self::from_cache_bytes), with another limit on the nesting of the value
+    pub fn from_cache_bytes_with(bytes: &[u8], max_depth: MaxDepth) -> Result<Value, CacheError> {
+        let body = bytes
+            .strip_prefix(MAGIC)
+            .ok_or(CacheError::NotACacheEntry)?;
+        let (&version, body) = body.split_first().ok_or(CacheError::NotACacheEntry)?;
+        if version != CACHE_FORMAT_VERSION {
+            return Err(CacheError::UnsupportedVersion(version));
+        }
+
+        let mut decoder = Decoder {
+            bytes,
+            offset: bytes.len() - body.len(),
+            max_depth,
+            path: KsonPath::root(),
+        };
+        let value = decoder.value()?;
+        if decoder.offset < bytes.len() {
+            return Err(CacheError::TrailingBytes {
+                offset: decoder.offset,
+            });
+        }
+        Ok(value)
+    }
+}
+
+impl KsonValue {
+    /// Encodes this value in the binary cache format, to be decoded into an owned [`Value`] with
+    /// [`Value::from_cache_bytes`]
+    pub fn to_cache_bytes(&self) -> Vec<u8> {
+        self.to_value().to_cache_bytes()
+    }
+}
+
+fn encode(value: &Value, out: &mut Vec<u8>) {
+    // Values are written in the order they're entered, with the keys of properties before their values
+    for step in value.walk() {
+        let Step::Enter { key, value, .. } = step else {
+            continue;
+        };
+        if let Some(key) = key {
+            encode_str(key, out);
+        }
+        match value {
+            Value::Null => out.push(NULL),
+            Value::Bool(false) => out.push(FALSE),
+            Value::Bool(true) => out.push(TRUE),
+            Value::Integer(integer) => {
+                out.push(INTEGER);
+                out.extend_from_slice(&integer.to_le_bytes());
+            }
+            Value::BigInteger(integer) => {
+                out.push(BIG_INTEGER);
+                encode_str(integer.as_str(), out);
+            }
+            Value::Decimal(decimal) => {
+                out.push(DECIMAL);
+                out.extend_from_slice(&decimal.to_bits().to_le_bytes());
+            }
+            Value::String(string) => {
+                out.push(STRING);
+                encode_str(string, out);
+            }
+            Value::Embed { tag: None, content } => {
+                out.push(EMBED);
+                encode_str(content, out);
+            }
+            Value::Embed {
+                tag: Some(tag),
+                content,
+            } => {
+                out.push(TAGGED_EMBED);
+                encode_str(tag, out);
+                encode_str(content, out);
+            }
+            Value::Array(elements) => {
+                out.push(ARRAY);
+                encode_len(elements.len(), out);
+            }
+            Value::Object(map) => {
+                out.push(OBJECT);
+                encode_len(map.len(), out);
+            }
+        }
+    }
+}
+
+fn encode_str(string: &str, out: &mut Vec<u8>) {
+    encode_len(string.len(), out);
+    out.extend_from_slice(string.as_bytes());
+}
+
+/// Writes a length as an unsigned LEB128 varint
+fn encode_len(mut len: usize, out: &mut Vec<u8>) {
+    while len >= 0x80 {
+        out.push((len as u8) | 0x80);
+        len >>= 7;
+    }
+    out.push(len as u8);
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    /// Decoding recurses into arrays and objects, so their nesting is bounded
+    max_depth: MaxDepth,
+    /// The path of the value being decoded
+    path: KsonPath,
+}
+
+impl Decoder<'_> {
+    fn value(&mut self) -> Result<Value, CacheError> {
+        let type_offset = self.offset;
+        Ok(match self.byte()? {
+            NULL => Value::Null,
+            FALSE => Value::Bool(false),
+            TRUE => Value::Bool(true),
+            INTEGER => Value::Integer(i64::from_le_bytes(self.array()?)),
+            BIG_INTEGER => {
+                let offset = self.offset;
+                let integer = BigInteger::parse(&self.string()?);
+                Value::BigInteger(integer.ok_or(CacheError::Corrupted { offset })?)
+            }
+            DECIMAL => Value::Decimal(f64::from_bits(u64::from_le_bytes(self.array()?))),
+            STRING => Value::String(self.string()?),
+            EMBED => Value::Embed {
+                tag: None,
+                content: self.string()?,
+            },
+            TAGGED_EMBED => Value::Embed {
+                tag: Some(self.string()?),
+                content: self.string()?,
+            },
+            ARRAY => {
+                let len = self.len()?;
+                // Every element takes at least a byte, which bounds the allocation for corrupted lengths
+                let mut elements = Vec::with_capacity(len.min(self.remaining()));
+                for index in 0..len {
+                    elements.push(self.child(PathSegment::Index(index))?);
+                }
+                Value::Array(elements)
+            }
+            OBJECT => {
+                let len = self.len()?;
+                // Every property takes at least two bytes, which bounds the allocation for corrupted lengths
+                let mut properties = Vec::with_capacity(len.min(self.remaining() / 2));
+                let mut keys = HashSet::with_capacity(properties.capacity());
+                for _ in 0..len {
+                    let key_offset = self.offset;
+                    let key = self.string()?;
+                    // Encoded maps have distinct keys
+                    if !keys.insert(key.clone()) {
+                        return Err(CacheError::Corrupted { offset: key_offset });
+                    }
+                    let property = self.child(PathSegment::Key(key.clone()))?;
+                    properties.push((key, property));
+                }
+                Value::Object(Map::from_entries(properties))
+            }
+            _ => {
+                return Err(CacheError::Corrupted {
+                    offset: type_offset,
+                });
+            }
+        })
+    }
+
+    /// Decodes an element or property, failing if it's nested too deep
+    fn child(&mut self, segment: PathSegment) -> Result<Value, CacheError> {
+        self.path.push(segment);
+        if !self.max_depth.allows(self.path.len()) {
+            return Err(CacheError::TooDeep(DepthError {
+                max_depth: self.max_depth,
+                path: std::mem::take(&mut self.path),
+            }));
+        }
+        let value = self.value()?;
+        self.path.pop();
+        Ok(value)
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.offset
+    }
+
+    fn byte(&mut self) -> Result<u8, CacheError> {
+        let byte = *self
+            .bytes
+            .get(self.offset)
+            .ok_or(CacheError::UnexpectedEnd)?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn slice(&mut self, len: usize) -> Result<&[u8], CacheError> {
+        if len > self.remaining() {
+            return Err(CacheError::UnexpectedEnd);
+        }
+        let slice = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CacheError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.slice(N)?);
+        Ok(array)
+    }
+
+    fn len(&mut self) -> Result<usize, CacheError> {
+        let start = self.offset;
+        let mut len: usize = 0;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            len |= usize::from(byte & 0x7f)
+                .checked_shl(shift)
+                .ok_or(CacheError::Corrupted { offset: start })?;
+            if byte & 0x80 == 0 {
+                return Ok(len);
+            }
+        }
+        Err(CacheError::Corrupted { offset: start })
+    }
+
+    fn string(&mut self) -> Result<String, CacheError> {
+        let len = self.len()?;
+        let start = self.offset;
+        let bytes = self.slice(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| CacheError::Corrupted { offset: start })
+    }
+}
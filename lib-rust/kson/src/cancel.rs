@@ -0,0 +1,186 @@
+//! Cancellation of long-running operations, for interactive tools (e.g. language servers) that abandon
+//! work made stale by newer edits, and for servers bounding the time spent on a request.
+//!
+//! A [`CancellationToken`] is shared between the code running an operation and the code that may cancel
+//! it. Operations made of several steps check the token between steps and fail with [`Cancelled`] once
+//! it's cancelled, like [`Formatter::format_cancellable`](crate::format::Formatter::format_cancellable)
+//! between its passes. Calls into kson-lib (parsing, formatting, converting or validating a single
+//! document) can't be interrupted once started, so [`CancellationToken::run`] makes them on a worker
+//! thread and stops waiting for them as soon as the token is cancelled:
+//!
+//! ```no_run
+//! use kson_rs::cancel::CancellationToken;
+//! use kson_rs::value::Value;
+//!
+//! let token = CancellationToken::new();
+//! let worker = {
+//!     let token = token.clone();
+//!     std::thread::spawn(move || token.run(|| Value::parse("key: value")))
+//! };
+//! // The document was edited again in the meantime
+//! token.cancel();
+//! let _ = worker.join().unwrap();
+//! ```
+//!
+//! The operations taking a token are:
+//!
+//! - parsing: [`Value::parse_cancellable`](crate::value::Value::parse_cancellable)
+//! - formatting: [`Formatter::format_cancellable`](crate::format::Formatter::format_cancellable)
+//! - converting: [`Kson::try_to_json_cancellable`](crate::Kson::try_to_json_cancellable) and
+//!   [`Kson::try_to_yaml_cancellable`](crate::Kson::try_to_yaml_cancellable)
+//! - validating: [`Kson::try_check_cancellable`](crate::Kson::try_check_cancellable) and
+//!   [`schema::validate_cancellable`](crate::schema::validate_cancellable), which checks the token
+//!   before each subschema
+//! - the batch operations of the `workspace` module (behind the `workspace` feature), for each file
+//!
+//! A token can also be given a deadline, after which it counts as cancelled, so that pathological inputs
+//! can't hold up a request-serving path even if nobody is around to cancel it:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use kson_rs::cancel::CancellationToken;
+//! use kson_rs::FormatOptions;
+//!
+//! let formatter = FormatOptions::builder().build();
+//! let token = CancellationToken::new().with_timeout(Duration::from_millis(200));
+//! match formatter.format_cancellable("key: value", &token) {
+//!     Ok(formatted) => println!("{formatted}"),
+//!     Err(_) => eprintln!("formatting took too long"),
+//! }
+//! ```
+//!
+//! Deadlines are enforced like cancellation: between steps, and while waiting for a call into kson-lib
+//! made through [`CancellationToken::run`], which fails as soon as the deadline passes. The abandoned
+//! call still runs to completion on its worker thread, which keeps using a CPU until then, but the caller
+//! is no longer held up by it.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// How often [`CancellationToken::run`] checks whether the token was cancelled while waiting
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A flag shared between an operation and the code that may cancel it, with an optional deadline (see
+/// the [module documentation](self)). Clones share the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a token that is cancelled along with this one, and also once `deadline` passes. A
+    /// deadline later than the one this token already has is ignored.
+    pub fn with_deadline(&self, deadline: Instant) -> Self {
+        Self {
+            cancelled: self.cancelled.clone(),
+            deadline: Some(
+                self.deadline
+                    .map_or(deadline, |current| current.min(deadline)),
+            ),
+        }
+    }
+
+    /// Like [`CancellationToken::with_deadline`], with a deadline `timeout` from now
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.with_deadline(deadline),
+            // Too far in the future to be represented, which is as good as no deadline at all
+            None => self.clone(),
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Cancels the operations using this token or any of its clones (including the ones with a
+    /// deadline). Cancelling can't be undone.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the token was cancelled or its deadline passed
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Fails if the token was cancelled, for operations to call between their steps
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Runs an operation that can't be interrupted on a worker thread, failing without running it if the
+    /// token is already cancelled, and as soon as the token is cancelled (or its deadline passes) while it
+    /// runs. The operation is then left to complete in the background, and its result is discarded.
+    /// Panics of the operation are resumed on the calling thread.
+    ///
+    /// A token without a deadline and without clones can't be cancelled while the operation runs, so the
+    /// operation then runs on the calling thread.
+    pub fn run<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, Cancelled> {
+        self.check()?;
+        if self.deadline.is_none() && Arc::strong_count(&self.cancelled) == 1 {
+            return Ok(operation());
+        }
+        let (sender, receiver) = mpsc::channel();
+        let worker = std::thread::spawn(move || {
+            // The receiver is gone once the caller gave up on the result
+            let _ = sender.send(operation());
+        });
+        loop {
+            let wait = self.deadline.map_or(POLL_INTERVAL, |deadline| {
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(POLL_INTERVAL)
+            });
+            match receiver.recv_timeout(wait) {
+                Ok(result) => {
+                    self.check()?;
+                    return Ok(result);
+                }
+                Err(RecvTimeoutError::Timeout) => self.check()?,
+                Err(RecvTimeoutError::Disconnected) => match worker.join() {
+                    Err(panic) => std::panic::resume_unwind(panic),
+                    Ok(()) => unreachable!("the worker sends a result before returning"),
+                },
+            }
+        }
+    }
+}
+
+/// The error returned by an operation whose [`CancellationToken`] was cancelled, or whose deadline passed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the operation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Cancelled operations reading files report an error of kind [`std::io::ErrorKind::Interrupted`]
+/// wrapping [`Cancelled`]
+impl From<Cancelled> for std::io::Error {
+    fn from(cancelled: Cancelled) -> Self {
+        std::io::Error::new(std::io::ErrorKind::Interrupted, cancelled)
+    }
+}
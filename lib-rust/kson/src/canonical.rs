@@ -0,0 +1,105 @@
+//! A canonical form of documents, and their content digest, for reproducible fingerprints (e.g. cache
+//! keys, or detecting configuration changes).
+//!
+//! The canonical form of a document is its value with the properties of objects sorted by key, rendered
+//! by [`Value::to_kson`]. Documents that only differ in formatting, comments or property order thus have
+//! the same canonical form and digest:
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//!
+//! let canonical = Kson::canonicalize("# Scaled up\nreplicas: 3, image: 'app:1.4.2'").unwrap();
+//! assert_eq!(canonical, "image: 'app:1.4.2'\nreplicas: 3");
+//! # #[cfg(feature = "digest")]
+//! assert_eq!(
+//!     Kson::digest("replicas: 3\nimage: 'app:1.4.2'").unwrap(),
+//!     Kson::digest("{image: 'app:1.4.2', replicas: 3}").unwrap()
+//! );
+//! ```
+//!
+//! Array elements keep their order, and values keep their type (`1` and `1.0` differ). The digest
+//! ([`Kson::digest`], with the `digest` feature) is the SHA-256 of the canonical form, which is also how
+//! [`DocumentStore`](crate::store::DocumentStore) identifies documents.
+
+#[cfg(feature = "digest")]
+use crate::digest::Digest;
+use crate::error::KsonErrors;
+use crate::value::{Builder, Step, Value};
+use crate::{Kson, KsonValue};
+
+impl Kson {
+    /// Returns the canonical form of the document, or the errors that prevented parsing it
+    pub fn canonicalize(document: &str) -> Result<String, KsonErrors> {
+        Ok(Value::parse(document)?.to_canonical_kson())
+    }
+
+    /// Returns the SHA-256 digest of the canonical form of the document, or the errors that prevented
+    /// parsing it
+    #[cfg(feature = "digest")]
+    pub fn digest(document: &str) -> Result<Digest, KsonErrors> {
+        Ok(Value::parse(document)?.digest())
+    }
+}
+
+impl Value {
+    /// Returns this value with the properties of its objects sorted by key, recursively
+    pub fn canonical(&self) -> Value {
+        let mut builder = Builder::sorting_keys();
+        for step in self.walk() {
+            let built = match step {
+                Step::Enter {
+                    key,
+                    value: Value::Array(elements),
+                    ..
+                } => {
+                    builder.open_array(key.map(str::to_string), elements.len());
+                    None
+                }
+                Step::Enter {
+                    key,
+                    value: Value::Object(map),
+                    ..
+                } => {
+                    builder.open_object(key.map(str::to_string), map.len());
+                    None
+                }
+                Step::Enter { key, value, .. } => {
+                    builder.push(key.map(str::to_string), value.clone())
+                }
+                Step::Leave {
+                    value: Value::Array(_) | Value::Object(_),
+                    ..
+                } => builder.close(),
+                Step::Leave { .. } => None,
+            };
+            if let Some(canonical) = built {
+                return canonical;
+            }
+        }
+        unreachable!("the walk ends by leaving the root")
+    }
+
+    /// Renders the canonical form of this value
+    pub fn to_canonical_kson(&self) -> String {
+        self.canonical().to_kson()
+    }
+
+    /// The SHA-256 digest of the canonical form of this value
+    #[cfg(feature = "digest")]
+    pub fn digest(&self) -> Digest {
+        Digest::of(&self.to_canonical_kson())
+    }
+}
+
+impl KsonValue {
+    /// Renders the canonical form of this value
+    pub fn to_canonical_kson(&self) -> String {
+        self.to_value().to_canonical_kson()
+    }
+
+    /// The SHA-256 digest of the canonical form of this value
+    #[cfg(feature = "digest")]
+    pub fn digest(&self) -> Digest {
+        self.to_value().digest()
+    }
+}
@@ -0,0 +1,349 @@
+//! Reading the comments of a document per value, and writing them back when rendering built values.
+//!
+//! [`Kson::comments`] parses a document into a [`CommentedValue`] tree, attaching each comment to a value:
+//!
+//! - the *leading* comments of a value are the comment lines directly above the line it starts on (the
+//!   line of its key, for a property), without blank lines in between
+//! - its *trailing* comment follows it on the line it ends on, with only commas in between
+//!
+//! [`Value`]s don't hold comments, so [`Value::to_kson_with_comments`] takes them by path, as
+//! [`CommentedValue::comments_by_path`] returns them. This lets tools carry the comments of a document
+//! over to the values they rebuild from it:
+//!
+//! ```no_run
+//! use kson_rs::path::KsonPath;
+//! use kson_rs::value::Value;
+//! use kson_rs::{FormatOptions, Kson};
+//!
+//! let document = "# Where to listen\nport: 80 # the default\n";
+//! let commented = Kson::comments(document).unwrap();
+//! let port = commented.at(&KsonPath::root().key("port")).unwrap().comments();
+//! assert_eq!(port.leading, ["Where to listen"]);
+//! assert_eq!(port.trailing.as_deref(), Some("the default"));
+//!
+//! let formatter = FormatOptions::builder().build();
+//! let value = Value::object([("port", Value::Integer(8080))]);
+//! assert_eq!(
+//!     value.to_kson_with_comments(&formatter, &commented.comments_by_path()),
+//!     "# Where to listen\nport: 8080 # the default"
+//! );
+//! ```
+//!
+//! Comments are kept without their `#` and the space following it. Comments attached to no value, e.g.
+//! at the end of an object, aren't reported. Values sharing a start line share their leading comments
+//! with the outermost of them, and values sharing an end line their trailing comment with the innermost.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::error::KsonErrors;
+use crate::format::Formatter;
+use crate::line_index::LineIndex;
+use crate::path::{KsonPath, PathSegment};
+use crate::value::{Value, sorted_property_keys};
+use crate::{Kson, KsonValue, Message, MessageSeverity, TokenType};
+
+/// The comments attached to a value (see the [module documentation](self))
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Comments {
+    /// The comment lines above the value, top to bottom
+    pub leading: Vec<String>,
+    /// The comment following the value on its last line
+    pub trailing: Option<String>,
+}
+
+impl Comments {
+    pub fn is_empty(&self) -> bool {
+        self.leading.is_empty() && self.trailing.is_none()
+    }
+}
+
+/// A value of a document along with its comments, and those of its descendants (see the
+/// [module documentation](self))
+#[derive(Clone, Debug)]
+pub struct CommentedValue {
+    value: KsonValue,
+    comments: Comments,
+    /// The properties or elements of the value, in document order
+    children: Vec<(PathSegment, CommentedValue)>,
+}
+
+impl CommentedValue {
+    pub fn value(&self) -> &KsonValue {
+        &self.value
+    }
+
+    /// The comments attached to this value
+    pub fn comments(&self) -> &Comments {
+        &self.comments
+    }
+
+    /// The properties (for objects) or elements (for arrays) of the value, in document order
+    pub fn children(&self) -> impl Iterator<Item = (&PathSegment, &CommentedValue)> {
+        self.children
+            .iter()
+            .map(|(segment, child)| (segment, child))
+    }
+
+    /// Returns the property or element at `segment`, if any
+    pub fn get(&self, segment: &PathSegment) -> Option<&CommentedValue> {
+        self.children
+            .iter()
+            .find(|(child_segment, _)| child_segment == segment)
+            .map(|(_, child)| child)
+    }
+
+    /// Returns the descendant at `path` (relative to this value), if any
+    pub fn at(&self, path: &KsonPath) -> Option<&CommentedValue> {
+        path.segments()
+            .iter()
+            .try_fold(self, |node, segment| node.get(segment))
+    }
+
+    /// The comments of this value and of its descendants, keyed by their path from this value, in the
+    /// form [`Value::to_kson_with_comments`] takes. Values without comments are left out.
+    pub fn comments_by_path(&self) -> BTreeMap<KsonPath, Comments> {
+        let mut comments = BTreeMap::new();
+        let mut pending = vec![(KsonPath::root(), self)];
+        while let Some((path, node)) = pending.pop() {
+            for (segment, child) in &node.children {
+                let mut child_path = path.clone();
+                child_path.push(segment.clone());
+                pending.push((child_path, child));
+            }
+            if !node.comments.is_empty() {
+                comments.insert(path, node.comments.clone());
+            }
+        }
+        comments
+    }
+}
+
+impl Kson {
+    /// Parses the document along with its comments, attached to its values, or returns the errors that
+    /// prevented parsing it
+    pub fn comments(document: &str) -> Result<CommentedValue, KsonErrors> {
+        let analysis = Kson::analyze(document, None);
+        let errors: Vec<Message> = analysis
+            .errors()
+            .into_iter()
+            .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+            .collect();
+        let root = match analysis.kson_value() {
+            Some(root) if errors.is_empty() => root,
+            _ => return Err(KsonErrors::from_messages(document, &errors)),
+        };
+        let index = LineIndex::new(document);
+
+        // Comments by line, noting whether they are alone on their line
+        let mut standalone = BTreeMap::new();
+        let mut trailing = Vec::new();
+        for token in analysis.tokens() {
+            if !matches!(token.token_type(), TokenType::Comment) {
+                continue;
+            }
+            let start = index.offset_of(&token.start());
+            let line = token.start().line().max(0) as usize;
+            let text = comment_text(&document[start..index.offset_of(&token.end())]);
+            if index.line(line).trim_start().starts_with('#') {
+                standalone.insert(line, text);
+            } else {
+                trailing.push((line, start, text));
+            }
+        }
+
+        let mut nodes = Vec::new();
+        collect_nodes(&root, &mut KsonPath::root(), None, 0, &index, &mut nodes);
+
+        let mut comments = vec![Comments::default(); nodes.len()];
+        let mut claimed_lines = BTreeSet::new();
+        for (node, comments) in nodes.iter().zip(&mut comments) {
+            if !claimed_lines.insert(node.anchor_line) {
+                continue;
+            }
+            comments.leading = (0..node.anchor_line)
+                .rev()
+                .map_while(|line| standalone.get(&line).cloned())
+                .collect();
+            comments.leading.reverse();
+        }
+        for (line, start, text) in trailing {
+            // Later nodes are nested deeper, so the innermost value ending before the comment wins
+            let owner = nodes.iter().rposition(|node| {
+                node.end_line == line
+                    && node.end <= start
+                    && document[node.end..start]
+                        .chars()
+                        .all(|c| c == ',' || c.is_whitespace())
+            });
+            if let Some(owner) = owner {
+                comments[owner].trailing = Some(text);
+            }
+        }
+
+        // Nodes come after their parent, so going backwards completes each node before it's moved into
+        // its parent
+        let mut built: Vec<Option<CommentedValue>> = nodes
+            .iter()
+            .zip(comments)
+            .map(|(node, comments)| {
+                Some(CommentedValue {
+                    value: node.value.clone(),
+                    comments,
+                    children: Vec::new(),
+                })
+            })
+            .collect();
+        for node_index in (1..nodes.len()).rev() {
+            let (Some(parent), Some(mut child)) =
+                (nodes[node_index].parent, built[node_index].take())
+            else {
+                continue;
+            };
+            child.children.reverse();
+            let segment = nodes[node_index].path.last().cloned();
+            if let (Some(segment), Some(parent)) = (segment, &mut built[parent]) {
+                parent.children.push((segment, child));
+            }
+        }
+        let mut root = built[0]
+            .take()
+            .expect("the root is never moved into a parent");
+        root.children.reverse();
+        Ok(root)
+    }
+}
+
+impl Value {
+    /// Renders this value as KSON like [`Value::to_kson_with`] does, with the given comments attached to
+    /// the values at their paths. Comments for paths without a value are ignored.
+    pub fn to_kson_with_comments(
+        &self,
+        formatter: &Formatter,
+        comments: &BTreeMap<KsonPath, Comments>,
+    ) -> String {
+        let kson = self.to_kson_with(formatter);
+        if comments.values().all(Comments::is_empty) {
+            return kson;
+        }
+        let Some(root) = Kson::analyze(&kson, None).kson_value() else {
+            return kson;
+        };
+        let index = LineIndex::new(&kson);
+        let mut nodes = Vec::new();
+        collect_nodes(&root, &mut KsonPath::root(), None, 0, &index, &mut nodes);
+
+        let mut above: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+        let mut after: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+        for node in &nodes {
+            let Some(comments) = comments.get(&node.path) else {
+                continue;
+            };
+            above
+                .entry(node.anchor_line)
+                .or_default()
+                .extend(comments.leading.iter().map(String::as_str));
+            if let Some(trailing) = &comments.trailing {
+                after.entry(node.end_line).or_default().push(trailing);
+            }
+        }
+
+        let mut commented = String::with_capacity(kson.len());
+        for line in 0..index.line_count() {
+            let text = index.line(line);
+            if let Some(comments) = above.get(&line) {
+                let indentation: String = text.chars().take_while(|c| c.is_whitespace()).collect();
+                for comment in comments {
+                    commented.push_str(&indentation);
+                    write_comment(comment, &mut commented);
+                    commented.push('\n');
+                }
+            }
+            commented.push_str(text);
+            if let Some(comments) = after.get(&line) {
+                commented.push(' ');
+                // A line holds a single comment, so the trailing comments of values ending on the same
+                // line are joined
+                write_comment(&comments.join("; "), &mut commented);
+            }
+            if line + 1 < index.line_count() {
+                commented.push('\n');
+            }
+        }
+        // Let the formatter settle the comments in, e.g. into the indentation of their values
+        formatter.format(&commented)
+    }
+}
+
+/// A value of a document, with the lines it starts and ends on
+struct Node {
+    value: KsonValue,
+    path: KsonPath,
+    /// The index of the parent node, if any
+    parent: Option<usize>,
+    /// The line the value starts on, or the line of its key for a property
+    anchor_line: usize,
+    end_line: usize,
+    /// The byte offset of the end of the value
+    end: usize,
+}
+
+/// Collects the nodes of the value and of its descendants, parents first, properties and elements in
+/// document order
+fn collect_nodes(
+    value: &KsonValue,
+    path: &mut KsonPath,
+    parent: Option<usize>,
+    anchor_line: usize,
+    index: &LineIndex,
+    nodes: &mut Vec<Node>,
+) {
+    let node_index = nodes.len();
+    nodes.push(Node {
+        value: value.clone(),
+        path: path.clone(),
+        parent,
+        anchor_line,
+        end_line: value.end().line().max(0) as usize,
+        end: index.offset_of(&value.end()),
+    });
+    match value {
+        KsonValue::KsonObject(object) => {
+            let mut properties = object.properties();
+            for (name, key) in sorted_property_keys(object) {
+                let Some(property) = properties.remove(&name) else {
+                    continue;
+                };
+                path.push(PathSegment::Key(name));
+                let anchor_line = key.start().line().max(0) as usize;
+                collect_nodes(&property, path, Some(node_index), anchor_line, index, nodes);
+                path.pop();
+            }
+        }
+        KsonValue::KsonArray(array) => {
+            for (element_index, element) in array.elements().iter().enumerate() {
+                path.push(PathSegment::Index(element_index));
+                let anchor_line = element.start().line().max(0) as usize;
+                collect_nodes(element, path, Some(node_index), anchor_line, index, nodes);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The text of a comment token, without its `#` and the space following it
+fn comment_text(token: &str) -> String {
+    let text = token.strip_prefix('#').unwrap_or(token);
+    text.strip_prefix(' ')
+        .unwrap_or(text)
+        .trim_end()
+        .to_string()
+}
+
+fn write_comment(text: &str, out: &mut String) {
+    out.push('#');
+    if !text.is_empty() {
+        out.push(' ');
+        out.push_str(text);
+    }
+}
@@ -0,0 +1,647 @@
+//! Project configuration, shared by the tools working on the documents of a project (the CLI, the
+//! language server, CI checks).
+//!
+//! The configuration is read from a `.kson.kson` or `kson.toml` file, found in the directory the tool
+//! runs from or the closest of its ancestors (see [`ProjectConfig::discover`]):
+//!
+//! ```kson
+//! format: {
+//!   indent: 4          # a number of spaces, or 'tabs'
+//!   style: delimited   # plain, delimited, compact or classic
+//!   quotes: double     # single or double
+//!   comment_width: 100
+//!   max_width: 120
+//!   blank_lines: preserve
+//!   floats: shortest   # preserve, shortest or a number of digits after the point
+//!   sort_keys: alphabetical  # preserve, alphabetical or alphabetical-case-insensitive
+//!   line_ending: preserve    # lf, crlf or preserve
+//!   final_newline: true
+//!   trailing_commas: multiline  # never or multiline
+//! }
+//! lint: {
+//!   secrets: error     # off, warning or error
+//!   unused-keys: { level: warning, ignore: ['/metadata/**'] }
+//! }
+//! schemas: {
+//!   'configs/*.kson': 'schemas/service.kson'
+//! }
+//! references: {
+//!   keys: [include, '$ref']
+//! }
+//! ```
+//!
+//! The same settings in `kson.toml`:
+//!
+//! ```toml
+//! [format]
+//! indent = 4
+//! style = "delimited"
+//!
+//! [lint]
+//! secrets = "error"
+//!
+//! [schemas]
+//! "configs/*.kson" = "schemas/service.kson"
+//!
+//! [references]
+//! keys = ["include", "$ref"]
+//! ```
+//!
+//! Every section is optional, and paths are relative to the directory of the configuration file. The
+//! `schemas` section maps [file globs](FileGlob) to the schema the matching documents are validated
+//! against, see [`ProjectConfig::schema_for`]. The `references` section names the properties whose
+//! values reference other documents, see [`crate::references`].
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::format::{
+    BlankLines, FloatFormat, Formatter, KeyOrder, LineEnding, QuoteStyle, TrailingCommas,
+};
+use crate::pointer::{PointerError, PointerGlob};
+use crate::value::{Map, Value};
+use crate::{FormatOptions, FormattingStyle, IndentType, MessageSeverity, indent_type};
+
+/// The names of the configuration files
+pub const CONFIG_FILENAMES: [&str; 2] = [".kson.kson", "kson.toml"];
+
+/// The reason a project configuration couldn't be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    /// The configuration file is not valid KSON or TOML
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    /// A setting has an unknown name or an invalid value
+    InvalidSetting {
+        /// The dotted name of the setting (e.g. `format.indent`)
+        setting: String,
+        message: String,
+    },
+    /// A directory has both a `.kson.kson` and a `kson.toml`, so it's unclear which one applies
+    Conflicting(PathBuf),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(error) => error.fmt(f),
+            ConfigError::Parse { path, message } => {
+                write!(f, "invalid configuration in {}: {message}", path.display())
+            }
+            ConfigError::InvalidSetting { setting, message } => {
+                write!(f, "invalid setting `{setting}`: {message}")
+            }
+            ConfigError::Conflicting(dir) => write!(
+                f,
+                "both {} and {} found in {}, remove one of them",
+                CONFIG_FILENAMES[0],
+                CONFIG_FILENAMES[1],
+                dir.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(error: std::io::Error) -> Self {
+        ConfigError::Io(error)
+    }
+}
+
+/// The indentation of formatted documents
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(u32),
+    Tabs,
+}
+
+/// The `format` section: how documents are formatted. Unset settings keep the formatter's defaults.
+#[derive(Clone, Default)]
+pub struct FormatSettings {
+    pub indent: Option<Indent>,
+    pub style: Option<FormattingStyle>,
+    pub quotes: Option<QuoteStyle>,
+    pub comment_width: Option<usize>,
+    pub max_width: Option<usize>,
+    pub blank_lines: Option<BlankLines>,
+    pub floats: Option<FloatFormat>,
+    pub sort_keys: Option<KeyOrder>,
+    pub line_ending: Option<LineEnding>,
+    pub final_newline: Option<bool>,
+    pub trailing_commas: Option<TrailingCommas>,
+}
+
+impl FormatSettings {
+    /// The options for [`Kson::format`](crate::Kson::format)
+    pub fn options(&self) -> FormatOptions {
+        let indent = match self.indent.unwrap_or(Indent::Spaces(2)) {
+            Indent::Spaces(spaces) => IndentType::Spaces(indent_type::Spaces::new(spaces as i32)),
+            Indent::Tabs => IndentType::Tabs(indent_type::Tabs::new()),
+        };
+        FormatOptions::new(indent, self.style.unwrap_or(FormattingStyle::Plain), &[])
+    }
+
+    /// A formatter applying all the settings, including those the core formatter doesn't support
+    pub fn formatter(&self) -> Formatter {
+        let mut formatter = Formatter::new(self.options());
+        if let Some(quotes) = self.quotes {
+            formatter = formatter.quote_style(quotes);
+        }
+        if let Some(width) = self.comment_width {
+            formatter = formatter.reflow_comments(width);
+        }
+        if let Some(width) = self.max_width {
+            formatter = formatter.max_width(width);
+        }
+        if let Some(blank_lines) = self.blank_lines {
+            formatter = formatter.blank_lines(blank_lines);
+        }
+        if let Some(floats) = self.floats {
+            formatter = formatter.float_format(floats);
+        }
+        if let Some(key_order) = self.sort_keys {
+            formatter = formatter.sort_keys(key_order);
+        }
+        if let Some(line_ending) = self.line_ending {
+            formatter = formatter.line_ending(line_ending);
+        }
+        if let Some(final_newline) = self.final_newline {
+            formatter = formatter.final_newline(final_newline);
+        }
+        if let Some(trailing_commas) = self.trailing_commas {
+            formatter = formatter.trailing_commas(trailing_commas);
+        }
+        formatter
+    }
+}
+
+/// How a lint rule reports its findings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleLevel {
+    Off,
+    Warning,
+    Error,
+}
+
+impl RuleLevel {
+    /// The severity of the rule's findings, if it's enabled
+    pub fn severity(self) -> Option<MessageSeverity> {
+        match self {
+            RuleLevel::Off => None,
+            RuleLevel::Warning => Some(MessageSeverity::Warning),
+            RuleLevel::Error => Some(MessageSeverity::Error),
+        }
+    }
+}
+
+/// The setting of a lint rule in the `lint` section, written either as a level (`secrets: error`) or as
+/// an object with a `level` and rule-specific options
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleSetting {
+    pub level: RuleLevel,
+    /// The other properties of the setting, interpreted by the rule
+    pub options: Map,
+}
+
+/// A glob matching file paths relative to a directory, with `/` as separator (even on Windows):
+/// - `*` matches any run of characters within a path component and `?` a single character
+/// - `**` as a whole component matches zero or more directories
+/// - a glob without `/` (e.g. `*.kson`) matches file names in any directory, like in `.gitignore` files,
+///   and a leading `/` anchors a glob to the directory instead (e.g. `/*.kson`)
+///
+/// `\*`, `\?` and `\\` match those characters literally.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileGlob {
+    pattern: String,
+    glob: PointerGlob,
+}
+
+impl FileGlob {
+    pub fn parse(pattern: &str) -> Result<Self, PointerError> {
+        let trimmed = pattern.trim_start_matches("./");
+        let anchored = if trimmed.contains('/') {
+            trimmed.trim_start_matches('/').to_string()
+        } else {
+            format!("**/{trimmed}")
+        };
+        // A file glob is a JsonPointerGlob with the pointer escapes of `~` and without the leading `/`
+        let glob = PointerGlob::parse(&format!("/{}", anchored.replace('~', "~0")))?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            glob,
+        })
+    }
+
+    /// The glob as written
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether the relative path matches this glob
+    pub fn matches(&self, path: &Path) -> bool {
+        let components: Vec<_> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+        self.glob.matches(&components)
+    }
+}
+
+impl FromStr for FileGlob {
+    type Err = PointerError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        Self::parse(pattern)
+    }
+}
+
+/// Associations of files with the schemas they should be validated against, by glob, so that tools don't
+/// need to be told the schema of each file. When several globs match a file, the last one wins, which
+/// allows following a general glob with more specific exceptions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SchemaAssociations {
+    associations: Vec<(FileGlob, PathBuf)>,
+}
+
+impl SchemaAssociations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Associates the files matching `glob` with the schema at the given path
+    pub fn associate(mut self, glob: FileGlob, schema: impl Into<PathBuf>) -> Self {
+        self.associations.push((glob, schema.into()));
+        self
+    }
+
+    /// The schema associated with the given relative path, if any
+    pub fn schema_for(&self, file: &Path) -> Option<&Path> {
+        self.associations
+            .iter()
+            .rev()
+            .find(|(glob, _)| glob.matches(file))
+            .map(|(_, schema)| schema.as_path())
+    }
+
+    /// The globs along with the schemas they are associated with, in order
+    pub fn iter(&self) -> impl Iterator<Item = (&FileGlob, &Path)> {
+        self.associations
+            .iter()
+            .map(|(glob, schema)| (glob, schema.as_path()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.associations.is_empty()
+    }
+}
+
+/// The configuration of a project (see the [module documentation](self))
+#[derive(Clone)]
+pub struct ProjectConfig {
+    root: PathBuf,
+    path: Option<PathBuf>,
+    format: FormatSettings,
+    rules: BTreeMap<String, RuleSetting>,
+    schemas: SchemaAssociations,
+    reference_keys: Vec<String>,
+}
+
+impl ProjectConfig {
+    /// The configuration of a project without configuration file, rooted at the given directory
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            path: None,
+            format: FormatSettings::default(),
+            rules: BTreeMap::new(),
+            schemas: SchemaAssociations::new(),
+            reference_keys: Vec::new(),
+        }
+    }
+
+    /// Loads the configuration file of the closest directory to `start` (itself included) that has one,
+    /// returning `None` if no ancestor has one
+    pub fn discover(start: &Path) -> Result<Option<Self>, ConfigError> {
+        for dir in start.ancestors() {
+            let mut found = CONFIG_FILENAMES
+                .iter()
+                .map(|name| dir.join(name))
+                .filter(|path| path.is_file());
+            if let Some(path) = found.next() {
+                if found.next().is_some() {
+                    return Err(ConfigError::Conflicting(dir.to_path_buf()));
+                }
+                return Self::load(&path).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Loads the given configuration file, read as TOML if its extension is `.toml` and as KSON otherwise
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path)?;
+        let parse_error = |message: String| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message,
+        };
+        let value = if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            let table: toml::Table = text
+                .parse()
+                .map_err(|error: toml::de::Error| parse_error(error.to_string()))?;
+            toml_to_value(toml::Value::Table(table))
+        } else {
+            Value::parse(&text).map_err(|errors| parse_error(errors.to_string()))?
+        };
+
+        let root = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        let mut config = Self::from_value(root, &value)?;
+        config.path = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Reads the configuration from an already parsed document, e.g. the unsaved contents of the
+    /// configuration file in an editor. Relative paths are resolved against `root`.
+    pub fn from_value(root: impl Into<PathBuf>, value: &Value) -> Result<Self, ConfigError> {
+        let mut config = Self::new(root);
+        for (section, settings) in object(value, "")?.iter() {
+            match section.as_str() {
+                "format" => config.format = read_format(settings)?,
+                "lint" => {
+                    for (rule, setting) in object(settings, "lint")?.iter() {
+                        let name = format!("lint.{rule}");
+                        config
+                            .rules
+                            .insert(rule.clone(), read_rule(setting, &name)?);
+                    }
+                }
+                "schemas" => {
+                    for (pattern, schema) in object(settings, "schemas")?.iter() {
+                        let setting = format!("schemas.{pattern}");
+                        let glob = FileGlob::parse(pattern)
+                            .map_err(|error| invalid(&setting, &error.to_string()))?;
+                        let Value::String(schema) = schema else {
+                            return Err(invalid(&setting, "expected the path of a schema"));
+                        };
+                        let schema = config.root.join(schema);
+                        config.schemas = config.schemas.associate(glob, schema);
+                    }
+                }
+                "references" => config.reference_keys = read_reference_keys(settings)?,
+                _ => return Err(invalid(section, "unknown section")),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The directory of the project, which relative paths in the configuration are resolved against
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The configuration file, if the configuration was loaded from one
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn format(&self) -> &FormatSettings {
+        &self.format
+    }
+
+    /// The setting of the lint rule with the given name, if the configuration has one
+    pub fn rule(&self, name: &str) -> Option<&RuleSetting> {
+        self.rules.get(name)
+    }
+
+    /// The settings of all the lint rules in the configuration, by rule name
+    pub fn rules(&self) -> &BTreeMap<String, RuleSetting> {
+        &self.rules
+    }
+
+    /// The file globs of the `schemas` section along with the schemas they map to
+    pub fn schemas(&self) -> &SchemaAssociations {
+        &self.schemas
+    }
+
+    /// The schema of the given file according to the `schemas` section, if any. Relative paths are
+    /// relative to the project root, and absolute paths outside of the root have no schema.
+    pub fn schema_for(&self, file: &Path) -> Option<&Path> {
+        let relative = if file.is_absolute() {
+            file.strip_prefix(&self.root).ok()?
+        } else {
+            file
+        };
+        self.schemas.schema_for(relative)
+    }
+
+    /// The names of the properties whose values reference other documents (or other parts of the same
+    /// document), from the `references` section
+    pub fn reference_keys(&self) -> &[String] {
+        &self.reference_keys
+    }
+}
+
+fn invalid(setting: &str, message: &str) -> ConfigError {
+    ConfigError::InvalidSetting {
+        setting: setting.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn object<'a>(value: &'a Value, setting: &str) -> Result<&'a Map, ConfigError> {
+    match value {
+        Value::Object(map) => Ok(map),
+        _ if setting.is_empty() => Err(invalid("", "the configuration must be an object")),
+        _ => Err(invalid(setting, "expected an object")),
+    }
+}
+
+fn read_format(value: &Value) -> Result<FormatSettings, ConfigError> {
+    let mut settings = FormatSettings::default();
+    for (name, setting) in object(value, "format")?.iter() {
+        let full_name = format!("format.{name}");
+        let error = |message: &str| invalid(&full_name, message);
+        match (name.as_str(), setting) {
+            ("indent", Value::Integer(spaces)) => {
+                let spaces = u32::try_from(*spaces)
+                    .map_err(|_| error("expected a number of spaces or 'tabs'"))?;
+                settings.indent = Some(Indent::Spaces(spaces));
+            }
+            ("indent", Value::String(tabs)) if tabs == "tabs" => {
+                settings.indent = Some(Indent::Tabs)
+            }
+            ("indent", _) => return Err(error("expected a number of spaces or 'tabs'")),
+            ("style", Value::String(style)) => {
+                settings.style = Some(match style.as_str() {
+                    "plain" => FormattingStyle::Plain,
+                    "delimited" => FormattingStyle::Delimited,
+                    "compact" => FormattingStyle::Compact,
+                    "classic" => FormattingStyle::Classic,
+                    _ => return Err(error("expected plain, delimited, compact or classic")),
+                });
+            }
+            ("style", _) => return Err(error("expected plain, delimited, compact or classic")),
+            ("quotes", Value::String(quotes)) if quotes == "single" => {
+                settings.quotes = Some(QuoteStyle::Single);
+            }
+            ("quotes", Value::String(quotes)) if quotes == "double" => {
+                settings.quotes = Some(QuoteStyle::Double);
+            }
+            ("quotes", _) => return Err(error("expected single or double")),
+            ("comment_width", Value::Integer(width)) if *width > 0 => {
+                settings.comment_width = Some(*width as usize);
+            }
+            ("comment_width", _) => return Err(error("expected a positive number of columns")),
+            ("max_width", Value::Integer(width)) if *width > 0 => {
+                settings.max_width = Some(*width as usize);
+            }
+            ("max_width", _) => return Err(error("expected a positive number of columns")),
+            ("blank_lines", Value::String(policy)) if policy == "normalize" => {
+                settings.blank_lines = Some(BlankLines::Normalize);
+            }
+            ("blank_lines", Value::String(policy)) if policy == "preserve" => {
+                settings.blank_lines = Some(BlankLines::Preserve);
+            }
+            ("blank_lines", Value::Integer(max)) if *max >= 0 => {
+                settings.blank_lines = Some(BlankLines::Collapse(*max as usize));
+            }
+            ("blank_lines", _) => {
+                return Err(error(
+                    "expected normalize, preserve or a maximum number of blank lines",
+                ));
+            }
+            ("floats", Value::String(floats)) if floats == "preserve" => {
+                settings.floats = Some(FloatFormat::Preserve);
+            }
+            ("floats", Value::String(floats)) if floats == "shortest" => {
+                settings.floats = Some(FloatFormat::Shortest);
+            }
+            ("floats", Value::Integer(digits)) if *digits > 0 => {
+                settings.floats = Some(FloatFormat::Fixed(*digits as usize));
+            }
+            ("floats", _) => {
+                return Err(error(
+                    "expected preserve, shortest or a positive number of digits",
+                ));
+            }
+            ("sort_keys", Value::String(order)) => {
+                settings.sort_keys = Some(match order.as_str() {
+                    "preserve" => KeyOrder::Preserve,
+                    "alphabetical" => KeyOrder::Alphabetical,
+                    "alphabetical-case-insensitive" => KeyOrder::AlphabeticalCaseInsensitive,
+                    _ => {
+                        return Err(error(
+                            "expected preserve, alphabetical or alphabetical-case-insensitive",
+                        ));
+                    }
+                });
+            }
+            ("sort_keys", _) => {
+                return Err(error(
+                    "expected preserve, alphabetical or alphabetical-case-insensitive",
+                ));
+            }
+            ("line_ending", Value::String(line_ending)) => {
+                settings.line_ending = Some(match line_ending.as_str() {
+                    "lf" => LineEnding::Lf,
+                    "crlf" => LineEnding::Crlf,
+                    "preserve" => LineEnding::Preserve,
+                    _ => return Err(error("expected lf, crlf or preserve")),
+                });
+            }
+            ("line_ending", _) => return Err(error("expected lf, crlf or preserve")),
+            ("final_newline", Value::Bool(final_newline)) => {
+                settings.final_newline = Some(*final_newline);
+            }
+            ("final_newline", _) => return Err(error("expected true or false")),
+            ("trailing_commas", Value::String(commas)) if commas == "never" => {
+                settings.trailing_commas = Some(TrailingCommas::Never);
+            }
+            ("trailing_commas", Value::String(commas)) if commas == "multiline" => {
+                settings.trailing_commas = Some(TrailingCommas::Multiline);
+            }
+            ("trailing_commas", _) => return Err(error("expected never or multiline")),
+            _ => return Err(error("unknown setting")),
+        }
+    }
+    Ok(settings)
+}
+
+fn read_reference_keys(value: &Value) -> Result<Vec<String>, ConfigError> {
+    let mut keys = Vec::new();
+    for (name, setting) in object(value, "references")?.iter() {
+        match (name.as_str(), setting) {
+            ("keys", Value::Array(elements)) => {
+                for element in elements {
+                    let Value::String(key) = element else {
+                        return Err(invalid("references.keys", "expected a list of keys"));
+                    };
+                    keys.push(key.clone());
+                }
+            }
+            ("keys", _) => return Err(invalid("references.keys", "expected a list of keys")),
+            _ => return Err(invalid(&format!("references.{name}"), "unknown setting")),
+        }
+    }
+    Ok(keys)
+}
+
+fn read_rule(value: &Value, setting: &str) -> Result<RuleSetting, ConfigError> {
+    let level = |level: &Value| match level {
+        Value::String(level) if level == "off" => Ok(RuleLevel::Off),
+        Value::String(level) if level == "warning" => Ok(RuleLevel::Warning),
+        Value::String(level) if level == "error" => Ok(RuleLevel::Error),
+        _ => Err(invalid(setting, "expected off, warning or error")),
+    };
+    match value {
+        Value::Object(map) => {
+            let mut options = map.clone();
+            let level = match options.remove("level") {
+                Some(value) => level(&value)?,
+                None => return Err(invalid(setting, "missing `level`")),
+            };
+            Ok(RuleSetting { level, options })
+        }
+        _ => Ok(RuleSetting {
+            level: level(value)?,
+            options: Map::new(),
+        }),
+    }
+}
+
+fn toml_to_value(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(string) => Value::String(string),
+        toml::Value::Integer(integer) => Value::Integer(integer),
+        toml::Value::Float(float) => Value::Decimal(float),
+        toml::Value::Boolean(boolean) => Value::Bool(boolean),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(elements) => {
+            Value::Array(elements.into_iter().map(toml_to_value).collect())
+        }
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_value(value)))
+                .collect(),
+        ),
+    }
+}
@@ -0,0 +1,367 @@
+//! Conversions of KSON to JSON and YAML beyond what [`Kson::to_json`] and [`Kson::to_yaml`] offer.
+//!
+//! [`Kson::conversion_effects`] is a dry run, reporting what a conversion would lose without producing
+//! its output, e.g. to audit a migration away from KSON before running it:
+//!
+//! ```no_run
+//! use kson_rs::{Kson, TranspileOptions, transpile_options};
+//!
+//! let options = TranspileOptions::Json(transpile_options::Json::new(false));
+//! let effects = Kson::conversion_effects("# the answer\nkey: 42", &options).unwrap();
+//! for effect in effects {
+//!     println!("{}:{}: {}", effect.start.line() + 1, effect.start.column() + 1, effect.message);
+//! }
+//! ```
+//!
+//! [`Kson::to_json_with_policy`] and [`Kson::to_yaml_with_policy`] decide what happens to embed blocks
+//! tag by tag, where the options of the plain conversions either retain all tags or none:
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//! use kson_rs::conversion::EmbedTagPolicy;
+//!
+//! // Keep `sql` tags, flatten everything else to strings
+//! let policy = EmbedTagPolicy::strip_all().retain("sql");
+//! let json = Kson::to_json_with_policy("query: %sql\n  select 1\n  %%", &policy);
+//! ```
+//!
+//! [`Kson::to_json_with_floats`] and [`Kson::to_yaml_with_floats`] rewrite the decimals of the document
+//! as a [`FloatFormat`] says before converting it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::format::{FloatFormat, apply_float_format};
+use crate::line_index::LineIndex;
+use crate::path::KsonPath;
+use crate::query::QueryNode;
+use crate::value::Value;
+use crate::{
+    Kson, KsonValue, Message, MessageSeverity, Position, TokenType, TranspileOptions, result,
+    transpile_options,
+};
+
+/// The largest integer up to which every integer is exactly representable as an `f64` (2^53)
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// Something a conversion would lose, as reported by [`Kson::conversion_effects`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConversionEffect {
+    pub message: String,
+    /// The start of the affected part of the document
+    pub start: Position,
+    /// The end of the affected part of the document
+    pub end: Position,
+    pub kind: ConversionEffectKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConversionEffectKind {
+    /// A comment is dropped, since JSON has no comments (YAML output keeps them)
+    CommentDropped { text: String },
+    /// An embed block becomes a plain string holding its content, losing its tag if it has one, because
+    /// the conversion doesn't retain embed tags
+    EmbedFlattened {
+        tag: Option<String>,
+        /// The path of the embed block in the document
+        path: KsonPath,
+    },
+    /// A number can't be represented exactly as a 64-bit float, which is how JSON and YAML parsers
+    /// commonly read numbers, so readers of the output will see `value` instead
+    PrecisionLost {
+        /// The number as written in the document
+        text: String,
+        /// The closest 64-bit float (infinite if the number is out of range)
+        value: f64,
+        /// The path of the number in the document
+        path: KsonPath,
+    },
+}
+
+impl Kson {
+    /// Reports what converting the input with the given options would lose, in document order, without
+    /// converting it. Returns the errors that prevented parsing the input instead, if any, which the
+    /// conversion would fail with.
+    pub fn conversion_effects(
+        input: &str,
+        options: &TranspileOptions,
+    ) -> Result<Vec<ConversionEffect>, Vec<Message>> {
+        let analysis = Kson::analyze(input, None);
+        let Some(value) = analysis.kson_value() else {
+            return Err(analysis
+                .errors()
+                .into_iter()
+                .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+                .collect());
+        };
+
+        let (drops_comments, retains_embed_tags) = match options {
+            TranspileOptions::Json(json) => (true, json.retain_embed_tags()),
+            TranspileOptions::Yaml(yaml) => (false, yaml.retain_embed_tags()),
+        };
+        let mut effects = Vec::new();
+        if drops_comments {
+            for token in analysis.tokens() {
+                if !matches!(token.token_type(), TokenType::Comment) {
+                    continue;
+                }
+                let text = token.text();
+                effects.push(ConversionEffect {
+                    message: format!("The comment `{}` will be dropped", text.trim()),
+                    start: token.start(),
+                    end: token.end(),
+                    kind: ConversionEffectKind::CommentDropped { text },
+                });
+            }
+        }
+        collect_value_effects(
+            &value,
+            &LineIndex::new(input),
+            retains_embed_tags,
+            &mut effects,
+        );
+
+        effects.sort_by_key(|effect| (effect.start.line(), effect.start.column()));
+        Ok(effects)
+    }
+}
+
+fn collect_value_effects(
+    root: &KsonValue,
+    text: &LineIndex,
+    retains_embed_tags: bool,
+    effects: &mut Vec<ConversionEffect>,
+) {
+    let mut pending = vec![(KsonPath::root(), root.clone())];
+    while let Some((path, value)) = pending.pop() {
+        let kind = match &value {
+            KsonValue::KsonEmbed(embed) if !retains_embed_tags => {
+                let tag = embed.tag();
+                let message = match &tag {
+                    Some(tag) => {
+                        format!("The embed block tagged `{tag}` will become a plain string")
+                    }
+                    None => "The embed block will become a plain string".to_string(),
+                };
+                Some((message, ConversionEffectKind::EmbedFlattened { tag, path }))
+            }
+            KsonValue::KsonNumber(_) => {
+                let number = text.slice(&value.start(), &value.end()).trim();
+                match number.parse::<f64>() {
+                    Ok(float) if !is_exact(number, float) => {
+                        let message = format!(
+                            "The number `{number}` will be read as `{float}` by most JSON and YAML parsers"
+                        );
+                        let kind = ConversionEffectKind::PrecisionLost {
+                            text: number.to_string(),
+                            value: float,
+                            path,
+                        };
+                        Some((message, kind))
+                    }
+                    _ => None,
+                }
+            }
+            _ => {
+                pending.extend(
+                    value
+                        .children()
+                        .into_iter()
+                        .map(|(segment, child)| (path.clone().join(segment), child)),
+                );
+                None
+            }
+        };
+        if let Some((message, kind)) = kind {
+            effects.push(ConversionEffect {
+                message,
+                start: value.start(),
+                end: value.end(),
+                kind,
+            });
+        }
+    }
+}
+
+/// Whether the float is exactly the number written as `text`, i.e. whether they have the same significant
+/// digits
+fn is_exact(text: &str, float: f64) -> bool {
+    if !float.is_finite() {
+        return false;
+    }
+    if float.fract() == 0.0 && float.abs() <= MAX_SAFE_INTEGER {
+        // Integral values in this range are exact, however they are written (e.g. `1e3` or `10.0`)
+        return true;
+    }
+    // `{:e}` gives the shortest digits that read back as the same float, so any other significant digit
+    // in the text is lost
+    significant_digits(text) == significant_digits(&format!("{float:e}"))
+}
+
+/// The digits of the mantissa of a number, without leading or trailing zeros
+fn significant_digits(number: &str) -> String {
+    let mantissa = number.split(['e', 'E']).next().unwrap_or(number);
+    let digits: String = mantissa.chars().filter(char::is_ascii_digit).collect();
+    digits
+        .trim_start_matches('0')
+        .trim_end_matches('0')
+        .to_string()
+}
+
+type Transform = Arc<dyn Fn(&str) -> Value + Send + Sync>;
+
+/// What a conversion does with an embed block
+#[derive(Clone)]
+pub enum EmbedTagAction {
+    /// Keep the tag, so the block becomes an object with its tag and content
+    Retain,
+    /// Drop the tag, so the block becomes a string holding its content
+    Strip,
+    /// Replace the block by the value computed from its content
+    Transform(Transform),
+}
+
+impl std::fmt::Debug for EmbedTagAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmbedTagAction::Retain => f.write_str("Retain"),
+            EmbedTagAction::Strip => f.write_str("Strip"),
+            EmbedTagAction::Transform(_) => f.write_str("Transform(..)"),
+        }
+    }
+}
+
+/// The [`EmbedTagAction`] to take for each embed tag during a conversion, for
+/// [`Kson::to_json_with_policy`] and [`Kson::to_yaml_with_policy`]. Tags are matched case-insensitively,
+/// and blocks with no tag or a tag without an action get the default one.
+#[derive(Clone, Debug)]
+pub struct EmbedTagPolicy {
+    actions: HashMap<String, EmbedTagAction>,
+    default: EmbedTagAction,
+}
+
+impl EmbedTagPolicy {
+    /// A policy retaining every tag by default, like `retain_embed_tags: true`
+    pub fn retain_all() -> Self {
+        Self {
+            actions: HashMap::new(),
+            default: EmbedTagAction::Retain,
+        }
+    }
+
+    /// A policy stripping every tag by default, like `retain_embed_tags: false`
+    pub fn strip_all() -> Self {
+        Self {
+            actions: HashMap::new(),
+            default: EmbedTagAction::Strip,
+        }
+    }
+
+    pub fn retain(self, tag: &str) -> Self {
+        self.action(tag, EmbedTagAction::Retain)
+    }
+
+    pub fn strip(self, tag: &str) -> Self {
+        self.action(tag, EmbedTagAction::Strip)
+    }
+
+    /// Replaces the blocks with the given tag by the value `transform` computes from their content, e.g.
+    /// to parse them
+    pub fn transform(
+        self,
+        tag: &str,
+        transform: impl Fn(&str) -> Value + Send + Sync + 'static,
+    ) -> Self {
+        self.action(tag, EmbedTagAction::Transform(Arc::new(transform)))
+    }
+
+    /// Sets the action for the blocks with the given tag, replacing any previous one
+    pub fn action(mut self, tag: &str, action: EmbedTagAction) -> Self {
+        self.actions.insert(tag.to_lowercase(), action);
+        self
+    }
+
+    /// The action for blocks with the given tag (or with no tag)
+    pub fn action_for(&self, tag: Option<&str>) -> &EmbedTagAction {
+        tag.and_then(|tag| self.actions.get(&tag.to_lowercase()))
+            .unwrap_or(&self.default)
+    }
+}
+
+impl Kson {
+    /// Like [`Kson::to_json`], with the embed tags retained according to the policy
+    pub fn to_json_with_policy(
+        kson: &str,
+        policy: &EmbedTagPolicy,
+    ) -> std::result::Result<result::Success, result::Failure> {
+        Kson::to_json(
+            &apply_embed_policy(kson, policy),
+            transpile_options::Json::new(true),
+        )
+    }
+
+    /// Like [`Kson::to_yaml`], with the embed tags retained according to the policy
+    pub fn to_yaml_with_policy(
+        kson: &str,
+        policy: &EmbedTagPolicy,
+    ) -> std::result::Result<result::Success, result::Failure> {
+        Kson::to_yaml(
+            &apply_embed_policy(kson, policy),
+            transpile_options::Yaml::new(true),
+        )
+    }
+}
+
+impl Kson {
+    /// Like [`Kson::to_json`], with the decimals of the document written as `floats` says
+    pub fn to_json_with_floats(
+        kson: &str,
+        options: transpile_options::Json,
+        floats: FloatFormat,
+    ) -> std::result::Result<result::Success, result::Failure> {
+        Kson::to_json(&apply_float_format(kson, floats), options)
+    }
+
+    /// Like [`Kson::to_yaml`], with the decimals of the document written as `floats` says
+    pub fn to_yaml_with_floats(
+        kson: &str,
+        options: transpile_options::Yaml,
+        floats: FloatFormat,
+    ) -> std::result::Result<result::Success, result::Failure> {
+        Kson::to_yaml(&apply_float_format(kson, floats), options)
+    }
+}
+
+/// Rewrites the embed blocks of the document that aren't retained by the policy, so that converting it
+/// with embed tags retained applies the policy. Invalid documents are returned as is, for the conversion
+/// to report their errors.
+fn apply_embed_policy(kson: &str, policy: &EmbedTagPolicy) -> String {
+    let Some(root) = Kson::analyze(kson, None).kson_value() else {
+        return kson.to_string();
+    };
+
+    let text = LineIndex::new(kson);
+    let mut replacements = Vec::new();
+    let mut pending = vec![root];
+    while let Some(value) = pending.pop() {
+        let KsonValue::KsonEmbed(embed) = &value else {
+            pending.extend(value.children().into_iter().map(|(_, child)| child));
+            continue;
+        };
+        let replacement = match policy.action_for(embed.tag().as_deref()) {
+            EmbedTagAction::Retain => continue,
+            EmbedTagAction::Strip => Value::String(embed.content()),
+            EmbedTagAction::Transform(transform) => transform(&embed.content()),
+        };
+        let range = text.offset_of(&value.start())..text.offset_of(&value.end());
+        replacements.push((range, replacement.to_inline_kson()));
+    }
+
+    // Replace from the end, so that the offsets of the remaining blocks stay valid
+    replacements.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    let mut rewritten = kson.to_string();
+    for (range, replacement) in replacements {
+        rewritten.replace_range(range, &replacement);
+    }
+    rewritten
+}
@@ -0,0 +1,393 @@
+//! Schema coverage: which constraints of a schema a corpus of documents exercises, e.g. to check that
+//! the example configurations (or test fixtures) of a schema go through all of it before rolling it out.
+//!
+//! [`Kson::schema_coverage`] lists the constraints of the schema that documents can exercise:
+//!
+//! - each property declared in `properties`, exercised by the documents having it
+//! - each value listed in an `enum`, exercised by the documents having it
+//! - each branch of `anyOf` and `oneOf`, exercised by the documents with a value it accepts
+//! - `then` and `else`, exercised by the documents with a value they are selected for by `if`
+//!
+//! Constraints are found under the keywords holding subschemas, like
+//! [`WorkspaceIndex::key_usage`](crate::index::WorkspaceIndex::key_usage) finds declared properties,
+//! and documents exercise them wherever they may apply: properties and enum values inside composition
+//! branches count as exercised whether the branch accepts the value or not. The report can be rendered
+//! as JSON with [`SchemaCoverage::to_value`] and [`Value::to_json`], or as a standalone HTML page:
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//!
+//! let schema = "properties: { protocol: { enum: [tcp, udp] } }";
+//! let coverage = Kson::schema_coverage(schema, &["protocol: tcp"]).unwrap();
+//! assert_eq!((coverage.covered(), coverage.constraints.len()), (2, 3));
+//! for constraint in coverage.uncovered() {
+//!     println!("`#{}` is not covered: {}", constraint.schema_path, constraint.kind);
+//! }
+//! std::fs::write("coverage.html", coverage.to_html()).unwrap();
+//! ```
+
+use std::collections::BTreeSet;
+use std::ops::ControlFlow;
+
+use crate::error::KsonErrors;
+use crate::path::KsonPath;
+use crate::schema::{CompositionKeyword, branch_errors, for_each_possible_schema};
+use crate::value::{Value, values_equal};
+use crate::{Kson, KsonValue};
+
+/// The constraints of a schema, with the number of documents exercising each of them
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchemaCoverage {
+    /// The number of documents measured (those that don't parse are left out)
+    pub documents: usize,
+    /// The constraints of the schema, in schema order
+    pub constraints: Vec<Constraint>,
+}
+
+/// A constraint of a schema (see the [module documentation](self))
+#[derive(Clone, Debug, PartialEq)]
+pub struct Constraint {
+    /// The location of the constraint in the schema, e.g. `/properties/protocol/enum/1` for the second
+    /// value of an `enum`
+    pub schema_path: KsonPath,
+    pub kind: ConstraintKind,
+    /// The number of documents exercising the constraint
+    pub documents: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstraintKind {
+    /// A property declared in `properties`
+    Property(String),
+    /// A value listed in an `enum`
+    EnumValue(Value),
+    /// A branch of `anyOf` or `oneOf` (with its index), or `then` or `else`
+    Branch {
+        keyword: CompositionKeyword,
+        index: Option<usize>,
+    },
+}
+
+impl Constraint {
+    fn new(schema_path: KsonPath, kind: ConstraintKind) -> Self {
+        Self {
+            schema_path,
+            kind,
+            documents: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for ConstraintKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConstraintKind::Property(name) => write!(f, "property `{name}`"),
+            ConstraintKind::EnumValue(value) => {
+                write!(f, "enum value `{}`", value.to_inline_kson())
+            }
+            ConstraintKind::Branch {
+                keyword,
+                index: Some(index),
+            } => write!(f, "`{keyword}` branch #{}", index + 1),
+            ConstraintKind::Branch {
+                keyword,
+                index: None,
+            } => write!(f, "`{keyword}` branch"),
+        }
+    }
+}
+
+impl SchemaCoverage {
+    /// The number of constraints exercised by at least one document
+    pub fn covered(&self) -> usize {
+        self.constraints
+            .iter()
+            .filter(|constraint| constraint.documents > 0)
+            .count()
+    }
+
+    /// The share of the constraints exercised, between 0 and 1 (1 for a schema without constraints)
+    pub fn fraction(&self) -> f64 {
+        if self.constraints.is_empty() {
+            1.0
+        } else {
+            self.covered() as f64 / self.constraints.len() as f64
+        }
+    }
+
+    /// The constraints that no document exercises
+    pub fn uncovered(&self) -> impl Iterator<Item = &Constraint> {
+        self.constraints
+            .iter()
+            .filter(|constraint| constraint.documents == 0)
+    }
+
+    /// The report as a value, e.g. to write it as JSON with [`Value::to_json`]
+    pub fn to_value(&self) -> Value {
+        let constraints = self.constraints.iter().map(|constraint| {
+            let (kind, detail) = match &constraint.kind {
+                ConstraintKind::Property(name) => {
+                    ("property", ("name", Value::from(name.as_str())))
+                }
+                ConstraintKind::EnumValue(value) => ("enumValue", ("value", value.clone())),
+                ConstraintKind::Branch { keyword, .. } => {
+                    ("branch", ("keyword", Value::String(keyword.to_string())))
+                }
+            };
+            Value::object([
+                (
+                    "schemaPath",
+                    Value::String(constraint.schema_path.to_string()),
+                ),
+                ("kind", Value::from(kind)),
+                detail,
+                ("documents", Value::Integer(constraint.documents as i64)),
+            ])
+        });
+        Value::object([
+            ("documents", Value::Integer(self.documents as i64)),
+            ("covered", Value::Integer(self.covered() as i64)),
+            ("total", Value::Integer(self.constraints.len() as i64)),
+            ("constraints", Value::list(constraints)),
+        ])
+    }
+
+    /// The report as a standalone HTML page, with the constraints no document exercises highlighted
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Schema coverage</title>\n\
+             <style>\n\
+             body { font-family: sans-serif; }\n\
+             td, th { padding: 0.2em 0.8em; text-align: left; }\n\
+             tr.uncovered { background: #fdd; }\n\
+             </style>\n</head>\n<body>\n<h1>Schema coverage</h1>\n",
+        );
+        html.push_str(&format!(
+            "<p>{} of {} constraints ({:.1}%) exercised by {} documents</p>\n",
+            self.covered(),
+            self.constraints.len(),
+            self.fraction() * 100.0,
+            self.documents
+        ));
+        html.push_str(
+            "<table>\n<thead><tr><th>Constraint</th><th>Schema path</th><th>Documents</th></tr></thead>\n<tbody>\n",
+        );
+        for constraint in &self.constraints {
+            let class = if constraint.documents == 0 {
+                "uncovered"
+            } else {
+                "covered"
+            };
+            html.push_str(&format!(
+                "<tr class=\"{class}\"><td>{}</td><td><code>{}</code></td><td>{}</td></tr>\n",
+                escape_html(&constraint.kind.to_string()),
+                escape_html(&constraint.schema_path.to_string()),
+                constraint.documents
+            ));
+        }
+        html.push_str("</tbody>\n</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+impl Kson {
+    /// Measures which constraints of the schema the documents exercise (see the
+    /// [module documentation](crate::coverage)), or returns the errors that prevented parsing the schema.
+    /// Branches are checked with [`SchemaValidator`](crate::SchemaValidator), like
+    /// [`schema::explain_compositions`](crate::schema::explain_compositions) does.
+    pub fn schema_coverage(schema: &str, documents: &[&str]) -> Result<SchemaCoverage, KsonErrors> {
+        let root = Value::parse(schema)?;
+        let schema = Kson::analyze(schema, None)
+            .kson_value()
+            .expect("the schema was checked to parse");
+        let mut constraints = Vec::new();
+        collect_constraints(&root, &KsonPath::root(), &mut constraints);
+
+        let mut measured = 0;
+        for document in documents {
+            let Some(document) = Kson::analyze(document, None).kson_value() else {
+                continue;
+            };
+            measured += 1;
+            let exercised = exercised_constraints(&root, &schema, &document);
+            for constraint in &mut constraints {
+                if exercised.contains(&constraint.schema_path) {
+                    constraint.documents += 1;
+                }
+            }
+        }
+        Ok(SchemaCoverage {
+            documents: measured,
+            constraints,
+        })
+    }
+}
+
+/// Collects the constraints of the subschema and of the subschemas nested in it, in schema order
+fn collect_constraints(schema: &Value, path: &KsonPath, constraints: &mut Vec<Constraint>) {
+    let Value::Object(keywords) = schema else {
+        return;
+    };
+    for (keyword, value) in keywords.iter() {
+        let keyword_path = path.clone().key(keyword.as_str());
+        match (keyword.as_str(), value) {
+            ("properties", Value::Object(properties)) => {
+                for (name, subschema) in properties.iter() {
+                    let property_path = keyword_path.clone().key(name.as_str());
+                    constraints.push(Constraint::new(
+                        property_path.clone(),
+                        ConstraintKind::Property(name.clone()),
+                    ));
+                    collect_constraints(subschema, &property_path, constraints);
+                }
+            }
+            ("enum", Value::Array(values)) => {
+                for (index, value) in values.iter().enumerate() {
+                    constraints.push(Constraint::new(
+                        keyword_path.clone().index(index),
+                        ConstraintKind::EnumValue(value.clone()),
+                    ));
+                }
+            }
+            (name @ ("anyOf" | "oneOf"), Value::Array(branches)) => {
+                let keyword = if name == "anyOf" {
+                    CompositionKeyword::AnyOf
+                } else {
+                    CompositionKeyword::OneOf
+                };
+                for (index, branch) in branches.iter().enumerate() {
+                    let branch_path = keyword_path.clone().index(index);
+                    constraints.push(Constraint::new(
+                        branch_path.clone(),
+                        ConstraintKind::Branch {
+                            keyword,
+                            index: Some(index),
+                        },
+                    ));
+                    collect_constraints(branch, &branch_path, constraints);
+                }
+            }
+            (name @ ("then" | "else"), Value::Object(_)) if keywords.contains_key("if") => {
+                let keyword = if name == "then" {
+                    CompositionKeyword::Then
+                } else {
+                    CompositionKeyword::Else
+                };
+                constraints.push(Constraint::new(
+                    keyword_path.clone(),
+                    ConstraintKind::Branch {
+                        keyword,
+                        index: None,
+                    },
+                ));
+                collect_constraints(value, &keyword_path, constraints);
+            }
+            // Only keywords holding subschemas are followed, since other objects (e.g. `const` values)
+            // could have a `properties` property without being schemas
+            (
+                "additionalProperties"
+                | "additionalItems"
+                | "items"
+                | "contains"
+                | "propertyNames"
+                | "not"
+                | "if"
+                | "then"
+                | "else"
+                | "unevaluatedProperties"
+                | "unevaluatedItems",
+                Value::Object(_),
+            ) => collect_constraints(value, &keyword_path, constraints),
+            ("items" | "prefixItems" | "allOf", Value::Array(subschemas)) => {
+                for (index, subschema) in subschemas.iter().enumerate() {
+                    collect_constraints(subschema, &keyword_path.clone().index(index), constraints);
+                }
+            }
+            (
+                "patternProperties" | "$defs" | "definitions" | "dependentSchemas",
+                Value::Object(subschemas),
+            ) => {
+                for (name, subschema) in subschemas.iter() {
+                    let subschema_path = keyword_path.clone().key(name.as_str());
+                    collect_constraints(subschema, &subschema_path, constraints);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The locations of the constraints that the document exercises
+fn exercised_constraints(
+    root: &Value,
+    schema: &KsonValue,
+    document: &KsonValue,
+) -> BTreeSet<KsonPath> {
+    let mut exercised = BTreeSet::new();
+    for_each_possible_schema(schema, document, &mut |applicable| {
+        let keywords = applicable.schema.properties();
+        let schema_path = applicable.schema_path;
+        if let (Some(KsonValue::KsonObject(properties)), KsonValue::KsonObject(object)) =
+            (keywords.get("properties"), applicable.instance)
+        {
+            let present = object.property_keys();
+            for name in properties.property_keys().into_keys() {
+                if present.contains_key(&name) {
+                    exercised.insert(schema_path.clone().key("properties").key(name));
+                }
+            }
+        }
+        if let Some(KsonValue::KsonArray(allowed)) = keywords.get("enum") {
+            let value = applicable.instance.to_value();
+            for (index, allowed) in allowed.elements().iter().enumerate() {
+                if values_equal(&value, &allowed.to_value()) {
+                    exercised.insert(schema_path.clone().key("enum").index(index));
+                }
+            }
+        }
+
+        let has_branches = ["anyOf", "oneOf", "if"]
+            .iter()
+            .any(|keyword| keywords.contains_key(*keyword));
+        if !has_branches {
+            return ControlFlow::Continue(());
+        }
+        let instance = applicable.instance.to_value().to_json();
+        let accepts = |path: &KsonPath| branch_errors(root, path, &instance).is_none();
+        for keyword in ["anyOf", "oneOf"] {
+            let Some(KsonValue::KsonArray(branches)) = keywords.get(keyword) else {
+                continue;
+            };
+            for index in 0..branches.elements().len() {
+                let branch_path = schema_path.clone().key(keyword).index(index);
+                if accepts(&branch_path) {
+                    exercised.insert(branch_path);
+                }
+            }
+        }
+        if keywords.contains_key("if") {
+            let selected = if accepts(&schema_path.clone().key("if")) {
+                "then"
+            } else {
+                "else"
+            };
+            exercised.insert(schema_path.clone().key(selected));
+        }
+        ControlFlow::Continue(())
+    });
+    exercised
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
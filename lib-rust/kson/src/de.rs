@@ -0,0 +1,404 @@
+//! Serde deserialization of parsed documents, enabled through the `serde` feature.
+//!
+//! [`Deserializer`] walks a [`KsonValue`] still held by kson-lib, fetching each node when it is
+//! visited rather than converting the whole tree into a [`Value`](crate::value::Value) first. It
+//! describes the values it holds, so it can feed any serde `Serializer` through
+//! [`serde_transcode`](https://docs.rs/serde-transcode), which is how [`transcode`] converts KSON to
+//! other formats without an intermediate value tree:
+//!
+//! ```no_run
+//! let mut json = Vec::new();
+//! kson_rs::de::transcode("name: kson\ntags: [fast, small]", &mut serde_json::Serializer::new(&mut json))
+//!     .unwrap();
+//! assert_eq!(json, br#"{"name":"kson","tags":["fast","small"]}"#);
+//! ```
+//!
+//! [`from_str`] deserializes a document straight into Rust types, e.g. to load a configuration file into
+//! a struct. Its errors locate the offending value in the document. [`from_kson_value`] does the same
+//! from a value already parsed (e.g. one found with [`KsonValue::pointer`]). Only the nodes the type
+//! reads are fetched from kson-lib, so peak memory is that of the parsed document and of the result:
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//!
+//! let analysis = Kson::analyze("name: api\nports: [80, 443]", None);
+//! let ports = analysis.kson_value().unwrap().pointer("/ports").unwrap();
+//! let ports: Vec<u16> = kson_rs::de::from_kson_value(ports).unwrap();
+//! assert_eq!(ports, [80, 443]);
+//! ```
+
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+
+use crate::error::KsonErrors;
+use crate::value::sorted_property_keys;
+use crate::{Kson, KsonValue, Message, MessageSeverity, Position, kson_value};
+
+/// The error returned when a value can't be deserialized, or [serialized](crate::ser)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    message: String,
+    /// The zero-based line and column of the value the error is about, if known
+    position: Option<(usize, usize)>,
+}
+
+impl Error {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The zero-based line of the value the error is about, for errors found in a document
+    pub fn line(&self) -> Option<usize> {
+        self.position.map(|(line, _)| line)
+    }
+
+    /// The zero-based column of the value the error is about, for errors found in a document
+    pub fn column(&self) -> Option<usize> {
+        self.position.map(|(_, column)| column)
+    }
+
+    /// Locates the error at the given position, unless it was already located at a value nested in the
+    /// one starting there
+    fn at(mut self, position: &Position) -> Self {
+        self.position.get_or_insert((
+            position.line().max(0) as usize,
+            position.column().max(0) as usize,
+        ));
+        self
+    }
+}
+
+/// Shows the position of the value the error is about (one-based, as editors do), if known
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)?;
+        if let Some((line, column)) = self.position {
+            write!(f, " at line {}, column {}", line + 1, column + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl serde::de::Error for Error {
+    fn custom<T: std::fmt::Display>(message: T) -> Self {
+        Self {
+            message: message.to_string(),
+            position: None,
+        }
+    }
+}
+
+/// Parses a document and deserializes it into a `T` (see the [module documentation](self)). Fails with
+/// the first error of the document if it doesn't parse.
+pub fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, Error> {
+    let analysis = Kson::analyze(input, None);
+    if let Some(error) = analysis
+        .errors()
+        .into_iter()
+        .find(|message| matches!(message.severity(), MessageSeverity::Error))
+    {
+        return Err(<Error as serde::de::Error>::custom(error.message()).at(&error.start()));
+    }
+    let value = analysis
+        .kson_value()
+        .ok_or_else(|| <Error as serde::de::Error>::custom("the document has no value"))?;
+    from_kson_value(value)
+}
+
+/// Deserializes a value held by kson-lib into a `T`, fetching its nodes as they are visited (see the
+/// [module documentation](self))
+pub fn from_kson_value<T: DeserializeOwned>(value: KsonValue) -> Result<T, Error> {
+    T::deserialize(Deserializer::from(value))
+}
+
+/// Deserializes a value held by kson-lib (see the [module documentation](self))
+///
+/// Embed blocks are read as strings holding their content, and object properties are visited in
+/// document order. Enum variants are written like `serde_json` expects them: unit variants as strings
+/// holding their name, and other variants as objects with the variant name as their only key.
+pub struct Deserializer {
+    value: KsonValue,
+}
+
+impl From<KsonValue> for Deserializer {
+    fn from(value: KsonValue) -> Self {
+        Self { value }
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for KsonValue {
+    type Deserializer = Deserializer;
+
+    fn into_deserializer(self) -> Deserializer {
+        Deserializer::from(self)
+    }
+}
+
+impl<'de> serde::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let start = self.value.start();
+        self.visit_any(visitor).map_err(|error| error.at(&start))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let start = self.value.start();
+        match self.value {
+            KsonValue::KsonNull(_) => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+        .map_err(|error| error.at(&start))
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let start = self.value.start();
+        visitor
+            .visit_newtype_struct(self)
+            .map_err(|error| error.at(&start))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let start = self.value.start();
+        let variant = match self.value {
+            KsonValue::KsonString(string) => Some(Variant {
+                name: string.value(),
+                value: None,
+            }),
+            KsonValue::KsonObject(object) => {
+                let mut properties = object.properties().into_iter();
+                match (properties.next(), properties.next()) {
+                    (Some((name, value)), None) => Some(Variant {
+                        name,
+                        value: Some(value),
+                    }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match variant {
+            Some(variant) => visitor.visit_enum(variant),
+            None => Err(<Error as serde::de::Error>::custom(
+                "expected an enum variant: its name, or an object with its name as only key",
+            )),
+        }
+        .map_err(|error| error.at(&start))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl Deserializer {
+    fn visit_any<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            KsonValue::KsonNull(_) => visitor.visit_unit(),
+            KsonValue::KsonBoolean(boolean) => visitor.visit_bool(boolean.value()),
+            KsonValue::KsonNumber(kson_value::KsonNumber::Integer(integer)) => {
+                visitor.visit_i64(integer.value())
+            }
+            KsonValue::KsonNumber(kson_value::KsonNumber::Decimal(decimal)) => {
+                visitor.visit_f64(decimal.value())
+            }
+            KsonValue::KsonString(string) => visitor.visit_string(string.value()),
+            KsonValue::KsonEmbed(embed) => visitor.visit_string(embed.content()),
+            KsonValue::KsonArray(array) => visitor.visit_seq(Elements {
+                elements: array.elements().into_iter(),
+            }),
+            KsonValue::KsonObject(object) => {
+                let mut values = object.properties();
+                let properties: Vec<_> = sorted_property_keys(&object)
+                    .into_iter()
+                    .filter_map(|(name, key)| {
+                        let value = values.remove(&name)?;
+                        Some((name, key.start(), value))
+                    })
+                    .collect();
+                visitor.visit_map(Properties {
+                    properties: properties.into_iter(),
+                    value: None,
+                })
+            }
+        }
+    }
+}
+
+struct Elements {
+    elements: std::vec::IntoIter<KsonValue>,
+}
+
+impl<'de> SeqAccess<'de> for Elements {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        self.elements
+            .next()
+            .map(|element| seed.deserialize(Deserializer::from(element)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.elements.len())
+    }
+}
+
+struct Properties {
+    /// The names of the remaining properties, with the start of their key and their value
+    properties: std::vec::IntoIter<(String, Position, KsonValue)>,
+    /// The value of the property whose key was just visited
+    value: Option<KsonValue>,
+}
+
+impl<'de> MapAccess<'de> for Properties {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let Some((name, start, value)) = self.properties.next() else {
+            return Ok(None);
+        };
+        self.value = Some(value);
+        seed.deserialize(name.into_deserializer())
+            .map(Some)
+            .map_err(|error: Error| error.at(&start))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| <Error as serde::de::Error>::custom("value requested before its key"))?;
+        seed.deserialize(Deserializer::from(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.properties.len())
+    }
+}
+
+/// An enum variant, with its value unless it's a unit variant written as a string
+struct Variant {
+    name: String,
+    value: Option<KsonValue>,
+}
+
+impl<'de> EnumAccess<'de> for Variant {
+    type Error = Error;
+    type Variant = VariantValue;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantValue), Error> {
+        let variant = seed.deserialize(self.name.into_deserializer())?;
+        Ok((variant, VariantValue { value: self.value }))
+    }
+}
+
+struct VariantValue {
+    value: Option<KsonValue>,
+}
+
+impl VariantValue {
+    fn deserializer(self) -> Result<Deserializer, Error> {
+        self.value.map(Deserializer::from).ok_or_else(|| {
+            <Error as serde::de::Error>::custom(
+                "expected an object holding the value of the variant, found its name only",
+            )
+        })
+    }
+}
+
+impl<'de> VariantAccess<'de> for VariantValue {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.value {
+            None | Some(KsonValue::KsonNull(_)) => Ok(()),
+            Some(value) => Err(<Error as serde::de::Error>::custom(
+                "expected a unit variant, which holds no value",
+            )
+            .at(&value.start())),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self.deserializer()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        serde::Deserializer::deserialize_seq(self.deserializer()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        serde::Deserializer::deserialize_map(self.deserializer()?, visitor)
+    }
+}
+
+/// The error returned by [`transcode`]
+#[derive(Debug)]
+pub enum TranscodeError<E> {
+    /// The input is not valid KSON
+    Parse(KsonErrors),
+    /// The serializer failed
+    Serialize(E),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for TranscodeError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TranscodeError::Parse(errors) => errors.fmt(f),
+            TranscodeError::Serialize(error) => write!(f, "serialization failed: {error}"),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for TranscodeError<E> {}
+
+/// Parses a document and streams its value into a serde `Serializer` (e.g. one of `serde_json` or
+/// `ciborium`), as described in the [module documentation](self)
+pub fn transcode<S: serde::Serializer>(
+    input: &str,
+    serializer: S,
+) -> Result<S::Ok, TranscodeError<S::Error>> {
+    let analysis = Kson::analyze(input, None);
+    let errors: Vec<Message> = analysis
+        .errors()
+        .into_iter()
+        .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+        .collect();
+    match analysis.kson_value() {
+        Some(value) if errors.is_empty() => {
+            serde_transcode::transcode(Deserializer::from(value), serializer)
+                .map_err(TranscodeError::Serialize)
+        }
+        _ => Err(TranscodeError::Parse(KsonErrors::from_messages(
+            input, &errors,
+        ))),
+    }
+}
@@ -0,0 +1,93 @@
+//! Control over how deeply the values handled by this crate may nest arrays and objects.
+//!
+//! [`Value`]s are walked, compared, cloned and dropped without recursing, so they can nest as deeply as
+//! memory allows. Bounding their depth still matters: kson-lib's parser rejects documents nested deeper
+//! than [`MaxDepth::DEFAULT`] (so deeper values can't be written out and parsed back), and serde recurses
+//! through the types it serializes. Values parsed from text are bounded by the parser, and the
+//! conversions from other sources reject deeper values with a [`DepthError`]: by default beyond
+//! [`MaxDepth::DEFAULT`], or beyond the limit given to their `_with` variant, like
+//! [`Value::from_cache_bytes_with`] or [`ser::to_value_with`](crate::ser::to_value_with). Values built in
+//! code, and JSON values converted with `From` (which can't fail), aren't checked, but
+//! [`MaxDepth::check`] and `Value::from_json_with` can check them.
+
+use crate::path::KsonPath;
+use crate::query::QueryNode;
+use crate::value::Value;
+
+/// The deepest nesting of arrays and objects to accept: a scalar has depth 0, and `{ a: [1] }` depth 2
+/// (see [`Value::depth`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaxDepth(usize);
+
+impl MaxDepth {
+    /// The limit of kson-lib's parser, which can't be raised from Rust, and the default of the APIs
+    /// taking a limit
+    pub const DEFAULT: MaxDepth = MaxDepth(128);
+
+    pub const fn new(max_depth: usize) -> Self {
+        Self(max_depth)
+    }
+
+    pub const fn get(self) -> usize {
+        self.0
+    }
+
+    /// Whether a value at the given depth is within the limit
+    pub(crate) fn allows(self, depth: usize) -> bool {
+        depth <= self.0
+    }
+
+    /// Checks that the value doesn't nest deeper than the limit, without recursing into it. Fails with
+    /// the path of the first value found beyond the limit.
+    pub fn check(self, value: &Value) -> Result<(), DepthError> {
+        // The children left to visit at each level, along with the path leading to the current one
+        let mut pending = vec![value.children().into_iter()];
+        let mut path = KsonPath::root();
+        while let Some(children) = pending.last_mut() {
+            match children.next() {
+                Some((segment, child)) => {
+                    path.push(segment);
+                    if !self.allows(path.len()) {
+                        return Err(DepthError {
+                            max_depth: self,
+                            path,
+                        });
+                    }
+                    pending.push(child.children().into_iter());
+                }
+                None => {
+                    pending.pop();
+                    path.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for MaxDepth {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The error returned when a value nests deeper than a [`MaxDepth`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DepthError {
+    pub max_depth: MaxDepth,
+    /// The path of a value beyond the limit
+    pub path: KsonPath,
+}
+
+impl std::fmt::Display for DepthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the value at `{}` is nested deeper than the limit of {} levels",
+            self.path,
+            self.max_depth.get()
+        )
+    }
+}
+
+impl std::error::Error for DepthError {}
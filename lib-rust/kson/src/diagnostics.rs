@@ -0,0 +1,85 @@
+//! Control over how many diagnostics are collected before giving up.
+//!
+//! Batch linters want every problem in a document, while hot request paths only need to know whether
+//! there is one, and are better off bailing out as soon as it's found.
+//!
+//! The checks of this crate (e.g. [`schema::validate`](crate::schema::validate) or the format checks of
+//! [`Kson::try_check`]) stop as soon as the limit is reached. kson-lib parses and validates a document in
+//! one call each, so the limit can only cut them short in between: [`Kson::check`] skips validation when
+//! parsing found enough diagnostics, and otherwise drops those past the limit once validation is done.
+
+use crate::{Kson, Message, MessageSeverity, SchemaValidator};
+
+/// How many diagnostics to collect before stopping
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DiagnosticLimit {
+    /// Collect every diagnostic
+    #[default]
+    All,
+    /// Stop at the first diagnostic. Validation by kson-lib still runs to completion (see the
+    /// [module documentation](self)), so this saves the time of the checks run by this crate.
+    FailFast,
+    /// Stop once this many diagnostics have been collected
+    AtMost(usize),
+}
+
+impl DiagnosticLimit {
+    /// The maximum number of diagnostics to collect, if any
+    pub fn max(self) -> Option<usize> {
+        match self {
+            DiagnosticLimit::All => None,
+            DiagnosticLimit::FailFast => Some(1),
+            DiagnosticLimit::AtMost(max) => Some(max),
+        }
+    }
+
+    /// Whether `count` diagnostics are enough to stop
+    pub(crate) fn is_reached(self, count: usize) -> bool {
+        self.max().is_some_and(|max| count >= max)
+    }
+
+    /// The limit left once `count` diagnostics have been collected, which must not reach it
+    pub(crate) fn after(self, count: usize) -> DiagnosticLimit {
+        match self.max() {
+            Some(max) => DiagnosticLimit::AtMost(max - count),
+            None => DiagnosticLimit::All,
+        }
+    }
+
+    /// Drops the diagnostics beyond the limit
+    pub(crate) fn truncate<T>(self, diagnostics: &mut Vec<T>) {
+        if let Some(max) = self.max() {
+            diagnostics.truncate(max);
+        }
+    }
+}
+
+impl Kson {
+    /// Parses the document and, if it parses without errors, validates it against the schema (if any),
+    /// returning the diagnostics found up to the given limit.
+    ///
+    /// Validation is skipped when parsing fails, or when parsing alone already produced enough diagnostics
+    /// (e.g. a warning with [`DiagnosticLimit::FailFast`]). Otherwise kson-lib's validator collects every
+    /// diagnostic, and those past the limit are dropped. It doesn't check formats, which
+    /// [`Kson::try_check`] adds given a [`Schema`](crate::schema::Schema), stopping at the limit.
+    pub fn check(
+        document: &str,
+        schema: Option<&SchemaValidator>,
+        limit: DiagnosticLimit,
+    ) -> Vec<Message> {
+        let mut diagnostics = Kson::analyze(document, None).errors();
+        let parse_failed = diagnostics
+            .iter()
+            .any(|message| matches!(message.severity(), MessageSeverity::Error));
+        if parse_failed || limit.is_reached(diagnostics.len()) {
+            limit.truncate(&mut diagnostics);
+            return diagnostics;
+        }
+
+        if let Some(schema) = schema {
+            diagnostics.extend(schema.validate(document, None));
+        }
+        limit.truncate(&mut diagnostics);
+        diagnostics
+    }
+}
@@ -0,0 +1,247 @@
+//! A registry of the languages embedded in embed blocks, by tag.
+//!
+//! KSON only sees the content of an embed block as text. Applications that know what the tags of their
+//! documents mean (say `sql` or `json`) can describe them as [`Dialect`]s, with the comment syntax
+//! editors need and a validator checking that the content actually parses, and then validate every
+//! embed block of a document with [`EmbedDialects::validate`]:
+//!
+//! ```no_run
+//! use kson_rs::dialect::{Dialect, DialectIssue, EmbedDialects};
+//!
+//! let mut dialects = EmbedDialects::with_builtins();
+//! dialects.register(
+//!     "json",
+//!     Dialect::new("JSON").validator(|content| match serde_json::from_str::<serde_json::Value>(content) {
+//!         Ok(_) => Vec::new(),
+//!         Err(error) => vec![DialectIssue::new(error.to_string(), error.line() - 1, error.column() - 1)],
+//!     }),
+//! );
+//! for diagnostic in dialects.validate("payload: %json\n  {\"key\": }\n  %%") {
+//!     eprintln!("{}: {}", diagnostic.path, diagnostic.message);
+//! }
+//! ```
+
+use std::collections::HashMap;
+
+use crate::embed::{EmbedDecodeError, decode_base64, decode_hex};
+use crate::path::KsonPath;
+use crate::query::QueryNode;
+use crate::{Kson, KsonValue, Position};
+
+type Validator = Box<dyn Fn(&str) -> Vec<DialectIssue> + Send + Sync>;
+
+/// The description of a language embedded in embed blocks
+pub struct Dialect {
+    name: String,
+    line_comment: Option<String>,
+    block_comment: Option<(String, String)>,
+    validator: Option<Validator>,
+}
+
+impl Dialect {
+    /// Describes a language, by its human-readable name (e.g. `"PostgreSQL"`)
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            line_comment: None,
+            block_comment: None,
+            validator: None,
+        }
+    }
+
+    /// Sets the prefix of line comments, e.g. `--` for SQL
+    pub fn line_comment(mut self, prefix: impl Into<String>) -> Self {
+        self.line_comment = Some(prefix.into());
+        self
+    }
+
+    /// Sets the delimiters of block comments, e.g. `/*` and `*/`
+    pub fn block_comment(mut self, open: impl Into<String>, close: impl Into<String>) -> Self {
+        self.block_comment = Some((open.into(), close.into()));
+        self
+    }
+
+    /// Sets the function checking the content of embed blocks, which returns the problems it finds
+    pub fn validator(
+        mut self,
+        validator: impl Fn(&str) -> Vec<DialectIssue> + Send + Sync + 'static,
+    ) -> Self {
+        self.validator = Some(Box::new(validator));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn line_comment_prefix(&self) -> Option<&str> {
+        self.line_comment.as_deref()
+    }
+
+    pub fn block_comment_delimiters(&self) -> Option<(&str, &str)> {
+        self.block_comment
+            .as_ref()
+            .map(|(open, close)| (open.as_str(), close.as_str()))
+    }
+
+    /// Checks the content of an embed block, returning no issues if the dialect has no validator
+    pub fn validate(&self, content: &str) -> Vec<DialectIssue> {
+        self.validator
+            .as_ref()
+            .map_or_else(Vec::new, |validator| validator(content))
+    }
+}
+
+impl std::fmt::Debug for Dialect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dialect")
+            .field("name", &self.name)
+            .field("line_comment", &self.line_comment)
+            .field("block_comment", &self.block_comment)
+            .field("has_validator", &self.validator.is_some())
+            .finish()
+    }
+}
+
+/// A problem found by a [`Dialect`] validator in the content of an embed block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DialectIssue {
+    pub message: String,
+    /// Zero-based line of the problem, relative to the first line of the embed content
+    pub line: usize,
+    /// Zero-based column of the problem (in characters), relative to the start of its line
+    pub column: usize,
+}
+
+impl DialectIssue {
+    pub fn new(message: impl Into<String>, line: usize, column: usize) -> Self {
+        Self {
+            message: message.into(),
+            line,
+            column,
+        }
+    }
+}
+
+impl From<EmbedDecodeError> for DialectIssue {
+    fn from(error: EmbedDecodeError) -> Self {
+        Self::new(error.to_string(), error.line, error.column)
+    }
+}
+
+/// A problem found in an embed block of a document by [`EmbedDialects::validate`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DialectDiagnostic {
+    pub message: String,
+    /// The tag of the embed block
+    pub tag: String,
+    /// The start of the embed block in the document
+    pub start: Position,
+    /// The end of the embed block in the document
+    pub end: Position,
+    /// The path of the embed block in the document
+    pub path: KsonPath,
+    /// Zero-based line of the problem, relative to the first line of the embed content
+    pub line: usize,
+    /// Zero-based column of the problem (in characters), relative to the start of its line
+    pub column: usize,
+}
+
+/// Dialects by embed tag (see the [module documentation](self)). Tags are matched case-insensitively.
+#[derive(Debug, Default)]
+pub struct EmbedDialects {
+    dialects: HashMap<String, Dialect>,
+}
+
+impl EmbedDialects {
+    /// An empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry knowing about the binary encodings of [`crate::embed`], tagged `base64` and `hex`
+    pub fn with_builtins() -> Self {
+        let mut dialects = Self::new();
+        dialects.register(
+            "base64",
+            Dialect::new("Base64").validator(|content| {
+                decode_base64(content)
+                    .err()
+                    .map(DialectIssue::from)
+                    .into_iter()
+                    .collect()
+            }),
+        );
+        dialects.register(
+            "hex",
+            Dialect::new("Hexadecimal").validator(|content| {
+                decode_hex(content)
+                    .err()
+                    .map(DialectIssue::from)
+                    .into_iter()
+                    .collect()
+            }),
+        );
+        dialects
+    }
+
+    /// Registers the dialect of embed blocks with the given tag, replacing any previous one
+    pub fn register(&mut self, tag: &str, dialect: Dialect) -> &mut Self {
+        self.dialects.insert(tag.to_lowercase(), dialect);
+        self
+    }
+
+    /// Returns the dialect of embed blocks with the given tag, if it's registered
+    pub fn get(&self, tag: &str) -> Option<&Dialect> {
+        self.dialects.get(&tag.to_lowercase())
+    }
+
+    /// Parses the document and validates each embed block whose tag has a registered dialect, in document
+    /// order.
+    ///
+    /// Returns no diagnostics if the document fails to parse, since those errors are already reported by
+    /// [`Kson::analyze`].
+    pub fn validate(&self, document: &str) -> Vec<DialectDiagnostic> {
+        match Kson::analyze(document, None).kson_value() {
+            Some(value) => self.validate_value(&value),
+            None => Vec::new(),
+        }
+    }
+
+    /// Validates each embed block of an already parsed value (see [`validate`](Self::validate))
+    pub fn validate_value(&self, value: &KsonValue) -> Vec<DialectDiagnostic> {
+        let mut diagnostics = Vec::new();
+        // Children are pushed in reverse so that they are popped in document order
+        let mut pending = vec![(KsonPath::root(), value.clone())];
+        while let Some((path, value)) = pending.pop() {
+            if let KsonValue::KsonEmbed(embed) = &value {
+                let Some(tag) = embed.tag() else {
+                    continue;
+                };
+                let Some(dialect) = self.get(&tag) else {
+                    continue;
+                };
+                for issue in dialect.validate(&embed.content()) {
+                    diagnostics.push(DialectDiagnostic {
+                        message: issue.message,
+                        tag: tag.clone(),
+                        start: value.start(),
+                        end: value.end(),
+                        path: path.clone(),
+                        line: issue.line,
+                        column: issue.column,
+                    });
+                }
+                continue;
+            }
+            pending.extend(
+                value
+                    .children()
+                    .into_iter()
+                    .rev()
+                    .map(|(segment, child)| (path.clone().join(segment), child)),
+            );
+        }
+        diagnostics
+    }
+}
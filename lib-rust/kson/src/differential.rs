@@ -0,0 +1,224 @@
+//! Differential testing against `serde_json`, used by the tests to check that KSON agrees with a
+//! reference parser on the JSON it accepts.
+//!
+//! KSON is a superset of JSON, so accepting inputs that `serde_json` rejects is expected (and reported as
+//! [`Divergence::Accepted`] for callers that want to check it against a list of known extensions), while
+//! rejecting valid JSON or reading it into a different value is a bug.
+
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crate::value::{Map, Value, values_equal};
+use crate::{Kson, MessageSeverity};
+
+/// A difference between how KSON and `serde_json` read an input
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Divergence {
+    /// KSON rejects valid JSON, with these errors
+    Rejected(Vec<String>),
+    /// KSON accepts invalid JSON
+    Accepted,
+    /// Both accept the input but read different values
+    Mismatch { kson: Value, json: Value },
+}
+
+/// Parses the input with both KSON and `serde_json`, returning how they diverge, if they do. Inputs that
+/// are not UTF-8 are out of scope, since KSON only parses strings.
+pub(crate) fn compare(input: &[u8]) -> Option<Divergence> {
+    let text = std::str::from_utf8(input).ok()?;
+    let json = serde_json::from_str::<serde_json::Value>(text)
+        .ok()
+        .map(|json| from_json(&json));
+
+    let analysis = Kson::analyze(text, None);
+    let errors: Vec<String> = analysis
+        .errors()
+        .into_iter()
+        .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+        .map(|message| message.message())
+        .collect();
+    let kson = analysis
+        .kson_value()
+        .filter(|_| errors.is_empty())
+        .map(|value| value.to_value());
+
+    match (kson, json) {
+        (None, None) => None,
+        (None, Some(_)) => Some(Divergence::Rejected(errors)),
+        (Some(_), None) => Some(Divergence::Accepted),
+        (Some(kson), Some(json)) => {
+            (!values_equal(&kson, &json)).then_some(Divergence::Mismatch { kson, json })
+        }
+    }
+}
+
+/// Locates the fixtures of a suite: the directory named by the environment variable `var` if it's set,
+/// or else `default`, relative to the crate. Returns `None` (after printing why) when the default
+/// directory doesn't exist, e.g. because the Kotlin build checking it out hasn't run, so that the tests
+/// using it are skipped. Setting `KSON_REQUIRE_SUITES` (as CI should) turns that into a failure.
+///
+/// # Panics
+///
+/// If the directory named by `var` doesn't exist, which is a mistake rather than a missing build step
+pub(crate) fn suite_dir(var: &str, default: &str) -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(var) {
+        let dir = PathBuf::from(dir);
+        assert!(dir.is_dir(), "{var} is not a directory: {}", dir.display());
+        return Some(dir);
+    }
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(default);
+    if dir.is_dir() {
+        return Some(dir);
+    }
+    assert!(
+        std::env::var_os("KSON_REQUIRE_SUITES").is_none(),
+        "no suite at {} (set {var} to its location, or run the Kotlin build)",
+        dir.display()
+    );
+    eprintln!(
+        "skipping: no suite at {} (set {var} to its location, or run the Kotlin build)",
+        dir.display()
+    );
+    None
+}
+
+/// Which files of a directory [`compare_dir`] compares, and how. The environment can narrow the run
+/// without changing the tests, see [`SuiteOptions::from_env`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SuiteOptions {
+    filters: Vec<String>,
+    threads: Option<usize>,
+    timings: bool,
+}
+
+impl SuiteOptions {
+    /// Reads the options from the environment:
+    ///
+    /// - `KSON_SUITE_FILTER`: comma-separated substrings, only the files whose name contains one of them
+    ///   are compared
+    /// - `KSON_SUITE_THREADS`: the number of files compared in parallel (by default, the available
+    ///   parallelism)
+    /// - `KSON_SUITE_TIMINGS`: if set, the time taken by each file is printed, slowest first
+    pub(crate) fn from_env() -> Self {
+        let mut options = Self::default();
+        if let Ok(filters) = std::env::var("KSON_SUITE_FILTER") {
+            for filter in filters.split(',').filter(|filter| !filter.is_empty()) {
+                options = options.filter(filter);
+            }
+        }
+        if let Some(threads) = std::env::var("KSON_SUITE_THREADS")
+            .ok()
+            .and_then(|threads| threads.parse().ok())
+        {
+            options = options.threads(threads);
+        }
+        options.timings(std::env::var_os("KSON_SUITE_TIMINGS").is_some())
+    }
+
+    /// Only compares the files whose name contains `filter`, or one of the other filters
+    pub(crate) fn filter(mut self, filter: impl Into<String>) -> Self {
+        self.filters.push(filter.into());
+        self
+    }
+
+    pub(crate) fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads.max(1));
+        self
+    }
+
+    pub(crate) fn timings(mut self, timings: bool) -> Self {
+        self.timings = timings;
+        self
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.filters.is_empty()
+            || self
+                .filters
+                .iter()
+                .any(|filter| name.contains(filter.as_str()))
+    }
+}
+
+/// Compares the files of the directory selected by the options (see [`compare`]), in parallel, returning
+/// the divergences by file name
+pub(crate) fn compare_dir(
+    dir: &Path,
+    options: &SuiteOptions,
+) -> std::io::Result<Vec<(String, Divergence)>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_file() && options.matches(&name) {
+            files.push((name, entry.path()));
+        }
+    }
+    files.sort();
+
+    let next = AtomicUsize::new(0);
+    let threads = options
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, NonZero::get))
+        .min(files.len())
+        .max(1);
+    let results: Vec<Vec<_>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    while let Some((name, path)) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let started = Instant::now();
+                        let divergence = std::fs::read(path).map(|input| compare(&input));
+                        results.push((name, divergence, started.elapsed()));
+                    }
+                    results
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    let mut results: Vec<_> = results.into_iter().flatten().collect();
+
+    if options.timings {
+        results.sort_by_key(|(_, _, elapsed)| std::cmp::Reverse(*elapsed));
+        for (name, _, elapsed) in &results {
+            eprintln!("{:>10.3}ms {name}", elapsed.as_secs_f64() * 1000.0);
+        }
+    }
+    results.sort_by_key(|(name, _, _)| *name);
+
+    let mut divergences = Vec::new();
+    for (name, divergence, _) in results {
+        if let Some(divergence) = divergence? {
+            divergences.push((name.clone(), divergence));
+        }
+    }
+    Ok(divergences)
+}
+
+fn from_json(json: &serde_json::Value) -> Value {
+    match json {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(boolean) => Value::Bool(*boolean),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(integer) => Value::Integer(integer),
+            None => Value::Decimal(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(string) => Value::String(string.clone()),
+        serde_json::Value::Array(elements) => {
+            Value::Array(elements.iter().map(from_json).collect())
+        }
+        serde_json::Value::Object(properties) => Value::Object(
+            properties
+                .iter()
+                .map(|(key, value)| (key.as_str(), from_json(value)))
+                .collect::<Map>(),
+        ),
+    }
+}
@@ -0,0 +1,60 @@
+//! SHA-256 digests, enabled through the `digest` feature, which fingerprint documents by their
+//! [canonical form](crate::canonical). The `store` feature builds on them to store documents by digest.
+
+use std::str::FromStr;
+
+use sha2::{Digest as _, Sha256};
+
+/// A SHA-256 digest, displayed in lowercase hex. The digest of the canonical form of a document (see
+/// [`Kson::digest`](crate::Kson::digest)) identifies it in a `DocumentStore`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest([u8; 32]);
+
+impl Digest {
+    /// The digest of the given text
+    pub fn of(text: &str) -> Self {
+        Self(Sha256::digest(text.as_bytes()).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The error returned when parsing something that isn't 64 hexadecimal digits as a [`Digest`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidDigest(pub String);
+
+impl std::fmt::Display for InvalidDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` is not a SHA-256 digest in hex", self.0)
+    }
+}
+
+impl std::error::Error for InvalidDigest {}
+
+impl FromStr for Digest {
+    type Err = InvalidDigest;
+
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidDigest(hex.to_string());
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut bytes = [0; 32];
+        for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(bytes))
+    }
+}
@@ -0,0 +1,218 @@
+//! Editing of KSON documents that keeps their comments and formatting.
+//!
+//! [`KsonDocument`] holds the text of a document and applies each edit as a minimal change to it: only
+//! the text of the edited value is replaced (or the new property inserted), so comments, key order,
+//! blank lines and the style of the untouched values are kept as they were. This is meant for tools
+//! rewriting configuration files written by people:
+//!
+//! ```no_run
+//! use kson_rs::document::KsonDocument;
+//! use kson_rs::path::KsonPath;
+//! use kson_rs::value::Value;
+//!
+//! let mut document = KsonDocument::parse("# Where to listen\nserver:\n  port: 80\n").unwrap();
+//! document
+//!     .set(&KsonPath::root().key("server").key("port"), Value::Integer(8080))
+//!     .unwrap();
+//! assert_eq!(document.text(), "# Where to listen\nserver:\n  port: 8080\n");
+//! ```
+//!
+//! Values are rendered like [`Value::to_kson_with`] does in the
+//! [`Delimited`](crate::FormattingStyle::Delimited) style, indented like the line they start on. Strings
+//! replacing a quoted string keep its delimiter.
+
+use crate::error::KsonErrors;
+use crate::format::Formatter;
+use crate::line_index::LineIndex;
+use crate::path::{KsonPath, PathSegment};
+use crate::schema::{TextEdit, insert_properties, position};
+use crate::value::{Value, write_quoted};
+use crate::{FormatOptions, FormattingStyle, IndentType, Kson, KsonValue, indent_type};
+
+/// The error returned when an edit can't be applied
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditError {
+    /// The value at this path (a prefix of the edited path) is neither an object nor an array, or is an
+    /// array without the element the edited path goes through
+    NotFound(KsonPath),
+    /// The edited document doesn't read as the expected value, which is a bug. The document is left
+    /// unchanged.
+    Verification(KsonPath),
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::NotFound(path) => {
+                write!(f, "there is no object or array element at `{path}` to edit")
+            }
+            EditError::Verification(path) => {
+                write!(
+                    f,
+                    "editing `{path}` produced a document that doesn't read as intended"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// A document edited in place (see the [module documentation](self))
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KsonDocument {
+    text: String,
+}
+
+impl KsonDocument {
+    /// Parses the document, failing with its errors (warnings are ignored)
+    pub fn parse(text: impl Into<String>) -> Result<Self, KsonErrors> {
+        let text = text.into();
+        Value::parse(&text)?;
+        Ok(Self { text })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+
+    /// Returns the current value of the document
+    pub fn value(&self) -> Value {
+        self.root().to_value()
+    }
+
+    /// Returns the value at the path, if there is one
+    pub fn get(&self, path: &KsonPath) -> Option<Value> {
+        self.value().get_path(path).cloned()
+    }
+
+    /// Sets the value at the path, returning the change made to the text.
+    ///
+    /// An existing value is replaced in place. A missing property is appended to its object (along with
+    /// the objects leading to it, if the path goes through more missing properties). Array elements can
+    /// be replaced, but not added.
+    pub fn set(&mut self, path: &KsonPath, value: impl Into<Value>) -> Result<TextEdit, EditError> {
+        let value = value.into();
+        let index = LineIndex::new(&self.text);
+        let mut current = self.root();
+        let mut segments = path.segments().iter();
+        let mut reached = KsonPath::root();
+        let edit = loop {
+            let Some(segment) = segments.next() else {
+                break self.replace(&current, &value, &index);
+            };
+            let child = match (&current, segment) {
+                (KsonValue::KsonObject(object), segment) => {
+                    let key = segment_key(segment);
+                    match object.properties().remove(&key) {
+                        Some(child) => child,
+                        None => {
+                            // Wrap the value in the objects leading to it, innermost first
+                            let rest: Vec<&PathSegment> = segments.collect();
+                            let nested = rest.iter().rev().fold(value.clone(), |value, segment| {
+                                Value::object([(segment_key(segment), value)])
+                            });
+                            let property = format!(
+                                "{}: {}",
+                                render(&Value::String(key), ""),
+                                render(&nested, &line_indentation(&index, &current))
+                            );
+                            break insert_properties(&current, object, &[property], &index);
+                        }
+                    }
+                }
+                (KsonValue::KsonArray(array), segment) => {
+                    let element = match segment {
+                        PathSegment::Index(index) => Some(*index),
+                        PathSegment::Key(key) => key.parse().ok(),
+                    };
+                    let mut elements = array.elements();
+                    match element.filter(|&element| element < elements.len()) {
+                        Some(element) => elements.swap_remove(element),
+                        None => return Err(EditError::NotFound(reached)),
+                    }
+                }
+                _ => return Err(EditError::NotFound(reached)),
+            };
+            reached.push(segment.clone());
+            current = child;
+        };
+
+        let text = edit.apply(&self.text);
+        let edited = Value::parse(&text).map_err(|_| EditError::Verification(path.clone()))?;
+        if edited.get_path(path) != Some(&value) {
+            return Err(EditError::Verification(path.clone()));
+        }
+        self.text = text;
+        Ok(edit)
+    }
+
+    fn root(&self) -> KsonValue {
+        Kson::analyze(&self.text, None)
+            .kson_value()
+            .expect("the document was checked to parse")
+    }
+
+    /// An edit replacing the existing value
+    fn replace(&self, existing: &KsonValue, value: &Value, index: &LineIndex) -> TextEdit {
+        let (start, end) = (existing.start(), existing.end());
+        let old_text = index.slice(&start, &end);
+        let new_text = match (existing, value) {
+            // Keep the delimiter of quoted strings
+            (KsonValue::KsonString(_), Value::String(string))
+                if old_text.starts_with(['\'', '"']) =>
+            {
+                let mut quoted = String::new();
+                write_quoted(string, old_text.chars().next().unwrap(), &mut quoted);
+                quoted
+            }
+            _ => render(value, &line_indentation(index, existing)),
+        };
+        TextEdit {
+            start: position(&start),
+            end: position(&end),
+            new_text,
+        }
+    }
+}
+
+/// Renders the value in the delimited style, with its lines after the first indented by `indent`
+fn render(value: &Value, indent: &str) -> String {
+    let options = FormatOptions::new(
+        IndentType::Spaces(indent_type::Spaces::new(2)),
+        FormattingStyle::Delimited,
+        &[],
+    );
+    let rendered = value.to_kson_with(&Formatter::new(options));
+    let mut lines = rendered.trim_end_matches('\n').split('\n');
+    let mut text = lines.next().unwrap_or_default().to_string();
+    for line in lines {
+        text.push('\n');
+        if !line.is_empty() {
+            text.push_str(indent);
+        }
+        text.push_str(line);
+    }
+    text
+}
+
+/// The whitespace at the start of the line the value starts on
+fn line_indentation(index: &LineIndex, value: &KsonValue) -> String {
+    let (line, _) = position(&value.start());
+    index
+        .line(line)
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .collect()
+}
+
+fn segment_key(segment: &PathSegment) -> String {
+    match segment {
+        PathSegment::Key(key) => key.clone(),
+        PathSegment::Index(index) => index.to_string(),
+    }
+}
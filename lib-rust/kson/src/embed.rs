@@ -0,0 +1,369 @@
+//! Decoding of binary payloads stored in embed blocks tagged `base64` or `hex`, and parsing of documents
+//! nested in embed blocks tagged `kson` or `json`.
+//!
+//! Whitespace (including line breaks) is ignored while decoding, so long payloads can be wrapped
+//! across as many lines as needed inside the embed block.
+
+use crate::kson_value::KsonEmbed;
+use crate::line_index::LineIndex;
+use crate::{Kson, KsonValue, MessageSeverity, Position};
+
+/// An error found while decoding the content of an embed block, located in the decoded text: the document
+/// holding the embed block for [`KsonEmbed::decode_bytes`], the content itself for [`decode_base64`] and
+/// [`decode_hex`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmbedDecodeError {
+    /// What went wrong
+    pub kind: EmbedDecodeErrorKind,
+    /// Byte offset of the problem
+    pub offset: usize,
+    /// Zero-based line of the problem
+    pub line: usize,
+    /// Zero-based column of the problem (in characters), relative to the start of its line
+    pub column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EmbedDecodeErrorKind {
+    /// The embed tag does not name a supported binary encoding (`base64` or `hex`)
+    UnsupportedTag(Option<String>),
+    /// The content contains a character that is not valid in the encoding
+    InvalidCharacter(char),
+    /// The content ends in the middle of an encoded byte
+    UnexpectedEnd,
+    /// Padding (`=`) appears somewhere other than at the end of base64 content, or doesn't complete the
+    /// last group of four characters
+    InvalidPadding,
+}
+
+impl std::fmt::Display for EmbedDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            EmbedDecodeErrorKind::UnsupportedTag(Some(tag)) => write!(
+                f,
+                "unsupported embed tag `{tag}`, expected `base64` or `hex`"
+            )?,
+            EmbedDecodeErrorKind::UnsupportedTag(None) => {
+                write!(f, "missing embed tag, expected `base64` or `hex`")?
+            }
+            EmbedDecodeErrorKind::InvalidCharacter(c) => write!(f, "invalid character `{c}`")?,
+            EmbedDecodeErrorKind::UnexpectedEnd => write!(f, "unexpected end of encoded content")?,
+            EmbedDecodeErrorKind::InvalidPadding => write!(f, "unexpected padding")?,
+        }
+        write!(f, " at line {}, column {}", self.line + 1, self.column + 1)
+    }
+}
+
+impl std::error::Error for EmbedDecodeError {}
+
+impl KsonEmbed {
+    /// Decodes the content of this embed block according to its tag, which must be `base64` or `hex`
+    /// (case-insensitive). Errors are located in `document`, the text the embed block was parsed from.
+    pub fn decode_bytes(&self, document: &str) -> Result<Vec<u8>, EmbedDecodeError> {
+        let tag = self.tag();
+        let content = self.content();
+        let index = LineIndex::new(document);
+        let decoded = match tag.as_deref().map(str::to_ascii_lowercase).as_deref() {
+            Some("base64") => decode_base64(&content),
+            Some("hex") => decode_hex(&content),
+            _ => {
+                // Point at the tag, for lack of a problem in the content
+                let start = index.offset_of(&self.start());
+                return Err(error_at(
+                    document,
+                    start,
+                    EmbedDecodeErrorKind::UnsupportedTag(tag),
+                ));
+            }
+        };
+        decoded.map_err(|error| {
+            let (first_line, indent) = self.content_origin(document, &index);
+            // Content lines are the lines of the document without the indentation of the block
+            let line = error.line + first_line;
+            let line_start = content[..error.offset].rfind('\n').map_or(0, |i| i + 1);
+            let offset = index.offset(line, 0) + indent + (error.offset - line_start);
+            EmbedDecodeError {
+                offset: offset.min(document.len()),
+                line,
+                column: error.column + indent,
+                ..error
+            }
+        })
+    }
+
+    /// The zero-based line of the document the content starts on, which is the line after the tag, and
+    /// the indentation removed from each of its lines. Like kson-lib, the indentation is the smallest one
+    /// of the lines between the tag and the closing delimiter (`%%` or `$$`), blank lines and the line of
+    /// the delimiter included. Indentation is made of spaces and tabs, so it's the same number of
+    /// characters, bytes and UTF-16 code units.
+    fn content_origin(&self, document: &str, index: &LineIndex) -> (usize, usize) {
+        let first_line = self.start().line().max(0) as usize + 1;
+        let start = index.offset(first_line, 0);
+        let end = index
+            .offset_of(&self.end())
+            .saturating_sub("%%".len())
+            .max(start);
+        let indent = document[start..end]
+            .split('\n')
+            .map(|line| line.len() - line.trim_start_matches([' ', '\t', '\r']).len())
+            .min()
+            .unwrap_or(0);
+        (first_line, indent)
+    }
+}
+
+impl KsonEmbed {
+    /// Parses the content of this embed block as a document of its own, if it's tagged `kson` or `json`
+    /// (case-insensitive). JSON content is parsed as KSON, which it is a subset of. Errors are located in
+    /// `document`, the text the embed block was parsed from.
+    pub fn parse_nested(&self, document: &str) -> Result<NestedDocument, NestedDocumentError> {
+        let tag = self.tag();
+        if !matches!(
+            tag.as_deref().map(str::to_ascii_lowercase).as_deref(),
+            Some("kson" | "json")
+        ) {
+            return Err(NestedDocumentError::UnsupportedTag(tag));
+        }
+
+        let (line_offset, column_offset) = self.content_origin(document, &LineIndex::new(document));
+        let content = self.content();
+        let analysis = Kson::analyze(&content, None);
+        let errors: Vec<NestedParseError> = analysis
+            .errors()
+            .into_iter()
+            .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+            .map(|message| {
+                let start = message.start();
+                NestedParseError {
+                    message: message.message(),
+                    line: start.line() as usize + line_offset,
+                    column: start.column() as usize + column_offset,
+                }
+            })
+            .collect();
+        match analysis.kson_value() {
+            Some(value) if errors.is_empty() => Ok(NestedDocument {
+                value,
+                line_offset,
+                column_offset,
+            }),
+            _ => Err(NestedDocumentError::Parse(errors)),
+        }
+    }
+}
+
+/// A document parsed from the content of an embed block by [`KsonEmbed::parse_nested`]
+///
+/// The positions of its values are relative to the content of the embed block, and
+/// [`outer_position`](Self::outer_position) maps them to positions in the enclosing document.
+#[derive(Clone, Debug)]
+pub struct NestedDocument {
+    value: KsonValue,
+    line_offset: usize,
+    column_offset: usize,
+}
+
+impl NestedDocument {
+    pub fn value(&self) -> &KsonValue {
+        &self.value
+    }
+
+    pub fn into_value(self) -> KsonValue {
+        self.value
+    }
+
+    /// Maps a position in the nested document to the zero-based line and column (in UTF-16 code units, like
+    /// `position`) of the same place in the enclosing document
+    pub fn outer_position(&self, position: &Position) -> (usize, usize) {
+        (
+            position.line() as usize + self.line_offset,
+            position.column() as usize + self.column_offset,
+        )
+    }
+}
+
+/// The reason the content of an embed block couldn't be parsed as a document
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NestedDocumentError {
+    /// The embed tag is not `kson` or `json`
+    UnsupportedTag(Option<String>),
+    /// The content is not a valid document
+    Parse(Vec<NestedParseError>),
+}
+
+/// An error found while parsing a nested document, located in the enclosing document
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NestedParseError {
+    pub message: String,
+    /// Zero-based line of the problem in the enclosing document
+    pub line: usize,
+    /// Zero-based column of the problem in the enclosing document, in UTF-16 code units like the positions
+    /// reported by kson-lib
+    pub column: usize,
+}
+
+impl std::fmt::Display for NestedDocumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NestedDocumentError::UnsupportedTag(Some(tag)) => {
+                write!(
+                    f,
+                    "unsupported embed tag `{tag}`, expected `kson` or `json`"
+                )
+            }
+            NestedDocumentError::UnsupportedTag(None) => {
+                write!(f, "missing embed tag, expected `kson` or `json`")
+            }
+            NestedDocumentError::Parse(errors) => {
+                let errors: Vec<String> = errors
+                    .iter()
+                    .map(|error| {
+                        format!(
+                            "{} at line {}, column {}",
+                            error.message,
+                            error.line + 1,
+                            error.column + 1
+                        )
+                    })
+                    .collect();
+                write!(f, "invalid nested document: {}", errors.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for NestedDocumentError {}
+
+/// Decodes standard or URL-safe base64, with optional padding. The content must use a single alphabet,
+/// padding must be exactly what completes the last group of four characters, and the bits of the last
+/// character beyond the final byte must be zero, so that every byte string has one encoding.
+pub fn decode_base64(content: &str) -> Result<Vec<u8>, EmbedDecodeError> {
+    let mut bytes = Vec::with_capacity(content.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut buffered_bits = 0;
+    let mut sextets = 0;
+    let mut url_safe = None;
+    let mut padding_start = None;
+    let mut padding = 0;
+    let mut last = (0, 'A');
+
+    for (offset, c) in content.char_indices().filter(|(_, c)| !c.is_whitespace()) {
+        if c == '=' {
+            padding_start.get_or_insert(offset);
+            padding += 1;
+            // Padding fills the last group of four characters, and there's nothing to fill after a full group
+            if padding > (4 - sextets % 4) % 4 {
+                return Err(error_at(
+                    content,
+                    offset,
+                    EmbedDecodeErrorKind::InvalidPadding,
+                ));
+            }
+            continue;
+        }
+        if padding_start.is_some() {
+            return Err(error_at(
+                content,
+                offset,
+                EmbedDecodeErrorKind::InvalidPadding,
+            ));
+        }
+
+        let invalid = || error_at(content, offset, EmbedDecodeErrorKind::InvalidCharacter(c));
+        let sextet = match c {
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' | '/' | '-' | '_' => {
+                let is_url_safe = matches!(c, '-' | '_');
+                if *url_safe.get_or_insert(is_url_safe) != is_url_safe {
+                    return Err(invalid());
+                }
+                if matches!(c, '+' | '-') { 62 } else { 63 }
+            }
+            _ => return Err(invalid()),
+        };
+        last = (offset, c);
+        sextets += 1;
+        buffer = (buffer << 6) | sextet;
+        buffered_bits += 6;
+        if buffered_bits >= 8 {
+            buffered_bits -= 8;
+            bytes.push((buffer >> buffered_bits) as u8);
+            buffer &= (1 << buffered_bits) - 1;
+        }
+    }
+
+    let (last_offset, last_char) = last;
+    // A single leftover sextet can't encode a full byte
+    if buffered_bits == 6 {
+        return Err(error_at(
+            content,
+            last_offset,
+            EmbedDecodeErrorKind::UnexpectedEnd,
+        ));
+    }
+    // The last character encodes bits past the final byte
+    if buffer != 0 {
+        return Err(error_at(
+            content,
+            last_offset,
+            EmbedDecodeErrorKind::InvalidCharacter(last_char),
+        ));
+    }
+    // Padding, when there is some, must complete the last group
+    if let Some(padding_start) = padding_start
+        && padding != (4 - sextets % 4) % 4
+    {
+        return Err(error_at(
+            content,
+            padding_start,
+            EmbedDecodeErrorKind::InvalidPadding,
+        ));
+    }
+    Ok(bytes)
+}
+
+/// Decodes hexadecimal content (upper or lower case)
+pub fn decode_hex(content: &str) -> Result<Vec<u8>, EmbedDecodeError> {
+    let mut bytes = Vec::with_capacity(content.len() / 2);
+    let mut high_nibble = None;
+    let mut last_offset = 0;
+
+    for (offset, c) in content.char_indices().filter(|(_, c)| !c.is_whitespace()) {
+        last_offset = offset;
+        let Some(nibble) = c.to_digit(16) else {
+            return Err(error_at(
+                content,
+                offset,
+                EmbedDecodeErrorKind::InvalidCharacter(c),
+            ));
+        };
+        match high_nibble.take() {
+            None => high_nibble = Some(nibble),
+            Some(high) => bytes.push((high << 4 | nibble) as u8),
+        }
+    }
+
+    if high_nibble.is_some() {
+        return Err(error_at(
+            content,
+            last_offset,
+            EmbedDecodeErrorKind::UnexpectedEnd,
+        ));
+    }
+    Ok(bytes)
+}
+
+fn error_at(text: &str, offset: usize, kind: EmbedDecodeErrorKind) -> EmbedDecodeError {
+    let before = &text[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count();
+    EmbedDecodeError {
+        kind,
+        offset,
+        line,
+        column,
+    }
+}
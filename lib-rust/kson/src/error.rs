@@ -0,0 +1,590 @@
+//! Errors carrying the document they were found in, for applications reporting problems to their users.
+//!
+//! [`Message`]s and [`SchemaDiagnostic`]s locate problems with line/column positions only. [`KsonErrors`]
+//! keeps the source text along with them, resolved into [`KsonError`]s spanning byte ranges of it. With the
+//! `miette` feature, both implement `miette::Diagnostic`, so they render as annotated snippets of the
+//! document.
+//!
+//! Tools handling several documents can name the document with [`KsonErrors::with_source_name`] and
+//! describe what they were doing with it with [`KsonErrors::with_context`] before passing errors on:
+//!
+//! ```no_run
+//! # use kson_rs::value::Value;
+//! # fn load() -> Result<Value, kson_rs::error::KsonErrors> {
+//! let text = std::fs::read_to_string("deploy.kson").unwrap();
+//! let config = Value::parse(&text).map_err(|errors| {
+//!     errors
+//!         .with_source_name("deploy.kson")
+//!         .with_context("loading the deployment configuration")
+//! })?;
+//! # Ok(config)
+//! # }
+//! ```
+
+use std::ops::Range;
+
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::diagnostics::DiagnosticLimit;
+use crate::line_index::LineIndex;
+use crate::schema::{Schema, SchemaDiagnostic, SchemaDiagnosticKind};
+use crate::value::Value;
+use crate::{Analysis, FormatOptions, Kson, Message, MessageSeverity, result, transpile_options};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl From<MessageSeverity> for Severity {
+    fn from(severity: MessageSeverity) -> Self {
+        match severity {
+            MessageSeverity::Error => Severity::Error,
+            MessageSeverity::Warning => Severity::Warning,
+        }
+    }
+}
+
+/// A problem found in a document (see [`KsonErrors`])
+#[derive(Clone, Debug, PartialEq)]
+pub struct KsonError {
+    message: String,
+    severity: Severity,
+    span: Range<usize>,
+    help: Option<String>,
+}
+
+impl KsonError {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// The byte range of the document the problem is about
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    /// Details on how to fix the problem, if any
+    pub fn help(&self) -> Option<&str> {
+        self.help.as_deref()
+    }
+}
+
+impl std::fmt::Display for KsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for KsonError {}
+
+/// The problems found in a document, along with its source text
+#[derive(Clone, Debug, PartialEq)]
+pub struct KsonErrors {
+    source: Document,
+    errors: Vec<KsonError>,
+    /// The operations that produced the errors, innermost first
+    context: Vec<String>,
+    /// The bug that stopped the operation, if it didn't get to report the problems of the document
+    internal: Option<InternalError>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Document {
+    name: Option<String>,
+    text: String,
+}
+
+impl KsonErrors {
+    fn new(source: &str, errors: Vec<KsonError>) -> Self {
+        Self {
+            source: Document {
+                name: None,
+                text: source.to_string(),
+            },
+            errors,
+            context: Vec::new(),
+            internal: None,
+        }
+    }
+
+    /// Reports a bug hit while processing `source`, as a single error spanning the start of the document
+    pub fn from_internal_error(source: &str, internal: InternalError) -> Self {
+        let error = KsonError {
+            message: internal.to_string(),
+            severity: Severity::Error,
+            span: 0..0,
+            help: Some("this is a bug in kson rather than a problem with the document".to_string()),
+        };
+        Self {
+            internal: Some(internal),
+            ..Self::new(source, vec![error])
+        }
+    }
+
+    /// Resolves the messages reported by [`Kson::analyze`] or
+    /// [`SchemaValidator::validate`](crate::SchemaValidator::validate) for `source`
+    pub fn from_messages(source: &str, messages: &[Message]) -> Self {
+        let index = LineIndex::new(source);
+        let errors = messages
+            .iter()
+            .map(|message| KsonError {
+                message: message.message(),
+                severity: message.severity().into(),
+                span: span(&index, &message.start(), &message.end()),
+                help: None,
+            })
+            .collect();
+        Self::new(source, errors)
+    }
+
+    /// Warnings found by this crate in `source`, each with the byte range it's about
+    pub(crate) fn from_warnings(source: &str, warnings: Vec<(Range<usize>, String)>) -> Self {
+        let errors = warnings
+            .into_iter()
+            .map(|(span, message)| KsonError {
+                message,
+                severity: Severity::Warning,
+                span,
+                help: None,
+            })
+            .collect();
+        Self::new(source, errors)
+    }
+
+    /// Resolves the diagnostics reported by the checks of [`crate::schema`] for `source`
+    pub fn from_schema_diagnostics(source: &str, diagnostics: &[SchemaDiagnostic]) -> Self {
+        let index = LineIndex::new(source);
+        let errors = diagnostics
+            .iter()
+            .map(|diagnostic| KsonError {
+                message: diagnostic.message.clone(),
+                severity: diagnostic.severity.into(),
+                span: span(&index, &diagnostic.start, &diagnostic.end),
+                help: schema_help(&diagnostic.kind),
+            })
+            .collect();
+        Self::new(source, errors)
+    }
+
+    /// Adds the problems of `other`, found in the same document, keeping the problems in document order
+    pub fn extend(&mut self, other: KsonErrors) {
+        self.errors.extend(other.errors);
+        self.errors.sort_by_key(|error| error.span.start);
+    }
+
+    /// Names the document, typically with its file name, which is then shown in front of the errors
+    pub fn with_source_name(mut self, name: impl Into<String>) -> Self {
+        self.source.name = Some(name.into());
+        self
+    }
+
+    /// Describes the operation that produced the errors (e.g. `"loading the deployment configuration"`),
+    /// which is then shown in front of them. Calling this again adds an outer context, shown first.
+    pub fn with_context(mut self, context: impl Into<String>) -> Self {
+        self.context.push(context.into());
+        self
+    }
+
+    /// The text of the document
+    pub fn source(&self) -> &str {
+        &self.source.text
+    }
+
+    pub fn source_name(&self) -> Option<&str> {
+        self.source.name.as_deref()
+    }
+
+    /// The contexts added with [`with_context`](Self::with_context), innermost first
+    pub fn context(&self) -> &[String] {
+        &self.context
+    }
+
+    pub fn errors(&self) -> &[KsonError] {
+        &self.errors
+    }
+
+    /// The bug that stopped the operation, in which case the document may have problems that weren't
+    /// reported
+    pub fn internal_error(&self) -> Option<&InternalError> {
+        self.internal.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Whether any of the problems is an error, as opposed to a warning
+    pub fn has_errors(&self) -> bool {
+        self.errors
+            .iter()
+            .any(|error| error.severity == Severity::Error)
+    }
+}
+
+/// Shows the contexts (outermost first) and the name of the document in front of the problem, e.g.
+/// `loading the deployment configuration: deploy.kson:3:7: Unclosed list`
+impl std::fmt::Display for KsonErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for context in self.context.iter().rev() {
+            write!(f, "{context}: ")?;
+        }
+        match (&self.source.name, self.errors.as_slice()) {
+            (Some(name), [error]) => {
+                let (line, column) = self.source.line_column(error.span.start);
+                write!(f, "{name}:{line}:{column}: {error}")
+            }
+            (None, [error]) => write!(f, "{error}"),
+            (Some(name), errors) => write!(f, "found {} problems in {name}", errors.len()),
+            (None, errors) => write!(f, "found {} problems in the document", errors.len()),
+        }
+    }
+}
+
+impl std::error::Error for KsonErrors {}
+
+/// A bug surfaced as a panic, either in this crate or as an exception thrown by kson-lib (which the
+/// bindings turn into panics once the call into kson-lib has returned)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InternalError {
+    message: String,
+}
+
+impl InternalError {
+    /// The panic message, if the panic carried a string
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for InternalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "internal error in kson: {}", self.message)
+    }
+}
+
+impl std::error::Error for InternalError {}
+
+/// Runs `f`, turning a panic into an [`InternalError`], for long-running applications (servers, editors...)
+/// that would rather report a failed operation than abort
+///
+/// Panics never unwind through kson-lib's frames: it doesn't call back into Rust, and its exceptions are
+/// only turned into panics after the call has returned, so catching them here is sound. The panic hook
+/// still runs, so the panic is reported as usual.
+///
+/// [`Value::parse`] and the `try_` variants of the calls into kson-lib (like [`Kson::try_format`]) already
+/// do this.
+pub fn catch_internal_errors<T>(f: impl FnOnce() -> T) -> Result<T, InternalError> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|payload| {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload.downcast_ref::<&str>().map_or_else(
+                || "unknown panic".to_string(),
+                |message| message.to_string(),
+            ),
+        };
+        InternalError { message }
+    })
+}
+
+/// Panic-free variants of the calls into kson-lib, for callers that can't use
+/// [`catch_internal_errors`] around each call themselves
+impl Kson {
+    /// Like [`Kson::check`], but also checks the formats required by the schema (see [`Schema`]), and fails
+    /// with the source text attached when any diagnostic (error or warning) is found
+    pub fn try_check(
+        document: &str,
+        schema: Option<&Schema>,
+        limit: DiagnosticLimit,
+    ) -> Result<(), KsonErrors> {
+        // A token nobody else holds can't be cancelled
+        Kson::try_check_cancellable(document, schema, limit, &CancellationToken::new()).unwrap()
+    }
+
+    /// Like [`Kson::format`], but fails with an [`InternalError`] rather than panicking
+    pub fn try_format(document: &str, options: FormatOptions) -> Result<String, InternalError> {
+        catch_internal_errors(|| Kson::format(document, options))
+    }
+
+    /// Like [`Kson::analyze`], but fails with an [`InternalError`] rather than panicking
+    pub fn try_analyze(document: &str, filepath: Option<&str>) -> Result<Analysis, InternalError> {
+        catch_internal_errors(|| Kson::analyze(document, filepath))
+    }
+
+    /// Like [`Kson::to_json`], but fails with the source text attached, including when kson-lib hits a bug
+    /// (see [`KsonErrors::internal_error`])
+    pub fn try_to_json(
+        document: &str,
+        options: transpile_options::Json,
+    ) -> Result<String, KsonErrors> {
+        transpiled(document, || Kson::to_json(document, options))
+    }
+
+    /// Like [`Kson::to_yaml`], but fails with the source text attached, including when kson-lib hits a bug
+    /// (see [`KsonErrors::internal_error`])
+    pub fn try_to_yaml(
+        document: &str,
+        options: transpile_options::Yaml,
+    ) -> Result<String, KsonErrors> {
+        transpiled(document, || Kson::to_yaml(document, options))
+    }
+}
+
+/// Cancellable variants of the panic-free calls, making the calls into kson-lib through
+/// [`CancellationToken::run`] (see the [`cancel`](crate::cancel) module). They fail with [`Cancelled`]
+/// once the token is cancelled, and return the result of the call otherwise.
+impl Kson {
+    /// Like [`Kson::try_check`], also checking the token before each subschema when checking formats
+    /// (see [`schema::validate_cancellable`](crate::schema::validate_cancellable))
+    pub fn try_check_cancellable(
+        document: &str,
+        schema: Option<&Schema>,
+        limit: DiagnosticLimit,
+        token: &CancellationToken,
+    ) -> Result<Result<(), KsonErrors>, Cancelled> {
+        let checked = catch_internal_errors(|| {
+            let messages = token.run({
+                let document = document.to_string();
+                let validator = schema.map(|schema| schema.validator().clone());
+                move || Kson::check(&document, validator.as_ref(), limit)
+            })?;
+            let mut errors = KsonErrors::from_messages(document, &messages);
+            // Formats are only checked in documents that parse, so they never come on top of syntax errors
+            if let Some(schema) = schema
+                && !limit.is_reached(messages.len())
+            {
+                let formats = schema.limited_format_diagnostics(
+                    document,
+                    limit.after(messages.len()),
+                    token,
+                )?;
+                errors.extend(KsonErrors::from_schema_diagnostics(document, &formats));
+            }
+            Ok(errors)
+        });
+        let errors = match checked {
+            Ok(errors) => errors?,
+            Err(internal) => KsonErrors::from_internal_error(document, internal),
+        };
+        Ok(if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        })
+    }
+
+    /// Like [`Kson::try_to_json`], failing once the token is cancelled
+    pub fn try_to_json_cancellable(
+        document: &str,
+        options: transpile_options::Json,
+        token: &CancellationToken,
+    ) -> Result<Result<String, KsonErrors>, Cancelled> {
+        let document = document.to_string();
+        token.run(move || Kson::try_to_json(&document, options))
+    }
+
+    /// Like [`Kson::try_to_yaml`], failing once the token is cancelled
+    pub fn try_to_yaml_cancellable(
+        document: &str,
+        options: transpile_options::Yaml,
+        token: &CancellationToken,
+    ) -> Result<Result<String, KsonErrors>, Cancelled> {
+        let document = document.to_string();
+        token.run(move || Kson::try_to_yaml(&document, options))
+    }
+}
+
+fn transpiled(
+    document: &str,
+    transpile: impl FnOnce() -> Result<result::Success, result::Failure>,
+) -> Result<String, KsonErrors> {
+    match catch_internal_errors(transpile) {
+        Ok(Ok(success)) => Ok(success.output()),
+        Ok(Err(failure)) => Err(KsonErrors::from_messages(document, &failure.errors())),
+        Err(internal) => Err(KsonErrors::from_internal_error(document, internal)),
+    }
+}
+
+impl Value {
+    /// Parses a document, failing with its errors (warnings are ignored). A bug in kson-lib is reported as
+    /// an error too, see [`KsonErrors::internal_error`].
+    pub fn parse(document: &str) -> Result<Value, KsonErrors> {
+        catch_internal_errors(|| {
+            let analysis = Kson::analyze(document, None);
+            let errors: Vec<Message> = analysis
+                .errors()
+                .into_iter()
+                .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+                .collect();
+            match analysis.kson_value() {
+                Some(value) if errors.is_empty() => Ok(value.to_value()),
+                _ => Err(KsonErrors::from_messages(document, &errors)),
+            }
+        })
+        .unwrap_or_else(|internal| Err(KsonErrors::from_internal_error(document, internal)))
+    }
+
+    /// Like [`Value::parse`], but parses through [`CancellationToken::run`], failing once the token is
+    /// cancelled
+    pub fn parse_cancellable(
+        document: &str,
+        token: &CancellationToken,
+    ) -> Result<Result<Value, KsonErrors>, Cancelled> {
+        let document = document.to_string();
+        token.run(move || Value::parse(&document))
+    }
+}
+
+impl Document {
+    /// The one-based line and column of the byte offset
+    fn line_column(&self, offset: usize) -> (usize, usize) {
+        let before = &self.text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+}
+
+/// Reads spans like the underlying text does, labeling them with the name of the document if it has one
+#[cfg(feature = "miette")]
+impl miette::SourceCode for Document {
+    fn read_span<'a>(
+        &'a self,
+        span: &miette::SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn miette::SpanContents<'a> + 'a>, miette::MietteError> {
+        let contents = self
+            .text
+            .read_span(span, context_lines_before, context_lines_after)?;
+        let Some(name) = &self.name else {
+            return Ok(contents);
+        };
+        Ok(Box::new(miette::MietteSpanContents::new_named(
+            name.clone(),
+            contents.data(),
+            contents.span().clone(),
+            contents.line(),
+            contents.column(),
+            contents.line_count(),
+        )))
+    }
+}
+
+fn span(index: &LineIndex, start: &crate::Position, end: &crate::Position) -> Range<usize> {
+    let start = index.offset_of(start);
+    start..index.offset_of(end).max(start)
+}
+
+fn schema_help(kind: &SchemaDiagnosticKind) -> Option<String> {
+    match kind {
+        SchemaDiagnosticKind::InvalidFormat { .. } => None,
+        SchemaDiagnosticKind::NotInEnum { allowed, .. } => Some(format!(
+            "allowed values: {}",
+            allowed
+                .iter()
+                .map(Value::to_json)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        SchemaDiagnosticKind::CompositionFailed { failures, .. } => Some(
+            failures
+                .iter()
+                .map(|failure| {
+                    format!(
+                        "{} rejects it: {}",
+                        failure.schema_path,
+                        failure.errors.join("; ")
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        SchemaDiagnosticKind::DuplicateItem { first_path, .. } => {
+            Some(format!("the first occurrence is at {first_path}"))
+        }
+        SchemaDiagnosticKind::MissingProperties { fix, .. } => {
+            Some(format!("add `{}`", fix.new_text.trim()))
+        }
+        SchemaDiagnosticKind::UndeclaredProperty { declared, .. } => {
+            Some(format!("declared properties: {}", declared.join(", ")))
+        }
+        SchemaDiagnosticKind::Violation { schema_path } => {
+            Some(format!("required by the schema at {schema_path}"))
+        }
+    }
+}
+
+#[cfg(feature = "miette")]
+impl miette::Diagnostic for KsonError {
+    fn severity(&self) -> Option<miette::Severity> {
+        Some(match self.severity {
+            Severity::Error => miette::Severity::Error,
+            Severity::Warning => miette::Severity::Warning,
+        })
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn std::fmt::Display>)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        Some(Box::new(std::iter::once(miette::LabeledSpan::underline(
+            self.span.clone(),
+        ))))
+    }
+}
+
+/// Each problem is a related diagnostic, rendered against the source of the document
+#[cfg(feature = "miette")]
+impl miette::Diagnostic for KsonErrors {
+    fn severity(&self) -> Option<miette::Severity> {
+        Some(if self.has_errors() {
+            miette::Severity::Error
+        } else {
+            miette::Severity::Warning
+        })
+    }
+
+    fn source_code(&self) -> Option<&dyn miette::SourceCode> {
+        Some(&self.source)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        // A single problem is shown directly rather than as a related diagnostic
+        match self.errors.as_slice() {
+            [error] => error.labels(),
+            _ => None,
+        }
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        match self.errors.as_slice() {
+            [error] => miette::Diagnostic::help(error),
+            _ => None,
+        }
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn miette::Diagnostic> + 'a>> {
+        if self.errors.len() < 2 {
+            return None;
+        }
+        Some(Box::new(
+            self.errors
+                .iter()
+                .map(|error| error as &dyn miette::Diagnostic),
+        ))
+    }
+}
@@ -0,0 +1,1501 @@
+//! Formatting helpers built on top of [`Kson::format`].
+//!
+//! [`Formatter`] extends [`FormatOptions`] with knobs that are applied as extra passes over the output
+//! of the core formatter. [`FormatOptions::builder`] builds one from named options, so that options
+//! added later don't break callers:
+//!
+//! ```no_run
+//! use kson_rs::{FormatOptions, FormattingStyle, IndentType, indent_type};
+//!
+//! let formatter = FormatOptions::builder()
+//!     .indent(IndentType::Spaces(indent_type::Spaces::new(4)))
+//!     .style(FormattingStyle::Delimited)
+//!     .max_width(100)
+//!     .build();
+//! println!("{}", formatter.format("server: { host: localhost, port: 80 }"));
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZero;
+use std::ops::Range;
+
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::line_index::LineIndex;
+use crate::pointer::PointerGlob;
+use crate::query::QueryNode;
+use crate::value::sorted_property_keys;
+use crate::{
+    EmbedRule, FormatOptions, FormattingStyle, IndentType, Kson, KsonValue, MessageSeverity,
+    TokenType, indent_type, kson_value,
+};
+
+/// The comment prefix of layout directives (e.g. `# kson-format: single-line`)
+const LAYOUT_DIRECTIVE: &str = "kson-format:";
+
+/// A formatter that applies [`FormatOptions`] and the extra knobs configured on it
+#[derive(Clone)]
+pub struct Formatter {
+    options: FormatOptions,
+    comment_width: Option<usize>,
+    blank_lines: BlankLines,
+    layouts: Vec<(PointerGlob, Layout)>,
+    parallel_min_size: Option<usize>,
+    quote_style: QuoteStyle,
+    float_format: FloatFormat,
+    sort_keys: KeyOrder,
+    max_width: Option<usize>,
+    line_ending: LineEnding,
+    final_newline: bool,
+    trailing_commas: TrailingCommas,
+}
+
+/// The preferred delimiter for quoted strings. The other delimiter is still used when the preferred one
+/// would require more escaping (e.g. `"it's"` when preferring single quotes).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuoteStyle {
+    /// Prefer `'single quotes'`, which is what the core formatter does
+    #[default]
+    Single,
+    /// Prefer `"double quotes"`
+    Double,
+}
+
+impl QuoteStyle {
+    pub fn quote_char(self) -> char {
+        match self {
+            QuoteStyle::Single => '\'',
+            QuoteStyle::Double => '"',
+        }
+    }
+
+    /// Picks the delimiter for a string with the given (unescaped) content: the preferred one, unless
+    /// the other one needs less escaping
+    pub fn delimiter_for(self, content: &str) -> char {
+        let preferred = self.quote_char();
+        let other = if preferred == '"' { '\'' } else { '"' };
+        if content.matches(other).count() < content.matches(preferred).count() {
+            other
+        } else {
+            preferred
+        }
+    }
+}
+
+/// How decimal numbers are written. Integers are always written as they are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// Keep the text decimals are written with in the document (e.g. `1.50` or `2.5E3`), which is what
+    /// the core formatter does
+    #[default]
+    Preserve,
+    /// The shortest text that reads back as the same `f64` (e.g. `1.5` and `2500.0`), which is exact for
+    /// values that went through an `f64` anyway
+    Shortest,
+    /// Round to the given number of digits after the decimal point, at least one so that the number stays
+    /// a decimal (e.g. `1.50` and `2500.00` with 2 digits)
+    Fixed(usize),
+}
+
+impl FloatFormat {
+    /// Renders the decimal, or returns `None` if its text is to be kept as written (always the case for
+    /// [`FloatFormat::Preserve`] and non-finite values)
+    pub fn render(self, decimal: f64) -> Option<String> {
+        if !decimal.is_finite() {
+            return None;
+        }
+        match self {
+            FloatFormat::Preserve => None,
+            // Like `Value` rendering, the `Debug` representation always has a fraction or exponent
+            FloatFormat::Shortest => Some(format!("{decimal:?}")),
+            FloatFormat::Fixed(digits) => Some(format!("{decimal:.*}", digits.max(1))),
+        }
+    }
+}
+
+/// How the properties of objects are ordered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyOrder {
+    /// Keep the properties in the order they are written in, which is what the core formatter does
+    #[default]
+    Preserve,
+    /// Sort the properties by key, comparing their characters (so `Zone` comes before `name`)
+    Alphabetical,
+    /// Sort the properties by key ignoring case, keys only differing by case being sorted by their
+    /// characters
+    AlphabeticalCaseInsensitive,
+}
+
+impl KeyOrder {
+    /// Compares two keys, or returns `None` if the properties keep their order
+    fn compare(self, a: &str, b: &str) -> Option<Ordering> {
+        match self {
+            KeyOrder::Preserve => None,
+            KeyOrder::Alphabetical => Some(a.cmp(b)),
+            KeyOrder::AlphabeticalCaseInsensitive => Some(
+                a.to_lowercase()
+                    .cmp(&b.to_lowercase())
+                    .then_with(|| a.cmp(b)),
+            ),
+        }
+    }
+}
+
+/// The line endings of formatted documents
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// End lines with `\n`, which is what the core formatter does
+    #[default]
+    Lf,
+    /// End lines with `\r\n`, as Windows tools do
+    Crlf,
+    /// End lines like the first line of the input, so that formatting doesn't rewrite every line of a
+    /// document (`\n` for documents of a single line)
+    Preserve,
+}
+
+impl LineEnding {
+    /// The line ending of the input, once [`LineEnding::Preserve`] is resolved
+    fn resolve(self, input: &str) -> LineEnding {
+        match self {
+            LineEnding::Preserve => match input.find('\n') {
+                Some(end) if input[..end].ends_with('\r') => LineEnding::Crlf,
+                _ => LineEnding::Lf,
+            },
+            line_ending => line_ending,
+        }
+    }
+}
+
+/// Whether the last item of objects and lists is followed by a comma
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingCommas {
+    /// Only put commas between items, which is what the core formatter does
+    #[default]
+    Never,
+    /// Also put a comma after the last item of the objects and lists spread over multiple lines, so that
+    /// adding an item doesn't change the line of the previous one. Only [`FormattingStyle::Classic`]
+    /// separates items with commas, and its output is then no longer valid JSON.
+    Multiline,
+}
+
+/// How the values targeted by a layout override are laid out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Render the value on a single line, as a delimited object or list (e.g. `{ os: linux, arch: x64 }`).
+    /// Values containing comments, embed blocks or multiline strings can't be put on a single line, so
+    /// they are left as-is.
+    SingleLine,
+    /// Keep the value expanded over multiple lines, even if an override on one of its ancestors asks for
+    /// a single line
+    Expanded,
+}
+
+impl Layout {
+    fn from_directive(directive: &str) -> Option<Self> {
+        match directive {
+            "single-line" => Some(Layout::SingleLine),
+            "expanded" => Some(Layout::Expanded),
+            _ => None,
+        }
+    }
+}
+
+/// How the formatter treats blank lines written by the user
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlankLines {
+    /// Remove all blank lines, which is what the core formatter does
+    #[default]
+    Normalize,
+    /// Keep the blank lines written between top-level sections (a top-level property along with the
+    /// comments preceding it)
+    Preserve,
+    /// Like [`BlankLines::Preserve`], but collapse runs of blank lines to at most the given number
+    Collapse(usize),
+}
+
+/// Builds a [`Formatter`] from named options, see [`FormatOptions::builder`]. Every option of a
+/// [`Formatter`] can be set here.
+#[derive(Clone)]
+pub struct FormatOptionsBuilder {
+    indent: IndentType,
+    style: FormattingStyle,
+    embed_block_rules: Vec<EmbedRule>,
+    comment_width: Option<usize>,
+    blank_lines: BlankLines,
+    layouts: Vec<(PointerGlob, Layout)>,
+    parallel_min_size: Option<usize>,
+    quote_style: QuoteStyle,
+    float_format: FloatFormat,
+    sort_keys: KeyOrder,
+    max_width: Option<usize>,
+    line_ending: LineEnding,
+    final_newline: bool,
+    trailing_commas: TrailingCommas,
+}
+
+impl FormatOptions {
+    /// Starts building the options of a formatter, which by default indents with 2 spaces in the plain
+    /// style. Unlike [`FormatOptions::new`], new options don't break callers.
+    pub fn builder() -> FormatOptionsBuilder {
+        FormatOptionsBuilder {
+            indent: IndentType::Spaces(indent_type::Spaces::new(2)),
+            style: FormattingStyle::Plain,
+            embed_block_rules: Vec::new(),
+            comment_width: None,
+            blank_lines: BlankLines::default(),
+            layouts: Vec::new(),
+            parallel_min_size: None,
+            quote_style: QuoteStyle::default(),
+            float_format: FloatFormat::default(),
+            sort_keys: KeyOrder::default(),
+            max_width: None,
+            line_ending: LineEnding::default(),
+            final_newline: false,
+            trailing_commas: TrailingCommas::default(),
+        }
+    }
+}
+
+impl FormatOptionsBuilder {
+    pub fn indent(mut self, indent: IndentType) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    pub fn style(mut self, style: FormattingStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Adds a rule formatting the values at some paths as embed blocks
+    pub fn embed_block_rule(mut self, rule: EmbedRule) -> Self {
+        self.embed_block_rules.push(rule);
+        self
+    }
+
+    /// See [`Formatter::quote_style`]
+    pub fn quote_style(mut self, quote_style: QuoteStyle) -> Self {
+        self.quote_style = quote_style;
+        self
+    }
+
+    /// See [`Formatter::float_format`]
+    pub fn float_format(mut self, float_format: FloatFormat) -> Self {
+        self.float_format = float_format;
+        self
+    }
+
+    /// See [`Formatter::sort_keys`]
+    pub fn sort_keys(mut self, key_order: KeyOrder) -> Self {
+        self.sort_keys = key_order;
+        self
+    }
+
+    /// See [`Formatter::layout`]
+    pub fn layout(mut self, pattern: PointerGlob, layout: Layout) -> Self {
+        self.layouts.push((pattern, layout));
+        self
+    }
+
+    /// See [`Formatter::max_width`]
+    pub fn max_width(mut self, max_width: usize) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    /// See [`Formatter::line_ending`]
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// See [`Formatter::final_newline`]
+    pub fn final_newline(mut self, final_newline: bool) -> Self {
+        self.final_newline = final_newline;
+        self
+    }
+
+    /// See [`Formatter::trailing_commas`]
+    pub fn trailing_commas(mut self, trailing_commas: TrailingCommas) -> Self {
+        self.trailing_commas = trailing_commas;
+        self
+    }
+
+    /// See [`Formatter::blank_lines`]
+    pub fn blank_lines(mut self, policy: BlankLines) -> Self {
+        self.blank_lines = policy;
+        self
+    }
+
+    /// See [`Formatter::reflow_comments`]
+    pub fn reflow_comments(mut self, max_width: usize) -> Self {
+        self.comment_width = Some(max_width);
+        self
+    }
+
+    /// See [`Formatter::parallel`]
+    pub fn parallel(mut self, min_size: usize) -> Self {
+        self.parallel_min_size = Some(min_size);
+        self
+    }
+
+    /// The core options, without those only applied by a [`Formatter`] (like the maximum width)
+    pub fn options(&self) -> FormatOptions {
+        FormatOptions::new(self.indent.clone(), self.style, &self.embed_block_rules)
+    }
+
+    /// Builds a formatter applying the options
+    pub fn build(self) -> Formatter {
+        Formatter {
+            options: self.options(),
+            comment_width: self.comment_width,
+            blank_lines: self.blank_lines,
+            layouts: self.layouts,
+            parallel_min_size: self.parallel_min_size,
+            quote_style: self.quote_style,
+            float_format: self.float_format,
+            sort_keys: self.sort_keys,
+            max_width: self.max_width,
+            line_ending: self.line_ending,
+            final_newline: self.final_newline,
+            trailing_commas: self.trailing_commas,
+        }
+    }
+}
+
+impl Formatter {
+    pub fn new(options: FormatOptions) -> Self {
+        Self {
+            options,
+            comment_width: None,
+            blank_lines: BlankLines::default(),
+            layouts: Vec::new(),
+            parallel_min_size: None,
+            quote_style: QuoteStyle::default(),
+            float_format: FloatFormat::default(),
+            sort_keys: KeyOrder::default(),
+            max_width: None,
+            line_ending: LineEnding::default(),
+            final_newline: false,
+            trailing_commas: TrailingCommas::default(),
+        }
+    }
+
+    /// Sets the preferred delimiter for quoted strings. Strings that don't need quotes stay unquoted, and
+    /// [`FormattingStyle::Classic`] always uses double quotes, as required by JSON.
+    pub fn quote_style(mut self, quote_style: QuoteStyle) -> Self {
+        self.quote_style = quote_style;
+        self
+    }
+
+    /// Sets how decimal numbers are written, e.g. [`FloatFormat::Shortest`] to normalize them for people
+    /// or [`FloatFormat::Fixed`] for tabular data
+    pub fn float_format(mut self, float_format: FloatFormat) -> Self {
+        self.float_format = float_format;
+        self
+    }
+
+    /// Sets how the properties of objects are ordered, e.g. [`KeyOrder::Alphabetical`] so that the output
+    /// doesn't depend on the order the properties were written in. Comments on the lines above a
+    /// property, or after its value, move along with it.
+    pub fn sort_keys(mut self, key_order: KeyOrder) -> Self {
+        self.sort_keys = key_order;
+        self
+    }
+
+    /// Overrides the layout of the values whose path matches the JsonPointerGlob `pattern` (e.g.
+    /// `/matrix/include/*`). When several overrides match the same value, the last one wins.
+    ///
+    /// A layout can also be chosen from the document itself, by preceding a property or list item with a
+    /// `# kson-format: single-line` or `# kson-format: expanded` comment. Such directives take
+    /// precedence over the overrides configured here.
+    ///
+    /// Layout overrides don't apply to [`FormattingStyle::Compact`], which puts everything on as few
+    /// lines as possible anyway.
+    pub fn layout(mut self, pattern: PointerGlob, layout: Layout) -> Self {
+        self.layouts.push((pattern, layout));
+        self
+    }
+
+    /// Limits the width of lines, in characters (including indentation), where the formatter has a
+    /// choice: values are only put on a single line by a [layout](Formatter::layout) if they fit, and
+    /// comments are [reflowed](Formatter::reflow_comments) to the width unless another one is set. Lines
+    /// the core formatter can't break (e.g. long strings) may still be wider.
+    pub fn max_width(mut self, max_width: usize) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    /// Sets the line endings of the output, e.g. [`LineEnding::Preserve`] to keep those of each document.
+    /// Line breaks in strings and embed blocks are converted as well.
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Ends non-empty documents with exactly one line break, where the core formatter ends them with
+    /// none
+    pub fn final_newline(mut self, final_newline: bool) -> Self {
+        self.final_newline = final_newline;
+        self
+    }
+
+    /// Sets whether the last item of objects and lists is followed by a comma, see [`TrailingCommas`]
+    pub fn trailing_commas(mut self, trailing_commas: TrailingCommas) -> Self {
+        self.trailing_commas = trailing_commas;
+        self
+    }
+
+    /// Sets how blank lines written by the user are treated, see [`BlankLines`]
+    pub fn blank_lines(mut self, policy: BlankLines) -> Self {
+        self.blank_lines = policy;
+        self
+    }
+
+    /// Rewraps comments that don't fit in `max_width` columns (including indentation).
+    ///
+    /// Only comments on lines of their own are reflowed, one paragraph at a time: consecutive comment
+    /// lines at the same indentation form a paragraph, which ends at an empty comment line or at a list
+    /// item (`- `, `* `, `+ `, `1. ` or `1) `). Continuation lines of list items are indented to align
+    /// with the item's text. Paragraphs that already fit are left untouched, as are comment lines that
+    /// look preformatted (e.g. `#   indented code` or `#---`). Words longer than the width are never
+    /// split.
+    pub fn reflow_comments(mut self, max_width: usize) -> Self {
+        self.comment_width = Some(max_width);
+        self
+    }
+
+    /// Formats documents of at least `min_size` bytes on several threads, each formatting a share of the
+    /// top-level properties, and stitches the results together. The output is the same as when formatting
+    /// on a single thread.
+    ///
+    /// Only documents whose root is an object with each property starting a line of its own can be split
+    /// (which is the case of most machine-generated documents), others are formatted on a single thread,
+    /// as is everything in [`FormattingStyle::Compact`]. Splitting requires parsing the document up
+    /// front, so this only pays off for large documents (at least a few hundred kilobytes).
+    pub fn parallel(mut self, min_size: usize) -> Self {
+        self.parallel_min_size = Some(min_size);
+        self
+    }
+
+    pub fn format(&self, input: &str) -> String {
+        // A token nobody else holds can't be cancelled
+        self.format_cancellable(input, &CancellationToken::new())
+            .unwrap()
+    }
+
+    /// Like [`Formatter::format`], but checks the token between the passes of the formatter, and makes the
+    /// core formatting call through [`CancellationToken::run`], failing once the token is cancelled
+    pub fn format_cancellable(
+        &self,
+        input: &str,
+        token: &CancellationToken,
+    ) -> Result<String, Cancelled> {
+        token.check()?;
+        let sorted;
+        let source = if self.sort_keys == KeyOrder::Preserve {
+            input
+        } else {
+            // Properties are moved in the delimited style, where objects end with a brace rather than
+            // with an end dot depending on what follows them, and the result is formatted again
+            let delimited = FormatOptions::new(
+                self.options.indent_type(),
+                FormattingStyle::Delimited,
+                &self.options.embed_block_rules(),
+            );
+            let formatted = token.run({
+                let input = input.to_string();
+                move || Kson::format(&input, delimited)
+            })?;
+            sorted = apply_key_order(&formatted, self.sort_keys);
+            token.check()?;
+            &sorted
+        };
+        let mut output = token.run({
+            let (source, options) = (source.to_string(), self.options.clone());
+            let parallel = self
+                .parallel_min_size
+                .is_some_and(|min_size| source.len() >= min_size);
+            move || {
+                parallel
+                    .then(|| format_parallel(&source, &options))
+                    .flatten()
+                    .unwrap_or_else(|| Kson::format(&source, options))
+            }
+        })?;
+        if self.float_format != FloatFormat::Preserve {
+            token.check()?;
+            output = apply_float_format(&output, self.float_format);
+        }
+        let style = self.options.formatting_style();
+        if self.quote_style != QuoteStyle::default() && !matches!(style, FormattingStyle::Classic) {
+            token.check()?;
+            output = apply_quote_style(&output, self.quote_style);
+        }
+        let is_compact = matches!(style, FormattingStyle::Compact);
+        if !is_compact && (!self.layouts.is_empty() || output.contains(LAYOUT_DIRECTIVE)) {
+            token.check()?;
+            output = apply_layouts(&output, &self.layouts, self.max_width);
+        }
+        if self.trailing_commas == TrailingCommas::Multiline
+            && matches!(style, FormattingStyle::Classic)
+        {
+            token.check()?;
+            output = apply_trailing_commas(&output);
+        }
+        let max_blank_lines = match self.blank_lines {
+            BlankLines::Normalize => 0,
+            BlankLines::Preserve => usize::MAX,
+            BlankLines::Collapse(max) => max,
+        };
+        if max_blank_lines > 0 {
+            token.check()?;
+            output = restore_blank_lines(input, &output, max_blank_lines);
+        }
+        if let Some(max_width) = self.comment_width.or(self.max_width) {
+            token.check()?;
+            output = reflow_comments(&output, &standalone_comment_lines(&output), max_width);
+        }
+        if self.final_newline && !output.is_empty() {
+            output.truncate(output.trim_end_matches(['\r', '\n']).len());
+            output.push('\n');
+        }
+        if self.line_ending.resolve(input) == LineEnding::Crlf {
+            token.check()?;
+            output = output.replace("\r\n", "\n").replace('\n', "\r\n");
+        }
+        token.check()?;
+        Ok(output)
+    }
+
+    /// Formats the input twice and checks that the second pass leaves the output unchanged, returning
+    /// the formatted output if so
+    pub fn verify_idempotent(&self, input: &str) -> Result<String, IdempotencyViolation> {
+        let first = self.format(input);
+        let second = self.format(&first);
+        if first == second {
+            Ok(first)
+        } else {
+            Err(IdempotencyViolation { first, second })
+        }
+    }
+}
+
+/// Formatting is meant to be idempotent for every combination of [`FormatOptions`]: formatting
+/// already-formatted output must leave it unchanged. This error reports a case where it didn't, which
+/// is a bug in the formatter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdempotencyViolation {
+    /// The result of formatting the input once
+    pub first: String,
+    /// The result of formatting `first` again
+    pub second: String,
+}
+
+impl IdempotencyViolation {
+    /// The zero-based index of the first line that differs between both outputs
+    pub fn first_differing_line(&self) -> usize {
+        let mut first_lines = self.first.lines();
+        let mut second_lines = self.second.lines();
+        let mut index = 0;
+        loop {
+            match (first_lines.next(), second_lines.next()) {
+                (Some(a), Some(b)) if a == b => index += 1,
+                // Identical lines but different trailing newlines end up here too
+                _ => return index,
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for IdempotencyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "formatting is not idempotent: output changed on line {} when formatted a second time",
+            self.first_differing_line() + 1
+        )
+    }
+}
+
+impl std::error::Error for IdempotencyViolation {}
+
+impl FormatOptions {
+    /// Formats the input twice with these options and checks that the second pass leaves the output
+    /// unchanged, returning the formatted output if so
+    pub fn verify_idempotent(&self, input: &str) -> Result<String, IdempotencyViolation> {
+        Formatter::new(self.clone()).verify_idempotent(input)
+    }
+}
+
+/// Formats the top-level properties of the document in parallel (see [`Formatter::parallel`]), or returns
+/// `None` if it can't be split
+fn format_parallel(input: &str, options: &FormatOptions) -> Option<String> {
+    let threads = std::thread::available_parallelism().map_or(1, NonZero::get);
+    let style = options.formatting_style();
+    if threads < 2 || matches!(style, FormattingStyle::Compact) {
+        return None;
+    }
+
+    let analysis = Kson::analyze(input, None);
+    if analysis
+        .errors()
+        .iter()
+        .any(|message| matches!(message.severity(), MessageSeverity::Error))
+    {
+        return None;
+    }
+    let root = analysis.kson_value()?;
+    let KsonValue::KsonObject(object) = &root else {
+        return None;
+    };
+    let index = LineIndex::new(input);
+
+    // Delimited roots are split inside their braces, and each share is put back between braces
+    let root_start = index.offset_of(&root.start());
+    let root_end = index.offset_of(&root.end());
+    let delimited = input[root_start..].starts_with('{');
+    let content = if delimited {
+        if !input[root_end..].trim().is_empty() || !input[..root_end].ends_with('}') {
+            return None;
+        }
+        root_start + 1..root_end - 1
+    } else {
+        0..input.len()
+    };
+
+    let mut properties = object.properties();
+    let members = sorted_property_keys(object)
+        .into_iter()
+        .map(|(name, key)| {
+            let value = properties.remove(&name)?;
+            Some((index.offset_of(&key.start()), index.offset_of(&value.end())))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    // Cut the content into shares of roughly equal size, right before the comment and blank lines
+    // preceding a property
+    let share_size = content.len() / threads + 1;
+    let mut cuts = vec![content.start];
+    for pair in members.windows(2) {
+        let [(_, previous_end), (key_start, _)] = pair else {
+            unreachable!()
+        };
+        let cut = member_start(input, *key_start, *previous_end)?;
+        if cut - cuts.last().unwrap() >= share_size {
+            cuts.push(cut);
+        }
+    }
+    if cuts.len() < 2 {
+        return None;
+    }
+    cuts.push(content.end);
+
+    // Each share is formatted along with a marker property, so that it's laid out as if other properties
+    // followed it (or preceded it, for the last one), and the marker is then removed
+    let marker = (0..)
+        .map(|n| format!("kson_split_marker_{n}"))
+        .find(|marker| !input.contains(marker.as_str()))?;
+    let last = cuts.len() - 2;
+    let shares: Vec<Option<(String, bool)>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = cuts
+            .windows(2)
+            .enumerate()
+            .map(|(share, range)| {
+                let text = &input[range[0]..range[1]];
+                let marker = marker.as_str();
+                let options = options.clone();
+                scope.spawn(move || {
+                    let text = if share == last {
+                        format!("{marker}: 0\n{text}")
+                    } else {
+                        format!("{text}\n{marker}: 0\n")
+                    };
+                    let text = if delimited {
+                        format!("{{{text}}}")
+                    } else {
+                        text
+                    };
+                    let formatted = Kson::format(&text, options);
+                    let lines = strip_marker(&formatted, marker, share == last, style)?;
+                    Some((lines, formatted.ends_with('\n')))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().ok().flatten())
+            .collect()
+    });
+
+    let shares = shares.into_iter().collect::<Option<Vec<_>>>()?;
+    let mut lines: Vec<&str> = shares.iter().map(|(lines, _)| lines.as_str()).collect();
+    if matches!(style, FormattingStyle::Delimited | FormattingStyle::Classic) {
+        lines.insert(0, "{");
+        lines.push("}");
+    }
+    let mut output = lines.join("\n");
+    // The trailing newline (if any) is the one of the end of the document, which the last share has
+    if shares
+        .last()
+        .is_some_and(|(_, trailing_newline)| *trailing_newline)
+    {
+        output.push('\n');
+    }
+    Some(output)
+}
+
+/// Returns the offset at which the property whose key starts at `key_start` starts, including the
+/// comment and blank lines preceding it, or `None` if its key doesn't start a line
+fn member_start(input: &str, key_start: usize, previous_end: usize) -> Option<usize> {
+    let line_start = |offset: usize| input[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+    let mut start = line_start(key_start);
+    if start < previous_end || !input[start..key_start].trim().is_empty() {
+        return None;
+    }
+    while start > 0 {
+        let previous_line = line_start(start - 1);
+        let text = input[previous_line..start].trim();
+        if previous_line < previous_end || !(text.is_empty() || text.starts_with('#')) {
+            break;
+        }
+        start = previous_line;
+    }
+    Some(start)
+}
+
+/// Removes the marker property (and the braces around the properties, for styles that have them) from a
+/// formatted share, returning its lines without a trailing newline
+fn strip_marker(
+    formatted: &str,
+    marker: &str,
+    marker_first: bool,
+    style: FormattingStyle,
+) -> Option<String> {
+    let mut lines: Vec<&str> = formatted.lines().collect();
+    if matches!(style, FormattingStyle::Delimited | FormattingStyle::Classic) {
+        if lines.first()?.trim() != "{" || lines.last()?.trim() != "}" {
+            return None;
+        }
+        lines.remove(0);
+        lines.pop();
+    }
+    let is_marker = |line: &str| {
+        let line = line.trim_start().trim_start_matches('"');
+        line.starts_with(marker)
+            && line[marker.len()..]
+                .trim_start_matches('"')
+                .starts_with(':')
+    };
+    if marker_first {
+        is_marker(lines.first()?).then(|| lines.remove(0))?;
+    } else {
+        is_marker(lines.last()?).then(|| lines.pop())?;
+    }
+    Some(lines.join("\n"))
+}
+
+/// Returns the (zero-based) lines that start with a comment, as opposed to having a comment after
+/// some value or containing a `#` that is part of a string or embed block
+fn standalone_comment_lines(text: &str) -> HashSet<usize> {
+    let lines: Vec<&str> = text.lines().collect();
+    Kson::analyze(text, None)
+        .tokens()
+        .into_iter()
+        .filter(|token| matches!(token.token_type(), TokenType::Comment))
+        .map(|token| token.start().line() as usize)
+        .filter(|&line| {
+            lines
+                .get(line)
+                .is_some_and(|text| text.trim_start().starts_with('#'))
+        })
+        .collect()
+}
+
+/// Rewrites the decimals of the document as `float_format` says. Invalid documents are returned as is.
+pub(crate) fn apply_float_format(text: &str, float_format: FloatFormat) -> String {
+    if float_format == FloatFormat::Preserve {
+        return text.to_string();
+    }
+    let Some(root) = Kson::analyze(text, None).kson_value() else {
+        return text.to_string();
+    };
+
+    let index = LineIndex::new(text);
+    let mut replacements = Vec::new();
+    let mut pending = vec![root];
+    while let Some(value) = pending.pop() {
+        let KsonValue::KsonNumber(kson_value::KsonNumber::Decimal(decimal)) = &value else {
+            pending.extend(value.children().into_iter().map(|(_, child)| child));
+            continue;
+        };
+        let range = index.offset_of(&value.start())..index.offset_of(&value.end());
+        if let Some(rendered) = float_format.render(decimal.value())
+            && rendered != text[range.clone()]
+        {
+            replacements.push((range, rendered));
+        }
+    }
+
+    // Replace from the end, so that the offsets of the remaining decimals stay valid
+    replacements.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    let mut output = text.to_string();
+    for (range, rendered) in replacements {
+        output.replace_range(range, &rendered);
+    }
+    output
+}
+
+/// Sorts the properties of the objects of the document as `key_order` says, moving the comments on the
+/// lines above a property, or after its value, along with it. The document is expected to be in the
+/// delimited style, since end dots aren't moved. Invalid documents are returned as is.
+pub(crate) fn apply_key_order(text: &str, key_order: KeyOrder) -> String {
+    if key_order == KeyOrder::Preserve {
+        return text.to_string();
+    }
+    let analysis = Kson::analyze(text, None);
+    let has_errors = analysis
+        .errors()
+        .iter()
+        .any(|message| matches!(message.severity(), MessageSeverity::Error));
+    let Some(root) = analysis.kson_value().filter(|_| !has_errors) else {
+        return text.to_string();
+    };
+    let sorter = KeySorter {
+        text,
+        index: LineIndex::new(text),
+        key_order,
+    };
+    sorter.render(&root, 0..text.len())
+}
+
+struct KeySorter<'a> {
+    text: &'a str,
+    index: LineIndex<'a>,
+    key_order: KeyOrder,
+}
+
+/// A property of an object being sorted, with the byte offsets delimiting it
+struct SortedProperty {
+    name: String,
+    value: KsonValue,
+    /// Where the property starts, including the comments on the lines above it
+    start: usize,
+    value_start: usize,
+    value_end: usize,
+    /// The comma separating the property from the next one, which stays in place
+    comma: Range<usize>,
+    /// The comment after the value, with the whitespace preceding it, or an empty range
+    comment: Range<usize>,
+}
+
+impl KeySorter<'_> {
+    fn range_of(&self, value: &KsonValue) -> Range<usize> {
+        self.index.offset_of(&value.start())..self.index.offset_of(&value.end())
+    }
+
+    /// The text of `range`, which contains `value`, with the properties of the objects in `value` sorted
+    fn render(&self, value: &KsonValue, range: Range<usize>) -> String {
+        match value {
+            KsonValue::KsonObject(object) => self.render_object(object, range),
+            KsonValue::KsonArray(array) => {
+                let mut output = String::new();
+                let mut offset = range.start;
+                for element in array.elements() {
+                    let element_range = self.range_of(&element);
+                    output.push_str(&self.text[offset..element_range.start]);
+                    output.push_str(&self.render(&element, element_range.clone()));
+                    offset = element_range.end;
+                }
+                output.push_str(&self.text[offset..range.end]);
+                output
+            }
+            _ => self.text[range].to_string(),
+        }
+    }
+
+    fn render_object(&self, object: &kson_value::KsonObject, range: Range<usize>) -> String {
+        let mut values = object.properties();
+        let keys = sorted_property_keys(object);
+        let text = self.text;
+        let line_start =
+            |offset: usize| text[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        // Properties that don't start lines of their own (e.g. in `{ a: 1, b: 2 }`) have no comments
+        let line_based = keys.iter().all(|(_, key)| {
+            let key_start = self.index.offset_of(&key.start());
+            text[line_start(key_start)..key_start].trim().is_empty()
+        });
+
+        let mut properties: Vec<SortedProperty> = Vec::new();
+        for (name, key) in keys {
+            let Some(value) = values.remove(&name) else {
+                continue;
+            };
+            let key_start = self.index.offset_of(&key.start());
+            let value_range = self.range_of(&value);
+            let floor = properties
+                .last()
+                .map_or(range.start, |previous| previous.comment.end);
+            let mut start = key_start;
+            if line_based {
+                start = line_start(key_start);
+                while start > floor {
+                    let above = line_start(start - 1);
+                    if above < floor || !text[above..start].trim_start().starts_with('#') {
+                        break;
+                    }
+                    start = above;
+                }
+            }
+            let comma_end = value_range.end + usize::from(text[value_range.end..].starts_with(','));
+            let line_end = text[comma_end..]
+                .find('\n')
+                .map_or(text.len(), |newline| comma_end + newline);
+            let comment_end =
+                if line_based && text[comma_end..line_end].trim_start().starts_with('#') {
+                    line_end
+                } else {
+                    comma_end
+                };
+            properties.push(SortedProperty {
+                name,
+                value,
+                start,
+                value_start: value_range.start,
+                value_end: value_range.end,
+                comma: value_range.end..comma_end,
+                comment: comma_end..comment_end,
+            });
+        }
+        let Some(first) = properties.first() else {
+            return text[range].to_string();
+        };
+
+        let mut order: Vec<usize> = (0..properties.len()).collect();
+        order.sort_by(|&a, &b| {
+            self.key_order
+                .compare(&properties[a].name, &properties[b].name)
+                .unwrap_or(Ordering::Equal)
+        });
+        let mut output = text[range.start..first.start].to_string();
+        // Each property takes the place of another, whose comma and separator with the next one stay
+        for (slot_index, slot) in properties.iter().enumerate() {
+            let property = &properties[order[slot_index]];
+            output.push_str(&text[property.start..property.value_start]);
+            let value_range = property.value_start..property.value_end;
+            output.push_str(&self.render(&property.value, value_range));
+            output.push_str(&text[slot.comma.clone()]);
+            output.push_str(&text[property.comment.clone()]);
+            let next_start = properties
+                .get(slot_index + 1)
+                .map_or(range.end, |next| next.start);
+            output.push_str(&text[slot.comment.end..next_start]);
+        }
+        output
+    }
+}
+
+/// Switches the delimiter of quoted strings to the one preferred by `quote_style`, where possible
+fn apply_quote_style(text: &str, quote_style: QuoteStyle) -> String {
+    let index = LineIndex::new(text);
+    let mut string_ranges = Vec::new();
+    let mut open_quote = None;
+    for token in Kson::analyze(text, None).tokens() {
+        match token.token_type() {
+            TokenType::StringOpenQuote => open_quote = Some(index.offset_of(&token.start())),
+            TokenType::StringCloseQuote => {
+                if let Some(start) = open_quote.take() {
+                    string_ranges.push(start..index.offset_of(&token.end()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut output = text.to_string();
+    for range in string_ranges.into_iter().rev() {
+        if let Some(requoted) = requote(&text[range.clone()], quote_style) {
+            output.replace_range(range, &requoted);
+        }
+    }
+    output
+}
+
+/// Rewrites a quoted string (including its delimiters) to use the delimiter chosen by `quote_style`,
+/// or returns `None` if it already does
+fn requote(quoted: &str, quote_style: QuoteStyle) -> Option<String> {
+    let current = quoted.chars().next()?;
+    let raw_content = quoted.get(1..quoted.len().checked_sub(1)?)?;
+
+    // Parse the raw content into chunks: escape sequences stay together, so we can tell escaped quotes
+    // apart from escaped backslashes
+    let mut chunks: Vec<&str> = Vec::new();
+    let mut chars = raw_content.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let end = match (c, chars.peek()) {
+            ('\\', Some(&(next_start, next))) => {
+                chars.next();
+                next_start + next.len_utf8()
+            }
+            _ => start + c.len_utf8(),
+        };
+        chunks.push(&raw_content[start..end]);
+    }
+
+    let escaped_current = format!("\\{current}");
+    let content: String = chunks
+        .iter()
+        .map(|chunk| {
+            if *chunk == escaped_current {
+                &chunk[1..]
+            } else {
+                chunk
+            }
+        })
+        .collect();
+    let target = quote_style.delimiter_for(&content);
+    if target == current {
+        return None;
+    }
+
+    let mut requoted = String::with_capacity(quoted.len() + 2);
+    requoted.push(target);
+    for chunk in chunks {
+        if chunk == escaped_current {
+            requoted.push(current);
+        } else if chunk.starts_with(target) {
+            requoted.push('\\');
+            requoted.push(target);
+        } else {
+            requoted.push_str(chunk);
+        }
+    }
+    requoted.push(target);
+    Some(requoted)
+}
+
+/// A value of the document, along with its path and the line of its key (or of the value itself, for
+/// list elements and the root)
+struct LayoutNode {
+    path: Vec<String>,
+    value: KsonValue,
+    anchor_line: usize,
+}
+
+/// Collects all the values of the document, parents before their children
+fn collect_layout_nodes(
+    value: &KsonValue,
+    path: &mut Vec<String>,
+    anchor_line: usize,
+    nodes: &mut Vec<LayoutNode>,
+) {
+    nodes.push(LayoutNode {
+        path: path.clone(),
+        value: value.clone(),
+        anchor_line,
+    });
+    match value {
+        KsonValue::KsonObject(object) => {
+            let mut properties = object.properties();
+            for (name, key) in sorted_property_keys(object) {
+                let Some(property) = properties.remove(&name) else {
+                    continue;
+                };
+                path.push(name);
+                collect_layout_nodes(&property, path, key.start().line() as usize, nodes);
+                path.pop();
+            }
+        }
+        KsonValue::KsonArray(array) => {
+            for (index, element) in array.elements().iter().enumerate() {
+                path.push(index.to_string());
+                collect_layout_nodes(element, path, element.start().line() as usize, nodes);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Looks for a layout directive in the comment lines directly above `line`
+fn directive_layout(lines: &[&str], line: usize) -> Option<Layout> {
+    lines[..line.min(lines.len())]
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        .take_while(|line| line.starts_with('#'))
+        .find_map(|comment| {
+            let directive = comment
+                .trim_start_matches('#')
+                .trim()
+                .strip_prefix(LAYOUT_DIRECTIVE)?;
+            Layout::from_directive(directive.trim())
+        })
+}
+
+/// Renders the value on a single line, reusing the source text of its keys and scalars
+fn render_inline(value: &KsonValue, text: &str, index: &LineIndex) -> Option<String> {
+    match value {
+        KsonValue::KsonObject(object) => {
+            let mut properties = object.properties();
+            let entries = sorted_property_keys(object)
+                .into_iter()
+                .map(|(name, key)| {
+                    let key = &text[index.offset_of(&key.start())..index.offset_of(&key.end())];
+                    let value = render_inline(&properties.remove(&name)?, text, index)?;
+                    Some(format!("{key}: {value}"))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            if entries.is_empty() {
+                Some("{}".to_string())
+            } else {
+                Some(format!("{{ {} }}", entries.join(", ")))
+            }
+        }
+        KsonValue::KsonArray(array) => {
+            let elements = array
+                .elements()
+                .iter()
+                .map(|element| render_inline(element, text, index))
+                .collect::<Option<Vec<_>>>()?;
+            Some(format!("[{}]", elements.join(", ")))
+        }
+        KsonValue::KsonEmbed(_) => None,
+        _ => {
+            let source = &text[index.offset_of(&value.start())..index.offset_of(&value.end())];
+            (!source.contains('\n')).then(|| source.to_string())
+        }
+    }
+}
+
+/// Puts a comma after the last item of the objects and lists spread over multiple lines
+fn apply_trailing_commas(text: &str) -> String {
+    let index = LineIndex::new(text);
+    let tokens: Vec<_> = Kson::analyze(text, None)
+        .tokens()
+        .into_iter()
+        .filter(|token| {
+            !matches!(
+                token.token_type(),
+                TokenType::Whitespace | TokenType::Comment
+            )
+        })
+        .collect();
+    let mut insertions = Vec::new();
+    for pair in tokens.windows(2) {
+        let (last, close) = (&pair[0], &pair[1]);
+        let closes_container = matches!(
+            close.token_type(),
+            TokenType::CurlyBraceR | TokenType::SquareBracketR
+        );
+        let ends_item = !matches!(
+            last.token_type(),
+            TokenType::CurlyBraceL | TokenType::SquareBracketL | TokenType::Comma
+        );
+        if closes_container && ends_item && last.end().line() < close.start().line() {
+            insertions.push(index.offset_of(&last.end()));
+        }
+    }
+    let mut output = text.to_string();
+    for offset in insertions.into_iter().rev() {
+        output.insert(offset, ',');
+    }
+    output
+}
+
+/// Puts the values that should be on a single line on a single line
+fn apply_layouts(
+    text: &str,
+    overrides: &[(PointerGlob, Layout)],
+    max_width: Option<usize>,
+) -> String {
+    let analysis = Kson::analyze(text, None);
+    let Some(root) = analysis.kson_value() else {
+        return text.to_string();
+    };
+    let index = LineIndex::new(text);
+    let lines: Vec<&str> = text.lines().collect();
+    let comment_offsets: Vec<usize> = analysis
+        .tokens()
+        .into_iter()
+        .filter(|token| matches!(token.token_type(), TokenType::Comment))
+        .map(|token| index.offset_of(&token.start()))
+        .collect();
+
+    let mut nodes = Vec::new();
+    collect_layout_nodes(
+        &root,
+        &mut Vec::new(),
+        root.start().line() as usize,
+        &mut nodes,
+    );
+    let layouts: Vec<(Range<usize>, Layout, &KsonValue)> = nodes
+        .iter()
+        .filter_map(|node| {
+            let layout = directive_layout(&lines, node.anchor_line).or_else(|| {
+                overrides
+                    .iter()
+                    .rev()
+                    .find(|(pattern, _)| pattern.matches(&node.path))
+                    .map(|(_, layout)| *layout)
+            })?;
+            let span = index.offset_of(&node.value.start())..index.offset_of(&node.value.end());
+            Some((span, layout, &node.value))
+        })
+        .collect();
+
+    let mut replacements: Vec<(Range<usize>, String)> = Vec::new();
+    for (span, layout, value) in &layouts {
+        if *layout != Layout::SingleLine
+            || replacements
+                .iter()
+                .any(|(replaced, _)| replaced.start <= span.start && span.end <= replaced.end)
+        {
+            continue;
+        }
+        let has_expanded_descendant = layouts.iter().any(|(other, layout, _)| {
+            *layout == Layout::Expanded
+                && span.start <= other.start
+                && other.end <= span.end
+                && other != span
+        });
+        let has_comments = comment_offsets.iter().any(|offset| span.contains(offset));
+        if has_expanded_descendant || has_comments {
+            continue;
+        }
+        let Some(inline) = render_inline(value, text, &index) else {
+            continue;
+        };
+
+        // Pull a value that starts on the line after its key back next to it
+        let before = &text[..span.start];
+        let trimmed = before.trim_end();
+        let start = if trimmed.ends_with(':') && before[trimmed.len()..].contains('\n') {
+            trimmed.len()
+        } else {
+            span.start
+        };
+        let inline = if start == span.start {
+            inline
+        } else {
+            format!(" {inline}")
+        };
+        if let Some(max_width) = max_width {
+            let line_start = text[..start].rfind('\n').map_or(0, |newline| newline + 1);
+            let line_end = text[span.end..]
+                .find('\n')
+                .map_or(text.len(), |newline| span.end + newline);
+            let width = text[line_start..start].chars().count()
+                + inline.chars().count()
+                + text[span.end..line_end].chars().count();
+            if width > max_width {
+                continue;
+            }
+        }
+        replacements.push((start..span.end, inline));
+    }
+
+    let mut output = text.to_string();
+    replacements.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    for (range, replacement) in replacements {
+        output.replace_range(range, &replacement);
+    }
+    output
+}
+
+/// Returns the top-level property keys of the document, along with the line and column each starts at
+fn top_level_key_positions(text: &str) -> Vec<(String, usize, usize)> {
+    let Some(KsonValue::KsonObject(object)) = Kson::analyze(text, None).kson_value() else {
+        return Vec::new();
+    };
+    object
+        .property_keys()
+        .into_iter()
+        .map(|(name, key)| {
+            let start = key.start();
+            (name, start.line() as usize, start.column() as usize)
+        })
+        .collect()
+}
+
+/// Returns the line where the section containing `key_line` starts, i.e. the first of the comment lines
+/// directly preceding it (if any)
+fn section_start(lines: &[&str], key_line: usize) -> usize {
+    let mut start = key_line;
+    while start > 0 && lines[start - 1].trim_start().starts_with('#') {
+        start -= 1;
+    }
+    start
+}
+
+/// Re-inserts the blank lines found before each top-level section of the input in the formatted output
+fn restore_blank_lines(input: &str, output: &str, max_blank_lines: usize) -> String {
+    let input_lines: Vec<&str> = input.lines().collect();
+    let mut blank_lines_before: HashMap<String, usize> = HashMap::new();
+    for (key, line, _) in top_level_key_positions(input) {
+        let start = section_start(&input_lines, line);
+        let blank_count = input_lines[..start]
+            .iter()
+            .rev()
+            .take_while(|line| line.trim().is_empty())
+            .count();
+        // Blank lines at the start of the document aren't between sections
+        if blank_count > 0 && blank_count < start {
+            blank_lines_before.insert(key, blank_count.min(max_blank_lines));
+        }
+    }
+
+    let output_lines: Vec<&str> = output.lines().collect();
+    let mut insertions: HashMap<usize, usize> = HashMap::new();
+    for (key, line, column) in top_level_key_positions(output) {
+        let Some(&count) = blank_lines_before.get(&key) else {
+            continue;
+        };
+        // Only sections that start on a line of their own (e.g. not in compact output) can be separated
+        let key_starts_line = output_lines[line]
+            .chars()
+            .take(column)
+            .all(|c| c.is_whitespace() || c == '"' || c == '\'');
+        let start = section_start(&output_lines, line);
+        if key_starts_line && start > 0 && !output_lines[start - 1].trim().is_empty() {
+            insertions.insert(start, count);
+        }
+    }
+
+    let mut result = String::with_capacity(output.len());
+    for (index, line) in output_lines.iter().enumerate() {
+        if let Some(&count) = insertions.get(&index) {
+            result.push_str(&"\n".repeat(count));
+        }
+        result.push_str(line);
+        result.push('\n');
+    }
+    if !output.ends_with('\n') {
+        result.pop();
+    }
+    result
+}
+
+/// A paragraph of comment text being reflowed
+struct CommentParagraph<'a> {
+    indent: &'a str,
+    /// The list marker (including its trailing space) if the paragraph is a list item
+    marker: &'a str,
+    /// The original lines, used as-is if the paragraph doesn't need reflowing
+    lines: Vec<&'a str>,
+    words: Vec<&'a str>,
+}
+
+impl CommentParagraph<'_> {
+    fn write_to(&self, output: &mut Vec<String>, max_width: usize) {
+        if self
+            .lines
+            .iter()
+            .all(|line| line.chars().count() <= max_width)
+        {
+            output.extend(self.lines.iter().map(|line| line.to_string()));
+            return;
+        }
+
+        let first_prefix = format!("{}# {}", self.indent, self.marker);
+        let continuation_prefix = format!(
+            "{}# {}",
+            self.indent,
+            " ".repeat(self.marker.chars().count())
+        );
+        let mut line = first_prefix.clone();
+        let mut line_has_words = false;
+        for word in &self.words {
+            let width = line.chars().count() + word.chars().count() + usize::from(line_has_words);
+            if line_has_words && width > max_width {
+                output.push(std::mem::replace(&mut line, continuation_prefix.clone()));
+                line_has_words = false;
+            }
+            if line_has_words {
+                line.push(' ');
+            }
+            line.push_str(word);
+            line_has_words = true;
+        }
+        output.push(line);
+    }
+}
+
+/// Length of the list marker at the start of the comment text (including the space after it), if any
+fn list_marker_len(text: &str) -> Option<usize> {
+    if text.starts_with("- ") || text.starts_with("* ") || text.starts_with("+ ") {
+        return Some(2);
+    }
+    let digits = text.find(|c: char| !c.is_ascii_digit())?;
+    let rest = &text[digits..];
+    (digits > 0 && (rest.starts_with(". ") || rest.starts_with(") "))).then_some(digits + 2)
+}
+
+fn reflow_comments(text: &str, comment_lines: &HashSet<usize>, max_width: usize) -> String {
+    let mut output = Vec::new();
+    let mut paragraph: Option<CommentParagraph> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        let comment_text = comment_lines
+            .contains(&index)
+            .then(|| trimmed.strip_prefix("# "))
+            .flatten()
+            .filter(|text| !text.trim().is_empty());
+
+        let Some(comment_text) = comment_text else {
+            // Not reflowable: flush the current paragraph and keep the line as-is
+            if let Some(paragraph) = paragraph.take() {
+                paragraph.write_to(&mut output, max_width);
+            }
+            output.push(line.to_string());
+            continue;
+        };
+
+        // Continuation of the current paragraph?
+        if let Some(current) = &mut paragraph
+            && current.indent == indent
+        {
+            let hanging = current.marker.len();
+            let continues_list_item = hanging > 0
+                && comment_text.len() > hanging
+                && comment_text.bytes().take(hanging).all(|b| b == b' ')
+                && !comment_text[hanging..].starts_with(' ');
+            let continues_text = hanging == 0
+                && !comment_text.starts_with(' ')
+                && list_marker_len(comment_text).is_none();
+            if continues_list_item || continues_text {
+                current.lines.push(line);
+                current.words.extend(comment_text.split_whitespace());
+                continue;
+            }
+        }
+
+        if let Some(paragraph) = paragraph.take() {
+            paragraph.write_to(&mut output, max_width);
+        }
+        if comment_text.starts_with(' ') {
+            // Preformatted text
+            output.push(line.to_string());
+            continue;
+        }
+        let marker_len = list_marker_len(comment_text).unwrap_or(0);
+        paragraph = Some(CommentParagraph {
+            indent,
+            marker: &comment_text[..marker_len],
+            lines: vec![line],
+            words: comment_text[marker_len..].split_whitespace().collect(),
+        });
+    }
+    if let Some(paragraph) = paragraph.take() {
+        paragraph.write_to(&mut output, max_width);
+    }
+
+    let mut result = output.join("\n");
+    if text.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
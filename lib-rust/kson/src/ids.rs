@@ -0,0 +1,21 @@
+//! Interpretation of identifiers stored as strings, enabled through the `uuid` feature.
+
+use uuid::Uuid;
+
+use crate::KsonValue;
+
+impl KsonValue {
+    /// Interprets this value as a UUID, if it is a string in one of the formats accepted by
+    /// [`Uuid::parse_str`] (hyphenated, simple, braced or URN)
+    pub fn as_uuid(&self) -> Option<Uuid> {
+        match self {
+            KsonValue::KsonString(string) => Uuid::parse_str(&string.value()).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the text is a UUID in the hyphenated form required by the `uuid` schema format (RFC 4122)
+pub(crate) fn is_hyphenated_uuid(text: &str) -> bool {
+    text.len() == 36 && Uuid::try_parse(text).is_ok()
+}
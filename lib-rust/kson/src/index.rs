@@ -0,0 +1,618 @@
+//! An in-memory index of the documents of a workspace, kept up to date as files change, for long-running
+//! tools like the language server or a watch mode.
+//!
+//! Building the index parses and checks every document once, like [`Kson::validate_workspace`] and
+//! [`Kson::check_references`] do. Each [`FileChange`] then only reparses the changed document, and
+//! recomputes the results of the documents depending on it: the documents referencing it, and those it
+//! is the schema of.
+//!
+//! The index also answers queries spanning all documents, like [`WorkspaceIndex::workspace_symbols`],
+//! [`WorkspaceIndex::find_references`], [`WorkspaceIndex::search`] and [`WorkspaceIndex::key_usage`].
+//!
+//! ```no_run
+//! use kson_rs::config::ProjectConfig;
+//! use kson_rs::index::{FileChange, WorkspaceIndex};
+//!
+//! let config = ProjectConfig::new("/srv/configs");
+//! let mut index = WorkspaceIndex::build("/srv/configs", config).unwrap();
+//! // Typically called from a file watcher, or when an editor changes a document
+//! let affected = index
+//!     .apply(FileChange::Written("/srv/configs/common.kson".into()))
+//!     .unwrap();
+//! for path in affected {
+//!     if let Some(document) = index.document(&path) {
+//!         println!("{}: {} problems", path.display(), document.diagnostics().len());
+//!     }
+//! }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::ProjectConfig;
+use crate::error::KsonErrors;
+use crate::path::KsonPath;
+use crate::pointer::PointerGlob;
+use crate::query::{PathQuery, QueryNode};
+use crate::references::{
+    BrokenReference, Reference, Target, check_reference, load, normalize, references_in,
+};
+use crate::schema::{self, Schema, SchemaDiagnostic, SchemaDiagnosticKind};
+use crate::value::Value;
+use crate::workspace::{FileDiscovery, check_document, read};
+use crate::{Kson, KsonValue, MessageSeverity, Position};
+
+/// A change to a file of the workspace, for [`WorkspaceIndex::apply`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FileChange {
+    /// The file was created or modified on disk, and is read again
+    Written(PathBuf),
+    /// The contents of the file changed without being saved, e.g. in an editor
+    Edited(PathBuf, String),
+    /// The file was deleted
+    Deleted(PathBuf),
+}
+
+/// A property key of a document, as listed by [`WorkspaceIndex::workspace_symbols`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// The path of the property the key belongs to
+    pub path: KsonPath,
+    /// The start of the key
+    pub start: Position,
+    /// The end of the key
+    pub end: Position,
+}
+
+/// What to look for with [`WorkspaceIndex::find_references`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReferenceQuery {
+    /// The properties with the given key, wherever they are
+    Key(String),
+    /// The properties whose path matches the JsonPointerGlob (e.g. `/services/*/port`)
+    Path(PointerGlob),
+    /// The references to the value at `path` in the document at `file`, or to a value inside it
+    Definition { file: PathBuf, path: KsonPath },
+}
+
+/// A location found by [`WorkspaceIndex::find_references`] (a property key, or a reference) or by
+/// [`WorkspaceIndex::search`] (a value)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Usage {
+    pub file: PathBuf,
+    /// The path of the property, the reference or the value in the document
+    pub path: KsonPath,
+    pub start: Position,
+    pub end: Position,
+}
+
+/// A property declared by a schema that none of the documents associated with the schema use, as
+/// reported by [`WorkspaceIndex::key_usage`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnusedProperty {
+    /// The schema declaring the property
+    pub schema: PathBuf,
+    pub name: String,
+    /// The path of the declaration in the schema (e.g. `/$defs/server/properties/port`)
+    pub schema_path: KsonPath,
+    /// The start of the key declaring the property in the schema
+    pub start: Position,
+    /// The end of the key declaring the property in the schema
+    pub end: Position,
+}
+
+/// The results of [`WorkspaceIndex::key_usage`]
+#[derive(Clone, Default)]
+pub struct KeyUsage {
+    /// The properties declared by the schemas but used by none of their documents, sorted by schema and
+    /// position
+    pub unused_properties: Vec<UnusedProperty>,
+    /// The property keys of the documents that their schema doesn't declare, with the documents they
+    /// belong to, sorted by document and position
+    pub undeclared_keys: Vec<(PathBuf, SchemaDiagnostic)>,
+}
+
+/// A document of a [`WorkspaceIndex`], along with the results of its checks
+#[derive(Clone)]
+pub struct IndexedDocument {
+    text: String,
+    value: Option<Value>,
+    symbols: Vec<Symbol>,
+    references: Vec<Reference>,
+    schema: Option<PathBuf>,
+    diagnostics: KsonErrors,
+    broken_references: Vec<BrokenReference>,
+}
+
+impl IndexedDocument {
+    /// The text of the document, as last read or edited
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The parsed document, or `None` if it has syntax errors
+    pub fn value(&self) -> Option<&Value> {
+        self.value.as_ref()
+    }
+
+    /// The property keys of the document, in document order
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// The references of the document, in document order
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    /// The schema the project configuration associates the document with, if any
+    pub fn schema(&self) -> Option<&Path> {
+        self.schema.as_deref()
+    }
+
+    /// The problems found by parsing the document and validating it against its schema
+    pub fn diagnostics(&self) -> &KsonErrors {
+        &self.diagnostics
+    }
+
+    /// The references of the document whose target doesn't exist
+    pub fn broken_references(&self) -> &[BrokenReference] {
+        &self.broken_references
+    }
+}
+
+type ParsedSchema = Arc<Schema>;
+
+/// The documents of a workspace (see the [module documentation](self)), keyed by path. Paths are
+/// spelled like the root they were found under, so changes to documents must be applied with paths
+/// spelled the same way (e.g. absolute paths for an absolute root).
+///
+/// Changes to the project configuration are not picked up: they require building a new index.
+pub struct WorkspaceIndex {
+    root: PathBuf,
+    config: ProjectConfig,
+    discovery: FileDiscovery,
+    documents: BTreeMap<PathBuf, IndexedDocument>,
+    /// The schemas parsed so far, or `None` for those that don't parse
+    schemas: HashMap<PathBuf, Option<ParsedSchema>>,
+}
+
+impl WorkspaceIndex {
+    /// Indexes the documents under `root` (as found by [`FileDiscovery`])
+    pub fn build(root: impl Into<PathBuf>, config: ProjectConfig) -> std::io::Result<Self> {
+        let root = root.into();
+        let discovery = FileDiscovery::new(&root);
+        let mut index = Self {
+            root,
+            config,
+            discovery,
+            documents: BTreeMap::new(),
+            schemas: HashMap::new(),
+        };
+        for file in index.discovery.discover()? {
+            let text = read(&file)?;
+            let path = normalize(&file);
+            let document = index.parse(&path, text);
+            index.documents.insert(path, document);
+        }
+
+        let paths: Vec<PathBuf> = index.documents.keys().cloned().collect();
+        for path in &paths {
+            index.check(path);
+            index.resolve_references(path);
+        }
+        Ok(index)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn config(&self) -> &ProjectConfig {
+        &self.config
+    }
+
+    pub fn document(&self, path: &Path) -> Option<&IndexedDocument> {
+        self.documents.get(&normalize(path))
+    }
+
+    /// The indexed documents, sorted by path
+    pub fn documents(&self) -> impl Iterator<Item = (&Path, &IndexedDocument)> {
+        self.documents
+            .iter()
+            .map(|(path, document)| (path.as_path(), document))
+    }
+
+    /// The documents whose results depend on the given file: those referencing it, and those it is the
+    /// schema of
+    pub fn dependents(&self, path: &Path) -> Vec<&Path> {
+        let path = normalize(path);
+        self.documents
+            .iter()
+            .filter(|(_, document)| {
+                document.schema.as_ref() == Some(&path)
+                    || document
+                        .references
+                        .iter()
+                        .any(|reference| reference.target.as_ref() == Some(&path))
+            })
+            .map(|(dependent, _)| dependent.as_path())
+            .collect()
+    }
+
+    /// The property keys of the indexed documents matching the query, e.g. to jump to any key of a
+    /// project in an editor. Best matches come first, and matches of the same quality are sorted by
+    /// document and position.
+    ///
+    /// Keys match when they contain the query, ignoring case, with those equal to the query or starting
+    /// with it ranked first, or when they contain its characters in order (e.g. `dbpw` for
+    /// `db_password`). A query containing `/` is matched against the JSON Pointers of the keys instead
+    /// (e.g. `server/port` for `/services/0/server/port`). An empty query matches every key.
+    pub fn workspace_symbols(&self, query: &str) -> Vec<(&Path, &Symbol)> {
+        let query = query.to_lowercase();
+        let mut matches: Vec<_> = self
+            .documents
+            .iter()
+            .flat_map(|(path, document)| {
+                document
+                    .symbols
+                    .iter()
+                    .map(move |symbol| (path.as_path(), symbol))
+            })
+            .filter_map(|(path, symbol)| {
+                let rank = if query.contains('/') {
+                    symbol
+                        .path
+                        .to_string()
+                        .to_lowercase()
+                        .contains(&query)
+                        .then_some(0)
+                } else {
+                    match_rank(&symbol.name.to_lowercase(), &query)
+                };
+                Some((rank?, path, symbol))
+            })
+            .collect();
+        matches.sort_by_key(|(rank, _, _)| *rank);
+        matches
+            .into_iter()
+            .map(|(_, path, symbol)| (path, symbol))
+            .collect()
+    }
+
+    /// The locations using a key or a definition, sorted by document and position, e.g. to find all the
+    /// configurations to update when renaming a setting. Keys match exactly, paths by glob, and definitions are
+    /// matched by the references whose target resolves to them (see [`crate::references`]). A reference
+    /// to a value containing the definition (e.g. to the whole document) doesn't count as using it.
+    pub fn find_references(&self, query: &ReferenceQuery) -> Vec<Usage> {
+        let mut usages = Vec::new();
+        for (file, document) in &self.documents {
+            let usage = |path: &KsonPath, start: &Position, end: &Position| Usage {
+                file: file.clone(),
+                path: path.clone(),
+                start: start.clone(),
+                end: end.clone(),
+            };
+            match query {
+                ReferenceQuery::Key(key) => usages.extend(
+                    document
+                        .symbols
+                        .iter()
+                        .filter(|symbol| symbol.name == *key)
+                        .map(|symbol| usage(&symbol.path, &symbol.start, &symbol.end)),
+                ),
+                ReferenceQuery::Path(glob) => usages.extend(
+                    document
+                        .symbols
+                        .iter()
+                        .filter(|symbol| symbol.path.matches(glob))
+                        .map(|symbol| usage(&symbol.path, &symbol.start, &symbol.end)),
+                ),
+                ReferenceQuery::Definition { file: target, path } => {
+                    let target = normalize(target);
+                    usages.extend(
+                        document
+                            .references
+                            .iter()
+                            .filter(|reference| {
+                                *reference.target.as_ref().unwrap_or(file) == target
+                                    && reference
+                                        .pointer
+                                        .as_deref()
+                                        .map_or(Ok(KsonPath::root()), KsonPath::parse)
+                                        .is_ok_and(|pointer| pointer.starts_with(path))
+                            })
+                            .map(|reference| {
+                                usage(&reference.path, &reference.start, &reference.end)
+                            }),
+                    )
+                }
+            }
+        }
+        usages
+    }
+
+    /// The values matching the query in the indexed documents, sorted by document and position, e.g.
+    /// for a structural search of a project (`servers[*].port`, `$.**[?(@.enabled == false)]`). The
+    /// locations are those of the matched values, not of their keys. Documents that don't parse are
+    /// left out.
+    pub fn search(&self, query: &PathQuery) -> Vec<Usage> {
+        let mut usages = Vec::new();
+        for (file, document) in &self.documents {
+            // Only the documents with matches are parsed again, to locate them
+            let Some(value) = &document.value else {
+                continue;
+            };
+            if value.query(query).is_empty() {
+                continue;
+            }
+            let Some(root) = Kson::analyze(&document.text, None).kson_value() else {
+                continue;
+            };
+            usages.extend(query.evaluate(root).into_iter().map(|(path, node)| Usage {
+                file: file.clone(),
+                path,
+                start: node.start(),
+                end: node.end(),
+            }));
+        }
+        usages
+    }
+
+    /// Cross-references the properties declared by the schemas of the configuration against the keys
+    /// of the documents associated with them, e.g. to find the settings a schema still documents but
+    /// that no configuration sets anymore, and the keys that no schema describes (often typos).
+    ///
+    /// A property is unused when no document has it where the schema declares it. Properties declared
+    /// by any branch of `anyOf`, `oneOf` and `then`/`else` count as used when a document has them,
+    /// whichever branch applies, and all the properties of a schema that no document is associated with
+    /// are unused. Undeclared keys are found by [`schema::validate_closed_world`], except for those
+    /// whose path matches one of the `exceptions`. Documents without a schema and schemas that don't
+    /// parse are left out.
+    pub fn key_usage(&self, exceptions: &[PointerGlob]) -> KeyUsage {
+        let mut by_schema: BTreeMap<PathBuf, Vec<(&Path, &IndexedDocument)>> = self
+            .config
+            .schemas()
+            .iter()
+            .map(|(_, schema)| (normalize(schema), Vec::new()))
+            .collect();
+        for (path, document) in &self.documents {
+            if let Some(schema) = &document.schema {
+                by_schema
+                    .entry(schema.clone())
+                    .or_default()
+                    .push((path, document));
+            }
+        }
+
+        let mut usage = KeyUsage::default();
+        for (schema_file, documents) in by_schema {
+            let text = match self.documents.get(&schema_file) {
+                Some(document) => document.text.clone(),
+                None => match std::fs::read_to_string(&schema_file) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                },
+            };
+            let Some(schema) = Kson::analyze(&text, None).kson_value() else {
+                continue;
+            };
+
+            let mut used = BTreeSet::new();
+            for (path, document) in documents {
+                if let Some(value) = Kson::analyze(&document.text, None).kson_value() {
+                    used.extend(schema::used_properties(&schema, &value));
+                }
+                usage.undeclared_keys.extend(
+                    schema::validate_closed_world(&text, &document.text, exceptions)
+                        .into_iter()
+                        .filter(|diagnostic| {
+                            matches!(
+                                diagnostic.kind,
+                                SchemaDiagnosticKind::UndeclaredProperty { .. }
+                            )
+                        })
+                        .map(|diagnostic| (path.to_path_buf(), diagnostic)),
+                );
+            }
+            usage.unused_properties.extend(
+                schema::declared_properties(&schema)
+                    .into_iter()
+                    .filter(|(schema_path, _)| !used.contains(schema_path))
+                    .map(|(schema_path, key)| UnusedProperty {
+                        schema: schema_file.clone(),
+                        name: key.value(),
+                        schema_path,
+                        start: key.start(),
+                        end: key.end(),
+                    }),
+            );
+        }
+        usage
+            .undeclared_keys
+            .sort_by(|(a, a_diagnostic), (b, b_diagnostic)| {
+                let position = |diagnostic: &SchemaDiagnostic| {
+                    (diagnostic.start.line(), diagnostic.start.column())
+                };
+                a.cmp(b)
+                    .then_with(|| position(a_diagnostic).cmp(&position(b_diagnostic)))
+            });
+        usage
+    }
+
+    /// Updates the index after a change to a file, returning the documents whose results were
+    /// recomputed, sorted by path. These include the changed file, even when it was deleted, so that
+    /// callers can clear its results.
+    ///
+    /// Files that weren't indexed are added when they are under the root and have the extension of
+    /// documents. Reading a written file may fail, in which case the index is left untouched.
+    pub fn apply(&mut self, change: FileChange) -> std::io::Result<Vec<PathBuf>> {
+        let (path, text) = match change {
+            FileChange::Written(path) => {
+                let text = read(&path)?;
+                (normalize(&path), Some(text))
+            }
+            FileChange::Edited(path, text) => (normalize(&path), Some(text)),
+            FileChange::Deleted(path) => (normalize(&path), None),
+        };
+
+        // The file may be a schema, which is parsed again when next needed
+        self.schemas.remove(&path);
+        match text {
+            Some(text) if self.documents.contains_key(&path) || self.is_indexable(&path) => {
+                let document = self.parse(&path, text);
+                self.documents.insert(path.clone(), document);
+            }
+            Some(_) => {}
+            None => {
+                self.documents.remove(&path);
+            }
+        }
+
+        let mut affected: BTreeSet<PathBuf> = self
+            .dependents(&path)
+            .into_iter()
+            .map(Path::to_path_buf)
+            .collect();
+        affected.insert(path.clone());
+        for dependent in &affected {
+            if !self.documents.contains_key(dependent) {
+                continue;
+            }
+            let schema_changed = self.documents[dependent].schema.as_ref() == Some(&path);
+            if *dependent == path || schema_changed {
+                self.check(dependent);
+            }
+            self.resolve_references(dependent);
+        }
+        Ok(affected.into_iter().collect())
+    }
+
+    fn is_indexable(&self, path: &Path) -> bool {
+        path.starts_with(normalize(&self.root)) && self.discovery.has_extension(path)
+    }
+
+    /// Parses the document, leaving its checks to [`check`](Self::check) and
+    /// [`resolve_references`](Self::resolve_references)
+    fn parse(&self, path: &Path, text: String) -> IndexedDocument {
+        let analysis = Kson::analyze(&text, None);
+        let parsed = analysis.kson_value().filter(|_| {
+            !analysis
+                .errors()
+                .iter()
+                .any(|message| matches!(message.severity(), MessageSeverity::Error))
+        });
+        let (symbols, references) = match &parsed {
+            Some(root) => (
+                symbols_in(root),
+                references_in(path, root, self.config.reference_keys()),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+        IndexedDocument {
+            value: parsed.map(|root| root.to_value()),
+            symbols,
+            references,
+            schema: self.config.schema_for(path).map(normalize),
+            diagnostics: KsonErrors::from_messages(&text, &[]),
+            broken_references: Vec::new(),
+            text,
+        }
+    }
+
+    /// Validates the document against its schema
+    fn check(&mut self, path: &Path) {
+        let schema = self.documents[path]
+            .schema
+            .clone()
+            .and_then(|schema| self.schema(&schema));
+        let document = self.documents.get_mut(path).unwrap();
+        document.diagnostics = check_document(path, &document.text, schema.as_deref());
+    }
+
+    /// Parses the schema at the given path, preferring its indexed text (which may be unsaved) over the
+    /// file
+    fn schema(&mut self, path: &Path) -> Option<ParsedSchema> {
+        if let Some(schema) = self.schemas.get(path) {
+            return schema.clone();
+        }
+        let text = match self.documents.get(path) {
+            Some(document) => Some(document.text.clone()),
+            None => std::fs::read_to_string(path).ok(),
+        };
+        let schema = text.and_then(|text| Some(Arc::new(Schema::parse(&text).ok()?)));
+        self.schemas.insert(path.to_path_buf(), schema.clone());
+        schema
+    }
+
+    /// Checks the targets of the references of the document, looking them up in the index first
+    fn resolve_references(&mut self, path: &Path) {
+        let document = &self.documents[path];
+        let mut broken = Vec::new();
+        if let Some(own) = &document.value {
+            for reference in &document.references {
+                let loaded;
+                let target = match &reference.target {
+                    None => Target::Document(own),
+                    Some(target) => match self.documents.get(target) {
+                        Some(IndexedDocument {
+                            value: Some(value), ..
+                        }) => Target::Document(value),
+                        Some(_) => Target::Invalid,
+                        None => {
+                            loaded = load(target);
+                            loaded.target()
+                        }
+                    },
+                };
+                broken.extend(check_reference(path, reference, target));
+            }
+        }
+        self.documents.get_mut(path).unwrap().broken_references = broken;
+    }
+}
+
+/// The property keys of the document, in document order
+fn symbols_in(root: &KsonValue) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    let mut pending = vec![(KsonPath::root(), root.clone())];
+    while let Some((path, value)) = pending.pop() {
+        if let KsonValue::KsonObject(object) = &value {
+            for (name, key) in object.property_keys() {
+                symbols.push(Symbol {
+                    path: path.clone().key(name.clone()),
+                    name,
+                    start: key.start(),
+                    end: key.end(),
+                });
+            }
+        }
+        pending.extend(
+            value
+                .children()
+                .into_iter()
+                .map(|(segment, child)| (path.clone().join(segment), child)),
+        );
+    }
+    symbols.sort_by_key(|symbol| (symbol.start.line(), symbol.start.column()));
+    symbols
+}
+
+/// How well the lowercase key matches the lowercase query (lower is better), or `None` if it doesn't
+fn match_rank(key: &str, query: &str) -> Option<u8> {
+    if key == query {
+        Some(0)
+    } else if key.starts_with(query) {
+        Some(1)
+    } else if key.contains(query) {
+        Some(2)
+    } else {
+        let mut chars = key.chars();
+        query
+            .chars()
+            .all(|wanted| chars.any(|c| c == wanted))
+            .then_some(3)
+    }
+}
@@ -0,0 +1,255 @@
+//! Conversions between KSON values and [`serde_json::Value`]s, enabled through the `serde_json` feature.
+//!
+//! [`KsonValue`]s are views of documents parsed by kson-lib and can't be built from Rust, so JSON values
+//! convert into owned [`Value`]s, which can be rendered as KSON or compared with parsed documents. The
+//! other way around, both [`KsonValue`]s and [`Value`]s convert into JSON values:
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//! use kson_rs::value::Value;
+//!
+//! let document = Kson::analyze("name: kson\ntags: [fast, small]", None).kson_value().unwrap();
+//! let json = serde_json::Value::try_from(&document).unwrap();
+//! assert_eq!(json["tags"][1], "small");
+//! assert_eq!(Value::from(json), document.to_value());
+//! ```
+//!
+//! Embed blocks become strings holding their content (their tags are dropped, like in
+//! [`Value::to_json`]), and JSON numbers that don't fit in an `i64` become decimals, as do
+//! [`Value::BigInteger`]s (or `null` past the range of an `f64`). Non-finite decimals
+//! fail the conversion of a [`KsonValue`] with a [`JsonError`], and become `null` in the infallible
+//! conversion of a [`Value`]. Values of any depth convert without recursing, and
+//! [`Value::from_json_with`] rejects JSON nested deeper than a [`MaxDepth`]. Objects keep the order of
+//! their properties unless `serde_json` is built without its `preserve_order` feature.
+
+use crate::KsonValue;
+use crate::depth::{DepthError, MaxDepth};
+use crate::path::{KsonPath, PathSegment};
+use crate::value::{Builder, Step, Value};
+
+/// The error returned when a value can't be represented in JSON
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonError {
+    /// The path of the non-finite decimal
+    pub path: KsonPath,
+    pub value: f64,
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the value at `{}` is {}, which JSON can't represent",
+            self.path, self.value
+        )
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+impl From<&serde_json::Value> for Value {
+    fn from(json: &serde_json::Value) -> Self {
+        match from_json(json, MaxDepth::new(usize::MAX)) {
+            Ok(value) => value,
+            Err(_) => unreachable!("no value is nested deeper than usize::MAX levels"),
+        }
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Self {
+        Value::from(&json)
+    }
+}
+
+impl Value {
+    /// Converts a JSON value like [`Value::from`] does, failing if it nests deeper than `max_depth`
+    pub fn from_json_with(
+        json: &serde_json::Value,
+        max_depth: MaxDepth,
+    ) -> Result<Value, DepthError> {
+        from_json(json, max_depth)
+    }
+}
+
+/// Embed blocks become strings holding their content, and non-finite decimals (which JSON can't represent)
+/// become `null`, like in [`Value::to_json`]
+impl From<&Value> for serde_json::Value {
+    fn from(value: &Value) -> Self {
+        match to_json(value, false) {
+            Ok(json) => json,
+            Err(_) => unreachable!("only strict conversions fail"),
+        }
+    }
+}
+
+impl TryFrom<&KsonValue> for serde_json::Value {
+    type Error = JsonError;
+
+    fn try_from(value: &KsonValue) -> Result<Self, JsonError> {
+        to_json(&value.to_value(), true)
+    }
+}
+
+impl TryFrom<KsonValue> for serde_json::Value {
+    type Error = JsonError;
+
+    fn try_from(value: KsonValue) -> Result<Self, JsonError> {
+        serde_json::Value::try_from(&value)
+    }
+}
+
+fn from_json(json: &serde_json::Value, max_depth: MaxDepth) -> Result<Value, DepthError> {
+    /// The children of a JSON array or object left to convert
+    enum Children<'a> {
+        Elements(std::iter::Enumerate<std::slice::Iter<'a, serde_json::Value>>),
+        Properties(serde_json::map::Iter<'a>),
+    }
+
+    // The arrays and objects being converted, innermost last, rather than a recursion through them
+    let mut open = Vec::new();
+    let mut builder = Builder::default();
+    let mut path = KsonPath::root();
+    let mut entering = Some(json);
+    loop {
+        if let Some(json) = entering.take() {
+            if !max_depth.allows(path.len()) {
+                return Err(DepthError { max_depth, path });
+            }
+            let key = match path.last() {
+                Some(PathSegment::Key(key)) => Some(key.clone()),
+                _ => None,
+            };
+            let scalar = match json {
+                serde_json::Value::Null => Value::Null,
+                serde_json::Value::Bool(boolean) => Value::Bool(*boolean),
+                serde_json::Value::Number(number) => match number.as_i64() {
+                    Some(integer) => Value::Integer(integer),
+                    None => Value::Decimal(number.as_f64().unwrap_or(f64::NAN)),
+                },
+                serde_json::Value::String(string) => Value::String(string.clone()),
+                serde_json::Value::Array(elements) => {
+                    builder.open_array(key, elements.len());
+                    open.push(Children::Elements(elements.iter().enumerate()));
+                    continue;
+                }
+                serde_json::Value::Object(properties) => {
+                    builder.open_object(key, properties.len());
+                    open.push(Children::Properties(properties.iter()));
+                    continue;
+                }
+            };
+            if let Some(value) = builder.push(key, scalar) {
+                return Ok(value);
+            }
+            path.pop();
+            continue;
+        }
+
+        let child = match open.last_mut() {
+            Some(Children::Elements(elements)) => elements
+                .next()
+                .map(|(index, element)| (PathSegment::Index(index), element)),
+            Some(Children::Properties(properties)) => properties
+                .next()
+                .map(|(key, value)| (PathSegment::Key(key.clone()), value)),
+            None => unreachable!("the root is returned once converted"),
+        };
+        match child {
+            Some((segment, child)) => {
+                path.push(segment);
+                entering = Some(child);
+            }
+            None => {
+                open.pop();
+                if let Some(value) = builder.close() {
+                    return Ok(value);
+                }
+                path.pop();
+            }
+        }
+    }
+}
+
+/// Converts a value into JSON without recursing. Non-finite decimals fail the conversion if `strict`,
+/// and become `null` otherwise.
+fn to_json(value: &Value, strict: bool) -> Result<serde_json::Value, JsonError> {
+    // The arrays and objects being converted, innermost last, with the segment leading to them
+    let mut open: Vec<(Option<PathSegment>, serde_json::Value)> = Vec::new();
+    for step in value.walk() {
+        let segment = step.segment();
+        let (segment, json) = match step {
+            Step::Enter {
+                value: Value::Array(elements),
+                ..
+            } => {
+                let array = Vec::with_capacity(elements.len());
+                open.push((segment, serde_json::Value::Array(array)));
+                continue;
+            }
+            Step::Enter {
+                value: Value::Object(_),
+                ..
+            } => {
+                open.push((segment, serde_json::Value::Object(serde_json::Map::new())));
+                continue;
+            }
+            Step::Enter {
+                value: Value::BigInteger(integer),
+                ..
+            } => (
+                segment,
+                serde_json::Number::from_f64(integer.to_f64())
+                    .map_or(serde_json::Value::Null, serde_json::Value::Number),
+            ),
+            Step::Enter {
+                value: Value::Decimal(decimal),
+                ..
+            } => match serde_json::Number::from_f64(*decimal) {
+                Some(number) if decimal.is_finite() => (segment, serde_json::Value::Number(number)),
+                _ if !strict => (segment, serde_json::Value::Null),
+                _ => {
+                    let path = open
+                        .iter()
+                        .filter_map(|(segment, _)| segment.clone())
+                        .chain(segment)
+                        .fold(KsonPath::root(), KsonPath::join);
+                    return Err(JsonError {
+                        path,
+                        value: *decimal,
+                    });
+                }
+            },
+            Step::Enter { value, .. } => (
+                segment,
+                match value {
+                    Value::Bool(boolean) => serde_json::Value::Bool(*boolean),
+                    Value::Integer(integer) => serde_json::Value::Number((*integer).into()),
+                    Value::String(string)
+                    | Value::Embed {
+                        content: string, ..
+                    } => serde_json::Value::String(string.clone()),
+                    _ => serde_json::Value::Null,
+                },
+            ),
+            Step::Leave {
+                value: Value::Array(_) | Value::Object(_),
+                ..
+            } => open
+                .pop()
+                .expect("arrays and objects are left after being entered"),
+            Step::Leave { .. } => continue,
+        };
+        match open.last_mut() {
+            None => return Ok(json),
+            Some((_, serde_json::Value::Array(elements))) => elements.push(json),
+            Some((_, serde_json::Value::Object(properties))) => {
+                if let Some(PathSegment::Key(key)) = segment {
+                    properties.insert(key, json);
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    unreachable!("the walk ends by leaving the root")
+}
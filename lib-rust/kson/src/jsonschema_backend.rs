@@ -0,0 +1,109 @@
+//! Validation with the [`jsonschema`](https://docs.rs/jsonschema) crate, enabled through the
+//! `jsonschema` feature.
+//!
+//! [`JsonSchemaBackend`] is an alternative to [`SchemaValidator`](crate::SchemaValidator) for schemas
+//! relying on drafts or keywords that kson-lib doesn't support. Values are converted to `serde_json`
+//! values to be validated, and violations are reported as [`SchemaDiagnostic`]s pointing at the
+//! offending values in the document.
+
+use crate::path::KsonPath;
+use crate::query::QueryNode;
+use crate::schema::{SchemaDiagnostic, SchemaDiagnosticKind};
+use crate::value::Value;
+use crate::{Kson, KsonValue, MessageSeverity};
+
+/// The error returned when a schema can't be compiled
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonSchemaError {
+    /// The schema is not valid KSON
+    Parse(Vec<String>),
+    /// The schema was rejected by the `jsonschema` crate
+    InvalidSchema(String),
+}
+
+impl std::fmt::Display for JsonSchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonSchemaError::Parse(errors) => {
+                write!(f, "the schema is not valid KSON: {}", errors.join("; "))
+            }
+            JsonSchemaError::InvalidSchema(error) => write!(f, "invalid schema: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for JsonSchemaError {}
+
+/// A schema compiled by the `jsonschema` crate (see the [module documentation](self))
+pub struct JsonSchemaBackend {
+    validator: jsonschema::Validator,
+}
+
+impl JsonSchemaBackend {
+    /// Compiles a schema written in KSON (or JSON), for the draft named by its `$schema` keyword (the
+    /// latest one the `jsonschema` crate supports if there is none)
+    pub fn parse(schema: &str) -> Result<Self, JsonSchemaError> {
+        let analysis = Kson::analyze(schema, None);
+        let errors: Vec<String> = analysis
+            .errors()
+            .into_iter()
+            .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+            .map(|message| message.message())
+            .collect();
+        match analysis.kson_value() {
+            Some(schema) if errors.is_empty() => Self::from_value(&schema.to_value()),
+            _ => Err(JsonSchemaError::Parse(errors)),
+        }
+    }
+
+    /// Compiles a schema, for the draft named by its `$schema` keyword
+    pub fn from_value(schema: &Value) -> Result<Self, JsonSchemaError> {
+        jsonschema::validator_for(&schema.into())
+            .map(|validator| Self { validator })
+            .map_err(|error| JsonSchemaError::InvalidSchema(error.to_string()))
+    }
+
+    /// Compiles a schema with the given options, e.g. to force a draft or enable format validation
+    pub fn with_options(
+        schema: &Value,
+        options: &jsonschema::ValidationOptions,
+    ) -> Result<Self, JsonSchemaError> {
+        options
+            .build(&schema.into())
+            .map(|validator| Self { validator })
+            .map_err(|error| JsonSchemaError::InvalidSchema(error.to_string()))
+    }
+
+    pub fn is_valid(&self, value: &Value) -> bool {
+        self.validator.is_valid(&value.into())
+    }
+
+    /// Validates a parsed document, returning a diagnostic for each violation
+    pub fn validate(&self, document: &KsonValue) -> Vec<SchemaDiagnostic> {
+        let instance: serde_json::Value = (&document.to_value()).into();
+        self.validator
+            .iter_errors(&instance)
+            .map(|error| {
+                let path = KsonPath::parse(&error.instance_path.to_string()).unwrap_or_default();
+                let schema_path =
+                    KsonPath::parse(&error.schema_path.to_string()).unwrap_or_default();
+                // Fall back to the whole document if the path can't be followed
+                let mut value = document.clone();
+                for segment in path.segments() {
+                    match value.child(segment) {
+                        Some((_, child)) => value = child,
+                        None => break,
+                    }
+                }
+                SchemaDiagnostic {
+                    message: error.to_string(),
+                    severity: MessageSeverity::Warning,
+                    start: value.start(),
+                    end: value.end(),
+                    path,
+                    kind: SchemaDiagnosticKind::Violation { schema_path },
+                }
+            })
+            .collect()
+    }
+}
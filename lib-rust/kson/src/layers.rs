@@ -0,0 +1,442 @@
+//! Layered configuration: documents merged in order (e.g. defaults, then environment overrides, then
+//! local overrides) with the source locations of their values.
+//!
+//! [`merge_layers`] merges each [`Layer`] into the previous ones with [`Value::merge`]. A layer that
+//! changes the shape of a value, e.g. sets to a string what an earlier layer defines as an object, would
+//! silently clobber everything below it, so it's reported as a [`ShapeConflict`] locating the value in
+//! both layers:
+//!
+//! ```no_run
+//! use kson_rs::layers::{Layer, merge_layers};
+//! use kson_rs::merge::MergeStrategy;
+//!
+//! let layers = [
+//!     Layer::parse("defaults.kson", "server: { host: localhost, port: 80 }").unwrap(),
+//!     Layer::parse("production.kson", "server: 'api.example.com:443'").unwrap(),
+//! ];
+//! if let Err(conflicts) = merge_layers(&layers, MergeStrategy::default()) {
+//!     for conflict in conflicts {
+//!         // `/server` is an object in defaults.kson at line 1, column 9, but a scalar in
+//!         // production.kson at line 1, column 9
+//!         eprintln!("{conflict}");
+//!     }
+//! }
+//! ```
+//!
+//! `null` is not a shape of its own: a layer setting a value to `null` resets it, or deletes it with
+//! [`MergeStrategy::null_deletes`].
+//!
+//! A merged value can be valid in every layer but not once merged, or the other way around, so it's
+//! validated once merged. [`trace_layers`] keeps track of the layer each value comes from, and
+//! [`Merged::validate`] locates each schema violation in the layer that set the offending value:
+//!
+//! ```no_run
+//! use kson_rs::layers::{Layer, trace_layers};
+//! use kson_rs::merge::MergeStrategy;
+//!
+//! # let schema = kson_rs::schema::Schema::parse("type: object").ok().unwrap();
+//! let layers = [
+//!     Layer::parse("defaults.kson", "server: { host: localhost, port: 80 }").unwrap(),
+//!     Layer::parse("production.kson", "server: { port: 'https' }").unwrap(),
+//! ];
+//! let merged = trace_layers(&layers, MergeStrategy::default()).unwrap();
+//! for violation in merged.validate(&schema) {
+//!     // production.kson at line 1, column 17: ...
+//!     eprintln!("{violation}");
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::error::KsonErrors;
+use crate::line_index::LineIndex;
+use crate::merge::{ListMerge, MergeStrategy, ObjectMerge};
+use crate::path::{KsonPath, PathSegment};
+use crate::schema::Schema;
+use crate::span::Span;
+use crate::value::Value;
+use crate::{Kson, KsonValue, Message, MessageSeverity};
+
+/// A document to merge with [`merge_layers`], with where it comes from
+#[derive(Clone, Debug)]
+pub struct Layer {
+    source: String,
+    value: Value,
+    spans: BTreeMap<KsonPath, Span>,
+}
+
+impl Layer {
+    /// Parses a layer, named after its `source` (e.g. the path of its file) in diagnostics
+    pub fn parse(source: impl Into<String>, document: &str) -> Result<Self, KsonErrors> {
+        let analysis = Kson::analyze(document, None);
+        let errors: Vec<Message> = analysis
+            .errors()
+            .into_iter()
+            .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+            .collect();
+        match analysis.kson_value() {
+            Some(root) if errors.is_empty() => Ok(Self::new(source, &root, document)),
+            _ => Err(KsonErrors::from_messages(document, &errors)),
+        }
+    }
+
+    /// A layer of an already parsed document, `document` being the text it was parsed from
+    pub fn new(source: impl Into<String>, value: &KsonValue, document: &str) -> Self {
+        Self {
+            source: source.into(),
+            value: value.to_value(),
+            spans: value.spans(document),
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+}
+
+/// The shape of a value, which layers may not change
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Object,
+    Array,
+    Scalar,
+}
+
+impl Shape {
+    /// The shape of the value, or `None` for `null`, which fits any shape
+    fn of(value: &Value) -> Option<Shape> {
+        match value {
+            Value::Null => None,
+            Value::Object(_) => Some(Shape::Object),
+            Value::Array(_) => Some(Shape::Array),
+            _ => Some(Shape::Scalar),
+        }
+    }
+}
+
+impl std::fmt::Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Shape::Object => "an object",
+            Shape::Array => "an array",
+            Shape::Scalar => "a scalar",
+        })
+    }
+}
+
+/// Where a layer defines a value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    /// The source of the layer
+    pub source: String,
+    /// The path of the value in the layer, which differs from its path once merged for elements
+    /// appended to an array
+    pub path: KsonPath,
+    pub span: Span,
+    pub shape: Shape,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.source,
+            self.span.start.line + 1,
+            self.span.start.column + 1
+        )
+    }
+}
+
+/// A value that a layer defines with another shape than the layer it overrides (see the
+/// [module documentation](self))
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShapeConflict {
+    /// The path of the value once merged
+    pub path: KsonPath,
+    pub earlier: Location,
+    pub later: Location,
+}
+
+impl std::fmt::Display for ShapeConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` is {} in {}, but {} in {}",
+            self.path, self.earlier.shape, self.earlier, self.later.shape, self.later
+        )
+    }
+}
+
+/// Merges the layers in order, or returns the values whose shape a layer changes. The layers are still
+/// all merged to find the conflicts, so there is one for each layer changing the shape of a value.
+pub fn merge_layers(
+    layers: &[Layer],
+    strategy: MergeStrategy,
+) -> Result<Value, Vec<ShapeConflict>> {
+    trace_layers(layers, strategy).map(|merged| merged.value)
+}
+
+/// Like [`merge_layers`], also keeping track of the layer each value of the result comes from, e.g. to
+/// [validate](Merged::validate) it
+pub fn trace_layers(
+    layers: &[Layer],
+    strategy: MergeStrategy,
+) -> Result<Merged<'_>, Vec<ShapeConflict>> {
+    let mut merging = Merging {
+        layers,
+        strategy,
+        origins: BTreeMap::new(),
+        conflicts: Vec::new(),
+    };
+    let Some((first, rest)) = layers.split_first() else {
+        return Ok(Merged {
+            value: Value::Null,
+            layers,
+            origins: merging.origins,
+        });
+    };
+    merging.record(&KsonPath::root(), 0, &mut KsonPath::root(), &first.value);
+    let mut merged = first.value.clone();
+    for (layer_index, layer) in rest.iter().enumerate() {
+        merging.walk(
+            &merged,
+            &mut KsonPath::root(),
+            layer_index + 1,
+            &mut KsonPath::root(),
+            &layer.value,
+        );
+        merged.merge(layer.value.clone(), strategy);
+    }
+    if merging.conflicts.is_empty() {
+        Ok(Merged {
+            value: merged,
+            layers,
+            origins: merging.origins,
+        })
+    } else {
+        Err(merging.conflicts)
+    }
+}
+
+/// The result of [`trace_layers`]
+#[derive(Clone, Debug)]
+pub struct Merged<'a> {
+    pub value: Value,
+    layers: &'a [Layer],
+    /// The layer and path in that layer each value of the merged value comes from, by merged path
+    origins: BTreeMap<KsonPath, (usize, KsonPath)>,
+}
+
+/// A schema violation of a merged value (see [`Merged::validate`])
+#[derive(Clone)]
+pub struct LayerViolation {
+    pub message: String,
+    pub severity: MessageSeverity,
+    /// The path of the offending value in the merged value
+    pub path: KsonPath,
+    /// Where the offending value was set, `None` only if there are no layers
+    pub location: Option<Location>,
+}
+
+impl std::fmt::Display for LayerViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{location}: {}", self.message),
+            None => write!(f, "`{}`: {}", self.path, self.message),
+        }
+    }
+}
+
+impl Merged<'_> {
+    /// Where the layers set the value at `path` of the merged value, or the closest of its ancestors
+    /// that they set
+    pub fn origin(&self, path: &KsonPath) -> Option<Location> {
+        let mut path = path.clone();
+        loop {
+            if let Some((layer, layer_path)) = self.origins.get(&path) {
+                let layer = &self.layers[*layer];
+                let shape = layer
+                    .value
+                    .get_path(layer_path)
+                    .and_then(Shape::of)
+                    .unwrap_or(Shape::Scalar);
+                return Some(location_in(layer, layer_path, shape));
+            }
+            path = path.parent()?;
+        }
+    }
+
+    /// Validates the merged value against the schema, formats included (see [`Schema`]), locating each
+    /// violation in the layer that set the offending value, so that it gets fixed in the right document
+    pub fn validate(&self, schema: &Schema) -> Vec<LayerViolation> {
+        let document = self.value.to_kson();
+        let messages = schema.validator().validate(&document, None);
+        let Some(root) = Kson::analyze(&document, None).kson_value() else {
+            return Vec::new();
+        };
+        let spans = root.spans(&document);
+        let index = LineIndex::new(&document);
+        messages
+            .into_iter()
+            .map(|message| {
+                // The innermost value the message starts in
+                let offset = index.offset_of(&message.start());
+                let path = spans
+                    .iter()
+                    .filter(|(_, span)| span.start.offset <= offset && offset <= span.end.offset)
+                    .min_by_key(|(_, span)| span.end.offset - span.start.offset)
+                    .map_or_else(KsonPath::root, |(path, _)| path.clone());
+                LayerViolation {
+                    message: message.message(),
+                    severity: message.severity(),
+                    location: self.origin(&path),
+                    path,
+                }
+            })
+            .chain(
+                schema
+                    .format_diagnostics(&document)
+                    .into_iter()
+                    .map(|diagnostic| LayerViolation {
+                        message: diagnostic.message,
+                        severity: diagnostic.severity,
+                        location: self.origin(&diagnostic.path),
+                        path: diagnostic.path,
+                    }),
+            )
+            .collect()
+    }
+}
+
+struct Merging<'a> {
+    layers: &'a [Layer],
+    strategy: MergeStrategy,
+    /// The layer and path in that layer each value of the merged value comes from, by merged path
+    origins: BTreeMap<KsonPath, (usize, KsonPath)>,
+    conflicts: Vec<ShapeConflict>,
+}
+
+impl Merging<'_> {
+    /// Walks the value of a layer along the value merged so far, as [`Value::merge`] will merge it,
+    /// recording conflicts and where the values it sets come from
+    fn walk(
+        &mut self,
+        merged: &Value,
+        merged_path: &mut KsonPath,
+        layer: usize,
+        layer_path: &mut KsonPath,
+        value: &Value,
+    ) {
+        if self.strategy.null_deletes && matches!(value, Value::Null) {
+            return;
+        }
+        if let (Some(earlier), Some(later)) = (Shape::of(merged), Shape::of(value))
+            && earlier != later
+            && let Some(earlier) = self.location(merged_path, earlier)
+        {
+            self.conflicts.push(ShapeConflict {
+                path: merged_path.clone(),
+                earlier,
+                later: self.location_in(layer, layer_path, later),
+            });
+        }
+        match (merged, value) {
+            (Value::Object(merged), Value::Object(properties))
+                if self.strategy.objects == ObjectMerge::Deep =>
+            {
+                for (key, property) in properties.iter() {
+                    merged_path.push(PathSegment::Key(key.clone()));
+                    layer_path.push(PathSegment::Key(key.clone()));
+                    match merged.get(key) {
+                        Some(existing) => {
+                            self.walk(existing, merged_path, layer, layer_path, property)
+                        }
+                        None => self.record(merged_path, layer, layer_path, property),
+                    }
+                    layer_path.pop();
+                    merged_path.pop();
+                }
+            }
+            (Value::Array(merged), Value::Array(elements))
+                if self.strategy.lists == ListMerge::Append =>
+            {
+                for (element_index, element) in elements.iter().enumerate() {
+                    merged_path.push(PathSegment::Index(merged.len() + element_index));
+                    layer_path.push(PathSegment::Index(element_index));
+                    self.record(merged_path, layer, layer_path, element);
+                    layer_path.pop();
+                    merged_path.pop();
+                }
+            }
+            _ => self.record(merged_path, layer, layer_path, value),
+        }
+    }
+
+    /// Records that the value at `merged_path`, and everything in it, now comes from the layer
+    fn record(
+        &mut self,
+        merged_path: &KsonPath,
+        layer: usize,
+        layer_path: &mut KsonPath,
+        value: &Value,
+    ) {
+        self.origins
+            .retain(|path, _| !path.starts_with(merged_path));
+        self.record_value(&mut merged_path.clone(), layer, layer_path, value);
+    }
+
+    fn record_value(
+        &mut self,
+        merged_path: &mut KsonPath,
+        layer: usize,
+        layer_path: &mut KsonPath,
+        value: &Value,
+    ) {
+        self.origins
+            .insert(merged_path.clone(), (layer, layer_path.clone()));
+        let children: Vec<(PathSegment, &Value)> = match value {
+            Value::Object(properties) => properties
+                .iter()
+                .map(|(key, property)| (PathSegment::Key(key.clone()), property))
+                .collect(),
+            Value::Array(elements) => elements
+                .iter()
+                .enumerate()
+                .map(|(index, element)| (PathSegment::Index(index), element))
+                .collect(),
+            _ => Vec::new(),
+        };
+        for (segment, child) in children {
+            merged_path.push(segment.clone());
+            layer_path.push(segment);
+            self.record_value(merged_path, layer, layer_path, child);
+            layer_path.pop();
+            merged_path.pop();
+        }
+    }
+
+    /// Where the value at the merged path was defined
+    fn location(&self, merged_path: &KsonPath, shape: Shape) -> Option<Location> {
+        let (layer, layer_path) = self.origins.get(merged_path)?;
+        Some(self.location_in(*layer, layer_path, shape))
+    }
+
+    fn location_in(&self, layer: usize, layer_path: &KsonPath, shape: Shape) -> Location {
+        location_in(&self.layers[layer], layer_path, shape)
+    }
+}
+
+/// Where the layer defines the value at the path. The spans of a layer cover all of its values.
+fn location_in(layer: &Layer, layer_path: &KsonPath, shape: Shape) -> Location {
+    Location {
+        source: layer.source.clone(),
+        path: layer_path.clone(),
+        span: layer.spans[layer_path],
+        shape,
+    }
+}
@@ -0,0 +1,153 @@
+//! Lazy access to large documents.
+//!
+//! Converting a parsed document into a [`Value`] copies every node out of kson-lib. [`LazyDocument`]
+//! instead keeps the parsed tree on the kson-lib side and only materializes the subtrees reached through
+//! its pointer and query APIs, caching them, which pays off when reading a handful of keys from a huge
+//! document.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::path::{KsonPath, PathSegment};
+use crate::query::{PathQuery, QueryNode};
+use crate::value::{Value, sorted_property_keys};
+use crate::{Kson, KsonValue, Message, MessageSeverity};
+
+/// A parsed document whose subtrees are materialized on first access
+pub struct LazyDocument {
+    root: KsonValue,
+    /// The subtrees materialized so far, by path
+    cache: RefCell<HashMap<KsonPath, Value>>,
+}
+
+impl LazyDocument {
+    /// Parses the input, or returns the errors that prevented parsing it
+    pub fn parse(input: &str) -> Result<Self, Vec<Message>> {
+        let analysis = Kson::analyze(input, None);
+        match analysis.kson_value() {
+            Some(root) => Ok(Self::from(root)),
+            None => Err(analysis
+                .errors()
+                .into_iter()
+                .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+                .collect()),
+        }
+    }
+
+    /// Returns the value at the given path, if there is one
+    pub fn get_path(&self, path: &KsonPath) -> Option<Value> {
+        if let Some(value) = self.cached(path) {
+            return Some(value);
+        }
+
+        let mut node = self.root.clone();
+        for segment in path.segments() {
+            node = node.child(segment)?.1;
+        }
+        Some(self.materialize(path, &node))
+    }
+
+    /// Returns the value at the given JSON Pointer, or `None` if there is none or the pointer is malformed
+    pub fn pointer(&self, pointer: &str) -> Option<Value> {
+        self.get_path(&KsonPath::parse(pointer).ok()?)
+    }
+
+    /// Returns every value matched by the query, along with its path, in document order. Only the
+    /// matched values (and those compared by filters) are materialized.
+    pub fn query(&self, query: &PathQuery) -> Vec<(KsonPath, Value)> {
+        query
+            .evaluate(self.root.clone())
+            .into_iter()
+            .map(|(path, node)| {
+                let value = self
+                    .cached(&path)
+                    .unwrap_or_else(|| self.materialize(&path, &node));
+                (path, value)
+            })
+            .collect()
+    }
+
+    /// Whether the value at the given path has been materialized, on its own or as part of an ancestor
+    pub fn is_materialized(&self, path: &KsonPath) -> bool {
+        self.cached(path).is_some()
+    }
+
+    /// Looks for the value in the cache, either directly or inside a cached ancestor
+    fn cached(&self, path: &KsonPath) -> Option<Value> {
+        let cache = self.cache.borrow();
+        let segments = path.segments();
+        (0..=segments.len()).rev().find_map(|length| {
+            let ancestor: KsonPath = segments[..length].iter().cloned().collect();
+            let relative: KsonPath = segments[length..].iter().cloned().collect();
+            cache.get(&ancestor)?.get_path(&relative).cloned()
+        })
+    }
+
+    fn materialize(&self, path: &KsonPath, node: &KsonValue) -> Value {
+        let value = node.to_value();
+        self.cache.borrow_mut().insert(path.clone(), value.clone());
+        value
+    }
+}
+
+impl From<KsonValue> for LazyDocument {
+    fn from(root: KsonValue) -> Self {
+        Self {
+            root,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl QueryNode for KsonValue {
+    fn child(&self, segment: &PathSegment) -> Option<(PathSegment, Self)> {
+        match (self, segment) {
+            (KsonValue::KsonObject(object), PathSegment::Key(key)) => {
+                Some((segment.clone(), object.properties().remove(key)?))
+            }
+            (KsonValue::KsonObject(object), PathSegment::Index(index)) => {
+                let key = index.to_string();
+                let value = object.properties().remove(&key)?;
+                Some((PathSegment::Key(key), value))
+            }
+            (KsonValue::KsonArray(array), PathSegment::Index(index)) => {
+                Some((segment.clone(), array.elements().into_iter().nth(*index)?))
+            }
+            (KsonValue::KsonArray(array), PathSegment::Key(key)) => {
+                let index = key.parse().ok()?;
+                Some((
+                    PathSegment::Index(index),
+                    array.elements().into_iter().nth(index)?,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn children(&self) -> Vec<(PathSegment, Self)> {
+        match self {
+            KsonValue::KsonArray(array) => array
+                .elements()
+                .into_iter()
+                .enumerate()
+                .map(|(index, element)| (PathSegment::Index(index), element))
+                .collect(),
+            KsonValue::KsonObject(object) => {
+                let mut properties = object.properties();
+                sorted_property_keys(object)
+                    .into_iter()
+                    .filter_map(|(name, _)| {
+                        let value = properties.remove(&name)?;
+                        Some((PathSegment::Key(name), value))
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn to_value(&self) -> Cow<'_, Value> {
+        Cow::Owned(KsonValue::to_value(self))
+    }
+}
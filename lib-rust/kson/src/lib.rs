@@ -1,6 +1,70 @@
 mod generated;
 #[cfg(test)]
 mod test;
+pub mod ansi;
+pub mod borrowed;
+pub mod cache;
+pub mod cancel;
+pub mod canonical;
+pub mod comments;
+#[cfg(feature = "workspace")]
+pub mod config;
+pub mod conversion;
+pub mod coverage;
+#[cfg(feature = "serde")]
+pub mod de;
+pub mod depth;
+pub mod diagnostics;
+#[cfg(feature = "digest")]
+pub mod digest;
+pub mod dialect;
+#[cfg(test)]
+mod differential;
+pub mod document;
+pub mod embed;
+pub mod error;
+pub mod format;
+#[cfg(feature = "uuid")]
+mod ids;
+#[cfg(feature = "workspace")]
+pub mod index;
+#[cfg(feature = "serde_json")]
+pub mod json;
+#[cfg(feature = "jsonschema")]
+pub mod jsonschema_backend;
+pub mod layers;
+pub mod lazy;
+pub mod line_index;
+#[doc(hidden)]
+pub mod macros;
+pub mod merge;
+pub mod metrics;
+pub mod mutation;
+pub mod numbers;
+pub mod patch;
+pub mod path;
+pub mod pointer;
+#[cfg(feature = "workspace")]
+pub mod progress;
+pub mod query;
+#[cfg(feature = "workspace")]
+pub mod references;
+#[cfg(feature = "http")]
+pub mod remote;
+pub mod roundtrip;
+pub mod schema;
+pub mod secrets;
+#[cfg(feature = "serde")]
+pub mod ser;
+pub mod shared;
+pub mod span;
+#[cfg(feature = "store")]
+pub mod store;
+pub mod template;
+pub mod units;
+pub mod value;
+#[cfg(feature = "workspace")]
+pub mod workspace;
 
 pub use generated::*;
 
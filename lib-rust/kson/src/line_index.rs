@@ -0,0 +1,71 @@
+//! Conversion of the line/column positions reported by kson-lib into byte offsets.
+//!
+//! Columns count UTF-16 code units, since they come from the Kotlin side.
+
+use crate::Position;
+
+/// The lines of a document, to turn the positions kson-lib reports in it into byte offsets. Building it
+/// scans the document once, so tools locating several values share one index.
+pub struct LineIndex<'a> {
+    text: &'a str,
+    /// Byte offset at which each line starts
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        Self { text, line_starts }
+    }
+
+    /// Returns the byte offset of the given zero-based line and UTF-16 column, clamped to the end of the
+    /// line (or of the text)
+    pub(crate) fn offset(&self, line: usize, utf16_column: usize) -> usize {
+        let Some(&line_start) = self.line_starts.get(line) else {
+            return self.text.len();
+        };
+        let line_end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.text.len(), |next| next - 1);
+
+        let mut remaining = utf16_column;
+        for (offset, c) in self.text[line_start..line_end].char_indices() {
+            if remaining == 0 {
+                return line_start + offset;
+            }
+            remaining = remaining.saturating_sub(c.len_utf16());
+        }
+        line_end
+    }
+
+    pub(crate) fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns the text of the given zero-based line, without its line break
+    pub(crate) fn line(&self, line: usize) -> &'a str {
+        let start = self.offset(line, 0);
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.text.len(), |next| next - 1);
+        &self.text[start..end.max(start)]
+    }
+
+    pub(crate) fn offset_of(&self, position: &Position) -> usize {
+        self.offset(
+            position.line().max(0) as usize,
+            position.column().max(0) as usize,
+        )
+    }
+
+    /// Returns the text between the two positions
+    pub(crate) fn slice(&self, start: &Position, end: &Position) -> &'a str {
+        let start = self.offset_of(start);
+        let end = self.offset_of(end).max(start);
+        &self.text[start..end]
+    }
+}
@@ -0,0 +1,350 @@
+//! The [`kson!`](crate::kson) and [`kson_with_comments!`](crate::kson_with_comments) macros, building
+//! [`Value`](crate::value::Value)s with a KSON-like syntax.
+
+use std::collections::BTreeMap;
+
+use crate::comments::Comments;
+use crate::path::{KsonPath, PathSegment};
+
+/// Builds a [`Value`](crate::value::Value) from a KSON-like literal, checked by the compiler instead of
+/// being parsed at run time:
+///
+/// ```
+/// use kson_rs::kson;
+/// use kson_rs::value::Value;
+///
+/// let replicas = 3;
+/// let value = kson!({
+///     name: "api",
+///     "log-level": "info",
+///     replicas: replicas * 2,
+///     ports: [80, 443],
+///     tls: null,
+///     healthy: true,
+///     query: %sql "select 1\n",
+///     (format!("env_{}", "prod")): { debug: false },
+/// });
+/// assert_eq!(value.pointer("/replicas"), Some(&Value::Integer(6)));
+/// assert_eq!(value.pointer("/env_prod/debug"), Some(&Value::Bool(false)));
+/// ```
+///
+/// Keys are identifiers, literals, or expressions between parentheses, converted with `ToString`.
+/// Values are `null`, `true`, `false`, arrays, objects, embed blocks, or any expression converted with
+/// [`Value::from`](crate::value::Value) (so integer literals become integers, and decimal literals
+/// decimals). An embed block is a `%`, optionally followed by its tag, then its content as a literal or
+/// an expression between parentheses, e.g. `%sql "select 1"` or `%(script)`.
+///
+/// Comments are written as doc comments: `/// text` lines above a property or element are its leading
+/// comments, and a `//! text` after the comma following a property or element is its trailing comment.
+/// Values don't hold comments, so this macro accepts and drops them, while
+/// [`kson_with_comments!`](crate::kson_with_comments) returns them along with the value.
+#[macro_export]
+macro_rules! kson {
+    ($($value:tt)+) => {{
+        let mut literal = $crate::macros::LiteralComments::new(false);
+        $crate::kson_internal!(@root literal $($value)+)
+    }};
+}
+
+/// Like [`kson!`](crate::kson), but keeps the comments of the literal, returning the value along with
+/// its comments in the form [`Value::to_kson_with_comments`](crate::value::Value::to_kson_with_comments)
+/// takes:
+///
+/// ```no_run
+/// use kson_rs::FormatOptions;
+/// use kson_rs::kson_with_comments;
+///
+/// let (value, comments) = kson_with_comments!({
+///     /// Where to listen
+///     port: 8080, //! the default
+///     hosts: [
+///         /// The primary host
+///         "a",
+///         "b",
+///     ],
+///     script: %sh "echo hi\n",
+/// });
+/// let formatter = FormatOptions::builder().build();
+/// println!("{}", value.to_kson_with_comments(&formatter, &comments));
+/// ```
+#[macro_export]
+macro_rules! kson_with_comments {
+    ($($value:tt)+) => {{
+        let mut literal = $crate::macros::LiteralComments::new(true);
+        let value = $crate::kson_internal!(@root literal $($value)+);
+        (value, literal.finish())
+    }};
+}
+
+/// The implementation of [`kson!`], munching the elements of arrays and the properties of objects one
+/// at a time. The first token after the rule name is the [`LiteralComments`] recording the comments.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! kson_internal {
+    (@root $literal:ident #[doc = $comment:literal] $($rest:tt)+) => {{
+        $literal.leading($comment);
+        $crate::kson_internal!(@root $literal $($rest)+)
+    }};
+    (@root $literal:ident $($value:tt)+) => {{
+        $literal.enter(None);
+        $crate::kson_internal!(@value $literal $($value)+)
+    }};
+
+    (@value $literal:ident null) => {
+        $crate::value::Value::Null
+    };
+    (@value $literal:ident true) => {
+        $crate::value::Value::Bool(true)
+    };
+    (@value $literal:ident false) => {
+        $crate::value::Value::Bool(false)
+    };
+    (@value $literal:ident [$($elements:tt)*]) => {{
+        // Empty arrays don't push anything
+        #[allow(unused_mut)]
+        let mut elements = ::std::vec::Vec::new();
+        $crate::kson_internal!(@array elements $literal $($elements)*);
+        $crate::value::Value::Array(elements)
+    }};
+    (@value $literal:ident {$($properties:tt)*}) => {{
+        // Empty objects don't insert anything
+        #[allow(unused_mut)]
+        let mut properties = $crate::value::Map::new();
+        $crate::kson_internal!(@object properties $literal $($properties)*);
+        $crate::value::Value::Object(properties)
+    }};
+    (@value $literal:ident % $tag:ident $content:literal) => {
+        $crate::kson_internal!(@embed Some(stringify!($tag)), $content)
+    };
+    (@value $literal:ident % $tag:ident ($content:expr)) => {
+        $crate::kson_internal!(@embed Some(stringify!($tag)), $content)
+    };
+    (@value $literal:ident % $content:literal) => {
+        $crate::kson_internal!(@embed None::<&str>, $content)
+    };
+    (@value $literal:ident % ($content:expr)) => {
+        $crate::kson_internal!(@embed None::<&str>, $content)
+    };
+    (@value $literal:ident $other:expr) => {
+        $crate::value::Value::from($other)
+    };
+
+    (@embed $tag:expr, $content:expr) => {
+        $crate::value::Value::Embed {
+            tag: $tag.map(::std::string::ToString::to_string),
+            content: ::std::string::ToString::to_string(&$content),
+        }
+    };
+
+    // Arrays: each element is pushed onto the vector named by the first token as soon as it's munched
+    (@array $elements:ident $literal:ident) => {};
+    (@array $elements:ident $literal:ident , $($rest:tt)*) => {
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident #[doc = $comment:literal] $($rest:tt)*) => {
+        $literal.leading($comment);
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident #![doc = $comment:literal] $($rest:tt)*) => {
+        $literal.trailing($comment);
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident null $($rest:tt)*) => {
+        $crate::kson_internal!(@element $elements $literal (null));
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident true $($rest:tt)*) => {
+        $crate::kson_internal!(@element $elements $literal (true));
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident false $($rest:tt)*) => {
+        $crate::kson_internal!(@element $elements $literal (false));
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident [$($array:tt)*] $($rest:tt)*) => {
+        $crate::kson_internal!(@element $elements $literal ([$($array)*]));
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident {$($object:tt)*} $($rest:tt)*) => {
+        $crate::kson_internal!(@element $elements $literal ({$($object)*}));
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident % $tag:ident $content:literal $($rest:tt)*) => {
+        $crate::kson_internal!(@element $elements $literal (% $tag $content));
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident % $tag:ident ($content:expr) $($rest:tt)*) => {
+        $crate::kson_internal!(@element $elements $literal (% $tag ($content)));
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident % $content:literal $($rest:tt)*) => {
+        $crate::kson_internal!(@element $elements $literal (% $content));
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident % ($content:expr) $($rest:tt)*) => {
+        $crate::kson_internal!(@element $elements $literal (% ($content)));
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident $next:expr, $($rest:tt)*) => {
+        $crate::kson_internal!(@element $elements $literal ($next));
+        $crate::kson_internal!(@array $elements $literal $($rest)*);
+    };
+    (@array $elements:ident $literal:ident $last:expr) => {
+        $crate::kson_internal!(@element $elements $literal ($last));
+    };
+
+    (@element $elements:ident $literal:ident ($($value:tt)+)) => {
+        $literal.enter(Some($crate::path::PathSegment::Index($elements.len())));
+        let element = $crate::kson_internal!(@value $literal $($value)+);
+        $literal.leave();
+        $elements.push(element);
+    };
+
+    // Objects: each property is inserted into the map named by the first token as soon as it's munched
+    (@object $properties:ident $literal:ident) => {};
+    (@object $properties:ident $literal:ident , $($rest:tt)*) => {
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident #[doc = $comment:literal] $($rest:tt)*) => {
+        $literal.leading($comment);
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident #![doc = $comment:literal] $($rest:tt)*) => {
+        $literal.trailing($comment);
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : null $($rest:tt)*) => {
+        $crate::kson_internal!(@property $properties $literal $key (null));
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : true $($rest:tt)*) => {
+        $crate::kson_internal!(@property $properties $literal $key (true));
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : false $($rest:tt)*) => {
+        $crate::kson_internal!(@property $properties $literal $key (false));
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : [$($array:tt)*] $($rest:tt)*) => {
+        $crate::kson_internal!(@property $properties $literal $key ([$($array)*]));
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : {$($object:tt)*} $($rest:tt)*) => {
+        $crate::kson_internal!(@property $properties $literal $key ({$($object)*}));
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : % $tag:ident $content:literal $($rest:tt)*) => {
+        $crate::kson_internal!(@property $properties $literal $key (% $tag $content));
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : % $tag:ident ($content:expr) $($rest:tt)*) => {
+        $crate::kson_internal!(@property $properties $literal $key (% $tag ($content)));
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : % $content:literal $($rest:tt)*) => {
+        $crate::kson_internal!(@property $properties $literal $key (% $content));
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : % ($content:expr) $($rest:tt)*) => {
+        $crate::kson_internal!(@property $properties $literal $key (% ($content)));
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : $value:expr, $($rest:tt)*) => {
+        $crate::kson_internal!(@property $properties $literal $key ($value));
+        $crate::kson_internal!(@object $properties $literal $($rest)*);
+    };
+    (@object $properties:ident $literal:ident $key:tt : $value:expr) => {
+        $crate::kson_internal!(@property $properties $literal $key ($value));
+    };
+
+    (@property $properties:ident $literal:ident $key:tt ($($value:tt)+)) => {
+        let key = $crate::kson_internal!(@key $key);
+        $literal.enter(Some($crate::path::PathSegment::Key(::std::clone::Clone::clone(&key))));
+        let value = $crate::kson_internal!(@value $literal $($value)+);
+        $literal.leave();
+        $properties.insert(key, value);
+    };
+
+    (@key $key:ident) => {
+        ::std::string::ToString::to_string(stringify!($key))
+    };
+    (@key ($key:expr)) => {
+        ::std::string::ToString::to_string(&$key)
+    };
+    (@key $key:literal) => {
+        ::std::string::ToString::to_string(&$key)
+    };
+}
+
+/// Records the comments of a [`kson!`](crate::kson) literal by path as its values are built, or nothing
+/// for literals whose comments are dropped
+#[doc(hidden)]
+pub struct LiteralComments {
+    comments: Option<BTreeMap<KsonPath, Comments>>,
+    /// The path of the value being built
+    path: KsonPath,
+    /// The leading comments of the next value
+    leading: Vec<String>,
+    /// The path of the last value built, which trailing comments are attached to
+    last: Option<KsonPath>,
+}
+
+impl LiteralComments {
+    pub fn new(keep: bool) -> Self {
+        Self {
+            comments: keep.then(BTreeMap::new),
+            path: KsonPath::root(),
+            leading: Vec::new(),
+            last: None,
+        }
+    }
+
+    pub fn leading(&mut self, comment: &str) {
+        if self.comments.is_some() {
+            self.leading.push(comment_text(comment));
+        }
+    }
+
+    pub fn trailing(&mut self, comment: &str) {
+        let (Some(comments), Some(last)) = (&mut self.comments, &self.last) else {
+            return;
+        };
+        let trailing = &mut comments.entry(last.clone()).or_default().trailing;
+        // A line holds a single comment, so several trailing comments are joined
+        *trailing = Some(match trailing.take() {
+            Some(previous) => format!("{previous}; {}", comment_text(comment)),
+            None => comment_text(comment),
+        });
+    }
+
+    /// Starts building the value at `segment` of the current one (or the root value), which the pending
+    /// leading comments are attached to
+    pub fn enter(&mut self, segment: Option<PathSegment>) {
+        let Some(comments) = &mut self.comments else {
+            return;
+        };
+        if let Some(segment) = segment {
+            self.path.push(segment);
+        }
+        if !self.leading.is_empty() {
+            let leading = std::mem::take(&mut self.leading);
+            comments.entry(self.path.clone()).or_default().leading = leading;
+        }
+    }
+
+    pub fn leave(&mut self) {
+        if self.comments.is_some() {
+            self.last = Some(self.path.clone());
+            self.path.pop();
+        }
+    }
+
+    pub fn finish(self) -> BTreeMap<KsonPath, Comments> {
+        self.comments.unwrap_or_default()
+    }
+}
+
+/// The text of a doc comment, without the space following its `///` or `//!`
+fn comment_text(doc: &str) -> String {
+    doc.strip_prefix(' ').unwrap_or(doc).trim_end().to_string()
+}
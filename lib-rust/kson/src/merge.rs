@@ -0,0 +1,500 @@
+//! Deep merging of values, mainly to layer configurations (e.g. environment overrides on top of
+//! defaults).
+//!
+//! [`Value::merge`] merges an overlay into a base value, as configured by a [`MergeStrategy`]:
+//!
+//! ```no_run
+//! use kson_rs::merge::{ListMerge, MergeStrategy};
+//! use kson_rs::value::Value;
+//!
+//! let mut config = Value::parse("server: { host: localhost, port: 80 }\nplugins: [auth]").unwrap();
+//! let overrides = Value::parse("server: { port: 8080, host: null }\nplugins: [metrics]").unwrap();
+//! config.merge(
+//!     overrides,
+//!     MergeStrategy::default().lists(ListMerge::Append).null_deletes(true),
+//! );
+//! assert_eq!(
+//!     config,
+//!     Value::parse("server: { port: 8080 }\nplugins: [auth, metrics]").unwrap()
+//! );
+//! ```
+//!
+//! Properties of the base keep their position, and properties only in the overlay are appended in
+//! their overlay order.
+//!
+//! [`Value::merge_patch`] applies a [JSON Merge Patch](https://www.rfc-editor.org/rfc/rfc7396), the
+//! strategy of quick overrides: objects are deep-merged, arrays replaced, and `null` deletes.
+//!
+//! [`Kson::merge3`] merges concurrent edits of a document, like a version control system merging
+//! branches, but structurally: edits to different properties never conflict, however close they are in
+//! the text. Each [`Conflict`] has the values both sides want at its path:
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//!
+//! let parse = |document: &str| Kson::analyze(document, None).kson_value().unwrap();
+//! let base = parse("replicas: 1\nimage: 'api:1.0'");
+//! let ours = parse("replicas: 3\nimage: 'api:1.0'");
+//! let theirs = parse("replicas: 1\nimage: 'api:1.1'");
+//! match Kson::merge3(&base, &ours, &theirs) {
+//!     Ok(merged) => println!("{}", merged.to_kson()),
+//!     Err(conflicts) => {
+//!         for conflict in conflicts {
+//!             eprintln!("{conflict}");
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! [`merge_driver`] runs the three-way merge as a git merge driver, so that concurrent edits of KSON
+//! files stop conflicting textually.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::KsonErrors;
+use crate::format::Formatter;
+use crate::path::{KsonPath, PathSegment};
+use crate::value::{Map, Value};
+use crate::{Kson, KsonValue};
+
+/// How an object of the overlay is merged into an object of the base
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ObjectMerge {
+    /// Merge the properties of both objects, recursively merging the values of the properties in both
+    #[default]
+    Deep,
+    /// Replace the object of the base
+    Replace,
+}
+
+/// How an array of the overlay is merged into an array of the base
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListMerge {
+    /// Replace the array of the base
+    #[default]
+    Replace,
+    /// Append the elements of the overlay to those of the base
+    Append,
+}
+
+/// How [`Value::merge`] combines values. By default objects are deep-merged, arrays replaced, and
+/// `null` is a value like any other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MergeStrategy {
+    pub(crate) objects: ObjectMerge,
+    pub(crate) lists: ListMerge,
+    pub(crate) null_deletes: bool,
+}
+
+impl MergeStrategy {
+    pub fn objects(mut self, objects: ObjectMerge) -> Self {
+        self.objects = objects;
+        self
+    }
+
+    pub fn lists(mut self, lists: ListMerge) -> Self {
+        self.lists = lists;
+        self
+    }
+
+    /// Makes a `null` property of the overlay remove the property from the base, like in
+    /// [JSON Merge Patch](https://www.rfc-editor.org/rfc/rfc7396). `null` properties of objects added by
+    /// the overlay are then dropped as well, while `null` array elements are kept.
+    pub fn null_deletes(mut self, null_deletes: bool) -> Self {
+        self.null_deletes = null_deletes;
+        self
+    }
+}
+
+impl Value {
+    /// Merges `overlay` into this value (see the [module documentation](crate::merge)). Values that
+    /// aren't both objects or both arrays are replaced by the overlay, whatever the strategy.
+    pub fn merge(&mut self, overlay: Value, strategy: MergeStrategy) {
+        // Nested objects are merged from a stack of pairs rather than recursively
+        let mut pending = vec![(self, overlay)];
+        while let Some((base, mut overlay)) = pending.pop() {
+            let merges = match (&*base, &overlay) {
+                (Value::Object(_), Value::Object(_)) => strategy.objects == ObjectMerge::Deep,
+                (Value::Array(_), Value::Array(_)) => strategy.lists == ListMerge::Append,
+                _ => false,
+            };
+            if !merges {
+                *base = replacement(overlay, strategy);
+                continue;
+            }
+            match (base, &mut overlay) {
+                (Value::Object(base), Value::Object(properties)) => {
+                    let properties = std::mem::take(properties);
+                    pending.extend(merge_properties(base, properties, strategy));
+                }
+                (Value::Array(base), Value::Array(elements)) => base.append(elements),
+                _ => {}
+            }
+        }
+    }
+
+    /// Applies a JSON Merge Patch (RFC 7396): the patch is merged in with [`Value::merge`], deep-merging
+    /// objects, replacing arrays and deleting the properties set to `null`
+    pub fn merge_patch(&mut self, patch: Value) {
+        self.merge(patch, MergeStrategy::default().null_deletes(true));
+    }
+}
+
+impl KsonValue {
+    /// Returns this value with `other` merged into it, see [`Value::merge`]
+    pub fn merge(&self, other: &KsonValue, strategy: MergeStrategy) -> Value {
+        let mut value = self.to_value();
+        value.merge(other.to_value(), strategy);
+        value
+    }
+
+    /// Returns this value with the merge patch applied, see [`Value::merge_patch`]
+    pub fn merge_patch(&self, patch: &KsonValue) -> Value {
+        let mut value = self.to_value();
+        value.merge_patch(patch.to_value());
+        value
+    }
+}
+
+/// Values that were changed differently on both sides of a three-way merge (see [`Kson::merge3`]). A
+/// value that is absent on a side (e.g. a property one side deleted) is `None`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conflict {
+    pub path: KsonPath,
+    pub base: Option<Value>,
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |value: &Option<Value>| match value {
+            Some(value) => value.to_inline_kson(),
+            None => "nothing".to_string(),
+        };
+        write!(
+            f,
+            "conflicting changes at `{}`: ours changed it to {}, theirs to {}",
+            self.path,
+            describe(&self.ours),
+            describe(&self.theirs)
+        )
+    }
+}
+
+impl Kson {
+    /// Merges the changes made to `base` in `ours` and in `theirs`, or returns the conflicts between them
+    /// (see the [module documentation](crate::merge)).
+    ///
+    /// Objects are merged property by property, and a property added on both sides is merged as if it
+    /// had been an empty object in `base`. Other values, including arrays, are taken from the side that
+    /// changed them, and conflict if both sides changed them differently. Properties keep their order in
+    /// `ours`, followed by those only added in `theirs`.
+    pub fn merge3(
+        base: &KsonValue,
+        ours: &KsonValue,
+        theirs: &KsonValue,
+    ) -> Result<Value, Vec<Conflict>> {
+        Value::merge3(&base.to_value(), &ours.to_value(), &theirs.to_value())
+    }
+}
+
+impl Value {
+    /// Merges the changes made to `base` in `ours` and in `theirs`, see [`Kson::merge3`]
+    pub fn merge3(base: &Value, ours: &Value, theirs: &Value) -> Result<Value, Vec<Conflict>> {
+        let mut conflicts = Vec::new();
+        let mut path = KsonPath::root();
+        // The objects being merged, innermost last: their properties are merged one by one rather than
+        // recursively
+        let mut objects = Vec::new();
+        let mut merged =
+            match merge3_values(Some(base), Some(ours), Some(theirs), &path, &mut conflicts) {
+                Merge3::Merged(value) => value,
+                Merge3::Properties(object) => {
+                    objects.push(object);
+                    None
+                }
+            };
+        while let Some(object) = objects.last_mut() {
+            let value = match object.keys.get(object.next) {
+                Some(&key) => {
+                    path.push(PathSegment::Key(key.clone()));
+                    match merge3_values(
+                        object.base.and_then(|base| base.get(key)),
+                        object.ours.get(key),
+                        object.theirs.get(key),
+                        &path,
+                        &mut conflicts,
+                    ) {
+                        Merge3::Merged(value) => value,
+                        Merge3::Properties(nested) => {
+                            objects.push(nested);
+                            continue;
+                        }
+                    }
+                }
+                None => objects
+                    .pop()
+                    .map(|object| Value::Object(Map::from_entries(object.merged))),
+            };
+            path.pop();
+            match objects.last_mut() {
+                Some(parent) => {
+                    let key = parent.keys[parent.next];
+                    parent.next += 1;
+                    if let Some(value) = value {
+                        parent.merged.push((key.clone(), value));
+                    }
+                }
+                None => merged = value,
+            }
+        }
+
+        if conflicts.is_empty() {
+            // The root is present on both sides, so it can't have been removed
+            Ok(merged.unwrap_or(Value::Null))
+        } else {
+            Err(conflicts)
+        }
+    }
+}
+
+/// What [`merge_driver`] did with the files of a merge
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergeDriverOutcome {
+    /// The merged document was written to the file of `ours`
+    Merged,
+    /// Both sides changed some values differently, and the file of `ours` was left as it was
+    Conflicts(Vec<Conflict>),
+}
+
+impl MergeDriverOutcome {
+    /// The exit code git expects from a merge driver: zero once merged, and non-zero on conflicts
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MergeDriverOutcome::Merged => 0,
+            MergeDriverOutcome::Conflicts(_) => 1,
+        }
+    }
+}
+
+/// The reason [`merge_driver`] couldn't merge the files, which a merge driver reports as a conflict too
+#[derive(Debug)]
+pub enum MergeDriverError {
+    Io(std::io::Error),
+    /// One of the files is not valid KSON
+    Parse {
+        path: PathBuf,
+        errors: Box<KsonErrors>,
+    },
+}
+
+impl std::fmt::Display for MergeDriverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeDriverError::Io(error) => error.fmt(f),
+            MergeDriverError::Parse { path, errors } => {
+                write!(f, "{} is not valid KSON: {errors}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeDriverError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MergeDriverError::Io(error) => Some(error),
+            MergeDriverError::Parse { errors, .. } => Some(&**errors),
+        }
+    }
+}
+
+/// Merges the files git passes to a custom merge driver as `%O %A %B`: the common ancestor `base`, and
+/// the versions of the current branch (`ours`) and of the merged one (`theirs`). Like git expects, the
+/// merged document is written to the file of `ours`, formatted with `formatter`.
+///
+/// Comments are lost when both sides changed the document, since the merge is structural. When one side
+/// changed nothing, the file of the other side is kept as it's written.
+///
+/// A binary wrapping it is registered with `git config merge.kson.driver 'kson-merge %O %A %B'` and a
+/// `*.kson merge=kson` line in `.gitattributes`:
+///
+/// ```no_run
+/// use kson_rs::FormatOptions;
+/// use kson_rs::merge::{MergeDriverOutcome, merge_driver};
+///
+/// let args: Vec<String> = std::env::args().skip(1).collect();
+/// let [base, ours, theirs] = &args[..] else {
+///     eprintln!("usage: kson-merge <base> <ours> <theirs>");
+///     std::process::exit(2);
+/// };
+/// let formatter = FormatOptions::builder().build();
+/// match merge_driver(base.as_ref(), ours.as_ref(), theirs.as_ref(), &formatter) {
+///     Ok(outcome) => {
+///         if let MergeDriverOutcome::Conflicts(conflicts) = &outcome {
+///             for conflict in conflicts {
+///                 eprintln!("{conflict}");
+///             }
+///         }
+///         std::process::exit(outcome.exit_code());
+///     }
+///     Err(error) => {
+///         eprintln!("{error}");
+///         std::process::exit(2);
+///     }
+/// }
+/// ```
+pub fn merge_driver(
+    base: &Path,
+    ours: &Path,
+    theirs: &Path,
+    formatter: &Formatter,
+) -> Result<MergeDriverOutcome, MergeDriverError> {
+    let read = |path: &Path| {
+        let document = std::fs::read_to_string(path).map_err(io_error(path))?;
+        let value = Value::parse(&document).map_err(|errors| MergeDriverError::Parse {
+            path: path.to_path_buf(),
+            errors: Box::new(errors),
+        })?;
+        Ok((document, value))
+    };
+    let (_, base_value) = read(base)?;
+    let (_, ours_value) = read(ours)?;
+    let (theirs_document, theirs_value) = read(theirs)?;
+
+    let merged = match Value::merge3(&base_value, &ours_value, &theirs_value) {
+        Ok(merged) => merged,
+        Err(conflicts) => return Ok(MergeDriverOutcome::Conflicts(conflicts)),
+    };
+    // The file of a side that already has the merged document is kept, comments and all
+    if merged == ours_value {
+        return Ok(MergeDriverOutcome::Merged);
+    }
+    let document = if merged == theirs_value {
+        theirs_document
+    } else {
+        merged.to_kson_with(formatter)
+    };
+    std::fs::write(ours, document).map_err(io_error(ours))?;
+    Ok(MergeDriverOutcome::Merged)
+}
+
+/// Wraps an IO error with the path of the file it's about
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> MergeDriverError + '_ {
+    move |error| {
+        MergeDriverError::Io(std::io::Error::new(
+            error.kind(),
+            format!("{}: {error}", path.display()),
+        ))
+    }
+}
+
+/// The result of merging a value on each side of a three-way merge
+enum Merge3<'a> {
+    /// The merged value, or `None` if it's absent once merged
+    Merged(Option<Value>),
+    /// Objects to merge property by property
+    Properties(MergingObject<'a>),
+}
+
+/// An object being merged property by property (the base is `None` if the object was added on both
+/// sides, in which case it's merged as if it had been empty)
+struct MergingObject<'a> {
+    base: Option<&'a Map>,
+    ours: &'a Map,
+    theirs: &'a Map,
+    /// The keys of the properties to merge, in the order of the merged object
+    keys: Vec<&'a String>,
+    /// The position in `keys` of the property being merged
+    next: usize,
+    merged: Vec<(String, Value)>,
+}
+
+/// Merges the value at `path` on each side (`None` where it's absent), or returns the objects to merge
+/// property by property. On conflicts, `ours` is kept so that the merge can carry on.
+fn merge3_values<'a>(
+    base: Option<&'a Value>,
+    ours: Option<&'a Value>,
+    theirs: Option<&'a Value>,
+    path: &KsonPath,
+    conflicts: &mut Vec<Conflict>,
+) -> Merge3<'a> {
+    if ours == theirs || theirs == base {
+        return Merge3::Merged(ours.cloned());
+    }
+    if ours == base {
+        return Merge3::Merged(theirs.cloned());
+    }
+    let base_properties = match base {
+        Some(Value::Object(map)) => Some(Some(map)),
+        None => Some(None),
+        _ => None,
+    };
+    if let (Some(base), Some(Value::Object(ours)), Some(Value::Object(theirs))) =
+        (base_properties, ours, theirs)
+    {
+        let keys = ours
+            .keys()
+            .chain(theirs.keys().filter(|key| !ours.contains_key(key)))
+            .collect();
+        return Merge3::Properties(MergingObject {
+            base,
+            ours,
+            theirs,
+            keys,
+            next: 0,
+            merged: Vec::new(),
+        });
+    }
+    conflicts.push(Conflict {
+        path: path.clone(),
+        base: base.cloned(),
+        ours: ours.cloned(),
+        theirs: theirs.cloned(),
+    });
+    Merge3::Merged(ours.cloned())
+}
+
+/// Merges the properties of `overlay` into `base`, returning the pairs of values left to merge, i.e. the
+/// base properties that the overlay also has
+fn merge_properties(
+    base: &mut Map,
+    overlay: Map,
+    strategy: MergeStrategy,
+) -> Vec<(&mut Value, Value)> {
+    let mut nested = HashMap::new();
+    for (key, value) in overlay {
+        if strategy.null_deletes && matches!(value, Value::Null) {
+            base.remove(&key);
+        } else if base.contains_key(&key) {
+            nested.insert(key, value);
+        } else {
+            base.insert(key, replacement(value, strategy));
+        }
+    }
+    if nested.is_empty() {
+        return Vec::new();
+    }
+    base.iter_mut()
+        .filter_map(|(key, existing)| Some((existing, nested.remove(key)?)))
+        .collect()
+}
+
+/// The overlay value as it replaces a base value, without its `null` properties if they delete
+fn replacement(mut value: Value, strategy: MergeStrategy) -> Value {
+    if strategy.null_deletes {
+        strip_null_properties(&mut value);
+    }
+    value
+}
+
+fn strip_null_properties(value: &mut Value) {
+    let mut pending = vec![value];
+    while let Some(value) = pending.pop() {
+        if let Value::Object(map) = value {
+            map.retain(|_, value| !matches!(value, Value::Null));
+            pending.extend(map.iter_mut().map(|(_, value)| value));
+        }
+    }
+}
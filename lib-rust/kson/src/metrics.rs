@@ -0,0 +1,107 @@
+//! Size and shape statistics of a document, e.g. to plan capacity or tune parsing limits.
+
+use std::collections::BTreeMap;
+
+use crate::value::{Step, Value};
+use crate::{Kson, Message, MessageSeverity};
+
+/// Statistics about a document, as returned by [`Kson::metrics`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DocumentMetrics {
+    /// The length of the longest path from the root to a value, e.g. 0 for a scalar or an empty object,
+    /// and 2 for `{ a: [1] }`
+    pub depth: usize,
+    /// The number of values of each type, the root included
+    pub nodes: NodeCounts,
+    /// The total size in UTF-8 bytes of all strings and embed block contents (property names excluded)
+    pub string_bytes: usize,
+    /// The number of times each property name occurs, across all objects
+    pub keys: BTreeMap<String, usize>,
+}
+
+/// The number of values of each type in a document
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeCounts {
+    pub objects: usize,
+    pub arrays: usize,
+    pub strings: usize,
+    pub integers: usize,
+    pub decimals: usize,
+    pub booleans: usize,
+    pub nulls: usize,
+    pub embeds: usize,
+}
+
+impl NodeCounts {
+    /// The total number of values
+    pub fn total(&self) -> usize {
+        self.objects
+            + self.arrays
+            + self.strings
+            + self.integers
+            + self.decimals
+            + self.booleans
+            + self.nulls
+            + self.embeds
+    }
+}
+
+impl Kson {
+    /// Parses the input and computes its [`DocumentMetrics`], or returns the errors that prevented parsing
+    /// it
+    pub fn metrics(input: &str) -> Result<DocumentMetrics, Vec<Message>> {
+        let analysis = Kson::analyze(input, None);
+        match analysis.kson_value() {
+            Some(value) => Ok(value.to_value().metrics()),
+            None => Err(analysis
+                .errors()
+                .into_iter()
+                .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+                .collect()),
+        }
+    }
+}
+
+impl Value {
+    /// Computes the [`DocumentMetrics`] of this value
+    pub fn metrics(&self) -> DocumentMetrics {
+        let mut metrics = DocumentMetrics::default();
+        collect(self, &mut metrics);
+        metrics
+    }
+}
+
+fn collect(value: &Value, metrics: &mut DocumentMetrics) {
+    for step in value.walk() {
+        if let Step::Enter {
+            depth, key, value, ..
+        } = step
+        {
+            metrics.depth = metrics.depth.max(depth);
+            if let Some(key) = key {
+                *metrics.keys.entry(key.to_string()).or_default() += 1;
+            }
+            count(value, metrics);
+        }
+    }
+}
+
+fn count(value: &Value, metrics: &mut DocumentMetrics) {
+    let nodes = &mut metrics.nodes;
+    match value {
+        Value::Null => nodes.nulls += 1,
+        Value::Bool(_) => nodes.booleans += 1,
+        Value::Integer(_) | Value::BigInteger(_) => nodes.integers += 1,
+        Value::Decimal(_) => nodes.decimals += 1,
+        Value::String(string) => {
+            nodes.strings += 1;
+            metrics.string_bytes += string.len();
+        }
+        Value::Embed { content, .. } => {
+            nodes.embeds += 1;
+            metrics.string_bytes += content.len();
+        }
+        Value::Array(_) => nodes.arrays += 1,
+        Value::Object(_) => nodes.objects += 1,
+    }
+}
@@ -0,0 +1,364 @@
+//! Systematic mutations of conformance cases, to grow the negative (and near-miss positive) tests of a
+//! suite from its existing cases.
+//!
+//! [`mutate`] derives [`Mutant`]s from a valid document, each classified by construction as accepted
+//! (with the value it must read as) or rejected:
+//!
+//! - [`MutationKind::FlipQuotes`]: a quoted string switches between `"` and `'`, which keeps the value
+//! - [`MutationKind::Unicode`]: a string or key is replaced by a quoted string of [`UNICODE_EDGE_CASES`]
+//! - [`MutationKind::MismatchCloser`]: the `}` of an object becomes `]`, or the `]` of an array `}`
+//! - [`MutationKind::Truncate`]: the document is cut inside a quoted string, an embed block, or before
+//!   the closing brace or bracket of an object or array
+//!
+//! [`write_corpus`] writes the mutants of a directory of cases with the `y_`/`n_` prefixes of
+//! [JSONTestSuite](https://github.com/nst/JSONTestSuite), for the harnesses that already read it:
+//!
+//! ```no_run
+//! use kson_rs::mutation::{mutate, write_corpus};
+//!
+//! for mutant in mutate("server: { host: 'example.com', ports: [80, 443] }") {
+//!     if let Err(outcome) = mutant.check() {
+//!         eprintln!("{}: unexpected {outcome:?} for\n{}", mutant.name, mutant.input);
+//!     }
+//! }
+//! let written = write_corpus("suite/cases".as_ref(), "suite/mutants".as_ref()).unwrap();
+//! println!("{written} mutants");
+//! ```
+
+use std::path::Path;
+
+use crate::line_index::LineIndex;
+use crate::path::{KsonPath, PathSegment};
+use crate::value::{Map, Value, sorted_property_keys, write_quoted};
+use crate::{Kson, KsonValue, MessageSeverity};
+
+/// Strings that tend to trip up lexers and renderers: non-ASCII letters, combining characters, characters
+/// outside the Basic Multilingual Plane (surrogate pairs in UTF-16), line and paragraph separators, and
+/// invisible characters
+pub const UNICODE_EDGE_CASES: &[&str] = &[
+    "é",
+    "e\u{301}",
+    "😀",
+    "\u{10ffff}",
+    "\u{2028}\u{2029}",
+    "\u{feff}",
+    "\u{200b}",
+    "\u{fffd}",
+    "ﬀ",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MutationKind {
+    FlipQuotes,
+    Unicode,
+    MismatchCloser,
+    Truncate,
+}
+
+impl std::fmt::Display for MutationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MutationKind::FlipQuotes => "flip-quotes",
+            MutationKind::Unicode => "unicode",
+            MutationKind::MismatchCloser => "mismatch-closer",
+            MutationKind::Truncate => "truncate",
+        })
+    }
+}
+
+/// How a mutant must be read
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expectation {
+    /// The mutant parses, as this value
+    Accept(Value),
+    /// The mutant has errors
+    Reject,
+}
+
+/// How a mutant was actually read, as returned by [`Mutant::check`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Accepted(Value),
+    /// The errors reported for the mutant
+    Rejected(Vec<String>),
+}
+
+/// A mutation of a document (see the [module documentation](self))
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mutant {
+    /// Identifies the mutant among those of its document, e.g. `truncate-3`
+    pub name: String,
+    pub kind: MutationKind,
+    pub input: String,
+    pub expected: Expectation,
+}
+
+impl Mutant {
+    /// Parses the mutant, returning how it was read if that's not as expected
+    pub fn check(&self) -> Result<(), Outcome> {
+        let outcome = match Value::parse(&self.input) {
+            Ok(value) => Outcome::Accepted(value),
+            Err(errors) => Outcome::Rejected(
+                errors
+                    .errors()
+                    .iter()
+                    .map(|error| error.message().to_string())
+                    .collect(),
+            ),
+        };
+        match (&self.expected, &outcome) {
+            (Expectation::Accept(expected), Outcome::Accepted(actual)) if expected == actual => {
+                Ok(())
+            }
+            (Expectation::Reject, Outcome::Rejected(_)) => Ok(()),
+            _ => Err(outcome),
+        }
+    }
+}
+
+/// Derives the mutants of the document, in document order within each kind. Documents with errors
+/// have none, since their mutants couldn't be classified.
+pub fn mutate(document: &str) -> Vec<Mutant> {
+    let analysis = Kson::analyze(document, None);
+    let has_errors = analysis
+        .errors()
+        .iter()
+        .any(|message| matches!(message.severity(), MessageSeverity::Error));
+    let Some(root) = analysis.kson_value().filter(|_| !has_errors) else {
+        return Vec::new();
+    };
+    let index = LineIndex::new(document);
+    let value = root.to_value();
+    let mut sites = Vec::new();
+    collect_sites(&root, &mut KsonPath::root(), document, &index, &mut sites);
+
+    let mut mutants = Vec::new();
+    let mut push = |kind: MutationKind, input: String, expected: Expectation| {
+        let count = mutants
+            .iter()
+            .filter(|mutant: &&Mutant| mutant.kind == kind)
+            .count();
+        mutants.push(Mutant {
+            name: format!("{kind}-{count}"),
+            kind,
+            input,
+            expected,
+        });
+    };
+    let splice = |range: &std::ops::Range<usize>, replacement: &str| {
+        format!(
+            "{}{replacement}{}",
+            &document[..range.start],
+            &document[range.end..]
+        )
+    };
+
+    for site in &sites {
+        let Site::String { range, .. } = site else {
+            continue;
+        };
+        let text = &document[range.clone()];
+        let Some(quote) = text.chars().next().filter(|c| matches!(c, '"' | '\'')) else {
+            continue;
+        };
+        let content = &text[1..text.len() - 1];
+        if content.contains(['"', '\'', '\\']) {
+            continue;
+        }
+        let flipped = if quote == '"' { '\'' } else { '"' };
+        push(
+            MutationKind::FlipQuotes,
+            splice(range, &format!("{flipped}{content}{flipped}")),
+            Expectation::Accept(value.clone()),
+        );
+    }
+
+    for site in &sites {
+        let Site::String { range, target } = site else {
+            continue;
+        };
+        for edge_case in UNICODE_EDGE_CASES {
+            let Some(expected) = replace_string(&value, target, edge_case) else {
+                continue;
+            };
+            let mut quoted = String::new();
+            write_quoted(edge_case, '"', &mut quoted);
+            push(
+                MutationKind::Unicode,
+                splice(range, &quoted),
+                Expectation::Accept(expected),
+            );
+        }
+    }
+
+    for site in &sites {
+        if let Site::Closer { offset, closer } = site {
+            let swapped = if *closer == '}' { "]" } else { "}" };
+            push(
+                MutationKind::MismatchCloser,
+                splice(&(*offset..offset + 1), swapped),
+                Expectation::Reject,
+            );
+        }
+    }
+
+    for site in &sites {
+        let cut = match site {
+            // Right after the opening quote
+            Site::String { range, .. } if document[range.clone()].starts_with(['"', '\'']) => {
+                range.start + 1
+            }
+            // After the delimiter and tag of an embed block
+            Site::Embed { range } => document[range.clone()]
+                .find('\n')
+                .map_or(range.start + 1, |newline| range.start + newline + 1),
+            Site::Closer { offset, .. } => *offset,
+            _ => continue,
+        };
+        push(
+            MutationKind::Truncate,
+            document[..cut].to_string(),
+            Expectation::Reject,
+        );
+    }
+    mutants
+}
+
+/// Writes the mutants of every case in `cases` to `out` (created if needed), as `y_<case>_<mutant>.kson`
+/// for those to accept and `n_<case>_<mutant>.kson` for those to reject, returning how many were
+/// written. Cases that don't parse are skipped.
+pub fn write_corpus(cases: &Path, out: &Path) -> std::io::Result<usize> {
+    let mut entries = std::fs::read_dir(cases)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    std::fs::create_dir_all(out)?;
+
+    let mut written = 0;
+    for entry in entries {
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let Ok(document) = std::fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        let path = entry.path();
+        let case = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for mutant in mutate(&document) {
+            let prefix = match mutant.expected {
+                Expectation::Accept(_) => "y",
+                Expectation::Reject => "n",
+            };
+            let name = format!("{prefix}_{case}_{}.kson", mutant.name);
+            std::fs::write(out.join(name), &mutant.input)?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// A place in the document to mutate
+enum Site {
+    /// A string value or a property key, with the byte range of its text (quotes included)
+    String {
+        range: std::ops::Range<usize>,
+        target: StringTarget,
+    },
+    Embed {
+        range: std::ops::Range<usize>,
+    },
+    /// The closing brace or bracket of an object or array, at the given byte offset
+    Closer {
+        offset: usize,
+        closer: char,
+    },
+}
+
+/// What a string of the document is
+enum StringTarget {
+    Value(KsonPath),
+    /// The key of a property of the object at the path
+    Key(KsonPath, String),
+}
+
+fn collect_sites(
+    value: &KsonValue,
+    path: &mut KsonPath,
+    document: &str,
+    index: &LineIndex,
+    sites: &mut Vec<Site>,
+) {
+    let range = index.offset_of(&value.start())..index.offset_of(&value.end());
+    let (start, end) = (range.start, range.end);
+    match value {
+        KsonValue::KsonString(_) => sites.push(Site::String {
+            range,
+            target: StringTarget::Value(path.clone()),
+        }),
+        KsonValue::KsonEmbed(_) => sites.push(Site::Embed { range }),
+        KsonValue::KsonObject(object) => {
+            let mut properties = object.properties();
+            for (name, key) in sorted_property_keys(object) {
+                sites.push(Site::String {
+                    range: index.offset_of(&key.start())..index.offset_of(&key.end()),
+                    target: StringTarget::Key(path.clone(), name.clone()),
+                });
+                let Some(property) = properties.remove(&name) else {
+                    continue;
+                };
+                path.push(PathSegment::Key(name));
+                collect_sites(&property, path, document, index, sites);
+                path.pop();
+            }
+        }
+        KsonValue::KsonArray(array) => {
+            for (element_index, element) in array.elements().iter().enumerate() {
+                path.push(PathSegment::Index(element_index));
+                collect_sites(element, path, document, index, sites);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+    // Objects and arrays without delimiters (like a root object) end with the closer of their last value
+    let closer = match value {
+        KsonValue::KsonObject(_) if document[start..].starts_with('{') => Some('}'),
+        KsonValue::KsonArray(_) if document[start..].starts_with('[') => Some(']'),
+        _ => None,
+    };
+    if let Some(closer) = closer
+        && document[..end].ends_with(closer)
+    {
+        sites.push(Site::Closer {
+            offset: end - 1,
+            closer,
+        });
+    }
+}
+
+/// The value with the string (or key) replaced, or `None` if that would duplicate a key
+fn replace_string(value: &Value, target: &StringTarget, replacement: &str) -> Option<Value> {
+    let mut value = value.clone();
+    match target {
+        StringTarget::Value(path) => {
+            *value.get_path_mut(path)? = Value::String(replacement.to_string());
+        }
+        StringTarget::Key(path, name) => {
+            let Value::Object(map) = value.get_path_mut(path)? else {
+                return None;
+            };
+            if map.contains_key(replacement) {
+                return None;
+            }
+            *map = map
+                .iter()
+                .map(|(key, property)| {
+                    let key = if key == name { replacement } else { key };
+                    (key.to_string(), property.clone())
+                })
+                .collect::<Map>();
+        }
+    }
+    Some(value)
+}
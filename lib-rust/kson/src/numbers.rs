@@ -0,0 +1,399 @@
+//! How numbers are read, and a lenient parser for the digit separators people write.
+//!
+//! Numbers are read and written with `.` as the decimal point whatever the locale of the system:
+//! kson-lib converts them with Kotlin's locale-independent conversions, and this crate with Rust's
+//! (`str::parse` and `format!`), none of which look at the locale. `1,5` is never the number 1.5, and a
+//! document reads as the same value on every machine.
+//!
+//! People used to other conventions write digit separators, which KSON rejects. [`Value::parse_lenient`]
+//! accepts them, removing them before parsing and warning about each number it normalized, so that the
+//! document can be fixed later:
+//!
+//! ```no_run
+//! use kson_rs::value::Value;
+//!
+//! let parsed = Value::parse_lenient("population: 1,234,567\nbudget: 1_000_000.50").unwrap();
+//! assert_eq!(parsed.value, Value::parse("population: 1234567\nbudget: 1000000.50").unwrap());
+//! for warning in parsed.warnings.errors() {
+//!     eprintln!("{warning}");
+//! }
+//! ```
+//!
+//! Underscores are accepted between any two digits, e.g. `1_000` or `3.141_592`. Commas are only
+//! accepted as thousands separators (`1,000,000`, each comma followed by three digits) in property
+//! values: in lists they separate elements, so `[1,000]` keeps its two elements.
+//!
+//! Integers are read as `i64`s, and kson-lib rejects those out of its range.
+//! [`Value::parse_with_overflow`] lets callers decide what to do with them instead, as an
+//! [`IntegerOverflow`] policy:
+//!
+//! ```no_run
+//! use kson_rs::numbers::IntegerOverflow;
+//! use kson_rs::value::Value;
+//!
+//! let document = "id: 123456789012345678901234567890";
+//! assert!(Value::parse(document).is_err());
+//! let value = Value::parse_with_overflow(document, IntegerOverflow::Saturate).unwrap();
+//! assert_eq!(value, Value::parse("id: 9223372036854775807").unwrap());
+//! ```
+//!
+//! With [`IntegerOverflow::BigDecimal`], they're kept as [`BigInteger`]s, which are written back as
+//! numbers. JSON readers take them (e.g. as decimals), but kson-lib rejects the KSON, which only
+//! [`Value::parse_with_overflow`] reads back.
+//!
+//! Values converted from other sources follow their own rules: [`ser`](crate::ser) rejects integers out
+//! of range, and JSON numbers out of range become decimals (see [`json`](crate::json)).
+
+use std::cmp::Ordering;
+use std::ops::Range;
+#[cfg(feature = "bigdecimal")]
+use std::str::FromStr;
+
+#[cfg(feature = "bigdecimal")]
+use bigdecimal::BigDecimal;
+
+use crate::error::KsonErrors;
+use crate::line_index::LineIndex;
+use crate::value::Value;
+use crate::{Kson, TokenType};
+
+/// The result of [`Value::parse_lenient`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct LenientParse {
+    pub value: Value,
+    /// A warning for each number whose digit separators were removed, located in the original document
+    pub warnings: KsonErrors,
+}
+
+impl Value {
+    /// Parses a document like [`Value::parse`] does, accepting digit separators in numbers (see the
+    /// [module documentation](crate::numbers)). Errors are reported against the document with the
+    /// separators removed.
+    pub fn parse_lenient(document: &str) -> Result<LenientParse, KsonErrors> {
+        let separated = separated_numbers(document);
+        let mut normalized = document.to_string();
+        for range in separated.iter().rev() {
+            normalized.replace_range(range.clone(), &without_separators(&document[range.clone()]));
+        }
+        let value = Value::parse(&normalized)?;
+        let warnings = separated
+            .into_iter()
+            .map(|range| {
+                let written = &document[range.clone()];
+                let message = format!(
+                    "`{written}` has digit separators, which KSON doesn't support; write it `{}`",
+                    without_separators(written)
+                );
+                (range, message)
+            })
+            .collect();
+        Ok(LenientParse {
+            value,
+            warnings: KsonErrors::from_warnings(document, warnings),
+        })
+    }
+}
+
+/// What [`Value::parse_with_overflow`] does with the integers out of the range of an `i64`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IntegerOverflow {
+    /// Reject the document, like [`Value::parse`]
+    #[default]
+    Error,
+    /// Read the integer as `i64::MIN` or `i64::MAX`
+    Saturate,
+    /// Read the integer as the nearest decimal, losing precision
+    Float,
+    /// Read the integer as a [`BigInteger`], which [`Value::as_big_decimal`] reads exactly
+    #[cfg(feature = "bigdecimal")]
+    BigDecimal,
+}
+
+impl IntegerOverflow {
+    /// The text replacing the integer in the document, if any. Integers too large for a decimal (over
+    /// 308 digits) aren't replaced by [`IntegerOverflow::Float`], and are still rejected.
+    fn replacement(self, integer: &str) -> Option<String> {
+        let negative = integer.starts_with('-');
+        match self {
+            IntegerOverflow::Error => None,
+            IntegerOverflow::Saturate if negative => Some(i64::MIN.to_string()),
+            IntegerOverflow::Saturate => Some(i64::MAX.to_string()),
+            IntegerOverflow::Float => integer
+                .parse::<f64>()
+                .ok()
+                .filter(|decimal| decimal.is_finite())
+                .map(|decimal| format!("{decimal:e}")),
+            // Written as a string for kson-lib, and read back as a big integer
+            #[cfg(feature = "bigdecimal")]
+            IntegerOverflow::BigDecimal => Some(format!("'{integer}'")),
+        }
+    }
+
+    /// Whether the integer is read as a [`BigInteger`]
+    fn keeps_digits(self) -> bool {
+        #[cfg(feature = "bigdecimal")]
+        if self == IntegerOverflow::BigDecimal {
+            return true;
+        }
+        false
+    }
+}
+
+/// An integer out of the range of an `i64`, as read by [`IntegerOverflow::BigDecimal`]. It's kept as its
+/// decimal digits, and written as a number by [`Value::to_kson`] and [`Value::to_json`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BigInteger {
+    /// The digits without leading zeros, after a `-` for negative integers
+    digits: String,
+}
+
+impl BigInteger {
+    /// Reads an integer written in decimal, with an optional `-` and leading zeros, or returns `None` if it
+    /// isn't one or fits in an `i64` (which is a [`Value::Integer`] instead)
+    pub fn parse(integer: &str) -> Option<Self> {
+        let (sign, digits) = match integer.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", integer),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        if integer.parse::<i64>().is_ok() {
+            return None;
+        }
+        Some(BigInteger {
+            digits: format!("{sign}{}", digits.trim_start_matches('0')),
+        })
+    }
+
+    /// The integer in decimal, e.g. `-92233720368547758070`
+    pub fn as_str(&self) -> &str {
+        &self.digits
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.digits.starts_with('-')
+    }
+
+    /// The nearest decimal, which is infinite for integers of more than 308 digits
+    pub fn to_f64(&self) -> f64 {
+        self.digits.parse().unwrap_or(f64::NAN)
+    }
+}
+
+impl BigInteger {
+    /// Compares the absolute values
+    fn cmp_magnitude(&self, other: &Self) -> Ordering {
+        // Without leading zeros, more digits make a larger integer
+        let (a, b) = (
+            self.digits.trim_start_matches('-'),
+            other.digits.trim_start_matches('-'),
+        );
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    }
+}
+
+impl std::fmt::Display for BigInteger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.digits)
+    }
+}
+
+impl PartialOrd for BigInteger {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Big integers are ordered numerically
+impl Ord for BigInteger {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.is_negative(), other.is_negative()) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => self.cmp_magnitude(other),
+            (true, true) => other.cmp_magnitude(self),
+        }
+    }
+}
+
+impl Value {
+    /// Parses a document like [`Value::parse`] does, reading the integers out of the range of an `i64`
+    /// as the policy says. Errors are reported against the document with those integers replaced.
+    pub fn parse_with_overflow(
+        document: &str,
+        overflow: IntegerOverflow,
+    ) -> Result<Value, KsonErrors> {
+        let mut replaced = String::with_capacity(document.len());
+        // The ranges of the replaced document holding the strings that are read as big integers
+        let mut big_integers = Vec::new();
+        let mut end = 0;
+        for range in overflowing_integers(document) {
+            replaced.push_str(&document[end..range.start]);
+            end = range.end;
+            let integer = &document[range];
+            let start = replaced.len();
+            replaced.push_str(&overflow.replacement(integer).unwrap_or(integer.to_string()));
+            if overflow.keeps_digits() {
+                big_integers.push(start..replaced.len());
+            }
+        }
+        replaced.push_str(&document[end..]);
+
+        let mut value = Value::parse(&replaced)?;
+        if !big_integers.is_empty() {
+            // Strings of digits the document holds are left alone, so they're told apart by position
+            let index = LineIndex::new(&replaced);
+            let root = Kson::analyze(&replaced, None).kson_value();
+            value.rewrite(|path, value| {
+                let Value::String(digits) = value else {
+                    return None;
+                };
+                let integer = BigInteger::parse(digits)?;
+                let start = index.offset_of(&root.as_ref()?.get_path(path)?.start());
+                big_integers
+                    .iter()
+                    .any(|range| range.contains(&start))
+                    .then_some(Value::BigInteger(integer))
+            });
+        }
+        Ok(value)
+    }
+
+    /// Interprets this value as a decimal of arbitrary precision, if it is a finite number (big integers
+    /// included) or a string holding one
+    #[cfg(feature = "bigdecimal")]
+    pub fn as_big_decimal(&self) -> Option<BigDecimal> {
+        match self {
+            Value::Integer(integer) => Some(BigDecimal::from(*integer)),
+            Value::BigInteger(integer) => BigDecimal::from_str(integer.as_str()).ok(),
+            Value::Decimal(decimal) if decimal.is_finite() => {
+                BigDecimal::from_str(&decimal.to_string()).ok()
+            }
+            Value::String(string) => BigDecimal::from_str(string).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// The byte ranges of the integers out of the range of an `i64`
+fn overflowing_integers(document: &str) -> Vec<Range<usize>> {
+    let index = LineIndex::new(document);
+    Kson::analyze(document, None)
+        .tokens()
+        .into_iter()
+        .filter(|token| matches!(token.token_type(), TokenType::Number))
+        .map(|token| index.offset_of(&token.start())..index.offset_of(&token.end()))
+        .filter(|range| {
+            let number = &document[range.clone()];
+            !number.contains(['.', 'e', 'E']) && number.parse::<i64>().is_err()
+        })
+        .collect()
+}
+
+fn without_separators(number: &str) -> String {
+    number.chars().filter(|&c| c != '_' && c != ',').collect()
+}
+
+/// The byte ranges of the numbers written with digit separators, outside of strings, comments and embed
+/// blocks
+fn separated_numbers(document: &str) -> Vec<Range<usize>> {
+    let index = LineIndex::new(document);
+    let mut protected: Vec<Range<usize>> = Kson::analyze(document, None)
+        .tokens()
+        .into_iter()
+        .filter(|token| {
+            matches!(
+                token.token_type(),
+                TokenType::Comment
+                    | TokenType::StringContent
+                    | TokenType::EmbedTag
+                    | TokenType::EmbedContent
+            )
+        })
+        .map(|token| index.offset_of(&token.start())..index.offset_of(&token.end()))
+        .collect();
+    protected.sort_by_key(|range| range.start);
+
+    let bytes = document.as_bytes();
+    let mut numbers = Vec::new();
+    let mut protected = protected.into_iter().peekable();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        while protected.next_if(|range| range.end <= offset).is_some() {}
+        if let Some(range) = protected.peek()
+            && range.contains(&offset)
+        {
+            offset = range.end;
+            continue;
+        }
+        if !bytes[offset].is_ascii_digit() || (offset > 0 && is_word_byte(bytes[offset - 1])) {
+            offset += 1;
+            continue;
+        }
+        let (end, has_separators) = scan_number(bytes, offset, is_property_value(document, offset));
+        let overlaps_protected = protected.peek().is_some_and(|range| range.start < end);
+        let ends_cleanly = end == bytes.len() || !is_word_byte(bytes[end]);
+        if has_separators && ends_cleanly && !overlaps_protected {
+            numbers.push(offset..end);
+        }
+        offset = end;
+    }
+    numbers
+}
+
+/// Scans the digits of a number starting at `start`, with its separators, fraction and exponent,
+/// returning where it ends and whether it has separators
+fn scan_number(bytes: &[u8], start: usize, allow_commas: bool) -> (usize, bool) {
+    let digit = |offset: usize| bytes.get(offset).is_some_and(u8::is_ascii_digit);
+    let digits = |mut offset: usize| {
+        while digit(offset) {
+            offset += 1;
+        }
+        offset
+    };
+
+    let mut end = digits(start);
+    let mut has_separators = false;
+    let mut first_group = end - start;
+    loop {
+        match bytes.get(end) {
+            Some(b'_') if digit(end + 1) => end = digits(end + 1),
+            Some(b',') if allow_commas && first_group <= 3 && digits(end + 1) == end + 4 => {
+                end += 4
+            }
+            _ => break,
+        }
+        has_separators = true;
+        // Only the group before the first separator may be shorter than three digits
+        first_group = 3;
+    }
+    if bytes.get(end) == Some(&b'.') && digit(end + 1) {
+        end = digits(end + 1);
+        while bytes.get(end) == Some(&b'_') && digit(end + 1) {
+            end = digits(end + 1);
+            has_separators = true;
+        }
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        if digit(end + 1 + sign) {
+            end = digits(end + 1 + sign);
+        }
+    }
+    (end, has_separators)
+}
+
+/// Whether the number starting at `offset` follows a `:` (possibly with a sign in between)
+fn is_property_value(document: &str, offset: usize) -> bool {
+    document[..offset]
+        .trim_end_matches(['-', '+'])
+        .trim_end_matches([' ', '\t'])
+        .ends_with(':')
+}
+
+/// Whether the byte can be part of a word or number, so that a number can't start or end next to it
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'.' || byte >= 0x80
+}
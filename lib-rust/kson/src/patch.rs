@@ -0,0 +1,515 @@
+//! Patches: lists of edits to apply to a value, the KSON equivalent of
+//! [JSON Patch](https://www.rfc-editor.org/rfc/rfc6902).
+//!
+//! A patch document is a list of operations, each with an `op` and a `path` (a [`KsonPath`] written as
+//! a JSON Pointer), plus the `value` to `add`, `replace` with or `test` against, or the path to `move` or
+//! `copy` a value `from`:
+//!
+//! ```kson
+//! - op: test
+//!   path: /server/port
+//!   value: 80
+//! - op: replace
+//!   path: /server/port
+//!   value: 8080
+//! - op: add
+//!   path: /plugins/-
+//!   value: metrics
+//! ```
+//!
+//! Patches are applied with [`Value::apply_patch`], which applies all the operations or none, and can be
+//! generated from the differences between two values with [`Patch::diff`], so that changes to a
+//! configuration can be reviewed and recorded before they are applied:
+//!
+//! ```no_run
+//! use kson_rs::patch::Patch;
+//! use kson_rs::value::Value;
+//!
+//! let before = Value::parse("server: { port: 80 }\nplugins: [auth]").unwrap();
+//! let after = Value::parse("server: { port: 8080 }\nplugins: [auth, metrics]").unwrap();
+//! let patch = Patch::diff(&before, &after);
+//! println!("{}", patch.to_value().to_kson());
+//!
+//! let mut value = before.clone();
+//! value.apply_patch(&patch).unwrap();
+//! assert_eq!(value, after);
+//! ```
+//!
+//! Like in JSON Patch, `-` as the last segment of the path of an `add` appends to an array, and `test`
+//! compares values the way JSON does (integers equal decimals of the same value, and key order doesn't
+//! matter). Key order isn't part of a patch either: added properties are appended to their object.
+
+use crate::KsonValue;
+use crate::error::KsonErrors;
+use crate::path::{KsonPath, LookupError, PathSegment};
+use crate::value::{Map, Value, values_equal};
+
+/// An operation of a [`Patch`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    /// Sets the property at `path`, or inserts the array element at `path`, shifting the following ones
+    Add {
+        path: KsonPath,
+        value: Value,
+    },
+    Remove {
+        path: KsonPath,
+    },
+    /// Replaces the existing value at `path`
+    Replace {
+        path: KsonPath,
+        value: Value,
+    },
+    /// Removes the value at `from` and adds it at `path`
+    Move {
+        from: KsonPath,
+        path: KsonPath,
+    },
+    /// Adds a copy of the value at `from` at `path`
+    Copy {
+        from: KsonPath,
+        path: KsonPath,
+    },
+    /// Fails the patch unless the value at `path` is `value`
+    Test {
+        path: KsonPath,
+        value: Value,
+    },
+}
+
+impl Operation {
+    /// The name of the operation in patch documents, e.g. `add`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Add { .. } => "add",
+            Operation::Remove { .. } => "remove",
+            Operation::Replace { .. } => "replace",
+            Operation::Move { .. } => "move",
+            Operation::Copy { .. } => "copy",
+            Operation::Test { .. } => "test",
+        }
+    }
+
+    pub fn path(&self) -> &KsonPath {
+        match self {
+            Operation::Add { path, .. }
+            | Operation::Remove { path }
+            | Operation::Replace { path, .. }
+            | Operation::Move { path, .. }
+            | Operation::Copy { path, .. }
+            | Operation::Test { path, .. } => path,
+        }
+    }
+}
+
+/// A list of operations (see the [module documentation](self))
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Patch {
+    pub operations: Vec<Operation>,
+}
+
+/// The error returned when a patch can't be read or applied
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchError {
+    /// The patch document isn't valid KSON
+    Syntax(KsonErrors),
+    /// The operation at this index of the patch is malformed, or can't be applied for the given reason
+    Invalid { operation: usize, message: String },
+    /// A path of the operation at this index can't be followed
+    Lookup {
+        operation: usize,
+        error: LookupError,
+    },
+    /// The `test` operation at this index failed
+    TestFailed { operation: usize, path: KsonPath },
+}
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchError::Syntax(errors) => write!(f, "invalid patch: {errors}"),
+            PatchError::Invalid { operation, message } => {
+                write!(f, "operation {operation} of the patch: {message}")
+            }
+            PatchError::Lookup { operation, error } => {
+                write!(f, "operation {operation} of the patch: {error}")
+            }
+            PatchError::TestFailed { operation, path } => {
+                write!(
+                    f,
+                    "operation {operation} of the patch: the value at `{path}` isn't the expected one"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl Patch {
+    pub fn new(operations: Vec<Operation>) -> Self {
+        Self { operations }
+    }
+
+    /// Parses a patch document
+    pub fn parse(document: &str) -> Result<Self, PatchError> {
+        Self::from_value(&Value::parse(document).map_err(PatchError::Syntax)?)
+    }
+
+    /// Reads a patch from a list of operations
+    pub fn from_value(value: &Value) -> Result<Self, PatchError> {
+        let Value::Array(elements) = value else {
+            return Err(PatchError::Invalid {
+                operation: 0,
+                message: "a patch must be a list of operations".to_string(),
+            });
+        };
+        let operations = elements
+            .iter()
+            .enumerate()
+            .map(|(index, element)| {
+                read_operation(element).map_err(|message| PatchError::Invalid {
+                    operation: index,
+                    message,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { operations })
+    }
+
+    /// Renders the patch as a patch document
+    pub fn to_value(&self) -> Value {
+        Value::list(self.operations.iter().map(|operation| {
+            let mut map = Map::new();
+            map.insert("op", Value::from(operation.name()));
+            if let Operation::Move { from, .. } | Operation::Copy { from, .. } = operation {
+                map.insert("from", Value::String(from.to_string()));
+            }
+            map.insert("path", Value::String(operation.path().to_string()));
+            if let Operation::Add { value, .. }
+            | Operation::Replace { value, .. }
+            | Operation::Test { value, .. } = operation
+            {
+                map.insert("value", value.clone());
+            }
+            Value::Object(map)
+        }))
+    }
+
+    /// A patch turning `from` into `to` (up to key order), with an operation for each property that was
+    /// added, removed or changed, array elements compared by index
+    pub fn diff(from: &Value, to: &Value) -> Self {
+        let mut operations = Vec::new();
+        diff_values(from, to, &mut operations);
+        Self { operations }
+    }
+}
+
+impl Value {
+    /// Applies the operations of the patch in order. If one fails, the error is returned and this value
+    /// is left unchanged.
+    pub fn apply_patch(&mut self, patch: &Patch) -> Result<(), PatchError> {
+        // Objects are shared between clones until modified, so only the patched paths are copied
+        let mut patched = self.clone();
+        for (index, operation) in patch.operations.iter().enumerate() {
+            apply(&mut patched, operation).map_err(|failure| match failure {
+                Failure::Invalid(message) => PatchError::Invalid {
+                    operation: index,
+                    message,
+                },
+                Failure::Lookup(error) => PatchError::Lookup {
+                    operation: index,
+                    error,
+                },
+                Failure::TestFailed => PatchError::TestFailed {
+                    operation: index,
+                    path: operation.path().clone(),
+                },
+            })?;
+        }
+        *self = patched;
+        Ok(())
+    }
+}
+
+impl KsonValue {
+    /// Returns this value with the patch applied, see [`Value::apply_patch`]
+    pub fn apply_patch(&self, patch: &Patch) -> Result<Value, PatchError> {
+        let mut value = self.to_value();
+        value.apply_patch(patch)?;
+        Ok(value)
+    }
+}
+
+fn read_operation(value: &Value) -> Result<Operation, String> {
+    let Value::Object(map) = value else {
+        return Err("an operation must be an object".to_string());
+    };
+    let path = |name: &str| -> Result<KsonPath, String> {
+        match map.get(name) {
+            Some(Value::String(pointer)) => KsonPath::parse(pointer)
+                .map_err(|error| format!("invalid `{name}` pointer: {error}")),
+            Some(_) => Err(format!("`{name}` must be a JSON Pointer string")),
+            None => Err(format!("missing `{name}`")),
+        }
+    };
+    let value = || map.get("value").cloned().ok_or("missing `value`");
+    Ok(match map.get("op") {
+        Some(Value::String(op)) => match op.as_str() {
+            "add" => Operation::Add {
+                path: path("path")?,
+                value: value()?,
+            },
+            "remove" => Operation::Remove {
+                path: path("path")?,
+            },
+            "replace" => Operation::Replace {
+                path: path("path")?,
+                value: value()?,
+            },
+            "move" => Operation::Move {
+                from: path("from")?,
+                path: path("path")?,
+            },
+            "copy" => Operation::Copy {
+                from: path("from")?,
+                path: path("path")?,
+            },
+            "test" => Operation::Test {
+                path: path("path")?,
+                value: value()?,
+            },
+            other => return Err(format!("unknown operation `{other}`")),
+        },
+        Some(_) => return Err("`op` must be a string".to_string()),
+        None => return Err("missing `op`".to_string()),
+    })
+}
+
+/// Why an operation failed, before it's attributed to its index in the patch
+enum Failure {
+    Invalid(String),
+    Lookup(LookupError),
+    TestFailed,
+}
+
+impl From<LookupError> for Failure {
+    fn from(error: LookupError) -> Self {
+        Failure::Lookup(error)
+    }
+}
+
+fn apply(document: &mut Value, operation: &Operation) -> Result<(), Failure> {
+    match operation {
+        Operation::Add { path, value } => add(document, path, value.clone()),
+        Operation::Remove { path } => remove(document, path).map(drop),
+        Operation::Replace { path, value } => {
+            *lookup_mut(document, path)? = value.clone();
+            Ok(())
+        }
+        Operation::Move { from, path } => {
+            if path.starts_with(from) && path != from {
+                return Err(Failure::Invalid(format!(
+                    "can't move the value at `{from}` into itself"
+                )));
+            }
+            let value = remove(document, from)?;
+            add(document, path, value)
+        }
+        Operation::Copy { from, path } => {
+            let value = lookup_mut(document, from)?.clone();
+            add(document, path, value)
+        }
+        Operation::Test { path, value } => {
+            if values_equal(lookup_mut(document, path)?, value) {
+                Ok(())
+            } else {
+                Err(Failure::TestFailed)
+            }
+        }
+    }
+}
+
+fn add(document: &mut Value, path: &KsonPath, value: Value) -> Result<(), Failure> {
+    let Some((segment, parent)) = split_last(path) else {
+        *document = value;
+        return Ok(());
+    };
+    match lookup_mut(document, &parent)? {
+        Value::Object(map) => {
+            map.insert(segment.to_string(), value);
+        }
+        Value::Array(elements) => {
+            let index = if segment == PathSegment::Key("-".to_string()) {
+                elements.len()
+            } else {
+                array_index(&segment)
+                    .filter(|&index| index <= elements.len())
+                    .ok_or_else(|| LookupError::Missing {
+                        parent: parent.clone(),
+                        segment: segment.clone(),
+                    })?
+            };
+            elements.insert(index, value);
+        }
+        _ => return Err(LookupError::NotAContainer { parent, segment }.into()),
+    }
+    Ok(())
+}
+
+fn remove(document: &mut Value, path: &KsonPath) -> Result<Value, Failure> {
+    let Some((segment, parent)) = split_last(path) else {
+        return Err(Failure::Invalid("can't remove the root".to_string()));
+    };
+    let removed = match lookup_mut(document, &parent)? {
+        Value::Object(map) => map.remove(&segment.to_string()),
+        Value::Array(elements) => array_index(&segment)
+            .filter(|&index| index < elements.len())
+            .map(|index| elements.remove(index)),
+        _ => return Err(LookupError::NotAContainer { parent, segment }.into()),
+    };
+    removed.ok_or_else(|| LookupError::Missing { parent, segment }.into())
+}
+
+/// Looks the path up like [`Value::get_path`], saying which segment couldn't be followed
+fn lookup_mut<'a>(value: &'a mut Value, path: &KsonPath) -> Result<&'a mut Value, LookupError> {
+    let mut current = value;
+    for (depth, segment) in path.segments().iter().enumerate() {
+        let parent = || {
+            path.segments()[..depth]
+                .iter()
+                .cloned()
+                .collect::<KsonPath>()
+        };
+        current = match current {
+            Value::Object(map) => map.get_mut(&segment.to_string()),
+            Value::Array(elements) => {
+                array_index(segment).and_then(|index| elements.get_mut(index))
+            }
+            _ => {
+                return Err(LookupError::NotAContainer {
+                    parent: parent(),
+                    segment: segment.clone(),
+                });
+            }
+        }
+        .ok_or_else(|| LookupError::Missing {
+            parent: parent(),
+            segment: segment.clone(),
+        })?;
+    }
+    Ok(current)
+}
+
+fn split_last(path: &KsonPath) -> Option<(PathSegment, KsonPath)> {
+    Some((path.last()?.clone(), path.parent()?))
+}
+
+fn array_index(segment: &PathSegment) -> Option<usize> {
+    match segment {
+        PathSegment::Index(index) => Some(*index),
+        PathSegment::Key(key) => key.parse().ok(),
+    }
+}
+
+/// A step of diffing two values
+enum DiffTask<'a> {
+    /// Diffing the values reached through `segment` from the values at the given depth
+    Diff {
+        from: &'a Value,
+        to: &'a Value,
+        depth: usize,
+        segment: Option<PathSegment>,
+    },
+    Emit(Operation),
+}
+
+fn diff_values(from: &Value, to: &Value, operations: &mut Vec<Operation>) {
+    // Nested values are diffed from a stack of tasks rather than recursively, which keeps the operations in
+    // the same order: those of a property (or element) come before those of the next one
+    let mut path = KsonPath::root();
+    let mut pending = vec![DiffTask::Diff {
+        from,
+        to,
+        depth: 0,
+        segment: None,
+    }];
+    while let Some(task) = pending.pop() {
+        let (from, to) = match task {
+            DiffTask::Emit(operation) => {
+                operations.push(operation);
+                continue;
+            }
+            DiffTask::Diff {
+                from,
+                to,
+                depth,
+                segment,
+            } => {
+                while path.len() > depth {
+                    path.pop();
+                }
+                if let Some(segment) = segment {
+                    path.push(segment);
+                }
+                (from, to)
+            }
+        };
+        if from == to {
+            continue;
+        }
+
+        let depth = path.len();
+        let mut tasks = Vec::new();
+        match (from, to) {
+            (Value::Object(from), Value::Object(to)) => {
+                for (key, _) in from.iter().filter(|(key, _)| !to.contains_key(key)) {
+                    tasks.push(DiffTask::Emit(Operation::Remove {
+                        path: path.clone().key(key.as_str()),
+                    }));
+                }
+                for (key, value) in to.iter() {
+                    tasks.push(match from.get(key) {
+                        Some(existing) => DiffTask::Diff {
+                            from: existing,
+                            to: value,
+                            depth,
+                            segment: Some(PathSegment::Key(key.clone())),
+                        },
+                        None => DiffTask::Emit(Operation::Add {
+                            path: path.clone().key(key.as_str()),
+                            value: value.clone(),
+                        }),
+                    });
+                }
+            }
+            (Value::Array(from), Value::Array(to)) => {
+                for (index, (existing, value)) in from.iter().zip(to).enumerate() {
+                    tasks.push(DiffTask::Diff {
+                        from: existing,
+                        to: value,
+                        depth,
+                        segment: Some(PathSegment::Index(index)),
+                    });
+                }
+                for (index, value) in to.iter().enumerate().skip(from.len()) {
+                    tasks.push(DiffTask::Emit(Operation::Add {
+                        path: path.clone().index(index),
+                        value: value.clone(),
+                    }));
+                }
+                // From the end, so that the indices of the elements still to remove don't shift
+                for index in (to.len()..from.len()).rev() {
+                    tasks.push(DiffTask::Emit(Operation::Remove {
+                        path: path.clone().index(index),
+                    }));
+                }
+            }
+            _ => tasks.push(DiffTask::Emit(Operation::Replace {
+                path: path.clone(),
+                value: to.clone(),
+            })),
+        }
+        pending.extend(tasks.into_iter().rev());
+    }
+}
@@ -0,0 +1,255 @@
+//! Paths identifying a value inside a document.
+//!
+//! A path is displayed and parsed as an RFC 6901 JSON Pointer, in which `~` and `/` are escaped as `~0`
+//! and `~1` and any other character (including `.`, `[` and non-ASCII ones) is used as is. Pointers don't
+//! distinguish keys from indices, so when parsing, a token that is a canonical array index (`0`, `12`, but
+//! not `012`) becomes [`PathSegment::Index`]. Lookups accept such indices as object keys too.
+//!
+//! Values are looked up by path with [`Value::get_path`](crate::value::Value::get_path) and
+//! [`KsonValue::get_path`](crate::KsonValue::get_path), or straight from a pointer with their `pointer`
+//! methods. [`KsonValue::try_pointer`](crate::KsonValue::try_pointer) reports which segment of the path
+//! couldn't be followed, as a [`LookupError`].
+//!
+//! [`KsonPath::to_query`] renders the path in the [query syntax](crate::query) instead, which preserves
+//! the distinction.
+
+use std::str::FromStr;
+
+use crate::pointer::{PointerError, PointerGlob};
+
+/// One step of a [`KsonPath`]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PathSegment {
+    /// An object property
+    Key(String),
+    /// An array element
+    Index(usize),
+}
+
+impl std::fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathSegment::Key(key) => f.write_str(key),
+            PathSegment::Index(index) => write!(f, "{index}"),
+        }
+    }
+}
+
+/// The location of a value inside a document, as the sequence of keys and indices leading to it from the
+/// root. It is displayed as a JSON Pointer (e.g. `/dependencies/0/name`).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct KsonPath {
+    segments: Vec<PathSegment>,
+}
+
+impl KsonPath {
+    /// The path of the document's root value
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    /// Parses a JSON Pointer such as `/servers/0/name` (the empty string being the root)
+    pub fn parse(pointer: &str) -> Result<Self, PointerError> {
+        if let Some(c) = pointer.chars().next()
+            && c != '/'
+        {
+            return Err(PointerError::BadStart(c));
+        }
+
+        let mut segments = Vec::new();
+        for token in pointer.split('/').skip(1) {
+            let mut key = String::with_capacity(token.len());
+            let mut chars = token.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '~' => match chars.next() {
+                        Some('0') => key.push('~'),
+                        Some('1') => key.push('/'),
+                        Some(other) => return Err(PointerError::InvalidEscape(other)),
+                        None => return Err(PointerError::IncompleteEscape),
+                    },
+                    c => key.push(c),
+                }
+            }
+            segments.push(match key.parse() {
+                Ok(index) if is_canonical_index(&key) => PathSegment::Index(index),
+                _ => PathSegment::Key(key),
+            });
+        }
+        Ok(Self { segments })
+    }
+
+    /// Renders the path as a [`PathQuery`](crate::query::PathQuery) matching exactly this path, e.g.
+    /// `$.servers[0]['key.with.dots']`
+    pub fn to_query(&self) -> String {
+        let mut query = String::from("$");
+        for segment in &self.segments {
+            match segment {
+                PathSegment::Index(index) => query.push_str(&format!("[{index}]")),
+                PathSegment::Key(key) if is_plain_query_key(key) => {
+                    query.push('.');
+                    query.push_str(key);
+                }
+                PathSegment::Key(key) => {
+                    query.push_str("['");
+                    for c in key.chars() {
+                        if matches!(c, '\\' | '\'') {
+                            query.push('\\');
+                        }
+                        query.push(c);
+                    }
+                    query.push_str("']");
+                }
+            }
+        }
+        query
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// The last segment of the path, i.e. the key or index of the value within its parent
+    pub fn last(&self) -> Option<&PathSegment> {
+        self.segments.last()
+    }
+
+    /// The path of the parent value, if this isn't the root
+    pub fn parent(&self) -> Option<KsonPath> {
+        let (_, parent) = self.segments.split_last()?;
+        Some(KsonPath {
+            segments: parent.to_vec(),
+        })
+    }
+
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.segments.push(PathSegment::Key(key.into()));
+        self
+    }
+
+    pub fn index(mut self, index: usize) -> Self {
+        self.segments.push(PathSegment::Index(index));
+        self
+    }
+
+    pub fn starts_with(&self, prefix: &KsonPath) -> bool {
+        self.segments.starts_with(&prefix.segments)
+    }
+
+    /// Whether this path matches the given JsonPointerGlob
+    pub fn matches(&self, glob: &PointerGlob) -> bool {
+        let segments: Vec<String> = self.segments.iter().map(ToString::to_string).collect();
+        glob.matches(&segments)
+    }
+
+    /// The path of the child with the given segment
+    pub(crate) fn join(mut self, segment: PathSegment) -> Self {
+        self.segments.push(segment);
+        self
+    }
+
+    pub(crate) fn push(&mut self, segment: PathSegment) {
+        self.segments.push(segment);
+    }
+
+    pub(crate) fn pop(&mut self) {
+        self.segments.pop();
+    }
+}
+
+impl std::fmt::Display for KsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for segment in &self.segments {
+            let segment = segment.to_string();
+            write!(f, "/{}", segment.replace('~', "~0").replace('/', "~1"))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for KsonPath {
+    type Err = PointerError;
+
+    fn from_str(pointer: &str) -> Result<Self, Self::Err> {
+        Self::parse(pointer)
+    }
+}
+
+impl FromIterator<PathSegment> for KsonPath {
+    fn from_iter<T: IntoIterator<Item = PathSegment>>(iter: T) -> Self {
+        Self {
+            segments: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// The error returned when looking a path up fails, saying which segment couldn't be followed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LookupError {
+    /// The pointer is malformed
+    Pointer(PointerError),
+    /// The object or array at `parent` has no property or element `segment`
+    Missing {
+        parent: KsonPath,
+        segment: PathSegment,
+    },
+    /// The value at `parent` is neither an object nor an array, so `segment` can't be looked up in it
+    NotAContainer {
+        parent: KsonPath,
+        segment: PathSegment,
+    },
+}
+
+impl std::fmt::Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LookupError::Pointer(error) => write!(f, "{error}"),
+            LookupError::Missing { parent, segment } => {
+                write!(f, "there is no `{segment}` in the value at `{parent}`")
+            }
+            LookupError::NotAContainer { parent, segment } => write!(
+                f,
+                "can't look `{segment}` up in the value at `{parent}`, which is neither an object nor an array"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+impl From<PointerError> for LookupError {
+    fn from(error: PointerError) -> Self {
+        LookupError::Pointer(error)
+    }
+}
+
+/// Whether the token is an array index as written in a JSON Pointer, i.e. without leading zeros
+fn is_canonical_index(token: &str) -> bool {
+    token == "0"
+        || (!token.starts_with('0')
+            && !token.is_empty()
+            && token.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Whether the key can be written after a `.` in a query without being misread
+fn is_plain_query_key(key: &str) -> bool {
+    !key.is_empty()
+        && key != "*"
+        && key != "**"
+        && !key.bytes().all(|b| b.is_ascii_digit())
+        && !key.contains(|c: char| {
+            matches!(c, '.' | '[' | ']' | '\'' | '"' | '\\') || c.is_whitespace()
+        })
+}
@@ -0,0 +1,168 @@
+//! JsonPointerGlob patterns, the path syntax also used by [`EmbedRule`](crate::EmbedRule).
+//!
+//! A pattern is an RFC 6901 JSON Pointer whose tokens may also be:
+//! - `*`, matching any single key or array index
+//! - `**`, matching zero or more levels
+//! - a glob like `*admin*` or `file?`, where `*` matches any run of characters and `?` a single one
+//!
+//! Besides the RFC 6901 escapes (`~0` for `~` and `~1` for `/`), the backslash escapes `\*`, `\?` and
+//! `\\` can be used to match those characters literally.
+
+use std::str::FromStr;
+
+/// A parsed JsonPointerGlob pattern
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PointerGlob {
+    tokens: Vec<GlobToken>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum GlobToken {
+    Literal(String),
+    Wildcard,
+    RecursiveDescent,
+    Pattern(Vec<PatternChar>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PatternChar {
+    Literal(char),
+    /// `*`
+    Any,
+    /// `?`
+    One,
+}
+
+/// The error returned when a JsonPointerGlob pattern is malformed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PointerError {
+    /// A non-empty pointer must start with `/`
+    BadStart(char),
+    /// The pointer ends in the middle of an escape sequence
+    IncompleteEscape,
+    /// A `~` or `\` is followed by a character that can't be escaped
+    InvalidEscape(char),
+}
+
+impl std::fmt::Display for PointerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PointerError::BadStart(c) => write!(f, "pointer must start with `/`, found `{c}`"),
+            PointerError::IncompleteEscape => {
+                write!(f, "pointer ends with an incomplete escape sequence")
+            }
+            PointerError::InvalidEscape(c) => write!(f, "invalid escape sequence before `{c}`"),
+        }
+    }
+}
+
+impl std::error::Error for PointerError {}
+
+impl PointerGlob {
+    pub fn parse(pattern: &str) -> Result<Self, PointerError> {
+        if let Some(c) = pattern.chars().next()
+            && c != '/'
+        {
+            return Err(PointerError::BadStart(c));
+        }
+
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars().peekable();
+        while chars.next().is_some() {
+            // We just consumed a `/`: parse the token up to the next one
+            let mut token = Vec::new();
+            while let Some(&c) = chars.peek() {
+                if c == '/' {
+                    break;
+                }
+                chars.next();
+                let parsed = match c {
+                    '~' => match chars.next() {
+                        Some('0') => PatternChar::Literal('~'),
+                        Some('1') => PatternChar::Literal('/'),
+                        Some(other) => return Err(PointerError::InvalidEscape(other)),
+                        None => return Err(PointerError::IncompleteEscape),
+                    },
+                    '\\' => match chars.next() {
+                        Some(escaped @ ('*' | '?' | '\\')) => PatternChar::Literal(escaped),
+                        Some(other) => return Err(PointerError::InvalidEscape(other)),
+                        None => return Err(PointerError::IncompleteEscape),
+                    },
+                    '*' => PatternChar::Any,
+                    '?' => PatternChar::One,
+                    other => PatternChar::Literal(other),
+                };
+                token.push(parsed);
+            }
+            tokens.push(classify(token));
+        }
+        Ok(Self { tokens })
+    }
+
+    /// Whether the path (a sequence of object keys and array indices) matches this pattern
+    pub fn matches<S: AsRef<str>>(&self, path: &[S]) -> bool {
+        matches_tokens(&self.tokens, path)
+    }
+}
+
+impl FromStr for PointerGlob {
+    type Err = PointerError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        Self::parse(pattern)
+    }
+}
+
+fn classify(chars: Vec<PatternChar>) -> GlobToken {
+    let literal: Option<String> = chars
+        .iter()
+        .map(|c| match c {
+            PatternChar::Literal(c) => Some(*c),
+            _ => None,
+        })
+        .collect();
+    match (chars.as_slice(), literal) {
+        ([PatternChar::Any, PatternChar::Any], _) => GlobToken::RecursiveDescent,
+        ([PatternChar::Any], _) => GlobToken::Wildcard,
+        (_, Some(literal)) => GlobToken::Literal(literal),
+        (_, None) => GlobToken::Pattern(chars),
+    }
+}
+
+fn matches_tokens<S: AsRef<str>>(tokens: &[GlobToken], path: &[S]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return path.is_empty();
+    };
+    match token {
+        GlobToken::RecursiveDescent => {
+            (0..=path.len()).any(|skipped| matches_tokens(rest, &path[skipped..]))
+        }
+        _ => {
+            let Some((segment, path_rest)) = path.split_first() else {
+                return false;
+            };
+            let segment_matches = match token {
+                GlobToken::Literal(literal) => literal == segment.as_ref(),
+                GlobToken::Pattern(pattern) => {
+                    let segment: Vec<char> = segment.as_ref().chars().collect();
+                    matches_glob(pattern, &segment)
+                }
+                _ => true,
+            };
+            segment_matches && matches_tokens(rest, path_rest)
+        }
+    }
+}
+
+fn matches_glob(pattern: &[PatternChar], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((PatternChar::Any, rest)) => {
+            (0..=text.len()).any(|skipped| matches_glob(rest, &text[skipped..]))
+        }
+        Some((PatternChar::One, rest)) => !text.is_empty() && matches_glob(rest, &text[1..]),
+        Some((PatternChar::Literal(c), rest)) => {
+            text.first() == Some(c) && matches_glob(rest, &text[1..])
+        }
+    }
+}
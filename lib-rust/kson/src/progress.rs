@@ -0,0 +1,41 @@
+//! Progress reports of batch operations, for tools rendering progress bars (or sending LSP
+//! `$/progress` notifications) while whole workspaces are processed.
+//!
+//! Batch operations take a callback, called with a [`Progress`] every time a file is done. Files may
+//! be processed on several threads, so the callback must be `Sync`, and reports may arrive out of order
+//! (`done` is always the number of files done at the time of the report):
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//! use kson_rs::cancel::CancellationToken;
+//! use kson_rs::config::ProjectConfig;
+//!
+//! let config = ProjectConfig::new(".");
+//! let problems = Kson::validate_workspace_with(".", &config, &CancellationToken::new(), &|progress| {
+//!     eprintln!("[{}/{}] {}", progress.done, progress.total, progress.file.display());
+//! });
+//! ```
+
+use std::path::Path;
+
+/// The progress of a batch operation, reported when a file is done
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress<'a> {
+    /// The number of files done, including this one
+    pub done: usize,
+    /// The number of files to process
+    pub total: usize,
+    /// The file just done
+    pub file: &'a Path,
+}
+
+impl Progress<'_> {
+    /// The share of the files done, between 0 and 1 (1 when there are no files at all)
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.done as f64 / self.total as f64
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! Path queries: paths that may contain wildcards and filters, and so match any number of values.
+//!
+//! A query is a sequence of steps, optionally preceded by `$` (the root):
+//! - `.name` or `['name']`, selecting a property (or an array element, if `name` is an index)
+//! - `[0]`, selecting an array element
+//! - `.*` or `[*]`, selecting every property or element
+//! - `.**`, selecting the current value and all of its descendants
+//! - `[?(@.enabled)]`, selecting the properties or elements for which the predicate holds
+//!
+//! The first step may omit its leading `.` (e.g. `servers[*].host`). A predicate consists of a path
+//! relative to the candidate (`@`, `@.name`, `@['name'][0]`, ...), which on its own tests that the
+//! value exists and is neither `null` nor `false`. It may be followed by `==` or `!=` and a literal
+//! (`'text'`, `"text"`, a number, `true`, `false` or `null`) to compare the value with.
+
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use crate::path::{KsonPath, PathSegment};
+use crate::value::{Value, values_equal};
+
+/// A parsed path query
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathQuery {
+    steps: Vec<Step>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+    Wildcard,
+    RecursiveDescent,
+    Filter(Predicate),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Predicate {
+    path: Vec<PathSegment>,
+    comparison: Option<(Comparison, Value)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+}
+
+/// The error returned when a path query is malformed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryError {
+    /// What went wrong
+    pub kind: QueryErrorKind,
+    /// Byte offset of the problem in the query
+    pub offset: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryErrorKind {
+    /// A character that can't appear at this point of the query
+    UnexpectedCharacter(char),
+    /// The query ends in the middle of a step
+    UnexpectedEnd,
+    /// A property name is empty
+    EmptyKey,
+    /// A literal in a predicate is not a string, number, boolean or `null`
+    InvalidLiteral(String),
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            QueryErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character `{c}`")?,
+            QueryErrorKind::UnexpectedEnd => write!(f, "unexpected end of query")?,
+            QueryErrorKind::EmptyKey => write!(f, "empty property name")?,
+            QueryErrorKind::InvalidLiteral(literal) => write!(f, "invalid literal `{literal}`")?,
+        }
+        write!(f, " at offset {}", self.offset)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl PathQuery {
+    pub fn parse(query: &str) -> Result<Self, QueryError> {
+        let mut parser = Parser { query, offset: 0 };
+        let mut steps = Vec::new();
+        if matches!(query.as_bytes(), [b'$'] | [b'$', b'.' | b'[', ..]) {
+            parser.offset = 1;
+        }
+        if !matches!(parser.peek(), None | Some('.' | '[')) {
+            steps.push(parser.dotted_step()?);
+        }
+        while let Some(c) = parser.peek() {
+            parser.offset += c.len_utf8();
+            match c {
+                '.' => steps.push(parser.dotted_step()?),
+                '[' => steps.push(parser.bracketed_step()?),
+                _ => {
+                    return Err(parser.error_at(
+                        parser.offset - c.len_utf8(),
+                        QueryErrorKind::UnexpectedCharacter(c),
+                    ));
+                }
+            }
+        }
+        Ok(Self { steps })
+    }
+}
+
+impl FromStr for PathQuery {
+    type Err = QueryError;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        Self::parse(query)
+    }
+}
+
+impl Value {
+    /// Returns every value matched by the query, along with its path, in document order
+    pub fn query(&self, query: &PathQuery) -> Vec<(KsonPath, &Value)> {
+        query.evaluate(self)
+    }
+}
+
+/// A value that queries can navigate, without necessarily materializing it as a [`Value`]
+pub(crate) trait QueryNode: Clone {
+    /// Looks up a child by key or index, returning the segment it was actually found with (a key names
+    /// an array element if it is an index, and an index names the object property with the same key)
+    fn child(&self, segment: &PathSegment) -> Option<(PathSegment, Self)>;
+
+    /// The elements or properties of this value, in document order
+    fn children(&self) -> Vec<(PathSegment, Self)>;
+
+    /// Materializes a scalar (or subtree) to compare it in a predicate
+    fn to_value(&self) -> Cow<'_, Value>;
+}
+
+impl QueryNode for &Value {
+    fn child(&self, segment: &PathSegment) -> Option<(PathSegment, Self)> {
+        match (self, segment) {
+            (Value::Object(map), PathSegment::Key(key)) => Some((segment.clone(), map.get(key)?)),
+            (Value::Object(map), PathSegment::Index(index)) => {
+                let key = index.to_string();
+                let value = map.get(&key)?;
+                Some((PathSegment::Key(key), value))
+            }
+            (Value::Array(elements), PathSegment::Index(index)) => {
+                Some((segment.clone(), elements.get(*index)?))
+            }
+            (Value::Array(elements), PathSegment::Key(key)) => {
+                let index = key.parse().ok()?;
+                Some((PathSegment::Index(index), elements.get(index)?))
+            }
+            _ => None,
+        }
+    }
+
+    fn children(&self) -> Vec<(PathSegment, Self)> {
+        match self {
+            Value::Array(elements) => elements
+                .iter()
+                .enumerate()
+                .map(|(index, element)| (PathSegment::Index(index), element))
+                .collect(),
+            Value::Object(map) => map
+                .iter()
+                .map(|(key, property)| (PathSegment::Key(key.clone()), property))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn to_value(&self) -> Cow<'_, Value> {
+        Cow::Borrowed(*self)
+    }
+}
+
+impl PathQuery {
+    /// Returns every node matched by the query, along with its path, in document order
+    pub(crate) fn evaluate<N: QueryNode>(&self, root: N) -> Vec<(KsonPath, N)> {
+        let mut matches = vec![(KsonPath::root(), root)];
+        for step in &self.steps {
+            matches = matches
+                .into_iter()
+                .flat_map(|(path, node)| apply_step(step, path, node))
+                .collect();
+        }
+        matches
+    }
+}
+
+fn apply_step<N: QueryNode>(step: &Step, path: KsonPath, node: N) -> Vec<(KsonPath, N)> {
+    match step {
+        Step::Key(key) => node
+            .child(&PathSegment::Key(key.clone()))
+            .map(|(segment, child)| vec![(path.join(segment), child)])
+            .unwrap_or_default(),
+        Step::Index(index) => node
+            .child(&PathSegment::Index(*index))
+            .map(|(segment, child)| vec![(path.join(segment), child)])
+            .unwrap_or_default(),
+        Step::Wildcard => children(&path, &node),
+        Step::RecursiveDescent => {
+            let mut descendants = vec![(path, node)];
+            let mut index = 0;
+            while index < descendants.len() {
+                let (path, node) = &descendants[index];
+                // Insert the children right after their parent to keep document order
+                let nested = children(path, node);
+                descendants.splice(index + 1..index + 1, nested);
+                index += 1;
+            }
+            descendants
+        }
+        Step::Filter(predicate) => children(&path, &node)
+            .into_iter()
+            .filter(|(_, child)| predicate.holds(child))
+            .collect(),
+    }
+}
+
+fn children<N: QueryNode>(path: &KsonPath, node: &N) -> Vec<(KsonPath, N)> {
+    node.children()
+        .into_iter()
+        .map(|(segment, child)| (path.clone().join(segment), child))
+        .collect()
+}
+
+impl Predicate {
+    fn holds<N: QueryNode>(&self, candidate: &N) -> bool {
+        let mut node = candidate.clone();
+        for segment in &self.path {
+            match node.child(segment) {
+                Some((_, child)) => node = child,
+                None => return false,
+            }
+        }
+        let value = node.to_value();
+        match &self.comparison {
+            None => !matches!(*value, Value::Null | Value::Bool(false)),
+            Some((Comparison::Equal, literal)) => values_equal(&value, literal),
+            Some((Comparison::NotEqual, literal)) => !values_equal(&value, literal),
+        }
+    }
+}
+
+struct Parser<'a> {
+    query: &'a str,
+    offset: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.query[self.offset..].chars().next()
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.offset += expected.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), QueryError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.offset += c.len_utf8();
+                Ok(())
+            }
+            Some(c) => Err(self.error(QueryErrorKind::UnexpectedCharacter(c))),
+            None => Err(self.error(QueryErrorKind::UnexpectedEnd)),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.offset += c.len_utf8();
+        }
+    }
+
+    fn error(&self, kind: QueryErrorKind) -> QueryError {
+        self.error_at(self.offset, kind)
+    }
+
+    fn error_at(&self, offset: usize, kind: QueryErrorKind) -> QueryError {
+        QueryError { kind, offset }
+    }
+
+    /// Parses the step following a `.` (or at the start of the query)
+    fn dotted_step(&mut self) -> Result<Step, QueryError> {
+        let start = self.offset;
+        let name = self.name(false);
+        match name {
+            "" => Err(self.error_at(start, QueryErrorKind::EmptyKey)),
+            "*" => Ok(Step::Wildcard),
+            "**" => Ok(Step::RecursiveDescent),
+            name => Ok(Step::Key(name.to_string())),
+        }
+    }
+
+    /// Reads an unquoted property name, up to the next `.` or `[` (or, inside a predicate, up to the
+    /// next character that can't be part of the path)
+    fn name(&mut self, in_predicate: bool) -> &str {
+        let start = self.offset;
+        let rest = &self.query[start..];
+        let end = if in_predicate {
+            rest.find(|c: char| matches!(c, '.' | '[' | ']' | '=' | '!' | ')') || c.is_whitespace())
+        } else {
+            rest.find(['.', '['])
+        };
+        let end = end.unwrap_or(rest.len());
+        self.offset += end;
+        &self.query[start..start + end]
+    }
+
+    /// Parses the step following a `[`, up to and including the closing `]`
+    fn bracketed_step(&mut self) -> Result<Step, QueryError> {
+        self.skip_whitespace();
+        let step = match self.peek() {
+            Some('*') => {
+                self.offset += 1;
+                Step::Wildcard
+            }
+            Some('?') => {
+                self.offset += 1;
+                self.skip_whitespace();
+                self.expect('(')?;
+                let predicate = self.predicate()?;
+                self.expect(')')?;
+                Step::Filter(predicate)
+            }
+            _ => match self.segment()? {
+                PathSegment::Key(key) => Step::Key(key),
+                PathSegment::Index(index) => Step::Index(index),
+            },
+        };
+        self.skip_whitespace();
+        self.expect(']')?;
+        Ok(step)
+    }
+
+    /// Parses a quoted key or an index
+    fn segment(&mut self) -> Result<PathSegment, QueryError> {
+        match self.peek() {
+            Some(quote @ ('\'' | '"')) => Ok(PathSegment::Key(self.quoted(quote)?)),
+            Some(c) if c.is_ascii_digit() => {
+                let start = self.offset;
+                let digits = self.query[start..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(self.query.len() - start);
+                self.offset += digits;
+                let index = &self.query[start..self.offset];
+                index.parse().map(PathSegment::Index).map_err(|_| {
+                    self.error_at(start, QueryErrorKind::InvalidLiteral(index.to_string()))
+                })
+            }
+            Some(c) => Err(self.error(QueryErrorKind::UnexpectedCharacter(c))),
+            None => Err(self.error(QueryErrorKind::UnexpectedEnd)),
+        }
+    }
+
+    /// Parses a string delimited by `quote`, in which `\` escapes the next character
+    fn quoted(&mut self, quote: char) -> Result<String, QueryError> {
+        self.expect(quote)?;
+        let mut text = String::new();
+        let mut chars = self.query[self.offset..].char_indices();
+        while let Some((index, c)) = chars.next() {
+            let c = match c {
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => escaped,
+                    None => break,
+                },
+                c if c == quote => {
+                    self.offset += index + c.len_utf8();
+                    return Ok(text);
+                }
+                c => c,
+            };
+            text.push(c);
+        }
+        self.offset = self.query.len();
+        Err(self.error(QueryErrorKind::UnexpectedEnd))
+    }
+
+    fn predicate(&mut self) -> Result<Predicate, QueryError> {
+        self.skip_whitespace();
+        self.expect('@')?;
+        let mut path = Vec::new();
+        loop {
+            if self.eat('.') {
+                let start = self.offset;
+                match self.name(true) {
+                    "" => return Err(self.error_at(start, QueryErrorKind::EmptyKey)),
+                    name => path.push(PathSegment::Key(name.to_string())),
+                }
+            } else if self.eat('[') {
+                self.skip_whitespace();
+                path.push(self.segment()?);
+                self.skip_whitespace();
+                self.expect(']')?;
+            } else {
+                break;
+            }
+        }
+
+        self.skip_whitespace();
+        let comparison = if self.query[self.offset..].starts_with("==") {
+            Some(Comparison::Equal)
+        } else if self.query[self.offset..].starts_with("!=") {
+            Some(Comparison::NotEqual)
+        } else {
+            None
+        };
+        let comparison = match comparison {
+            Some(comparison) => {
+                self.offset += 2;
+                self.skip_whitespace();
+                let literal = self.literal()?;
+                self.skip_whitespace();
+                Some((comparison, literal))
+            }
+            None => None,
+        };
+        Ok(Predicate { path, comparison })
+    }
+
+    fn literal(&mut self) -> Result<Value, QueryError> {
+        if let Some(quote @ ('\'' | '"')) = self.peek() {
+            return self.quoted(quote).map(Value::String);
+        }
+
+        let start = self.offset;
+        let rest = &self.query[start..];
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == ')')
+            .unwrap_or(rest.len());
+        self.offset += end;
+        let literal = &rest[..end];
+        match literal {
+            "" => Err(self.error(match self.peek() {
+                Some(c) => QueryErrorKind::UnexpectedCharacter(c),
+                None => QueryErrorKind::UnexpectedEnd,
+            })),
+            "null" => Ok(Value::Null),
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => literal
+                .parse()
+                .map(Value::Integer)
+                .or_else(|_| literal.parse().map(Value::Decimal))
+                .map_err(|_| {
+                    self.error_at(start, QueryErrorKind::InvalidLiteral(literal.to_string()))
+                }),
+        }
+    }
+}
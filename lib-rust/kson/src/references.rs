@@ -0,0 +1,268 @@
+//! References between documents, for projects splitting their configuration across files (e.g.
+//! `include: 'common.kson'` or `$ref: 'defaults.kson#/server/port'`).
+//!
+//! Which properties hold references is configured per project, with the `references` section of the
+//! [project configuration](crate::config). References are written like the `$ref`s of JSON Schema: the
+//! path of a document, relative to the directory of the referencing document, optionally followed by `#`
+//! and a JSON Pointer to a value inside it. A reference starting with `#` points inside the referencing
+//! document, and references with a URL scheme (e.g. `https://`) are left alone.
+//!
+//! [`Kson::check_references`] reports the references of a workspace whose target doesn't exist:
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//! use kson_rs::config::ProjectConfig;
+//!
+//! let config = ProjectConfig::discover(".".as_ref()).unwrap().unwrap();
+//! for broken in Kson::check_references(".", &config).unwrap() {
+//!     let start = &broken.reference.start;
+//!     eprintln!(
+//!         "{}:{}:{}: {}",
+//!         broken.file.display(),
+//!         start.line() + 1,
+//!         start.column() + 1,
+//!         broken.message
+//!     );
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::ProjectConfig;
+use crate::path::{KsonPath, PathSegment};
+use crate::query::QueryNode;
+use crate::value::Value;
+use crate::workspace::FileDiscovery;
+use crate::{Kson, KsonValue, Position};
+
+/// A reference found in a document
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    /// The reference as written
+    pub text: String,
+    pub start: Position,
+    pub end: Position,
+    /// The path of the reference in the referencing document
+    pub path: KsonPath,
+    /// The referenced document, or `None` for a reference within the referencing document
+    pub target: Option<PathBuf>,
+    /// The JSON Pointer following the `#`, if any
+    pub pointer: Option<String>,
+}
+
+impl Reference {
+    /// Finds the references held by the given properties in the document at path `file`, in document
+    /// order. Returns no references if the document doesn't parse.
+    pub fn extract(file: &Path, document: &str, keys: &[String]) -> Vec<Reference> {
+        match Kson::analyze(document, None).kson_value() {
+            Some(root) if !keys.is_empty() => references_in(file, &root, keys),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The references held by the given properties of the document at `file`, parsed as `root`
+pub(crate) fn references_in(file: &Path, root: &KsonValue, keys: &[String]) -> Vec<Reference> {
+    let base = file.parent().unwrap_or(Path::new(""));
+    let mut references = Vec::new();
+    let mut pending = vec![(KsonPath::root(), root.clone())];
+    while let Some((path, value)) = pending.pop() {
+        let is_reference = matches!(
+            path.segments().last(),
+            Some(PathSegment::Key(key)) if keys.contains(key)
+        );
+        if let (true, KsonValue::KsonString(string)) = (is_reference, &value) {
+            let text = string.value();
+            if let Some((target, pointer)) = parse_reference(&text) {
+                references.push(Reference {
+                    target: target.map(|target| normalize(&base.join(target))),
+                    pointer: pointer.map(str::to_string),
+                    text,
+                    start: value.start(),
+                    end: value.end(),
+                    path,
+                });
+            }
+            continue;
+        }
+        pending.extend(
+            value
+                .children()
+                .into_iter()
+                .map(|(segment, child)| (path.clone().join(segment), child)),
+        );
+    }
+    references.sort_by_key(|reference| (reference.start.line(), reference.start.column()));
+    references
+}
+
+/// Splits a reference into its document and pointer, or returns `None` for references that aren't local
+/// (those with a URL scheme)
+fn parse_reference(text: &str) -> Option<(Option<&str>, Option<&str>)> {
+    let (target, pointer) = match text.split_once('#') {
+        Some((target, pointer)) => (target, Some(pointer)),
+        None => (text, None),
+    };
+    if target.contains("://") {
+        return None;
+    }
+    Some(((!target.is_empty()).then_some(target), pointer))
+}
+
+/// Resolves the `.` and `..` components of the path that can be resolved without looking at the file
+/// system, so that different spellings of the path of a document compare equal
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// A reference whose target doesn't exist, as reported by [`Kson::check_references`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct BrokenReference {
+    pub message: String,
+    /// The referencing document
+    pub file: PathBuf,
+    pub reference: Reference,
+    pub kind: BrokenReferenceKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrokenReferenceKind {
+    /// The referenced document doesn't exist, or can't be read
+    MissingDocument,
+    /// The referenced document doesn't parse, so the pointer can't be resolved
+    InvalidDocument,
+    /// The part after the `#` is not a JSON Pointer
+    InvalidPointer,
+    /// The referenced document has no value at the pointer
+    MissingValue,
+}
+
+/// What a reference points to, for [`check_reference`]
+pub(crate) enum Target<'a> {
+    Missing,
+    Invalid,
+    Document(&'a Value),
+}
+
+/// Checks that the target of the reference exists, returning the broken reference if it doesn't
+pub(crate) fn check_reference(
+    file: &Path,
+    reference: &Reference,
+    target: Target<'_>,
+) -> Option<BrokenReference> {
+    let document = match &reference.target {
+        Some(path) => path.display().to_string(),
+        None => "this document".to_string(),
+    };
+    let (kind, message) = match (target, &reference.pointer) {
+        (Target::Missing, _) => (
+            BrokenReferenceKind::MissingDocument,
+            format!("The referenced document {document} doesn't exist"),
+        ),
+        (Target::Invalid, Some(_)) => (
+            BrokenReferenceKind::InvalidDocument,
+            format!("The referenced document {document} doesn't parse"),
+        ),
+        (Target::Invalid, None) => return None,
+        (Target::Document(_), None) => return None,
+        (Target::Document(value), Some(pointer)) => match KsonPath::parse(pointer) {
+            Err(error) => (
+                BrokenReferenceKind::InvalidPointer,
+                format!("`{pointer}` is not a JSON Pointer: {error}"),
+            ),
+            Ok(path) if value.get_path(&path).is_none() => (
+                BrokenReferenceKind::MissingValue,
+                format!("There is no value at `{pointer}` in {document}"),
+            ),
+            Ok(_) => return None,
+        },
+    };
+    Some(BrokenReference {
+        message,
+        file: file.to_path_buf(),
+        reference: reference.clone(),
+        kind,
+    })
+}
+
+impl Kson {
+    /// Checks the references of the documents under `root` (as found by [`FileDiscovery`]), held by the
+    /// reference keys of the configuration, returning the broken ones by document, in document order.
+    /// Referenced documents don't need to be under `root`. Documents that don't parse are skipped, since
+    /// [`Kson::validate_workspace`] reports their errors.
+    pub fn check_references(
+        root: impl AsRef<Path>,
+        config: &ProjectConfig,
+    ) -> std::io::Result<Vec<BrokenReference>> {
+        let keys = config.reference_keys();
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // The referenced documents, loaded as needed
+        let mut targets: HashMap<PathBuf, Loaded> = HashMap::new();
+        let mut broken = Vec::new();
+        for file in FileDiscovery::new(root.as_ref()).discover()? {
+            let document = std::fs::read_to_string(&file)?;
+            let Some(root) = Kson::analyze(&document, None).kson_value() else {
+                continue;
+            };
+            let references = references_in(&file, &root, keys);
+            if references.is_empty() {
+                continue;
+            }
+            let own = root.to_value();
+            for reference in &references {
+                let target = match &reference.target {
+                    None => Target::Document(&own),
+                    Some(path) => targets
+                        .entry(path.clone())
+                        .or_insert_with(|| load(path))
+                        .target(),
+                };
+                broken.extend(check_reference(&file, reference, target));
+            }
+        }
+        Ok(broken)
+    }
+}
+
+/// A referenced document, as loaded from disk
+pub(crate) enum Loaded {
+    Missing,
+    Invalid,
+    Parsed(Value),
+}
+
+impl Loaded {
+    pub(crate) fn target(&self) -> Target<'_> {
+        match self {
+            Loaded::Missing => Target::Missing,
+            Loaded::Invalid => Target::Invalid,
+            Loaded::Parsed(value) => Target::Document(value),
+        }
+    }
+}
+
+pub(crate) fn load(path: &Path) -> Loaded {
+    match std::fs::read_to_string(path) {
+        Err(_) => Loaded::Missing,
+        Ok(text) => Value::parse(&text).map_or(Loaded::Invalid, Loaded::Parsed),
+    }
+}
@@ -0,0 +1,374 @@
+//! Resolution of `$ref`s to schemas hosted on HTTP(S) servers, enabled through the `http` feature.
+//!
+//! [`SchemaValidator`] only resolves references within the schema document, so
+//! [`RemoteSchemaResolver`] bundles remote schemas into it first: every referenced document is fetched
+//! (once, thanks to a cache shared across calls), stored under `definitions` keyed by its URL, and the
+//! `$ref`s pointing to it are rewritten into local references. References in fetched documents are
+//! rewritten as well, relative ones being resolved against the URL of their document.
+//!
+//! Only URLs under one of the prefixes added through [`RemoteSchemaResolver::allow`] are fetched, so by
+//! default no remote reference is followed. URLs are normalized before being compared (e.g. `..` segments
+//! are resolved), and [`HttpFetcher`] doesn't follow redirects, so that no request leaves the allowlist.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+use reqwest::Url;
+
+use crate::path::KsonPath;
+use crate::value::{Map, Value};
+use crate::{Kson, SchemaValidator};
+
+/// The error returned by a [`SchemaFetcher`]
+pub type FetchError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Retrieves the text of remote schema documents
+pub trait SchemaFetcher {
+    fn fetch(&self, url: &str) -> Result<String, FetchError>;
+}
+
+impl<F: Fn(&str) -> Result<String, FetchError>> SchemaFetcher for F {
+    fn fetch(&self, url: &str) -> Result<String, FetchError> {
+        self(url)
+    }
+}
+
+/// The default [`SchemaFetcher`], performing blocking GET requests with `reqwest`. Redirects fail the
+/// fetch, since their target wasn't checked against the allowlist.
+#[derive(Clone, Debug)]
+pub struct HttpFetcher {
+    client: reqwest::blocking::Client,
+}
+
+impl HttpFetcher {
+    /// Creates a fetcher with a client that doesn't follow redirects. Like
+    /// [`reqwest::blocking::Client::new`], panics if the TLS backend can't be initialized.
+    pub fn new() -> Self {
+        let client = reqwest::blocking::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("failed to initialize the HTTP client");
+        Self { client }
+    }
+
+    /// Uses the given client, e.g. to configure timeouts or proxies. Its redirect policy should be
+    /// [`Policy::none`](reqwest::redirect::Policy::none): redirects followed by the client lead to URLs
+    /// that weren't checked against the allowlist.
+    pub fn with_client(client: reqwest::blocking::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl Default for HttpFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchemaFetcher for HttpFetcher {
+    fn fetch(&self, url: &str) -> Result<String, FetchError> {
+        let response = self.client.get(url).send()?;
+        if response.status().is_redirection() {
+            return Err(format!("redirected with status {}", response.status()).into());
+        }
+        Ok(response.error_for_status()?.text()?)
+    }
+}
+
+/// The error returned when remote references can't be resolved
+#[derive(Debug)]
+pub enum RemoteSchemaError {
+    /// A `$ref` points to a URL that is not in the allowlist
+    NotAllowed(String),
+    /// A `$ref` is not a valid URL, or doesn't resolve to one against the URL of its document
+    InvalidUrl(String),
+    /// Fetching a document failed
+    Fetch { url: String, source: FetchError },
+    /// A document (the schema itself if `url` is `None`) is not valid KSON
+    Parse {
+        url: Option<String>,
+        errors: Vec<String>,
+    },
+    /// A `$ref` has a fragment that is not a JSON Pointer
+    UnsupportedFragment(String),
+    /// The bundled schema was rejected by [`Kson::parse_schema`]
+    InvalidSchema(Vec<String>),
+}
+
+impl std::fmt::Display for RemoteSchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteSchemaError::NotAllowed(url) => write!(f, "`{url}` is not in the allowlist"),
+            RemoteSchemaError::InvalidUrl(reference) => {
+                write!(f, "`{reference}` is not a valid URL")
+            }
+            RemoteSchemaError::Fetch { url, source } => {
+                write!(f, "failed to fetch `{url}`: {source}")
+            }
+            RemoteSchemaError::Parse {
+                url: Some(url),
+                errors,
+            } => {
+                write!(f, "`{url}` is not valid KSON: {}", errors.join("; "))
+            }
+            RemoteSchemaError::Parse { url: None, errors } => {
+                write!(f, "the schema is not valid KSON: {}", errors.join("; "))
+            }
+            RemoteSchemaError::UnsupportedFragment(reference) => {
+                write!(
+                    f,
+                    "unsupported fragment in `{reference}`, expected a JSON Pointer"
+                )
+            }
+            RemoteSchemaError::InvalidSchema(errors) => {
+                write!(f, "invalid schema: {}", errors.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for RemoteSchemaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RemoteSchemaError::Fetch { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Bundles remote schemas referenced through `$ref` into a schema document (see the [module
+/// documentation](self))
+pub struct RemoteSchemaResolver<F = HttpFetcher> {
+    fetcher: F,
+    allowlist: Vec<Url>,
+    cache: Mutex<HashMap<String, Value>>,
+}
+
+impl RemoteSchemaResolver {
+    /// Creates a resolver fetching documents with [`HttpFetcher`]
+    pub fn new() -> Self {
+        Self::with_fetcher(HttpFetcher::new())
+    }
+}
+
+impl Default for RemoteSchemaResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: SchemaFetcher> RemoteSchemaResolver<F> {
+    pub fn with_fetcher(fetcher: F) -> Self {
+        Self {
+            fetcher,
+            allowlist: Vec::new(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Allows fetching the URLs under `prefix` (e.g. `https://schemas.example.com/schemas/`): those with
+    /// its scheme, host and port, whose path starts with the segments of its path. A prefix that isn't an
+    /// absolute URL allows nothing.
+    pub fn allow(mut self, prefix: impl Into<String>) -> Self {
+        if let Ok(prefix) = Url::parse(&prefix.into()) {
+            self.allowlist.push(prefix);
+        }
+        self
+    }
+
+    /// Whether the allowlist allows fetching `url`, once normalized. URLs with credentials are never
+    /// allowed.
+    pub fn is_allowed(&self, url: &str) -> bool {
+        Url::parse(url).is_ok_and(|url| self.allows(&url))
+    }
+
+    fn allows(&self, url: &Url) -> bool {
+        url.username().is_empty()
+            && url.password().is_none()
+            && self.allowlist.iter().any(|prefix| is_under(url, prefix))
+    }
+
+    /// Forgets the documents fetched so far, so they are fetched again when next referenced
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// Returns the schema with the remote documents it references bundled in, as JSON
+    pub fn bundle(&self, schema: &str) -> Result<String, RemoteSchemaError> {
+        let mut root = parse(schema, None)?;
+        let mut queued = HashSet::new();
+        let mut pending = VecDeque::new();
+        self.rewrite_refs(&mut root, None, &mut queued, &mut pending)?;
+
+        let mut documents = Vec::new();
+        while let Some(url) = pending.pop_front() {
+            let mut document = self.load(url.as_str())?;
+            // An `$id` would change the base against which the rewritten references are resolved
+            if let Value::Object(keywords) = &mut document {
+                keywords.remove("$id");
+            }
+            self.rewrite_refs(&mut document, Some(&url), &mut queued, &mut pending)?;
+            documents.push((url, document));
+        }
+
+        if !documents.is_empty()
+            && let Value::Object(keywords) = &mut root
+        {
+            if !matches!(keywords.get("definitions"), Some(Value::Object(_))) {
+                keywords.insert("definitions", Value::Object(Map::new()));
+            }
+            if let Some(Value::Object(definitions)) = keywords.get_mut("definitions") {
+                for (url, document) in documents {
+                    definitions.insert(url.as_str(), document);
+                }
+            }
+        }
+        Ok(root.to_json())
+    }
+
+    /// Bundles the schema (see [`bundle`](Self::bundle)) and parses the result into a validator
+    pub fn parse_schema(&self, schema: &str) -> Result<SchemaValidator, RemoteSchemaError> {
+        match Kson::parse_schema(&self.bundle(schema)?) {
+            Ok(success) => Ok(success.schema_validator()),
+            Err(failure) => Err(RemoteSchemaError::InvalidSchema(
+                failure
+                    .errors()
+                    .iter()
+                    .map(|message| message.message())
+                    .collect(),
+            )),
+        }
+    }
+
+    fn load(&self, url: &str) -> Result<Value, RemoteSchemaError> {
+        if let Some(document) = self
+            .cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(url)
+        {
+            return Ok(document.clone());
+        }
+
+        let text = self
+            .fetcher
+            .fetch(url)
+            .map_err(|source| RemoteSchemaError::Fetch {
+                url: url.to_string(),
+                source,
+            })?;
+        let document = parse(&text, Some(url))?;
+        self.cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(url.to_string(), document.clone());
+        Ok(document)
+    }
+
+    /// Rewrites the `$ref`s found in `value` (part of the document at `base`, or of the root schema if it
+    /// is `None`) to point inside the bundle, queueing the documents they reference
+    fn rewrite_refs(
+        &self,
+        value: &mut Value,
+        base: Option<&Url>,
+        queued: &mut HashSet<Url>,
+        pending: &mut VecDeque<Url>,
+    ) -> Result<(), RemoteSchemaError> {
+        match value {
+            Value::Object(keywords) => {
+                if let Some(Value::String(reference)) = keywords.get_mut("$ref")
+                    && let Some(rewritten) = self.rewrite_ref(reference, base, queued, pending)?
+                {
+                    *reference = rewritten;
+                }
+                for (keyword, subschema) in keywords.iter_mut() {
+                    // These hold instance data rather than subschemas
+                    if !matches!(keyword.as_str(), "enum" | "const" | "default" | "examples") {
+                        self.rewrite_refs(subschema, base, queued, pending)?;
+                    }
+                }
+            }
+            Value::Array(elements) => {
+                for element in elements {
+                    self.rewrite_refs(element, base, queued, pending)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn rewrite_ref(
+        &self,
+        reference: &str,
+        base: Option<&Url>,
+        queued: &mut HashSet<Url>,
+        pending: &mut VecDeque<Url>,
+    ) -> Result<Option<String>, RemoteSchemaError> {
+        let absolute = if is_remote(reference) {
+            Url::parse(reference)
+        } else if let Some(base) = base {
+            base.join(reference)
+        } else {
+            // A reference within the root schema, or relative to its (unknown) location
+            return Ok(None);
+        };
+        let mut url = absolute.map_err(|_| RemoteSchemaError::InvalidUrl(reference.to_string()))?;
+
+        let fragment = url.fragment().unwrap_or_default().to_string();
+        if !fragment.is_empty() && !fragment.starts_with('/') {
+            return Err(RemoteSchemaError::UnsupportedFragment(
+                reference.to_string(),
+            ));
+        }
+        url.set_fragment(None);
+        if !self.allows(&url) {
+            return Err(RemoteSchemaError::NotAllowed(url.to_string()));
+        }
+        let key = KsonPath::root().key("definitions").key(url.as_str());
+        if queued.insert(url.clone()) {
+            pending.push_back(url);
+        }
+        Ok(Some(format!("#{key}{fragment}")))
+    }
+}
+
+fn parse(text: &str, url: Option<&str>) -> Result<Value, RemoteSchemaError> {
+    let analysis = Kson::analyze(text, None);
+    match analysis.kson_value() {
+        Some(value) => Ok(value.to_value()),
+        None => Err(RemoteSchemaError::Parse {
+            url: url.map(str::to_string),
+            errors: analysis
+                .errors()
+                .iter()
+                .map(|message| message.message())
+                .collect(),
+        }),
+    }
+}
+
+fn is_remote(reference: &str) -> bool {
+    reference.starts_with("http://") || reference.starts_with("https://")
+}
+
+/// Whether the URL has the scheme, host and port of `prefix`, and its path starts with the segments of the
+/// path of `prefix` (whose trailing slash, if any, doesn't count as a segment)
+fn is_under(url: &Url, prefix: &Url) -> bool {
+    if url.scheme() != prefix.scheme()
+        || url.host_str() != prefix.host_str()
+        || url.port_or_known_default() != prefix.port_or_known_default()
+    {
+        return false;
+    }
+    let (Some(segments), Some(prefix_segments)) = (url.path_segments(), prefix.path_segments())
+    else {
+        return false;
+    };
+    let mut prefix_segments: Vec<&str> = prefix_segments.collect();
+    if prefix_segments.last() == Some(&"") {
+        prefix_segments.pop();
+    }
+    let segments: Vec<&str> = segments.collect();
+    segments.starts_with(&prefix_segments)
+}
@@ -0,0 +1,402 @@
+//! Round-trip checks, for property tests of KSON handling.
+//!
+//! [`check_round_trips`] converts a document to JSON and back, and to a [`Value`] and back, checking
+//! that each step reads as the value the document started with. [`ValueGenerator`] produces arbitrary
+//! values to feed it, deterministically from a seed so that failures can be reproduced:
+//!
+//! ```no_run
+//! use kson_rs::roundtrip::{ValueGenerator, assert_round_trips};
+//!
+//! for value in ValueGenerator::new(42).take(100) {
+//!     assert_round_trips(&value.to_kson());
+//! }
+//! ```
+//!
+//! For fuzzing, [`DocumentRecipe`] describes a document by its value and the syntax to write it in. With
+//! the `arbitrary` feature, fuzzers can build recipes from their input, which the targets in the `fuzz`
+//! directory of the crate do (see its readme).
+
+use crate::value::{Map, Value, values_equal};
+use crate::{
+    FormatOptions, FormattingStyle, IndentType, Kson, MessageSeverity, indent_type,
+    transpile_options,
+};
+
+/// The conversions checked by [`check_round_trips`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundTrip {
+    /// KSON to JSON with [`Kson::to_json`], then back to KSON with [`Kson::format`]
+    Json,
+    /// KSON to [`Value`], then back to KSON with [`Value::to_kson`]
+    Value,
+}
+
+/// The reason a round trip failed
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoundTripError {
+    /// The input is not valid KSON to begin with
+    InvalidInput(Vec<String>),
+    /// A conversion reported errors
+    ConversionFailed {
+        route: RoundTrip,
+        errors: Vec<String>,
+    },
+    /// Converting produced `output`, which doesn't read as the original value (or doesn't parse, if
+    /// `actual` is `None`)
+    Changed {
+        route: RoundTrip,
+        output: String,
+        expected: Value,
+        actual: Option<Value>,
+    },
+}
+
+impl std::fmt::Display for RoundTripError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoundTripError::InvalidInput(errors) => {
+                write!(f, "invalid input: {}", errors.join("; "))
+            }
+            RoundTripError::ConversionFailed { route, errors } => {
+                write!(f, "{route:?} round trip failed: {}", errors.join("; "))
+            }
+            RoundTripError::Changed {
+                route,
+                output,
+                expected,
+                actual,
+            } => {
+                write!(
+                    f,
+                    "{route:?} round trip changed the value\noutput:\n{output}\n"
+                )?;
+                write!(f, "expected: {expected:?}\nactual: {actual:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RoundTripError {}
+
+/// Checks that converting the document to JSON and back, and to a [`Value`] and back, preserves its
+/// value. Values are compared like [JSON Schema](crate::schema) compares them, so e.g. an embed block
+/// that became a string in JSON still matches.
+pub fn check_round_trips(kson: &str) -> Result<(), RoundTripError> {
+    let expected = parse(kson).map_err(RoundTripError::InvalidInput)?;
+    let check = |route: RoundTrip, output: String| {
+        let actual = parse(&output).ok();
+        if actual
+            .as_ref()
+            .is_some_and(|actual| values_equal(&expected, actual))
+        {
+            Ok(())
+        } else {
+            Err(RoundTripError::Changed {
+                route,
+                output,
+                expected: expected.clone(),
+                actual,
+            })
+        }
+    };
+
+    let json = Kson::to_json(kson, transpile_options::Json::new(false))
+        .map_err(|failure| RoundTripError::ConversionFailed {
+            route: RoundTrip::Json,
+            errors: failure
+                .errors()
+                .iter()
+                .map(|error| error.message())
+                .collect(),
+        })?
+        .output();
+    check(RoundTrip::Json, json.clone())?;
+    let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+    check(
+        RoundTrip::Json,
+        Kson::format(
+            &json,
+            FormatOptions::new(indent, FormattingStyle::Plain, &[]),
+        ),
+    )?;
+
+    check(RoundTrip::Value, expected.to_kson())
+}
+
+/// Panics with a description of the failure if [`check_round_trips`] fails
+pub fn assert_round_trips(kson: &str) {
+    if let Err(error) = check_round_trips(kson) {
+        panic!("{error}\ninput:\n{kson}");
+    }
+}
+
+fn parse(kson: &str) -> Result<Value, Vec<String>> {
+    let analysis = Kson::analyze(kson, None);
+    let errors: Vec<String> = analysis
+        .errors()
+        .into_iter()
+        .filter(|message| matches!(message.severity(), MessageSeverity::Error))
+        .map(|message| message.message())
+        .collect();
+    match analysis.kson_value() {
+        Some(value) if errors.is_empty() => Ok(value.to_value()),
+        _ => Err(errors),
+    }
+}
+
+/// Generates arbitrary values, favoring the strings and numbers that are hardest to render (quotes,
+/// escapes, keywords, number-like strings, extreme numbers...)
+///
+/// The same seed always produces the same values. The generator is an endless [`Iterator`].
+#[derive(Clone, Debug)]
+pub struct ValueGenerator {
+    state: u64,
+    max_depth: usize,
+    max_len: usize,
+}
+
+/// Strings that need quoting or escaping, or otherwise tend to trip up renderers
+const TRICKY_STRINGS: &[&str] = &[
+    "",
+    " ",
+    "true",
+    "null",
+    "12",
+    "-3.5e7",
+    "it's",
+    "say \"hi\"",
+    "back\\slash",
+    "line\nbreak",
+    "tab\there",
+    "# not a comment",
+    "key: value",
+    "- item",
+    "$embed",
+    "%tag",
+    "é ünïcödé 😀",
+    "\u{0}\u{1f}",
+];
+
+const WORDS: &[&str] = &["alpha", "beta", "gamma", "kson", "value", "x", "_", "a-b"];
+
+impl ValueGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            max_depth: 4,
+            max_len: 5,
+        }
+    }
+
+    /// Sets how deeply arrays and objects may be nested (4 by default)
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets how many elements or properties arrays and objects may have (5 by default)
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn generate(&mut self) -> Value {
+        self.value(self.max_depth)
+    }
+
+    fn value(&mut self, depth: usize) -> Value {
+        let kinds = if depth == 0 { 6 } else { 8 };
+        match self.below(kinds) {
+            0 => Value::Null,
+            1 => Value::Bool(self.below(2) == 0),
+            2 => Value::Integer(self.integer()),
+            3 => Value::Decimal(self.decimal()),
+            4 => Value::String(self.string()),
+            5 => self.embed(),
+            6 => Value::Array(
+                (0..self.below(self.max_len + 1))
+                    .map(|_| self.value(depth - 1))
+                    .collect(),
+            ),
+            _ => Value::Object(
+                (0..self.below(self.max_len + 1))
+                    .map(|_| (self.string(), self.value(depth - 1)))
+                    .collect::<Map>(),
+            ),
+        }
+    }
+
+    fn integer(&mut self) -> i64 {
+        match self.below(4) {
+            0 => [0, -1, i64::MIN, i64::MAX][self.below(4)],
+            _ => self.below(2001) as i64 - 1000,
+        }
+    }
+
+    fn decimal(&mut self) -> f64 {
+        match self.below(4) {
+            0 => [0.5, -0.0, 1e-300, 1.7976931348623157e308][self.below(4)],
+            _ => (self.below(2_000_001) as f64 - 1_000_000.0) / 1000.0,
+        }
+    }
+
+    fn string(&mut self) -> String {
+        if self.below(3) == 0 {
+            TRICKY_STRINGS[self.below(TRICKY_STRINGS.len())].to_string()
+        } else {
+            WORDS[self.below(WORDS.len())].to_string()
+        }
+    }
+
+    /// An embed block of whole, unindented lines, which is the content embed blocks preserve exactly
+    fn embed(&mut self) -> Value {
+        let tag = (self.below(2) == 0).then(|| WORDS[self.below(3)].to_string());
+        let content = (0..=self.below(3))
+            .map(|_| format!("{}\n", WORDS[self.below(WORDS.len())]))
+            .collect();
+        Value::Embed { tag, content }
+    }
+
+    /// A number in `0..n`, advancing the state with SplitMix64
+    fn below(&mut self, n: usize) -> usize {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) % n as u64) as usize
+    }
+}
+
+impl Iterator for ValueGenerator {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        Some(self.generate())
+    }
+}
+
+/// How a [`DocumentRecipe`] writes its value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// On a single line (except for embed blocks), as written before formatting
+    Inline,
+    /// Formatted in the [`FormattingStyle::Plain`] style
+    Plain,
+    Delimited,
+    Compact,
+    Classic,
+}
+
+/// A document described by its value and the syntax to write it in, for fuzzers to build structured
+/// inputs from (with the `arbitrary` feature, it implements `arbitrary::Arbitrary`). Unlike raw bytes,
+/// every recipe renders to a valid document, so fuzzing reaches the formatter and the conversions
+/// instead of stopping at parse errors:
+///
+/// ```no_run
+/// use kson_rs::roundtrip::{DocumentRecipe, Syntax, assert_round_trips};
+/// use kson_rs::value::Value;
+///
+/// let recipe = DocumentRecipe {
+///     value: Value::list([Value::from("it's"), Value::from(-0.5)]),
+///     syntax: Syntax::Delimited,
+/// };
+/// assert_eq!(Value::parse(&recipe.render()).unwrap(), recipe.value);
+/// assert_round_trips(&recipe.render());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct DocumentRecipe {
+    pub value: Value,
+    pub syntax: Syntax,
+}
+
+impl DocumentRecipe {
+    /// Writes the value in the syntax of the recipe
+    pub fn render(&self) -> String {
+        let style = match self.syntax {
+            Syntax::Inline => return self.value.to_inline_kson(),
+            Syntax::Plain => FormattingStyle::Plain,
+            Syntax::Delimited => FormattingStyle::Delimited,
+            Syntax::Compact => FormattingStyle::Compact,
+            Syntax::Classic => FormattingStyle::Classic,
+        };
+        self.value
+            .to_kson_with(&FormatOptions::builder().style(style).build())
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for DocumentRecipe {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let syntax = *u.choose(&[
+            Syntax::Inline,
+            Syntax::Plain,
+            Syntax::Delimited,
+            Syntax::Compact,
+            Syntax::Classic,
+        ])?;
+        Ok(Self {
+            value: arbitrary_value(u, 4)?,
+            syntax,
+        })
+    }
+}
+
+/// Builds a value from the fuzzer's input, drawing strings from [`TRICKY_STRINGS`] and [`WORDS`] as
+/// often as from the input itself, like [`ValueGenerator`] does
+#[cfg(feature = "arbitrary")]
+fn arbitrary_value(u: &mut arbitrary::Unstructured<'_>, depth: usize) -> arbitrary::Result<Value> {
+    // Running out of input ends the document with scalars rather than failing
+    let kinds = if depth == 0 || u.is_empty() { 6 } else { 8 };
+    Ok(match u.choose_index(kinds).unwrap_or(0) {
+        0 => Value::Null,
+        1 => Value::Bool(u.arbitrary()?),
+        2 => Value::Integer(u.arbitrary()?),
+        // Non-finite decimals have no KSON syntax
+        3 => Value::Decimal(
+            Some(u.arbitrary::<f64>()?)
+                .filter(|d| d.is_finite())
+                .unwrap_or(0.0),
+        ),
+        4 => Value::String(arbitrary_string(u)?),
+        5 => {
+            let tag = if u.arbitrary()? {
+                Some(u.choose(WORDS)?.replace('-', "_"))
+            } else {
+                None
+            };
+            // Whole, unindented lines, which is the content embed blocks preserve exactly
+            let mut content = String::new();
+            for _ in 0..=u.int_in_range(0..=2)? {
+                content.push_str(u.choose(WORDS)?);
+                content.push('\n');
+            }
+            Value::Embed { tag, content }
+        }
+        6 => {
+            let len = u.int_in_range(0..=5)?;
+            Value::Array(
+                (0..len)
+                    .map(|_| arbitrary_value(u, depth - 1))
+                    .collect::<arbitrary::Result<_>>()?,
+            )
+        }
+        _ => {
+            let len = u.int_in_range(0..=5)?;
+            let mut map = Map::new();
+            for _ in 0..len {
+                let key = arbitrary_string(u)?;
+                map.insert(key, arbitrary_value(u, depth - 1)?);
+            }
+            Value::Object(map)
+        }
+    })
+}
+
+#[cfg(feature = "arbitrary")]
+fn arbitrary_string(u: &mut arbitrary::Unstructured<'_>) -> arbitrary::Result<String> {
+    Ok(match u.choose_index(3)? {
+        0 => u.choose(TRICKY_STRINGS)?.to_string(),
+        1 => u.choose(WORDS)?.to_string(),
+        _ => u.arbitrary()?,
+    })
+}
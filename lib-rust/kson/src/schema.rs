@@ -0,0 +1,1457 @@
+//! Schema checks performed on the Rust side, complementing the validation done by
+//! [`SchemaValidator`].
+//!
+//! The checks here walk the schema alongside the document, following the keywords that
+//! unconditionally apply a subschema to part of the document (`properties`, `additionalProperties`,
+//! `items`, `additionalItems`, `allOf` and local `$ref`s). Keywords whose applicability depends on the
+//! outcome of validation (`anyOf`, `oneOf`, `if`/`then`/`else`, ...) are not followed.
+
+use std::collections::{BTreeSet, HashMap};
+use std::ops::ControlFlow;
+
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::diagnostics::DiagnosticLimit;
+use crate::line_index::LineIndex;
+use crate::path::{KsonPath, PathSegment};
+use crate::pointer::PointerGlob;
+use crate::value::{Map, Value, sorted_property_keys, values_equal};
+use crate::{Kson, KsonValue, Message, MessageSeverity, Position, SchemaValidator, kson_value};
+
+/// Guards against `$ref` cycles that never descend into the document
+const MAX_REF_DEPTH: usize = 64;
+
+/// A problem found by one of the Rust-side schema checks
+#[derive(Clone)]
+pub struct SchemaDiagnostic {
+    pub message: String,
+    pub severity: MessageSeverity,
+    pub start: Position,
+    pub end: Position,
+    /// The path of the offending value in the document
+    pub path: KsonPath,
+    /// Structured details about the problem, e.g. for editors to offer quick fixes
+    pub kind: SchemaDiagnosticKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SchemaDiagnosticKind {
+    /// A string doesn't have the `format` required by its schema
+    InvalidFormat { format: String },
+    /// A value is not one of the values listed in the `enum` of its schema
+    NotInEnum {
+        /// The values allowed by the schema
+        allowed: Vec<Value>,
+        /// The allowed values that are close to the actual one, closest first
+        suggestions: Vec<Value>,
+    },
+    /// A value is rejected by a composition keyword (`allOf`, `anyOf`, `oneOf`) or by the branch selected
+    /// by `if`
+    CompositionFailed {
+        keyword: CompositionKeyword,
+        /// The branches that rejected the value, with their errors
+        failures: Vec<BranchFailure>,
+        /// For `oneOf`, the indices of the branches that accepted the value, if more than one did
+        matched: Vec<usize>,
+    },
+    /// An element of an array with `uniqueItems` is equal to an earlier one (the diagnostic points at the
+    /// later one)
+    DuplicateItem {
+        /// The index and path of the earlier, equal element
+        first: usize,
+        first_path: KsonPath,
+        /// The index and path of the duplicate
+        duplicate: usize,
+        duplicate_path: KsonPath,
+    },
+    /// An object lacks properties listed in the `required` keyword of its schema
+    MissingProperties {
+        /// The missing properties, in the order the schema lists them
+        properties: Vec<String>,
+        /// An edit inserting the missing properties into the object, set to their schema `default` (or
+        /// `const`, or first `enum` value) if any, or else to a placeholder of their `type`
+        fix: TextEdit,
+    },
+    /// A property of an object is not declared by any of the schemas applying to the object, in
+    /// [closed-world](validate_closed_world) validation
+    UndeclaredProperty {
+        /// The properties declared for the object
+        declared: Vec<String>,
+        /// The declared properties that are close to the undeclared one, closest first
+        suggestions: Vec<String>,
+    },
+    /// A violation reported by another validator, such as
+    /// [`JsonSchemaBackend`](crate::jsonschema_backend::JsonSchemaBackend)
+    Violation {
+        /// The location of the keyword that rejected the value in the schema document
+        schema_path: KsonPath,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompositionKeyword {
+    AllOf,
+    AnyOf,
+    OneOf,
+    /// The `then` branch, selected because the value matched `if`
+    Then,
+    /// The `else` branch, selected because the value didn't match `if`
+    Else,
+}
+
+impl std::fmt::Display for CompositionKeyword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CompositionKeyword::AllOf => "allOf",
+            CompositionKeyword::AnyOf => "anyOf",
+            CompositionKeyword::OneOf => "oneOf",
+            CompositionKeyword::Then => "then",
+            CompositionKeyword::Else => "else",
+        })
+    }
+}
+
+/// A change to the text of a document: the text between `start` and `end`, given as zero-based line and
+/// (UTF-16) column pairs like [`Position`]s, is replaced by `new_text`. Insertions have `start == end`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextEdit {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub new_text: String,
+}
+
+impl TextEdit {
+    fn insert(at: (usize, usize), new_text: String) -> Self {
+        Self {
+            start: at,
+            end: at,
+            new_text,
+        }
+    }
+
+    /// Returns the document with this edit applied
+    pub fn apply(&self, document: &str) -> String {
+        let index = LineIndex::new(document);
+        let start = index.offset(self.start.0, self.start.1);
+        let end = index.offset(self.end.0, self.end.1).max(start);
+        let mut edited = String::with_capacity(document.len() + self.new_text.len());
+        edited.push_str(&document[..start]);
+        edited.push_str(&self.new_text);
+        edited.push_str(&document[end..]);
+        edited
+    }
+}
+
+/// A branch of a composition keyword that rejected a value
+#[derive(Clone, Debug, PartialEq)]
+pub struct BranchFailure {
+    /// The index of the branch in the `allOf`/`anyOf`/`oneOf` array (`None` for `then` and `else`)
+    pub index: Option<usize>,
+    /// The location of the branch in the schema document
+    pub schema_path: KsonPath,
+    /// The messages reported when validating the value against the branch alone
+    pub errors: Vec<String>,
+}
+
+impl SchemaDiagnostic {
+    /// Creates a diagnostic with the severity [`SchemaValidator`] uses for schema
+    /// violations
+    fn warning(
+        value: &KsonValue,
+        path: &KsonPath,
+        kind: SchemaDiagnosticKind,
+        message: String,
+    ) -> Self {
+        Self {
+            message,
+            severity: MessageSeverity::Warning,
+            start: value.start(),
+            end: value.end(),
+            path: path.clone(),
+            kind,
+        }
+    }
+}
+
+/// A schema parsed by kson-lib, along with its text. kson-lib's [`SchemaValidator`] treats `format` as an
+/// annotation, so the paths validating with a [`Schema`] ([`Kson::try_check`], [`Kson::validate_workspace`],
+/// [`Merged::validate`](crate::layers::Merged::validate), ...) also report the invalid formats found by
+/// [`validate_formats`], unless [`Schema::check_formats`] turns them off.
+#[derive(Clone)]
+pub struct Schema {
+    text: String,
+    validator: SchemaValidator,
+    check_formats: bool,
+}
+
+impl Schema {
+    /// Parses the schema, or returns the errors that prevented parsing it
+    pub fn parse(text: &str) -> Result<Self, Vec<Message>> {
+        match Kson::parse_schema(text) {
+            Ok(success) => Ok(Self {
+                text: text.to_string(),
+                validator: success.schema_validator(),
+                check_formats: true,
+            }),
+            Err(failure) => Err(failure.errors()),
+        }
+    }
+
+    /// Sets whether documents are checked against the `format` keywords of the schema (the default)
+    pub fn check_formats(mut self, check_formats: bool) -> Self {
+        self.check_formats = check_formats;
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn validator(&self) -> &SchemaValidator {
+        &self.validator
+    }
+
+    /// The strings of the document that don't have the `format` required by the schema (see
+    /// [`validate_formats`]), or none if formats aren't checked
+    pub fn format_diagnostics(&self, document: &str) -> Vec<SchemaDiagnostic> {
+        // A token nobody else holds can't be cancelled
+        self.format_diagnostics_cancellable(document, &CancellationToken::new())
+            .unwrap()
+    }
+
+    /// Like [`Schema::format_diagnostics`], but checks the token like [`validate_cancellable`] does
+    pub fn format_diagnostics_cancellable(
+        &self,
+        document: &str,
+        token: &CancellationToken,
+    ) -> Result<Vec<SchemaDiagnostic>, Cancelled> {
+        self.limited_format_diagnostics(document, DiagnosticLimit::All, token)
+    }
+
+    /// Like [`Schema::format_diagnostics_cancellable`], stopping once `limit` diagnostics have been found
+    pub(crate) fn limited_format_diagnostics(
+        &self,
+        document: &str,
+        limit: DiagnosticLimit,
+        token: &CancellationToken,
+    ) -> Result<Vec<SchemaDiagnostic>, Cancelled> {
+        if self.check_formats {
+            run_checks_cancellable(&self.text, document, &[Check::Formats], limit, token)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Runs all of the checks below (formats, enums, unique items, required properties and compositions),
+/// stopping once `limit` diagnostics have been found. Closed-world validation is opt-in, with
+/// [`validate_strict`].
+///
+/// Returns no diagnostics if either the schema or the document fails to parse, like
+/// [`validate_formats`].
+pub fn validate(schema: &str, document: &str, limit: DiagnosticLimit) -> Vec<SchemaDiagnostic> {
+    let checks = [
+        Check::Formats,
+        Check::Enums,
+        Check::UniqueItems,
+        Check::Required,
+        Check::Compositions,
+    ];
+    run_checks(schema, document, &checks, limit)
+}
+
+/// Like [`validate`], but parses the schema and the document through [`CancellationToken::run`] and
+/// checks the token before each subschema applying to the document, failing once it's cancelled
+pub fn validate_cancellable(
+    schema: &str,
+    document: &str,
+    limit: DiagnosticLimit,
+    token: &CancellationToken,
+) -> Result<Vec<SchemaDiagnostic>, Cancelled> {
+    let checks = [
+        Check::Formats,
+        Check::Enums,
+        Check::UniqueItems,
+        Check::Required,
+        Check::Compositions,
+    ];
+    run_checks_cancellable(schema, document, &checks, limit, token)
+}
+
+/// Like [`validate`], additionally running [`validate_closed_world`] with the given exceptions
+pub fn validate_strict(
+    schema: &str,
+    document: &str,
+    limit: DiagnosticLimit,
+    exceptions: &[PointerGlob],
+) -> Vec<SchemaDiagnostic> {
+    let checks = [
+        Check::Formats,
+        Check::Enums,
+        Check::UniqueItems,
+        Check::Required,
+        Check::Compositions,
+        Check::ClosedWorld(exceptions),
+    ];
+    run_checks(schema, document, &checks, limit)
+}
+
+/// Validates the `format` keyword for the formats supported by the enabled crate features (currently
+/// only `uuid`, behind the `uuid` feature). Formats are only annotations for
+/// [`SchemaValidator`], so these diagnostics come on top of the ones it reports,
+/// which is what validating with a [`Schema`] does.
+///
+/// Returns no diagnostics if either the schema or the document fails to parse, since those errors are
+/// already reported by [`Kson::parse_schema`] and [`Kson::analyze`].
+pub fn validate_formats(schema: &str, document: &str) -> Vec<SchemaDiagnostic> {
+    run_checks(schema, document, &[Check::Formats], DiagnosticLimit::All)
+}
+
+fn check_formats(applicable: &Applicable<'_>, diagnostics: &mut Vec<SchemaDiagnostic>) {
+    let (Some(KsonValue::KsonString(format)), KsonValue::KsonString(string)) = (
+        applicable.schema.properties().remove("format"),
+        applicable.instance,
+    ) else {
+        // Formats only constrain strings
+        return;
+    };
+    let format = format.value();
+    if let Some(message) = check_format(&format, &string.value()) {
+        let kind = SchemaDiagnosticKind::InvalidFormat { format };
+        diagnostics.push(SchemaDiagnostic::warning(
+            applicable.instance,
+            applicable.path,
+            kind,
+            message,
+        ));
+    }
+}
+
+#[cfg_attr(not(feature = "uuid"), allow(unused_variables))]
+fn check_format(format: &str, text: &str) -> Option<String> {
+    match format {
+        #[cfg(feature = "uuid")]
+        "uuid" if !crate::ids::is_hyphenated_uuid(text) => {
+            Some(format!("`{text}` is not a valid UUID"))
+        }
+        _ => None,
+    }
+}
+
+/// Validates the `enum` keyword, reporting for each mismatch the allowed values and the ones closest to
+/// the actual value, which [`SchemaValidator`] only describes in its message.
+///
+/// Returns no diagnostics if either the schema or the document fails to parse, like
+/// [`validate_formats`].
+pub fn validate_enums(schema: &str, document: &str) -> Vec<SchemaDiagnostic> {
+    run_checks(schema, document, &[Check::Enums], DiagnosticLimit::All)
+}
+
+fn check_enums(applicable: &Applicable<'_>, diagnostics: &mut Vec<SchemaDiagnostic>) {
+    let Some(KsonValue::KsonArray(allowed)) = applicable.schema.properties().remove("enum") else {
+        return;
+    };
+    let allowed: Vec<Value> = allowed.elements().iter().map(Value::from).collect();
+    let actual = applicable.instance.to_value();
+    if allowed.iter().any(|allowed| values_equal(allowed, &actual)) {
+        return;
+    }
+
+    let suggestions = rank_suggestions(&actual, &allowed);
+    let mut message = format!(
+        "Value {} is not one of the allowed values: {}",
+        display_value(&actual),
+        allowed
+            .iter()
+            .map(display_value)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    if let Some(closest) = suggestions.first() {
+        message.push_str(&format!(". Did you mean {}?", display_value(closest)));
+    }
+    let kind = SchemaDiagnosticKind::NotInEnum {
+        allowed,
+        suggestions,
+    };
+    diagnostics.push(SchemaDiagnostic::warning(
+        applicable.instance,
+        applicable.path,
+        kind,
+        message,
+    ));
+}
+
+/// Validates the `uniqueItems` keyword, comparing elements structurally (so objects with the same
+/// properties in a different order are duplicates, as are `1` and `1.0`) and reporting every element that
+/// duplicates an earlier one, along with the paths of both.
+///
+/// Returns no diagnostics if either the schema or the document fails to parse, like
+/// [`validate_formats`].
+pub fn validate_unique_items(schema: &str, document: &str) -> Vec<SchemaDiagnostic> {
+    run_checks(
+        schema,
+        document,
+        &[Check::UniqueItems],
+        DiagnosticLimit::All,
+    )
+}
+
+fn check_unique_items(applicable: &Applicable<'_>, diagnostics: &mut Vec<SchemaDiagnostic>) {
+    let KsonValue::KsonArray(array) = applicable.instance else {
+        return;
+    };
+    if !matches!(applicable.schema.properties().get("uniqueItems"), Some(KsonValue::KsonBoolean(unique)) if unique.value())
+    {
+        return;
+    }
+
+    let path = applicable.path;
+    let elements = array.elements();
+    let values: Vec<Value> = elements.iter().map(Value::from).collect();
+    for (duplicate, value) in values.iter().enumerate() {
+        let Some(first) = values[..duplicate]
+            .iter()
+            .position(|earlier| values_equal(earlier, value))
+        else {
+            continue;
+        };
+        let message =
+            format!("Item {duplicate} is a duplicate of item {first}, but items must be unique");
+        let kind = SchemaDiagnosticKind::DuplicateItem {
+            first,
+            first_path: path.clone().index(first),
+            duplicate,
+            duplicate_path: path.clone().index(duplicate),
+        };
+        let duplicate_path = path.clone().index(duplicate);
+        diagnostics.push(SchemaDiagnostic::warning(
+            &elements[duplicate],
+            &duplicate_path,
+            kind,
+            message,
+        ));
+    }
+}
+
+/// Validates the `required` keyword, reporting each object that lacks required properties along with a
+/// [`TextEdit`] inserting them, for editors to offer as a quick fix. The properties are inserted after the
+/// last property of the object, on their own lines with its indentation (or inline, for objects written
+/// on a single line).
+///
+/// Returns no diagnostics if either the schema or the document fails to parse, like
+/// [`validate_formats`].
+pub fn validate_required(schema: &str, document: &str) -> Vec<SchemaDiagnostic> {
+    run_checks(schema, document, &[Check::Required], DiagnosticLimit::All)
+}
+
+/// `root` is the whole schema, against which the `$ref`s of property schemas are resolved
+fn check_required(
+    applicable: &Applicable<'_>,
+    root: &KsonValue,
+    text: &LineIndex,
+    diagnostics: &mut Vec<SchemaDiagnostic>,
+) {
+    let KsonValue::KsonObject(object) = applicable.instance else {
+        return;
+    };
+    let keywords = applicable.schema.properties();
+    let Some(KsonValue::KsonArray(required)) = keywords.get("required") else {
+        return;
+    };
+    let present = object.properties();
+    let missing: Vec<String> = required
+        .elements()
+        .iter()
+        .filter_map(|name| match name {
+            KsonValue::KsonString(name) => Some(name.value()),
+            _ => None,
+        })
+        .filter(|name| !present.contains_key(name))
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+
+    let property_schemas = match keywords.get("properties") {
+        Some(KsonValue::KsonObject(properties)) => properties.properties(),
+        _ => Default::default(),
+    };
+    let properties: Vec<String> = missing
+        .iter()
+        .map(|name| {
+            let value = placeholder(property_schemas.get(name), root);
+            format!(
+                "{}: {}",
+                Value::from(name.as_str()).to_inline_kson(),
+                value.to_inline_kson()
+            )
+        })
+        .collect();
+    let message = format!(
+        "Missing required properties: {}",
+        missing
+            .iter()
+            .map(|name| format!("`{name}`"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let kind = SchemaDiagnosticKind::MissingProperties {
+        properties: missing,
+        fix: insert_properties(applicable.instance, object, &properties, text),
+    };
+    diagnostics.push(SchemaDiagnostic::warning(
+        applicable.instance,
+        applicable.path,
+        kind,
+        message,
+    ));
+}
+
+/// The value to insert for a missing property with the given schema
+fn placeholder(schema: Option<&KsonValue>, root: &KsonValue) -> Value {
+    let mut schema = schema.cloned();
+    for _ in 0..MAX_REF_DEPTH {
+        let Some(KsonValue::KsonObject(object)) = &schema else {
+            break;
+        };
+        let Some(KsonValue::KsonString(reference)) = object.properties().remove("$ref") else {
+            break;
+        };
+        schema = resolve_local_ref(root, &reference.value()).map(|(_, target)| target);
+    }
+    let Some(KsonValue::KsonObject(schema)) = schema else {
+        return Value::Null;
+    };
+
+    let keywords = schema.properties();
+    if let Some(value) = keywords.get("default").or_else(|| keywords.get("const")) {
+        return Value::from(value);
+    }
+    if let Some(KsonValue::KsonArray(allowed)) = keywords.get("enum")
+        && let Some(first) = allowed.elements().first()
+    {
+        return Value::from(first);
+    }
+    let type_name = match keywords.get("type") {
+        Some(KsonValue::KsonString(type_name)) => Some(type_name.value()),
+        // The first non-null type of a union
+        Some(KsonValue::KsonArray(types)) => {
+            types
+                .elements()
+                .iter()
+                .find_map(|type_name| match type_name {
+                    KsonValue::KsonString(type_name) if type_name.value() != "null" => {
+                        Some(type_name.value())
+                    }
+                    _ => None,
+                })
+        }
+        _ => None,
+    };
+    match type_name.as_deref() {
+        Some("string") => Value::String(String::new()),
+        Some("integer" | "number") => Value::Integer(0),
+        Some("boolean") => Value::Bool(false),
+        Some("array") => Value::Array(Vec::new()),
+        Some("object") => Value::Object(Map::new()),
+        _ => Value::Null,
+    }
+}
+
+/// An edit inserting the properties (rendered as `key: value`) into the object
+pub(crate) fn insert_properties(
+    instance: &KsonValue,
+    object: &kson_value::KsonObject,
+    properties: &[String],
+    text: &LineIndex,
+) -> TextEdit {
+    let (start, end) = (instance.start(), instance.end());
+    let braced = text.slice(&start, &end).starts_with('{');
+    let keys = sorted_property_keys(object);
+    let (Some((_, first_key)), Some((last_name, last_key))) = (keys.first(), keys.last()) else {
+        // Only braced objects can be empty
+        return TextEdit {
+            start: position(&start),
+            end: position(&end),
+            new_text: format!("{{ {} }}", properties.join(", ")),
+        };
+    };
+
+    let last_value = object
+        .properties()
+        .remove(last_name)
+        .unwrap_or_else(|| instance.clone());
+    let value_end = last_value.end();
+    if braced && end.line() == value_end.line() {
+        // The closing brace follows the last value: keep the properties on its line
+        let new_text = properties
+            .iter()
+            .map(|property| format!(", {property}"))
+            .collect();
+        return TextEdit::insert(position(&value_end), new_text);
+    }
+
+    // An unbraced object or dash list ending the object would swallow the properties appended after it, so
+    // they go before the first property instead
+    let open_ended = matches!(
+        last_value,
+        KsonValue::KsonObject(_) | KsonValue::KsonArray(_)
+    ) && !text
+        .slice(&last_value.start(), &value_end)
+        .starts_with(['{', '[']);
+    if open_ended {
+        let indent = indentation(first_key, text);
+        let new_text = properties
+            .iter()
+            .map(|property| format!("{property}\n{indent}"))
+            .collect();
+        return TextEdit::insert(position(&first_key.start()), new_text);
+    }
+
+    let indent = indentation(last_key, text);
+    let next_line = value_end.line().max(0) as usize + 1;
+    if next_line < text.line_count() {
+        let new_text = properties
+            .iter()
+            .map(|property| format!("{indent}{property}\n"))
+            .collect();
+        TextEdit::insert((next_line, 0), new_text)
+    } else {
+        // The object ends on the last line of the document
+        let new_text = properties
+            .iter()
+            .map(|property| format!("\n{indent}{property}"))
+            .collect();
+        let last_line = next_line - 1;
+        let line_end = text.line(last_line).encode_utf16().count();
+        TextEdit::insert((last_line, line_end), new_text)
+    }
+}
+
+/// The indentation of the key, i.e. the text before it on its line with anything but whitespace (such as
+/// the dash of a list item) blanked out
+fn indentation(key: &kson_value::KsonString, text: &LineIndex) -> String {
+    let (line, column) = position(&key.start());
+    text.line(line)
+        .chars()
+        .scan(column, |remaining, c| {
+            (*remaining > 0).then(|| {
+                *remaining = remaining.saturating_sub(c.len_utf16());
+                c
+            })
+        })
+        .map(|c| if c.is_whitespace() { c } else { ' ' })
+        .collect()
+}
+
+pub(crate) fn position(position: &Position) -> (usize, usize) {
+    (
+        position.line().max(0) as usize,
+        position.column().max(0) as usize,
+    )
+}
+
+/// Reports the properties that aren't declared by the schema as errors, as if every object schema had
+/// `additionalProperties: false`, so that misspelled properties are caught without having to close every
+/// schema by hand. Properties whose path matches one of the `exceptions` are allowed anyway, e.g.
+/// `/metadata/**` for a free-form section of the document.
+///
+/// Only objects with a `properties` keyword in one of their schemas are checked, so that schemas
+/// describing free-form maps (with no `properties`) don't reject everything. Objects whose schemas have
+/// `additionalProperties` or `patternProperties` are left to [`SchemaValidator`],
+/// since the schema says what it expects of their other properties. Properties declared by the branches of
+/// `anyOf`, `oneOf` and `then`/`else` count as declared, whichever branch applies.
+///
+/// Returns no diagnostics if either the schema or the document fails to parse, like
+/// [`validate_formats`].
+pub fn validate_closed_world(
+    schema: &str,
+    document: &str,
+    exceptions: &[PointerGlob],
+) -> Vec<SchemaDiagnostic> {
+    run_checks(
+        schema,
+        document,
+        &[Check::ClosedWorld(exceptions)],
+        DiagnosticLimit::All,
+    )
+}
+
+/// The properties declared for each object of the document by the subschemas applying to it, in the order
+/// the objects are visited
+#[derive(Default)]
+struct Declarations {
+    objects: Vec<DeclaredProperties>,
+    by_path: HashMap<KsonPath, usize>,
+}
+
+struct DeclaredProperties {
+    object: kson_value::KsonObject,
+    path: KsonPath,
+    names: BTreeSet<String>,
+    /// Whether a schema has a `properties` keyword for the object
+    closed: bool,
+    /// Whether a schema has `additionalProperties` or `patternProperties` for the object
+    open: bool,
+}
+
+impl Declarations {
+    fn declare(&mut self, applicable: &Applicable<'_>, root: &KsonValue) {
+        let KsonValue::KsonObject(object) = applicable.instance else {
+            return;
+        };
+        let index = *self
+            .by_path
+            .entry(applicable.path.clone())
+            .or_insert_with(|| {
+                self.objects.push(DeclaredProperties {
+                    object: object.clone(),
+                    path: applicable.path.clone(),
+                    names: BTreeSet::new(),
+                    closed: false,
+                    open: false,
+                });
+                self.objects.len() - 1
+            });
+        declare_properties(applicable.schema, root, &mut self.objects[index], 0);
+    }
+}
+
+/// Adds the properties declared by the subschema to `declared`, along with the ones declared by its
+/// branches. The walk already visits `allOf` and `$ref` on its own, but not from within branches, so they
+/// are followed here too (visiting a subschema twice is harmless).
+fn declare_properties(
+    schema: &kson_value::KsonObject,
+    root: &KsonValue,
+    declared: &mut DeclaredProperties,
+    depth: usize,
+) {
+    let keywords = schema.properties();
+    if keywords.contains_key("additionalProperties") || keywords.contains_key("patternProperties") {
+        declared.open = true;
+    }
+    if let Some(KsonValue::KsonObject(properties)) = keywords.get("properties") {
+        declared.closed = true;
+        declared.names.extend(properties.properties().into_keys());
+    }
+    if depth >= MAX_REF_DEPTH {
+        return;
+    }
+
+    let mut branches = Vec::new();
+    for keyword in ["allOf", "anyOf", "oneOf"] {
+        if let Some(KsonValue::KsonArray(array)) = keywords.get(keyword) {
+            branches.extend(array.elements());
+        }
+    }
+    for keyword in ["then", "else"] {
+        branches.extend(keywords.get(keyword).cloned());
+    }
+    if let Some(KsonValue::KsonString(reference)) = keywords.get("$ref")
+        && let Some((_, target)) = resolve_local_ref(root, &reference.value())
+    {
+        branches.push(target);
+    }
+    for branch in branches {
+        if let KsonValue::KsonObject(branch) = branch {
+            declare_properties(&branch, root, declared, depth + 1);
+        }
+    }
+}
+
+fn check_closed_world(
+    declarations: Declarations,
+    exceptions: &[PointerGlob],
+    diagnostics: &mut Vec<SchemaDiagnostic>,
+) {
+    for declared in declarations.objects {
+        if !declared.closed || declared.open {
+            continue;
+        }
+        let candidates: Vec<Value> = declared
+            .names
+            .iter()
+            .map(|name| Value::from(name.as_str()))
+            .collect();
+        for (name, key) in sorted_property_keys(&declared.object) {
+            if declared.names.contains(&name) {
+                continue;
+            }
+            let path = declared.path.clone().key(name.clone());
+            if exceptions.iter().any(|exception| path.matches(exception)) {
+                continue;
+            }
+
+            let suggestions: Vec<String> =
+                rank_suggestions(&Value::from(name.as_str()), &candidates)
+                    .into_iter()
+                    .filter_map(|mut suggestion| match &mut suggestion {
+                        Value::String(suggestion) => Some(std::mem::take(suggestion)),
+                        _ => None,
+                    })
+                    .collect();
+            let mut message = format!("Property `{name}` is not declared in the schema");
+            if let Some(closest) = suggestions.first() {
+                message.push_str(&format!(". Did you mean `{closest}`?"));
+            }
+            diagnostics.push(SchemaDiagnostic {
+                message,
+                severity: MessageSeverity::Error,
+                start: key.start(),
+                end: key.end(),
+                path,
+                kind: SchemaDiagnosticKind::UndeclaredProperty {
+                    declared: declared.names.iter().cloned().collect(),
+                    suggestions,
+                },
+            });
+        }
+    }
+}
+
+/// Every property declared by the schema, as the location of its entry in a `properties` keyword (e.g.
+/// `/$defs/server/properties/port`) along with its key, in document order
+#[cfg(feature = "workspace")]
+pub(crate) fn declared_properties(schema: &KsonValue) -> Vec<(KsonPath, kson_value::KsonString)> {
+    let mut declared = Vec::new();
+    let mut pending = vec![(KsonPath::root(), schema.clone())];
+    while let Some((schema_path, subschema)) = pending.pop() {
+        let KsonValue::KsonObject(subschema) = subschema else {
+            continue;
+        };
+        let keywords = subschema.properties();
+        if let Some(KsonValue::KsonObject(properties)) = keywords.get("properties") {
+            for (name, key) in sorted_property_keys(properties) {
+                declared.push((schema_path.clone().key("properties").key(name), key));
+            }
+        }
+
+        // Only keywords holding subschemas are followed, since other objects (e.g. `const` values)
+        // could have a `properties` property without being schemas
+        for (keyword, value) in keywords {
+            let keyword_path = schema_path.clone().key(keyword.as_str());
+            match (keyword.as_str(), &value) {
+                (
+                    "additionalProperties"
+                    | "additionalItems"
+                    | "items"
+                    | "contains"
+                    | "propertyNames"
+                    | "not"
+                    | "if"
+                    | "then"
+                    | "else"
+                    | "unevaluatedProperties"
+                    | "unevaluatedItems",
+                    KsonValue::KsonObject(_),
+                ) => pending.push((keyword_path, value.clone())),
+                (
+                    "items" | "prefixItems" | "allOf" | "anyOf" | "oneOf",
+                    KsonValue::KsonArray(array),
+                ) => {
+                    pending.extend(
+                        array
+                            .elements()
+                            .into_iter()
+                            .enumerate()
+                            .map(|(index, branch)| (keyword_path.clone().index(index), branch)),
+                    );
+                }
+                (
+                    "properties" | "patternProperties" | "$defs" | "definitions"
+                    | "dependentSchemas",
+                    KsonValue::KsonObject(map),
+                ) => pending.extend(
+                    map.properties()
+                        .into_iter()
+                        .map(|(name, subschema)| (keyword_path.clone().key(name), subschema)),
+                ),
+                _ => {}
+            }
+        }
+    }
+    declared.sort_by_key(|(_, key)| (key.start().line(), key.start().column()));
+    declared
+}
+
+/// The properties of the schema (as located by [`declared_properties`]) that the document uses, counting
+/// those declared by every branch of compositions, like [`validate_closed_world`] does
+#[cfg(feature = "workspace")]
+pub(crate) fn used_properties(schema: &KsonValue, document: &KsonValue) -> BTreeSet<KsonPath> {
+    let mut used = BTreeSet::new();
+    for_each_possible_schema(schema, document, &mut |applicable| {
+        let KsonValue::KsonObject(object) = applicable.instance else {
+            return ControlFlow::Continue(());
+        };
+        if let Some(KsonValue::KsonObject(properties)) =
+            applicable.schema.properties().get("properties")
+        {
+            let present = object.property_keys();
+            for name in properties.property_keys().into_keys() {
+                if present.contains_key(&name) {
+                    used.insert(applicable.schema_path.clone().key("properties").key(name));
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    });
+    used
+}
+
+/// Explains why values are rejected by `allOf`, `anyOf`, `oneOf` or `if`/`then`/`else`, reporting every
+/// branch that rejected the value along with its own errors, so that it's clear why each alternative
+/// failed. Each branch is checked with [`SchemaValidator`] against the value on
+/// its own, so `$ref`s in branches are resolved against the full schema.
+///
+/// Returns no diagnostics if either the schema or the document fails to parse, like
+/// [`validate_formats`].
+pub fn explain_compositions(schema: &str, document: &str) -> Vec<SchemaDiagnostic> {
+    run_checks(
+        schema,
+        document,
+        &[Check::Compositions],
+        DiagnosticLimit::All,
+    )
+}
+
+/// `root` is the whole schema, from which the branches are referenced
+fn check_compositions(
+    applicable: &Applicable<'_>,
+    root: &Value,
+    diagnostics: &mut Vec<SchemaDiagnostic>,
+) {
+    let keywords = applicable.schema.properties();
+    let instance = applicable.instance.to_value().to_json();
+    let check_branch = |keyword: &str, index: Option<usize>| {
+        let mut schema_path = applicable.schema_path.clone().key(keyword);
+        if let Some(index) = index {
+            schema_path = schema_path.index(index);
+        }
+        let errors = branch_errors(root, &schema_path, &instance)?;
+        Some(BranchFailure {
+            index,
+            schema_path,
+            errors,
+        })
+    };
+    let branch_count = |keyword: &str| match keywords.get(keyword) {
+        Some(KsonValue::KsonArray(branches)) => Some(branches.elements().len()),
+        _ => None,
+    };
+
+    let mut report =
+        |keyword: CompositionKeyword, failures: Vec<BranchFailure>, matched: Vec<usize>| {
+            let message = composition_message(keyword, &failures, &matched);
+            let kind = SchemaDiagnosticKind::CompositionFailed {
+                keyword,
+                failures,
+                matched,
+            };
+            diagnostics.push(SchemaDiagnostic::warning(
+                applicable.instance,
+                applicable.path,
+                kind,
+                message,
+            ));
+        };
+
+    for (name, keyword) in [
+        ("allOf", CompositionKeyword::AllOf),
+        ("anyOf", CompositionKeyword::AnyOf),
+        ("oneOf", CompositionKeyword::OneOf),
+    ] {
+        let Some(count) = branch_count(name) else {
+            continue;
+        };
+        let results: Vec<Option<BranchFailure>> = (0..count)
+            .map(|index| check_branch(name, Some(index)))
+            .collect();
+        let matched: Vec<usize> = (0..count)
+            .filter(|index| results[*index].is_none())
+            .collect();
+        let failures: Vec<BranchFailure> = results.into_iter().flatten().collect();
+        match keyword {
+            CompositionKeyword::AllOf if !failures.is_empty() => {
+                report(keyword, failures, Vec::new())
+            }
+            CompositionKeyword::AnyOf | CompositionKeyword::OneOf if matched.is_empty() => {
+                report(keyword, failures, Vec::new())
+            }
+            CompositionKeyword::OneOf if matched.len() > 1 => report(keyword, Vec::new(), matched),
+            _ => {}
+        }
+    }
+
+    if keywords.contains_key("if") {
+        let (name, keyword) = match check_branch("if", None) {
+            None => ("then", CompositionKeyword::Then),
+            Some(_) => ("else", CompositionKeyword::Else),
+        };
+        if keywords.contains_key(name)
+            && let Some(failure) = check_branch(name, None)
+        {
+            report(keyword, vec![failure], Vec::new());
+        }
+    }
+}
+
+/// Validates the instance (as JSON) against the subschema at `schema_path` alone, returning its errors if
+/// it fails. The subschema is referenced from a copy of the root schema, so its own `$ref`s still resolve.
+pub(crate) fn branch_errors(
+    root: &Value,
+    schema_path: &KsonPath,
+    instance: &str,
+) -> Option<Vec<String>> {
+    let schema = match root {
+        Value::Object(keywords) => {
+            // `$ref` overrides its sibling keywords, so the copy of the root only validates the branch
+            let mut keywords = keywords.clone();
+            keywords.insert("$ref", Value::String(format!("#{schema_path}")));
+            Value::Object(keywords)
+        }
+        _ => return None,
+    };
+    let validator = Kson::parse_schema(&schema.to_json())
+        .ok()?
+        .schema_validator();
+    let errors: Vec<String> = validator
+        .validate(instance, None)
+        .into_iter()
+        .map(|message| message.message())
+        .collect();
+    (!errors.is_empty()).then_some(errors)
+}
+
+fn composition_message(
+    keyword: CompositionKeyword,
+    failures: &[BranchFailure],
+    matched: &[usize],
+) -> String {
+    let mut message = match keyword {
+        CompositionKeyword::AllOf => "Value fails some of the `allOf` branches:".to_string(),
+        CompositionKeyword::AnyOf | CompositionKeyword::OneOf if matched.is_empty() => {
+            format!("Value matches none of the `{keyword}` alternatives:")
+        }
+        CompositionKeyword::AnyOf | CompositionKeyword::OneOf => {
+            let matched: Vec<String> = matched
+                .iter()
+                .map(|index| format!("#{}", index + 1))
+                .collect();
+            return format!(
+                "Value matches {} of the `{keyword}` alternatives ({}), but must match exactly one",
+                matched.len(),
+                matched.join(", ")
+            );
+        }
+        CompositionKeyword::Then => {
+            "Value matches the `if` condition but fails `then`:".to_string()
+        }
+        CompositionKeyword::Else => {
+            "Value doesn't match the `if` condition and fails `else`:".to_string()
+        }
+    };
+    for failure in failures {
+        let branch = match failure.index {
+            Some(index) => format!("#{}", index + 1),
+            None => keyword.to_string(),
+        };
+        message.push_str(&format!(
+            "\n- {branch} (`#{}`): {}",
+            failure.schema_path,
+            failure.errors.join("; ")
+        ));
+    }
+    message
+}
+
+/// Returns the allowed scalar values whose text is within a few edits of the actual value's, closest first
+fn rank_suggestions(actual: &Value, allowed: &[Value]) -> Vec<Value> {
+    let Some(actual_text) = scalar_text(actual) else {
+        return Vec::new();
+    };
+    let actual_text = actual_text.to_lowercase();
+    // Allow roughly one typo every three characters
+    let max_distance = (actual_text.chars().count() / 3).max(1);
+
+    let mut ranked: Vec<(usize, usize, &Value)> = allowed
+        .iter()
+        .enumerate()
+        .filter_map(|(index, candidate)| {
+            let candidate_text = scalar_text(candidate)?.to_lowercase();
+            let distance = edit_distance(&actual_text, &candidate_text);
+            // A candidate that has to be rewritten entirely is not a near match
+            let length = actual_text
+                .chars()
+                .count()
+                .max(candidate_text.chars().count());
+            (distance <= max_distance && distance < length).then_some((distance, index, candidate))
+        })
+        .collect();
+    ranked.sort_by_key(|(distance, index, _)| (*distance, *index));
+    ranked
+        .into_iter()
+        .map(|(_, _, value)| value.clone())
+        .collect()
+}
+
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.clone()),
+        Value::Array(_) | Value::Object(_) | Value::Embed { .. } => None,
+        scalar => Some(scalar.to_json()),
+    }
+}
+
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::Array(_) | Value::Object(_) => "`...`".to_string(),
+        Value::String(string) => format!("`{string}`"),
+        scalar => format!("`{}`", scalar.to_json()),
+    }
+}
+
+/// The Levenshtein distance between the two strings, in characters
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Check<'a> {
+    Formats,
+    Enums,
+    UniqueItems,
+    Required,
+    Compositions,
+    /// With the paths of the properties allowed to be undeclared
+    ClosedWorld(&'a [PointerGlob]),
+}
+
+/// Parses the schema and the document and runs the checks on every subschema applying to the document,
+/// stopping once the limit is reached. Returns no diagnostics if either of them fails to parse.
+fn run_checks(
+    schema: &str,
+    document: &str,
+    checks: &[Check<'_>],
+    limit: DiagnosticLimit,
+) -> Vec<SchemaDiagnostic> {
+    // A token nobody else holds can't be cancelled
+    run_checks_cancellable(schema, document, checks, limit, &CancellationToken::new()).unwrap()
+}
+
+/// Like [`run_checks`], parsing through [`CancellationToken::run`] and checking the token before each
+/// subschema
+fn run_checks_cancellable(
+    schema: &str,
+    document: &str,
+    checks: &[Check<'_>],
+    limit: DiagnosticLimit,
+    token: &CancellationToken,
+) -> Result<Vec<SchemaDiagnostic>, Cancelled> {
+    let text = LineIndex::new(document);
+    let parse = |text: &str| {
+        let text = text.to_string();
+        token.run(move || Kson::analyze(&text, None).kson_value())
+    };
+    let (Some(schema), Some(document)) = (parse(schema)?, parse(document)?) else {
+        return Ok(Vec::new());
+    };
+
+    let root = checks
+        .contains(&Check::Compositions)
+        .then(|| schema.to_value());
+    let mut diagnostics = Vec::new();
+    let mut declarations = Declarations::default();
+    let mut cancelled = Ok(());
+    for_each_applicable_schema(&schema, &document, &mut |applicable| {
+        cancelled = token.check();
+        if cancelled.is_err() {
+            return ControlFlow::Break(());
+        }
+        for check in checks {
+            match (check, &root) {
+                (Check::Formats, _) => check_formats(&applicable, &mut diagnostics),
+                (Check::Enums, _) => check_enums(&applicable, &mut diagnostics),
+                (Check::UniqueItems, _) => check_unique_items(&applicable, &mut diagnostics),
+                (Check::Required, _) => {
+                    check_required(&applicable, &schema, &text, &mut diagnostics)
+                }
+                (Check::Compositions, Some(root)) => {
+                    check_compositions(&applicable, root, &mut diagnostics)
+                }
+                (Check::Compositions, None) => {}
+                // Reported once every subschema applying to an object has declared its properties
+                (Check::ClosedWorld(_), _) => declarations.declare(&applicable, &schema),
+            }
+            if limit.is_reached(diagnostics.len()) {
+                return ControlFlow::Break(());
+            }
+        }
+        ControlFlow::Continue(())
+    });
+    cancelled?;
+    for check in checks {
+        if let Check::ClosedWorld(exceptions) = check {
+            check_closed_world(declarations, exceptions, &mut diagnostics);
+            break;
+        }
+    }
+    limit.truncate(&mut diagnostics);
+    Ok(diagnostics)
+}
+
+/// An (object) subschema applying to a value of the document
+pub(crate) struct Applicable<'a> {
+    pub(crate) schema: &'a kson_value::KsonObject,
+    /// The location of the subschema in the schema document
+    pub(crate) schema_path: &'a KsonPath,
+    pub(crate) instance: &'a KsonValue,
+    /// The location of the value in the document
+    pub(crate) path: &'a KsonPath,
+}
+
+/// Calls `visit` for every (object) subschema of `root` that applies to `instance` or one of its
+/// descendants, until it breaks
+pub(crate) fn for_each_applicable_schema(
+    root: &KsonValue,
+    instance: &KsonValue,
+    visit: &mut dyn FnMut(Applicable<'_>) -> ControlFlow<()>,
+) {
+    let mut walker = Walker {
+        root,
+        visit,
+        all_branches: false,
+    };
+    let _ = walker.walk(instance);
+}
+
+/// Like [`for_each_applicable_schema`], also visiting the branches of `anyOf`, `oneOf` and
+/// `then`/`else`, whether they apply or not
+pub(crate) fn for_each_possible_schema(
+    root: &KsonValue,
+    instance: &KsonValue,
+    visit: &mut dyn FnMut(Applicable<'_>) -> ControlFlow<()>,
+) {
+    let mut walker = Walker {
+        root,
+        visit,
+        all_branches: true,
+    };
+    let _ = walker.walk(instance);
+}
+
+struct Walker<'a> {
+    root: &'a KsonValue,
+    visit: &'a mut dyn FnMut(Applicable<'_>) -> ControlFlow<()>,
+    /// Whether to walk the branches of compositions that may not apply
+    all_branches: bool,
+}
+
+/// A subschema to walk, applying to a value of the document
+struct Pending {
+    schema: KsonValue,
+    schema_path: KsonPath,
+    instance: KsonValue,
+    path: KsonPath,
+    /// The number of `$ref`s followed to reach the subschema from one applying to the same value
+    ref_depth: usize,
+}
+
+impl Walker<'_> {
+    /// Walks the subschemas applying to `instance` depth-first, from a stack rather than recursively:
+    /// documents are only as deep as the parser allows, but `$ref`s can chain at every level
+    fn walk(&mut self, instance: &KsonValue) -> ControlFlow<()> {
+        let mut pending = vec![Pending {
+            schema: self.root.clone(),
+            schema_path: KsonPath::root(),
+            instance: instance.clone(),
+            path: KsonPath::root(),
+            ref_depth: 0,
+        }];
+        let mut next = Vec::new();
+        while let Some(subschema) = pending.pop() {
+            self.step(&subschema, &mut next)?;
+            pending.extend(next.drain(..).rev());
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Visits a subschema, adding those to walk next to `next`, in order
+    fn step(&mut self, subschema: &Pending, next: &mut Vec<Pending>) -> ControlFlow<()> {
+        let Pending {
+            schema,
+            schema_path,
+            instance,
+            path,
+            ref_depth,
+        } = subschema;
+        // Boolean schemas don't have subschemas
+        let KsonValue::KsonObject(schema_object) = schema else {
+            return ControlFlow::Continue(());
+        };
+        let keywords = schema_object.properties();
+        (self.visit)(Applicable {
+            schema: schema_object,
+            schema_path,
+            instance,
+            path,
+        })?;
+
+        let same_value = |schema: &KsonValue, schema_path: KsonPath, ref_depth: usize| Pending {
+            schema: schema.clone(),
+            schema_path,
+            instance: instance.clone(),
+            path: path.clone(),
+            ref_depth,
+        };
+        if let Some(KsonValue::KsonString(reference)) = keywords.get("$ref")
+            && *ref_depth < MAX_REF_DEPTH
+            && let Some((target_path, target)) = resolve_local_ref(self.root, &reference.value())
+        {
+            next.push(same_value(&target, target_path, ref_depth + 1));
+        }
+
+        let compositions: &[&str] = if self.all_branches {
+            &["allOf", "anyOf", "oneOf"]
+        } else {
+            &["allOf"]
+        };
+        for keyword in compositions {
+            let Some(KsonValue::KsonArray(branches)) = keywords.get(*keyword) else {
+                continue;
+            };
+            for (index, subschema) in branches.elements().iter().enumerate() {
+                let subschema_path = schema_path.clone().key(*keyword).index(index);
+                next.push(same_value(subschema, subschema_path, *ref_depth));
+            }
+        }
+        if self.all_branches {
+            for keyword in ["then", "else"] {
+                if let Some(subschema) = keywords.get(keyword) {
+                    let subschema_path = schema_path.clone().key(keyword);
+                    next.push(same_value(subschema, subschema_path, *ref_depth));
+                }
+            }
+        }
+
+        // A subschema applying to a child of the value
+        let child = |subschema: &KsonValue,
+                     keyword_path: Vec<PathSegment>,
+                     child: &KsonValue,
+                     segment: PathSegment| Pending {
+            schema: subschema.clone(),
+            schema_path: keyword_path
+                .into_iter()
+                .fold(schema_path.clone(), KsonPath::join),
+            instance: child.clone(),
+            path: path.clone().join(segment),
+            ref_depth: 0,
+        };
+        match instance {
+            KsonValue::KsonObject(object) => {
+                let property_schemas = match keywords.get("properties") {
+                    Some(KsonValue::KsonObject(properties)) => properties.properties(),
+                    _ => Default::default(),
+                };
+                // We can't tell which properties `patternProperties` covers, so in its presence we don't
+                // know which ones `additionalProperties` applies to either
+                let additional_properties = keywords
+                    .get("additionalProperties")
+                    .filter(|_| !keywords.contains_key("patternProperties"));
+
+                for (key, value) in object.properties() {
+                    let (subschema, keyword_path) = match property_schemas.get(&key) {
+                        Some(subschema) => (subschema, vec!["properties".to_string(), key.clone()]),
+                        None => match additional_properties {
+                            Some(subschema) => {
+                                (subschema, vec!["additionalProperties".to_string()])
+                            }
+                            None => continue,
+                        },
+                    };
+                    let keyword_path = keyword_path.into_iter().map(PathSegment::Key).collect();
+                    next.push(child(
+                        subschema,
+                        keyword_path,
+                        &value,
+                        PathSegment::Key(key),
+                    ));
+                }
+            }
+            KsonValue::KsonArray(array) => {
+                let elements = array.elements();
+                match keywords.get("items") {
+                    Some(KsonValue::KsonArray(item_schemas)) => {
+                        let item_schemas = item_schemas.elements();
+                        let additional_items = keywords.get("additionalItems");
+                        for (index, element) in elements.iter().enumerate() {
+                            let (subschema, keyword_path) = match item_schemas.get(index) {
+                                Some(subschema) => (
+                                    subschema,
+                                    vec![
+                                        PathSegment::Key("items".to_string()),
+                                        PathSegment::Index(index),
+                                    ],
+                                ),
+                                None => match additional_items {
+                                    Some(subschema) => (
+                                        subschema,
+                                        vec![PathSegment::Key("additionalItems".to_string())],
+                                    ),
+                                    None => continue,
+                                },
+                            };
+                            next.push(child(
+                                subschema,
+                                keyword_path,
+                                element,
+                                PathSegment::Index(index),
+                            ));
+                        }
+                    }
+                    Some(subschema) => {
+                        for (index, element) in elements.iter().enumerate() {
+                            next.push(child(
+                                subschema,
+                                vec![PathSegment::Key("items".to_string())],
+                                element,
+                                PathSegment::Index(index),
+                            ));
+                        }
+                    }
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Resolves a `$ref` of the form `#` or `#/json/pointer` against the root schema, returning the location
+/// of the target along with it
+fn resolve_local_ref(root: &KsonValue, reference: &str) -> Option<(KsonPath, KsonValue)> {
+    let path = KsonPath::parse(reference.strip_prefix('#')?).ok()?;
+    let mut current = root.clone();
+    for segment in path.segments() {
+        current = match (current, segment) {
+            (KsonValue::KsonObject(object), segment) => {
+                object.properties().remove(&segment.to_string())?
+            }
+            (KsonValue::KsonArray(array), PathSegment::Index(index)) => {
+                array.elements().into_iter().nth(*index)?
+            }
+            _ => return None,
+        };
+    }
+    Some((path, current))
+}
@@ -0,0 +1,224 @@
+//! Resolution of secrets referenced from documents, so that they never have to be written in them.
+//!
+//! A string value of the form `secret://<provider>/<path>` (e.g. `secret://vault/kv/db/password`)
+//! refers to a secret, which a [`SecretResolver`] (or an [`AsyncSecretResolver`], for providers
+//! reached over the network) looks up when the document is loaded:
+//!
+//! ```no_run
+//! use kson_rs::secrets::{SecretError, SecretReference, parse_with_secrets};
+//!
+//! let resolver = |reference: &SecretReference| -> Result<String, SecretError> {
+//!     match reference.provider.as_str() {
+//!         "env" => Ok(std::env::var(&reference.path)?),
+//!         provider => Err(format!("unknown secret provider `{provider}`").into()),
+//!     }
+//! };
+//! let config = parse_with_secrets("database: { password: 'secret://env/DB_PASSWORD' }", &resolver);
+//! ```
+
+use crate::error::KsonErrors;
+use crate::path::KsonPath;
+use crate::value::{Step, Value};
+
+/// The scheme marking string values as secret references
+pub const SECRET_SCHEME: &str = "secret://";
+
+/// The error returned by a [`SecretResolver`]
+pub type SecretError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A reference to a secret, parsed from a `secret://<provider>/<path>` string
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SecretReference {
+    /// The first segment after the scheme, naming where the secret is stored (e.g. `vault`)
+    pub provider: String,
+    /// The rest of the reference, identifying the secret within its provider
+    pub path: String,
+}
+
+impl SecretReference {
+    /// Parses a secret reference, returning `None` if the text doesn't start with [`SECRET_SCHEME`]
+    pub fn parse(text: &str) -> Option<Self> {
+        let reference = text.strip_prefix(SECRET_SCHEME)?;
+        let (provider, path) = reference.split_once('/').unwrap_or((reference, ""));
+        Some(Self {
+            provider: provider.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for SecretReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{SECRET_SCHEME}{}/{}", self.provider, self.path)
+    }
+}
+
+/// Looks up the value of secrets
+pub trait SecretResolver {
+    fn resolve(&self, reference: &SecretReference) -> Result<String, SecretError>;
+}
+
+impl<F: Fn(&SecretReference) -> Result<String, SecretError>> SecretResolver for F {
+    fn resolve(&self, reference: &SecretReference) -> Result<String, SecretError> {
+        self(reference)
+    }
+}
+
+/// Looks up the value of secrets asynchronously, e.g. from a secret manager reached over the network
+pub trait AsyncSecretResolver {
+    fn resolve(
+        &self,
+        reference: &SecretReference,
+    ) -> impl Future<Output = Result<String, SecretError>>;
+}
+
+/// A secret that couldn't be resolved
+#[derive(Debug)]
+pub struct UnresolvedSecret {
+    /// The path of the reference in the document
+    pub path: KsonPath,
+    pub reference: SecretReference,
+    pub error: SecretError,
+}
+
+impl std::fmt::Display for UnresolvedSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to resolve `{}` (at {}): {}",
+            self.reference, self.path, self.error
+        )
+    }
+}
+
+impl std::error::Error for UnresolvedSecret {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+/// The reason a document couldn't be loaded by [`parse_with_secrets`]
+#[derive(Debug)]
+pub enum LoadError {
+    /// The document is not valid KSON
+    Parse(KsonErrors),
+    /// Some secrets couldn't be resolved
+    Secrets(Vec<UnresolvedSecret>),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Parse(errors) => errors.fmt(f),
+            LoadError::Secrets(secrets) => {
+                let secrets: Vec<String> = secrets.iter().map(ToString::to_string).collect();
+                write!(f, "{}", secrets.join("\n"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Replaces every secret reference in the value by the secret it refers to, returning all of the
+/// secrets that couldn't be resolved if there are any
+pub fn resolve_secrets(
+    value: &Value,
+    resolver: &impl SecretResolver,
+) -> Result<Value, Vec<UnresolvedSecret>> {
+    let references = secret_references(value);
+    let resolved = references
+        .into_iter()
+        .map(|(path, reference)| {
+            let secret = resolver.resolve(&reference);
+            (path, reference, secret)
+        })
+        .collect();
+    replace_references(value, resolved)
+}
+
+/// Like [`resolve_secrets`], with an [`AsyncSecretResolver`]. Secrets are resolved one after the other,
+/// in document order.
+pub async fn resolve_secrets_async(
+    value: &Value,
+    resolver: &impl AsyncSecretResolver,
+) -> Result<Value, Vec<UnresolvedSecret>> {
+    let mut resolved = Vec::new();
+    for (path, reference) in secret_references(value) {
+        let secret = resolver.resolve(&reference).await;
+        resolved.push((path, reference, secret));
+    }
+    replace_references(value, resolved)
+}
+
+/// Parses the document and resolves its secrets with [`resolve_secrets`]
+pub fn parse_with_secrets(
+    document: &str,
+    resolver: &impl SecretResolver,
+) -> Result<Value, LoadError> {
+    let value = Value::parse(document).map_err(LoadError::Parse)?;
+    resolve_secrets(&value, resolver).map_err(LoadError::Secrets)
+}
+
+/// Parses the document and resolves its secrets with [`resolve_secrets_async`]
+pub async fn parse_with_secrets_async(
+    document: &str,
+    resolver: &impl AsyncSecretResolver,
+) -> Result<Value, LoadError> {
+    let value = Value::parse(document).map_err(LoadError::Parse)?;
+    resolve_secrets_async(&value, resolver)
+        .await
+        .map_err(LoadError::Secrets)
+}
+
+/// The secret references of the value, in document order
+fn secret_references(value: &Value) -> Vec<(KsonPath, SecretReference)> {
+    let mut references = Vec::new();
+    let mut path = KsonPath::root();
+    for step in value.walk() {
+        if let Some(segment) = step.segment() {
+            path.push(segment);
+        }
+        match step {
+            Step::Enter {
+                value: Value::String(text),
+                ..
+            } => {
+                if let Some(reference) = SecretReference::parse(text) {
+                    references.push((path.clone(), reference));
+                }
+            }
+            Step::Leave { .. } => path.pop(),
+            Step::Enter { .. } => {}
+        }
+    }
+    references
+}
+
+fn replace_references(
+    value: &Value,
+    resolved: Vec<(KsonPath, SecretReference, Result<String, SecretError>)>,
+) -> Result<Value, Vec<UnresolvedSecret>> {
+    let mut value = value.clone();
+    let mut unresolved = Vec::new();
+    for (path, reference, secret) in resolved {
+        match secret {
+            Ok(secret) => {
+                if let Some(target) = value.get_path_mut(&path) {
+                    *target = Value::String(secret);
+                }
+            }
+            Err(error) => unresolved.push(UnresolvedSecret {
+                path,
+                reference,
+                error,
+            }),
+        }
+    }
+
+    if unresolved.is_empty() {
+        Ok(value)
+    } else {
+        Err(unresolved)
+    }
+}
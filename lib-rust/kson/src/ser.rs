@@ -0,0 +1,479 @@
+//! Serde serialization of Rust values into KSON, enabled through the `serde` feature.
+//!
+//! [`to_value`] serializes into an owned [`Value`], which [`to_string`] then renders as KSON text,
+//! formatted with the given options ([`to_string_with`] takes a [`Formatter`] instead, for the options
+//! only a formatter applies, like [`QuoteStyle`](crate::format::QuoteStyle)). This makes it possible to
+//! write configuration files from Rust types directly, without going through JSON:
+//!
+//! ```no_run
+//! use std::collections::BTreeMap;
+//!
+//! use kson_rs::{FormatOptions, FormattingStyle, IndentType, indent_type};
+//!
+//! let options = FormatOptions::new(
+//!     IndentType::Spaces(indent_type::Spaces::new(2)),
+//!     FormattingStyle::Plain,
+//!     &[],
+//! );
+//! let ports = BTreeMap::from([("http", 80), ("https", 443)]);
+//! assert_eq!(
+//!     kson_rs::ser::to_string(&ports, &options).unwrap(),
+//!     "http: 80\nhttps: 443"
+//! );
+//! ```
+//!
+//! Values map to KSON the way `serde_json` maps them to JSON: `None` and unit values become `null`, unit
+//! enum variants become strings, other enum variants become an object with the variant name as its only
+//! key, and bytes become arrays of integers. Map keys must serialize as strings, integers, booleans or
+//! characters, and integers must fit in an `i64`, the range of KSON integers. Values nested deeper than
+//! [`MaxDepth::DEFAULT`] are rejected, unless serialized with [`to_value_with`] and a higher limit.
+
+use serde::Serialize;
+use serde::ser::{
+    SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+    SerializeTupleStruct, SerializeTupleVariant,
+};
+
+use crate::FormatOptions;
+pub use crate::de::Error;
+use crate::depth::{DepthError, MaxDepth};
+use crate::format::Formatter;
+use crate::path::{KsonPath, PathSegment};
+use crate::value::{Map, Value};
+
+impl serde::ser::Error for Error {
+    fn custom<T: std::fmt::Display>(message: T) -> Self {
+        <Error as serde::de::Error>::custom(message)
+    }
+}
+
+/// Serializes a value into an owned [`Value`], nested no deeper than [`MaxDepth::DEFAULT`]
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, Error> {
+    to_value_with(value, MaxDepth::DEFAULT)
+}
+
+/// Serializes a value into an owned [`Value`], failing if it nests deeper than `max_depth`
+pub fn to_value_with<T: Serialize + ?Sized>(
+    value: &T,
+    max_depth: MaxDepth,
+) -> Result<Value, Error> {
+    value.serialize(Serializer::new(max_depth))
+}
+
+/// Serializes a value into KSON text, formatted with the given options
+pub fn to_string<T: Serialize + ?Sized>(
+    value: &T,
+    options: &FormatOptions,
+) -> Result<String, Error> {
+    to_string_with(value, &Formatter::new(options.clone()))
+}
+
+/// Serializes a value into KSON text, formatted with the given formatter
+pub fn to_string_with<T: Serialize + ?Sized>(
+    value: &T,
+    formatter: &Formatter,
+) -> Result<String, Error> {
+    Ok(to_value(value)?.to_kson_with(formatter))
+}
+
+fn custom(message: impl std::fmt::Display) -> Error {
+    <Error as serde::ser::Error>::custom(message)
+}
+
+fn integer(value: impl TryInto<i64> + std::fmt::Display + Copy) -> Result<Value, Error> {
+    value
+        .try_into()
+        .map(Value::Integer)
+        .map_err(|_| custom(format!("{value} is out of the range of KSON integers")))
+}
+
+/// Wraps the value of an enum variant in an object keyed by the variant name
+fn variant(name: &str, value: Value) -> Value {
+    Value::Object(Map::from_iter([(name, value)]))
+}
+
+/// Serializes values into [`Value`]s (see the [module documentation](self))
+#[derive(Clone, Debug, Default)]
+pub struct Serializer {
+    max_depth: MaxDepth,
+    /// The path of the value being serialized, in the value serialized from the start
+    path: KsonPath,
+}
+
+impl Serializer {
+    /// A serializer rejecting values nested deeper than `max_depth`
+    pub fn new(max_depth: MaxDepth) -> Self {
+        Serializer {
+            max_depth,
+            path: KsonPath::root(),
+        }
+    }
+
+    /// The serializer of a value nested in this one
+    fn child(&self, segment: PathSegment) -> Result<Serializer, Error> {
+        let path = self.path.clone().join(segment);
+        if !self.max_depth.allows(path.len()) {
+            return Err(custom(DepthError {
+                max_depth: self.max_depth,
+                path,
+            }));
+        }
+        Ok(Serializer {
+            max_depth: self.max_depth,
+            path,
+        })
+    }
+
+    /// The serializer of the value of an enum variant, nested in an object keyed by its name
+    fn variant(&self, name: &str) -> Result<Serializer, Error> {
+        self.child(PathSegment::Key(name.to_string()))
+    }
+}
+
+impl serde::Serializer for Serializer {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = Elements;
+    type SerializeTuple = Elements;
+    type SerializeTupleStruct = Elements;
+    type SerializeTupleVariant = Elements;
+    type SerializeMap = Properties;
+    type SerializeStruct = Properties;
+    type SerializeStructVariant = Properties;
+
+    fn serialize_bool(self, value: bool) -> Result<Value, Error> {
+        Ok(Value::Bool(value))
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<Value, Error> {
+        integer(value)
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<Value, Error> {
+        integer(value)
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<Value, Error> {
+        integer(value)
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<Value, Error> {
+        integer(value)
+    }
+
+    fn serialize_i128(self, value: i128) -> Result<Value, Error> {
+        integer(value)
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<Value, Error> {
+        integer(value)
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<Value, Error> {
+        integer(value)
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<Value, Error> {
+        integer(value)
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<Value, Error> {
+        integer(value)
+    }
+
+    fn serialize_u128(self, value: u128) -> Result<Value, Error> {
+        integer(value)
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<Value, Error> {
+        Ok(Value::Decimal(value.into()))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<Value, Error> {
+        Ok(Value::Decimal(value))
+    }
+
+    fn serialize_char(self, value: char) -> Result<Value, Error> {
+        Ok(Value::String(value.to_string()))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Value, Error> {
+        Ok(Value::from(value))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Value, Error> {
+        Ok(Value::Array(
+            value
+                .iter()
+                .map(|&byte| Value::Integer(byte.into()))
+                .collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Error> {
+        Ok(Value::from(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        Ok(variant(name, value.serialize(self.variant(name)?)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Elements, Error> {
+        Ok(Elements {
+            variant: None,
+            elements: Vec::with_capacity(len.unwrap_or(0)),
+            serializer: self,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Elements, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Elements, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Elements, Error> {
+        Ok(Elements {
+            variant: Some(variant),
+            elements: Vec::with_capacity(len),
+            serializer: self.variant(variant)?,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Properties, Error> {
+        Ok(Properties {
+            variant: None,
+            properties: Map::new(),
+            key: None,
+            serializer: self,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Properties, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Properties, Error> {
+        Ok(Properties {
+            variant: Some(variant),
+            properties: Map::new(),
+            key: None,
+            serializer: self.variant(variant)?,
+        })
+    }
+}
+
+/// The elements of a sequence or tuple being serialized
+pub struct Elements {
+    /// The name of the enum variant holding the elements, if any
+    variant: Option<&'static str>,
+    elements: Vec<Value>,
+    /// The serializer of the array holding the elements
+    serializer: Serializer,
+}
+
+impl Elements {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let index = PathSegment::Index(self.elements.len());
+        self.elements
+            .push(value.serialize(self.serializer.child(index)?)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Value {
+        let array = Value::Array(self.elements);
+        match self.variant {
+            Some(name) => variant(name, array),
+            None => array,
+        }
+    }
+}
+
+impl SerializeSeq for Elements {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl SerializeTuple for Elements {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl SerializeTupleStruct for Elements {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl SerializeTupleVariant for Elements {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+/// The properties of a map or struct being serialized
+pub struct Properties {
+    /// The name of the enum variant holding the properties, if any
+    variant: Option<&'static str>,
+    properties: Map,
+    /// The key of the map entry whose value is serialized next
+    key: Option<String>,
+    /// The serializer of the object holding the properties
+    serializer: Serializer,
+}
+
+impl Properties {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<(), Error> {
+        let serializer = self.serializer.child(PathSegment::Key(key.clone()))?;
+        self.properties.insert(key, value.serialize(serializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Value {
+        let object = Value::Object(self.properties);
+        match self.variant {
+            Some(name) => variant(name, object),
+            None => object,
+        }
+    }
+}
+
+impl SerializeMap for Properties {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(match &mut to_value(key)? {
+            Value::String(key) => std::mem::take(key),
+            Value::Integer(key) => key.to_string(),
+            Value::Bool(key) => key.to_string(),
+            _ => return Err(custom("object keys must be strings, integers or booleans")),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| custom("value serialized before its key"))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl SerializeStruct for Properties {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl SerializeStructVariant for Properties {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
@@ -0,0 +1,230 @@
+//! Documents shared between threads, e.g. the configuration a web server looks keys up in on every
+//! request.
+//!
+//! A [`SharedDocument`] is immutable and reference-counted, so cloning it to hand it to another thread
+//! only bumps a counter, and indexes every value by its JSON Pointer when it's built, so that a lookup
+//! is a hash of the pointer followed by a walk down positions, without comparing keys:
+//!
+//! ```no_run
+//! use kson_rs::shared::SharedDocument;
+//! use kson_rs::value::Value;
+//!
+//! let config = SharedDocument::parse("server: { host: localhost, port: 8080 }").unwrap();
+//! let handles: Vec<_> = (0..4)
+//!     .map(|_| {
+//!         let config = config.clone();
+//!         std::thread::spawn(move || config.pointer("/server/port").cloned())
+//!     })
+//!     .collect();
+//! for handle in handles {
+//!     assert_eq!(handle.join().unwrap(), Some(Value::Integer(8080)));
+//! }
+//! ```
+//!
+//! To change a shared document, build a new one and swap it in, e.g. behind a lock held only for the
+//! swap: readers keep the document they cloned until they drop it.
+//!
+//! On hot paths (e.g. a feature flag read on every request), compile the [`Path`] once and use the typed
+//! getters, which neither parse nor allocate:
+//!
+//! ```no_run
+//! use kson_rs::shared::{Path, SharedDocument};
+//!
+//! let flags = SharedDocument::parse("checkout: { new_flow: true, rollout: 25 }").unwrap();
+//! let new_flow = Path::parse("/checkout/new_flow").unwrap();
+//! let rollout = Path::parse("/checkout/rollout").unwrap();
+//! // On each request
+//! if flags.get_bool(&new_flow).unwrap_or(false) && flags.get_i64(&rollout).unwrap_or(0) > 20 {
+//!     // ...
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::error::KsonErrors;
+use crate::path::KsonPath;
+use crate::pointer::PointerError;
+use crate::value::{Step, Value};
+
+/// An immutable document, cheap to clone and indexed for pointer lookups (see the
+/// [module documentation](self))
+#[derive(Clone, Debug)]
+pub struct SharedDocument {
+    inner: Arc<Indexed>,
+}
+
+#[derive(Debug)]
+struct Indexed {
+    value: Value,
+    /// The positions leading from the root to each value (property positions in objects, indices in
+    /// arrays), by JSON Pointer
+    positions: HashMap<String, Vec<usize>>,
+}
+
+impl SharedDocument {
+    /// Parses the document, failing with its errors (warnings are ignored)
+    pub fn parse(document: &str) -> Result<Self, KsonErrors> {
+        Ok(Self::new(Value::parse(document)?))
+    }
+
+    /// Indexes the value. This walks the whole value once, so documents are meant to be built once and
+    /// then cloned.
+    pub fn new(value: Value) -> Self {
+        let mut positions = HashMap::new();
+        index_values(&value, &mut positions);
+        Self {
+            inner: Arc::new(Indexed { value, positions }),
+        }
+    }
+
+    /// The root value
+    pub fn value(&self) -> &Value {
+        &self.inner.value
+    }
+
+    /// Returns the value at the given JSON Pointer, or `None` if there is none or the pointer is
+    /// malformed. Unlike [`Value::pointer`], object keys and array indices must be written the way
+    /// [`KsonPath`] displays them (e.g. `/ports/1`, not `/ports/01`).
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        let positions = self.inner.positions.get(pointer)?;
+        positions
+            .iter()
+            .try_fold(&self.inner.value, |value, &position| match value {
+                Value::Object(map) => map.get_index(position),
+                Value::Array(elements) => elements.get(position),
+                _ => None,
+            })
+    }
+
+    /// Returns the value at the given path, if there is one
+    pub fn get_path(&self, path: &KsonPath) -> Option<&Value> {
+        self.pointer(&path.to_string())
+    }
+
+    /// Returns the value at the compiled path, if there is one
+    pub fn get(&self, path: &Path) -> Option<&Value> {
+        self.pointer(&path.pointer)
+    }
+
+    /// Returns the string at the path, if there is one (embed blocks are not strings)
+    pub fn get_str(&self, path: &Path) -> Option<&str> {
+        match self.get(path)? {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn get_i64(&self, path: &Path) -> Option<i64> {
+        match self.get(path)? {
+            Value::Integer(integer) => Some(*integer),
+            _ => None,
+        }
+    }
+
+    /// Returns the number at the path, if it's a decimal or an integer (which may lose precision beyond
+    /// 2^53, and is infinite past the range of an `f64`)
+    pub fn get_f64(&self, path: &Path) -> Option<f64> {
+        match self.get(path)? {
+            Value::Decimal(decimal) => Some(*decimal),
+            Value::Integer(integer) => Some(*integer as f64),
+            Value::BigInteger(integer) => Some(integer.to_f64()),
+            _ => None,
+        }
+    }
+
+    pub fn get_bool(&self, path: &Path) -> Option<bool> {
+        match self.get(path)? {
+            Value::Bool(boolean) => Some(*boolean),
+            _ => None,
+        }
+    }
+
+    /// Whether both documents are clones of the same one
+    pub fn ptr_eq(&self, other: &SharedDocument) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+/// A path compiled for repeated lookups in [`SharedDocument`]s, e.g. once at startup for a lookup on
+/// every request
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Path {
+    /// The pointer as [`KsonPath`] displays it, which is how [`SharedDocument`] indexes its values
+    pointer: Box<str>,
+}
+
+impl Path {
+    /// Compiles a JSON Pointer such as `/servers/0/name` (the empty string being the root)
+    pub fn parse(pointer: &str) -> Result<Self, PointerError> {
+        Ok(Self::from(&KsonPath::parse(pointer)?))
+    }
+}
+
+impl From<&KsonPath> for Path {
+    fn from(path: &KsonPath) -> Self {
+        Self {
+            pointer: path.to_string().into(),
+        }
+    }
+}
+
+impl FromStr for Path {
+    type Err = PointerError;
+
+    fn from_str(pointer: &str) -> Result<Self, Self::Err> {
+        Self::parse(pointer)
+    }
+}
+
+impl std::fmt::Display for Path {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.pointer)
+    }
+}
+
+impl From<Value> for SharedDocument {
+    fn from(value: Value) -> Self {
+        Self::new(value)
+    }
+}
+
+impl PartialEq for SharedDocument {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || self.value() == other.value()
+    }
+}
+
+/// Records the positions leading to the value and to each value inside it, by pointer
+fn index_values(value: &Value, index: &mut HashMap<String, Vec<usize>>) {
+    let mut pointer = String::new();
+    // The length of the pointer to each value entered and not left yet
+    let mut lengths = Vec::new();
+    let mut positions = Vec::new();
+    for step in value.walk() {
+        match step {
+            Step::Enter {
+                depth,
+                position,
+                key,
+                ..
+            } => {
+                lengths.push(pointer.len());
+                if depth > 0 {
+                    let segment = key.map_or_else(|| position.to_string(), str::to_string);
+                    pointer.push('/');
+                    pointer.push_str(&segment.replace('~', "~0").replace('/', "~1"));
+                    positions.push(position);
+                }
+                index.insert(pointer.clone(), positions.clone());
+            }
+            Step::Leave { depth, .. } => {
+                pointer.truncate(lengths.pop().unwrap_or_default());
+                if depth > 0 {
+                    positions.pop();
+                }
+            }
+        }
+    }
+}
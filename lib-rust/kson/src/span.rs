@@ -0,0 +1,124 @@
+//! Source spans of parsed values, for tools reporting precise locations (linters, validators, editors).
+//!
+//! A [`KsonValue`] only knows the line and column it starts and ends at, as reported by kson-lib, so
+//! [`KsonValue::span`] takes a [`LineIndex`] of the source the value was parsed from to add byte offsets.
+//! To locate every value of a document, [`KsonValue::spans`] indexes the source itself:
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//! use kson_rs::line_index::LineIndex;
+//! use kson_rs::path::KsonPath;
+//!
+//! let source = "server: { port: 80 }";
+//! let value = Kson::analyze(source, None).kson_value().unwrap();
+//! let spans = value.spans(source);
+//! let port = spans[&KsonPath::root().key("server").key("port")];
+//! assert_eq!(&source[port.range()], "80");
+//! assert_eq!((port.start.line, port.start.column), (0, 16));
+//!
+//! let index = LineIndex::new(source);
+//! assert_eq!(value.span(&index), spans[&KsonPath::root()]);
+//! ```
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::KsonValue;
+use crate::line_index::LineIndex;
+use crate::path::{KsonPath, PathSegment};
+
+/// A location in a document. Unlike [`crate::Position`], it has a byte offset into the source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LineColumn {
+    /// The zero-based line
+    pub line: usize,
+    /// The zero-based column, in UTF-16 code units like kson-lib reports it
+    pub column: usize,
+    /// The byte offset into the source
+    pub offset: usize,
+}
+
+/// The text of a value in a document, from its first character up to (and excluding) `end`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Span {
+    pub start: LineColumn,
+    pub end: LineColumn,
+}
+
+impl Span {
+    pub(crate) fn new(index: &LineIndex, start: &crate::Position, end: &crate::Position) -> Self {
+        let position = |position: &crate::Position| LineColumn {
+            line: position.line().max(0) as usize,
+            column: position.column().max(0) as usize,
+            offset: index.offset_of(position),
+        };
+        Self {
+            start: position(start),
+            end: position(end),
+        }
+    }
+
+    /// The byte range of the span in the source, e.g. to slice it
+    pub fn range(&self) -> Range<usize> {
+        self.start.offset..self.end.offset.max(self.start.offset)
+    }
+
+    /// Whether the span contains the byte offset
+    pub fn contains(&self, offset: usize) -> bool {
+        self.range().contains(&offset)
+    }
+}
+
+impl KsonValue {
+    /// The span of this value in the text it was parsed from, given the index of that text
+    pub fn span(&self, index: &LineIndex) -> Span {
+        Span::new(index, &self.start(), &self.end())
+    }
+
+    /// The spans of this value and of all its descendants in `source`, the text it was parsed from,
+    /// keyed by their path from this value
+    pub fn spans(&self, source: &str) -> BTreeMap<KsonPath, Span> {
+        let index = LineIndex::new(source);
+        let mut spans = BTreeMap::new();
+        collect_spans(self, &mut KsonPath::root(), &index, &mut spans);
+        spans
+    }
+}
+
+fn collect_spans(
+    value: &KsonValue,
+    path: &mut KsonPath,
+    index: &LineIndex,
+    spans: &mut BTreeMap<KsonPath, Span>,
+) {
+    // The values to visit, with the length of their parent's path and the segment leading to them
+    let mut pending = vec![(value.clone(), path.len(), None)];
+    while let Some((value, depth, segment)) = pending.pop() {
+        while path.len() > depth {
+            path.pop();
+        }
+        if let Some(segment) = segment {
+            path.push(segment);
+        }
+        spans.insert(path.clone(), Span::new(index, &value.start(), &value.end()));
+        let depth = path.len();
+        match &value {
+            KsonValue::KsonObject(object) => {
+                let properties: Vec<_> = object.properties().into_iter().collect();
+                pending.extend(
+                    properties
+                        .into_iter()
+                        .rev()
+                        .map(|(name, property)| (property, depth, Some(PathSegment::Key(name)))),
+                );
+            }
+            KsonValue::KsonArray(array) => {
+                let elements = array.elements().into_iter().enumerate().rev();
+                pending.extend(elements.map(|(element_index, element)| {
+                    (element, depth, Some(PathSegment::Index(element_index)))
+                }));
+            }
+            _ => {}
+        }
+    }
+}
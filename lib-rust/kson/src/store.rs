@@ -0,0 +1,145 @@
+//! A content-addressed store of documents, for tools that snapshot configuration states (e.g. the
+//! configuration of each deployment).
+//!
+//! Documents are stored in [canonical form](crate::canonical), with the properties of objects sorted by
+//! key and formatted by [`Value::to_kson`], under the SHA-256 digest of that form (as returned by
+//! [`Kson::digest`](crate::Kson::digest)). Documents that only differ in formatting, comments or
+//! property order thus get the same digest and are stored once:
+//!
+//! ```no_run
+//! use kson_rs::store::DocumentStore;
+//!
+//! let store = DocumentStore::open("deployments/configs").unwrap();
+//! let digest = store.put("replicas: 3\nimage: 'app:1.4.2'").unwrap();
+//! assert_eq!(store.put("image: 'app:1.4.2', replicas: 3").unwrap(), digest);
+//! let config = store.get(&digest).unwrap();
+//! ```
+
+use std::path::{Path, PathBuf};
+
+pub use crate::digest::{Digest, InvalidDigest};
+use crate::error::KsonErrors;
+use crate::value::Value;
+
+/// The reason a [`DocumentStore`] operation failed
+#[derive(Debug)]
+pub enum StoreError {
+    Io(std::io::Error),
+    /// The document to store is not valid KSON
+    Parse(KsonErrors),
+    /// No document with this digest is stored
+    NotFound(Digest),
+    /// The stored document doesn't match its digest (or doesn't parse), e.g. because it was edited
+    Corrupted(Digest),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Io(error) => error.fmt(f),
+            StoreError::Parse(errors) => errors.fmt(f),
+            StoreError::NotFound(digest) => write!(f, "no document stored for {digest}"),
+            StoreError::Corrupted(digest) => {
+                write!(
+                    f,
+                    "the document stored for {digest} doesn't match its digest"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(error: std::io::Error) -> Self {
+        StoreError::Io(error)
+    }
+}
+
+/// A directory of documents keyed by digest (see the [module documentation](self)). Each document is
+/// stored in `<root>/<first two hex digits>/<digest>.kson`.
+#[derive(Clone, Debug)]
+pub struct DocumentStore {
+    root: PathBuf,
+}
+
+impl DocumentStore {
+    /// Opens the store in the given directory, creating it if it doesn't exist
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Parses the document and stores it in canonical form, returning its digest
+    pub fn put(&self, document: &str) -> Result<Digest, StoreError> {
+        let value = Value::parse(document).map_err(StoreError::Parse)?;
+        self.put_value(&value)
+    }
+
+    /// Stores the value in canonical form, returning its digest. Storing a document that is already
+    /// stored does nothing.
+    pub fn put_value(&self, value: &Value) -> Result<Digest, StoreError> {
+        let canonical = value.to_canonical_kson();
+        let digest = Digest::of(&canonical);
+        let path = self.path_of(&digest);
+        if path.exists() {
+            return Ok(digest);
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write to a temporary file first, so that readers never see a partially written document
+        let temporary = path.with_extension(format!("kson.{}.tmp", std::process::id()));
+        std::fs::write(&temporary, &canonical)?;
+        std::fs::rename(&temporary, &path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&temporary);
+        })?;
+        Ok(digest)
+    }
+
+    /// Returns the canonical text of the document with the given digest, after checking that it still
+    /// matches the digest
+    pub fn get_text(&self, digest: &Digest) -> Result<String, StoreError> {
+        let text = match std::fs::read_to_string(self.path_of(digest)) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Err(StoreError::NotFound(*digest));
+            }
+            Err(error) => return Err(StoreError::Io(error)),
+        };
+        if Digest::of(&text) != *digest {
+            return Err(StoreError::Corrupted(*digest));
+        }
+        Ok(text)
+    }
+
+    /// Loads the document with the given digest
+    pub fn get(&self, digest: &Digest) -> Result<Value, StoreError> {
+        let text = self.get_text(digest)?;
+        Value::parse(&text).map_err(|_| StoreError::Corrupted(*digest))
+    }
+
+    pub fn contains(&self, digest: &Digest) -> bool {
+        self.path_of(digest).is_file()
+    }
+
+    /// The path at which the document with the given digest is (or would be) stored
+    pub fn path_of(&self, digest: &Digest) -> PathBuf {
+        let hex = digest.to_string();
+        self.root.join(&hex[..2]).join(format!("{hex}.kson"))
+    }
+}
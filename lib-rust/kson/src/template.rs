@@ -0,0 +1,193 @@
+//! Template rendering, resolving placeholders like `{{release.version}}` from a context value.
+//!
+//! Placeholders name a path in the context, with keys (or array indices) separated by dots. A string
+//! that consists of a single placeholder is replaced by the value it names, whatever its type, while
+//! placeholders surrounded by other text (in strings or embed blocks) are replaced by the text of the
+//! value, which must then be a scalar:
+//!
+//! ```no_run
+//! use kson_rs::template::render_document;
+//! use kson_rs::value::Value;
+//!
+//! let context = Value::parse("release: { version: '1.4.2', replicas: 3 }").unwrap();
+//! let rendered = render_document(
+//!     "image: 'registry/app:{{release.version}}'\nreplicas: '{{ release.replicas }}'",
+//!     &context,
+//! )
+//! .unwrap();
+//! assert_eq!(rendered, Value::parse("image: 'registry/app:1.4.2'\nreplicas: 3").unwrap().to_kson());
+//! ```
+
+use crate::error::KsonErrors;
+use crate::path::KsonPath;
+use crate::value::Value;
+
+/// A placeholder that couldn't be resolved
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TemplateError {
+    /// The path of the value holding the placeholder in the template
+    pub path: KsonPath,
+    /// The text between the braces, trimmed
+    pub placeholder: String,
+    pub kind: TemplateErrorKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemplateErrorKind {
+    /// The context has no value at the path named by the placeholder
+    Unresolved,
+    /// The placeholder is surrounded by text but names an array or object, which has no text
+    NotScalar,
+    /// The placeholder is missing its closing `}}`
+    Unterminated,
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let placeholder = &self.placeholder;
+        match self.kind {
+            TemplateErrorKind::Unresolved => {
+                write!(f, "`{{{{{placeholder}}}}}` doesn't resolve to any value")?
+            }
+            TemplateErrorKind::NotScalar => write!(
+                f,
+                "`{{{{{placeholder}}}}}` resolves to an array or object, which can't be inserted into text"
+            )?,
+            TemplateErrorKind::Unterminated => {
+                write!(f, "unterminated placeholder `{{{{{placeholder}`")?
+            }
+        }
+        write!(f, " (at {})", self.path)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// The reason a document couldn't be rendered by [`render_document`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderError {
+    /// The template is not valid KSON
+    Parse(KsonErrors),
+    /// Some placeholders couldn't be resolved
+    Placeholders(Vec<TemplateError>),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::Parse(errors) => errors.fmt(f),
+            RenderError::Placeholders(errors) => {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+/// Resolves every placeholder of the template, returning all of the placeholders that couldn't be
+/// resolved if there are any
+pub fn render(template: &Value, context: &Value) -> Result<Value, Vec<TemplateError>> {
+    let mut errors = Vec::new();
+    let mut rendered = template.clone();
+    rendered.rewrite(|path, value| match value {
+        Value::String(text) if text.contains("{{") => {
+            if let Some(placeholder) = sole_placeholder(text) {
+                return match lookup(context, placeholder) {
+                    Some(value) => Some(value.clone()),
+                    None => {
+                        errors.push(error(path, placeholder, TemplateErrorKind::Unresolved));
+                        None
+                    }
+                };
+            }
+            interpolate(text, context, path, &mut errors).map(Value::String)
+        }
+        Value::Embed { tag, content } if content.contains("{{") => {
+            interpolate(content, context, path, &mut errors).map(|content| Value::Embed {
+                tag: tag.clone(),
+                content,
+            })
+        }
+        _ => None,
+    });
+
+    if errors.is_empty() {
+        Ok(rendered)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses the template, resolves its placeholders with [`render`] and renders the result as KSON
+pub fn render_document(template: &str, context: &Value) -> Result<String, RenderError> {
+    let template = Value::parse(template).map_err(RenderError::Parse)?;
+    render(&template, context)
+        .map(|rendered| rendered.to_kson())
+        .map_err(RenderError::Placeholders)
+}
+
+/// The placeholder making up the whole text, if that's what it is
+fn sole_placeholder(text: &str) -> Option<&str> {
+    let inner = text.strip_prefix("{{")?.strip_suffix("}}")?;
+    (!inner.contains("{{") && !inner.contains("}}")).then(|| inner.trim())
+}
+
+/// Replaces the placeholders in the text, or returns `None` if any of them fails to resolve
+fn interpolate(
+    text: &str,
+    context: &Value,
+    path: &KsonPath,
+    errors: &mut Vec<TemplateError>,
+) -> Option<String> {
+    let errors_before = errors.len();
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find("{{") {
+        rendered.push_str(&rest[..open]);
+        let after_open = &rest[open + 2..];
+        let Some(close) = after_open.find("}}") else {
+            errors.push(error(
+                path,
+                after_open.trim(),
+                TemplateErrorKind::Unterminated,
+            ));
+            rest = "";
+            break;
+        };
+        let placeholder = after_open[..close].trim();
+        match lookup(context, placeholder) {
+            None => errors.push(error(path, placeholder, TemplateErrorKind::Unresolved)),
+            Some(Value::Array(_) | Value::Object(_)) => {
+                errors.push(error(path, placeholder, TemplateErrorKind::NotScalar))
+            }
+            Some(Value::String(string)) => rendered.push_str(string),
+            Some(Value::Embed { content, .. }) => rendered.push_str(content),
+            Some(scalar) => rendered.push_str(&scalar.to_json()),
+        }
+        rest = &after_open[close + 2..];
+    }
+    rendered.push_str(rest);
+
+    (errors.len() == errors_before).then_some(rendered)
+}
+
+/// Looks up a dot-separated path in the context
+fn lookup<'a>(context: &'a Value, placeholder: &str) -> Option<&'a Value> {
+    if placeholder.is_empty() {
+        return None;
+    }
+    let path = placeholder
+        .split('.')
+        .fold(KsonPath::root(), |path, key| path.key(key.trim()));
+    context.get_path(&path)
+}
+
+fn error(path: &KsonPath, placeholder: &str, kind: TemplateErrorKind) -> TemplateError {
+    TemplateError {
+        path: path.clone(),
+        placeholder: placeholder.to_string(),
+        kind,
+    }
+}
@@ -1,5 +1,46 @@
 use super::*;
 
+/// A directory for the files of a test, removed with its content once dropped, including when the test
+/// fails
+struct TempDir(std::path::PathBuf);
+
+impl TempDir {
+    /// Creates an empty directory, named after the test and the process running it
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("kson-{name}-test-{}", std::process::id()));
+        // Left over by a run that was killed before cleaning up
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+impl AsRef<std::path::Path> for TempDir {
+    fn as_ref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+impl From<&TempDir> for std::path::PathBuf {
+    fn from(dir: &TempDir) -> Self {
+        dir.0.clone()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[test]
 fn test_kson_format() {
     let indent = IndentType::Spaces(indent_type::Spaces::new(2));
@@ -35,6 +76,43 @@ fn test_kson_format_classic() {
       "#);
 }
 
+#[test]
+fn test_to_ansi() {
+    use crate::ansi::{ColorChoice, Theme};
+
+    let theme = Theme {
+        key: "K",
+        string: "S",
+        number: "N",
+        keyword: "W",
+        comment: "C",
+        punctuation: "",
+        embed_tag: "T",
+        embed_content: "E",
+        error: "X",
+    };
+    let highlighted = Kson::to_ansi("# hi\nkey: 'v'\nlist: [1, true]", &theme)
+        .replace("\x1b[0m", ">")
+        .replace("\x1b[", "<");
+    assert_eq!(
+        highlighted,
+        "<Cm# hi>\n<Kmkey>: <Sm'><Smv><Sm'>\n<Kmlist>: [<Nm1>, <Wmtrue>]"
+    );
+    assert_eq!(
+        Kson::to_ansi(
+            "plain",
+            &Theme {
+                string: "",
+                ..theme
+            }
+        ),
+        "plain"
+    );
+
+    assert!(ColorChoice::Always.enabled_for(&std::io::stdout()));
+    assert!(!ColorChoice::Never.enabled_for(&std::io::stdout()));
+}
+
 #[test]
 fn test_kson_to_json_success() {
     let result = Kson::to_json("key: [1, 2, 3, 4]", transpile_options::Json::new(true));
@@ -167,6 +245,97 @@ fn test_kson_to_yaml_failure() {
     }
 }
 
+#[test]
+fn test_conversion_effects() {
+    use crate::conversion::ConversionEffectKind;
+
+    let document = r#"# settings
+pi: 3.14159265358979323846
+big: 9007199254740993
+small: 0.1
+exact: 1e3
+query: %sql
+  select 1
+  %%
+"#;
+
+    let json = TranspileOptions::Json(transpile_options::Json::new(false));
+    let effects = Kson::conversion_effects(document, &json).unwrap();
+    let lines: Vec<i32> = effects.iter().map(|effect| effect.start.line()).collect();
+    assert_eq!(lines, [0, 1, 2, 5]);
+    assert_eq!(
+        effects[0].kind,
+        ConversionEffectKind::CommentDropped {
+            text: "# settings".to_string()
+        }
+    );
+    insta::assert_snapshot!(effects[1].message, @"The number `3.14159265358979323846` will be read as `3.141592653589793` by most JSON and YAML parsers");
+    let ConversionEffectKind::PrecisionLost { value, path, .. } = &effects[2].kind else {
+        panic!("expected a precision loss, found {:?}", effects[2].kind)
+    };
+    assert_eq!(*value, 9007199254740992.0);
+    assert_eq!(path.to_string(), "/big");
+    let ConversionEffectKind::EmbedFlattened { tag, .. } = &effects[3].kind else {
+        panic!("expected a flattened embed, found {:?}", effects[3].kind)
+    };
+    assert_eq!(tag.as_deref(), Some("sql"));
+
+    // YAML keeps comments, and embed tags are retained here
+    let yaml = TranspileOptions::Yaml(transpile_options::Yaml::new(true));
+    let effects = Kson::conversion_effects(document, &yaml).unwrap();
+    assert_eq!(effects.len(), 2);
+
+    assert!(Kson::conversion_effects("key: [1, 2", &json).is_err());
+}
+
+#[test]
+fn test_embed_tag_policy() {
+    use crate::conversion::EmbedTagPolicy;
+    use crate::path::KsonPath;
+    use crate::value::Value;
+
+    let document = r#"
+query: %sql
+  select 1
+  %%
+note: %text
+  hello
+  %%
+ports: %csv
+  80,443
+  %%
+"#;
+    let policy = EmbedTagPolicy::strip_all()
+        .retain("SQL")
+        .transform("csv", |content| {
+            content
+                .trim()
+                .split(',')
+                .map(|port| Value::Integer(port.parse().unwrap()))
+                .collect::<Vec<_>>()
+                .into()
+        });
+
+    let json = Kson::to_json_with_policy(document, &policy).unwrap();
+    let value = Value::parse(&json.output()).unwrap();
+    assert_eq!(
+        value.get_path(&KsonPath::root().key("query").key("embedTag")),
+        Some(&Value::from("sql"))
+    );
+    assert!(matches!(
+        value.get_path(&KsonPath::root().key("note")),
+        Some(Value::String(note)) if note.trim() == "hello"
+    ));
+    assert_eq!(
+        value.get_path(&KsonPath::root().key("ports")),
+        Some(&Value::Array(vec![Value::Integer(80), Value::Integer(443)]))
+    );
+
+    let yaml = Kson::to_yaml_with_policy(document, &EmbedTagPolicy::retain_all().strip("sql"));
+    assert!(!yaml.unwrap().output().contains("embedTag: \"sql\""));
+    assert!(Kson::to_json_with_policy("key: [1, 2", &policy).is_err());
+}
+
 #[test]
 fn test_kson_analysis() {
     let analysis = Kson::analyze("key: [1, 2, 3, 4]", None);
@@ -402,3 +571,5071 @@ fn test_property_keys_empty_object() {
     assert_eq!(obj.property_keys().len(), 0);
     assert_eq!(obj.properties().len(), 0);
 }
+
+#[test]
+fn test_parse_duration() {
+    use crate::units::{UnitError, format_duration, parse_duration};
+    use std::time::Duration;
+
+    assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+    assert_eq!(
+        parse_duration("2h15m"),
+        Ok(Duration::from_secs(2 * 3600 + 15 * 60))
+    );
+    assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+    assert_eq!(
+        parse_duration("1m 250ms"),
+        Ok(Duration::from_millis(60_250))
+    );
+    assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+
+    assert_eq!(parse_duration(""), Err(UnitError::Empty));
+    assert_eq!(
+        parse_duration("30"),
+        Err(UnitError::MissingUnit("30".to_string()))
+    );
+    assert_eq!(
+        parse_duration("3 fortnights"),
+        Err(UnitError::UnknownUnit("fortnights".to_string()))
+    );
+    assert_eq!(
+        parse_duration("h"),
+        Err(UnitError::InvalidNumber("h".to_string()))
+    );
+
+    assert_eq!(format_duration(Duration::from_secs(8100)), "2h15m");
+    assert_eq!(format_duration(Duration::from_millis(1500)), "1s500ms");
+}
+
+#[test]
+fn test_parse_byte_size() {
+    use crate::units::{UnitError, format_byte_size, parse_byte_size};
+
+    assert_eq!(parse_byte_size("512MiB"), Ok(512 * 1024 * 1024));
+    assert_eq!(parse_byte_size("1.5 GB"), Ok(1_500_000_000));
+    assert_eq!(parse_byte_size("4096"), Ok(4096));
+    assert_eq!(parse_byte_size("2kib"), Ok(2048));
+
+    assert_eq!(
+        parse_byte_size("12 parsecs"),
+        Err(UnitError::UnknownUnit("parsecs".to_string()))
+    );
+    assert_eq!(parse_byte_size("99999999PiB"), Err(UnitError::Overflow));
+
+    assert_eq!(format_byte_size(512 * 1024 * 1024), "512MiB");
+    assert_eq!(format_byte_size(1000), "1000B");
+}
+
+#[test]
+fn test_kson_value_units() {
+    let analysis = Kson::analyze(
+        "timeout: 2h15m\nlimit: 512MiB\nraw: 1024\nname: server",
+        None,
+    );
+    let Some(KsonValue::KsonObject(obj)) = analysis.kson_value() else {
+        panic!("expected object");
+    };
+    let properties = obj.properties();
+
+    let timeout = &properties["timeout"];
+    assert_eq!(
+        timeout.as_duration(),
+        Some(std::time::Duration::from_secs(8100))
+    );
+    assert_eq!(timeout.as_byte_size(), None);
+    assert_eq!(properties["limit"].as_byte_size(), Some(512 * 1024 * 1024));
+    assert_eq!(properties["raw"].as_byte_size(), Some(1024));
+    assert_eq!(properties["name"].as_duration(), None);
+}
+
+#[test]
+fn test_decode_base64_and_hex() {
+    use crate::embed::{EmbedDecodeErrorKind, decode_base64, decode_hex};
+
+    assert_eq!(decode_base64("aGVsbG8="), Ok(b"hello".to_vec()));
+    assert_eq!(decode_base64("aGVs\n  bG8"), Ok(b"hello".to_vec()));
+    assert_eq!(decode_hex("DEAD beef"), Ok(vec![0xde, 0xad, 0xbe, 0xef]));
+
+    let error = decode_base64("aGVs\nbG*8").unwrap_err();
+    assert_eq!(error.kind, EmbedDecodeErrorKind::InvalidCharacter('*'));
+    assert_eq!((error.offset, error.line, error.column), (7, 1, 2));
+
+    let error = decode_base64("aG=Vs").unwrap_err();
+    assert_eq!(error.kind, EmbedDecodeErrorKind::InvalidPadding);
+
+    // Only the padding that completes the last group is allowed
+    assert_eq!(decode_base64("aGk="), Ok(b"hi".to_vec()));
+    let error = decode_base64("aGk==").unwrap_err();
+    assert_eq!(
+        (error.kind, error.offset),
+        (EmbedDecodeErrorKind::InvalidPadding, 4)
+    );
+    let error = decode_base64("aGVs=").unwrap_err();
+    assert_eq!(
+        (error.kind, error.offset),
+        (EmbedDecodeErrorKind::InvalidPadding, 4)
+    );
+    let error = decode_base64("aA=").unwrap_err();
+    assert_eq!(
+        (error.kind, error.offset),
+        (EmbedDecodeErrorKind::InvalidPadding, 2)
+    );
+
+    // Bits past the last byte must be zero, so `aGl=` isn't another encoding of `hi`
+    let error = decode_base64("aGl=").unwrap_err();
+    assert_eq!(
+        (error.kind, error.offset),
+        (EmbedDecodeErrorKind::InvalidCharacter('l'), 2)
+    );
+
+    // Standard and URL-safe characters can't be mixed
+    assert_eq!(decode_base64("-_-_"), decode_base64("+/+/"));
+    let error = decode_base64("+/-_").unwrap_err();
+    assert_eq!(
+        (error.kind, error.offset),
+        (EmbedDecodeErrorKind::InvalidCharacter('-'), 2)
+    );
+
+    let error = decode_hex("abc").unwrap_err();
+    assert_eq!(error.kind, EmbedDecodeErrorKind::UnexpectedEnd);
+    assert_eq!(
+        error.to_string(),
+        "unexpected end of encoded content at line 1, column 3"
+    );
+}
+
+#[test]
+fn test_embed_decode_bytes() {
+    use crate::embed::EmbedDecodeErrorKind;
+
+    let document = "data: $base64\n  aGVsbG8=\n  $$\nother: $text\n  hi\n  $$";
+    let analysis = Kson::analyze(document, None);
+    let Some(KsonValue::KsonObject(obj)) = analysis.kson_value() else {
+        panic!("expected object");
+    };
+    let properties = obj.properties();
+
+    let KsonValue::KsonEmbed(data) = &properties["data"] else {
+        panic!("expected embed");
+    };
+    assert_eq!(data.decode_bytes(document), Ok(b"hello".to_vec()));
+
+    let KsonValue::KsonEmbed(other) = &properties["other"] else {
+        panic!("expected embed");
+    };
+    let error = other.decode_bytes(document).unwrap_err();
+    assert_eq!((error.offset, error.line, error.column), (37, 3, 7));
+
+    // Errors in the content are located in the document
+    let document = "name: payload\nnested:\n  data: %hex\n    dead\n    be€f\n    %%";
+    let value = Kson::analyze(document, None).kson_value().unwrap();
+    let Some(KsonValue::KsonEmbed(data)) = value.pointer("/nested/data") else {
+        panic!("expected embed");
+    };
+    let error = data.decode_bytes(document).unwrap_err();
+    assert_eq!(error.kind, EmbedDecodeErrorKind::InvalidCharacter('€'));
+    assert_eq!((error.line, error.column), (4, 6));
+    assert!(document[error.offset..].starts_with('€'));
+    assert_eq!(
+        error.to_string(),
+        "invalid character `€` at line 5, column 7"
+    );
+
+    // The closing delimiter may end the last line of the content
+    let document = "data: %hex\n    dead\n  be€f%%";
+    let Some(KsonValue::KsonEmbed(data)) = Kson::analyze(document, None)
+        .kson_value()
+        .unwrap()
+        .pointer("/data")
+    else {
+        panic!("expected embed");
+    };
+    let error = data.decode_bytes(document).unwrap_err();
+    assert_eq!((error.line, error.column), (2, 4));
+    assert!(document[error.offset..].starts_with('€'));
+}
+
+#[test]
+#[cfg(feature = "uuid")]
+fn test_kson_value_as_uuid() {
+    let analysis = Kson::analyze(
+        "id: 67e55044-10b1-426f-9247-bb680e5fe0c8\nbraced: '{67e55044-10b1-426f-9247-bb680e5fe0c8}'\nname: server",
+        None,
+    );
+    let Some(KsonValue::KsonObject(obj)) = analysis.kson_value() else {
+        panic!("expected object");
+    };
+    let properties = obj.properties();
+
+    let expected = uuid::Uuid::from_u128(0x67e55044_10b1_426f_9247_bb680e5fe0c8);
+    assert_eq!(properties["id"].as_uuid(), Some(expected));
+    assert_eq!(properties["braced"].as_uuid(), Some(expected));
+    assert_eq!(properties["name"].as_uuid(), None);
+}
+
+#[test]
+#[cfg(feature = "uuid")]
+fn test_validate_uuid_format() {
+    use crate::diagnostics::DiagnosticLimit;
+
+    let schema = r#"
+        properties: {
+          id: { '$ref': '#/definitions/id' }
+          members: { items: { format: uuid } }
+        }
+        definitions: { id: { format: uuid } }
+    "#;
+    let document = r#"
+        id: 67e55044-10b1-426f-9247-bb680e5fe0c8
+        members: [67e55044-10b1-426f-9247-bb680e5fe0c8, not-a-uuid, 42]
+    "#;
+
+    let diagnostics = crate::schema::validate_formats(schema, document);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "`not-a-uuid` is not a valid UUID");
+    assert_eq!(diagnostics[0].start.line(), 2);
+
+    // Validating with a schema checks formats too, unless turned off
+    let parsed = crate::schema::Schema::parse(schema).ok().unwrap();
+    let errors = Kson::try_check(document, Some(&parsed), DiagnosticLimit::All).unwrap_err();
+    let messages: Vec<&str> = errors
+        .errors()
+        .iter()
+        .map(|error| error.message())
+        .collect();
+    assert_eq!(messages, ["`not-a-uuid` is not a valid UUID"]);
+
+    // Format checks stop at the limit
+    let invalid = "id: not-a-uuid\nmembers: [also-not-a-uuid]";
+    let count = |limit| {
+        Kson::try_check(invalid, Some(&parsed), limit)
+            .unwrap_err()
+            .errors()
+            .len()
+    };
+    assert_eq!(count(DiagnosticLimit::All), 2);
+    assert_eq!(count(DiagnosticLimit::FailFast), 1);
+
+    let unchecked = parsed.check_formats(false);
+    assert!(Kson::try_check(document, Some(&unchecked), DiagnosticLimit::All).is_ok());
+}
+
+#[test]
+fn test_format_is_idempotent_for_all_options() {
+    use crate::format::{
+        BlankLines, FloatFormat, FormatOptionsBuilder, KeyOrder, Layout, LineEnding, QuoteStyle,
+        TrailingCommas,
+    };
+    use crate::pointer::PointerGlob;
+
+    let input = r#"
+        # A leading comment, long enough to be reflowed when comments have a maximum width
+        name: kson
+        Zone: 'it\'s "quoted"'
+
+
+        nested: { list: [1, 2.5, 1.50e3, "three", { deep: true }], empty: {}, nothing: null }
+        # kson-format: single-line
+        matrix:
+          - { os: linux, arch: x64 }
+          - { os: macos, arch: arm64 }
+        script: $sh
+          echo "hello"
+          $$
+        # A trailing comment
+    "#;
+
+    // Each knob of the builder on its own, then all of them at once
+    type Knob = (
+        &'static str,
+        fn(FormatOptionsBuilder) -> FormatOptionsBuilder,
+    );
+    let knobs: [Knob; 19] = [
+        ("default", |builder| builder),
+        ("sort_keys", |builder| {
+            builder.sort_keys(KeyOrder::Alphabetical)
+        }),
+        ("sort_keys case-insensitive", |builder| {
+            builder.sort_keys(KeyOrder::AlphabeticalCaseInsensitive)
+        }),
+        ("max_width", |builder| builder.max_width(20)),
+        ("reflow_comments", |builder| builder.reflow_comments(30)),
+        ("max_width and reflow_comments", |builder| {
+            builder.max_width(40).reflow_comments(30)
+        }),
+        ("blank_lines", |builder| {
+            builder.blank_lines(BlankLines::Preserve)
+        }),
+        ("blank_lines collapsed", |builder| {
+            builder.blank_lines(BlankLines::Collapse(1))
+        }),
+        ("quote_style", |builder| {
+            builder.quote_style(QuoteStyle::Double)
+        }),
+        ("float_format", |builder| {
+            builder.float_format(FloatFormat::Shortest)
+        }),
+        ("float_format fixed", |builder| {
+            builder.float_format(FloatFormat::Fixed(2))
+        }),
+        ("layout single-line", |builder| {
+            builder.layout(PointerGlob::parse("/**").unwrap(), Layout::SingleLine)
+        }),
+        ("layout expanded", |builder| {
+            builder.layout(PointerGlob::parse("/matrix").unwrap(), Layout::Expanded)
+        }),
+        ("trailing_commas", |builder| {
+            builder.trailing_commas(TrailingCommas::Multiline)
+        }),
+        ("final_newline", |builder| builder.final_newline(true)),
+        ("line_ending", |builder| {
+            builder.line_ending(LineEnding::Crlf)
+        }),
+        ("line_ending preserved", |builder| {
+            builder.line_ending(LineEnding::Preserve)
+        }),
+        ("parallel", |builder| builder.parallel(1)),
+        ("all", |builder| {
+            builder
+                .sort_keys(KeyOrder::Alphabetical)
+                .max_width(40)
+                .reflow_comments(30)
+                .blank_lines(BlankLines::Collapse(1))
+                .quote_style(QuoteStyle::Double)
+                .float_format(FloatFormat::Fixed(2))
+                .layout(PointerGlob::parse("/**").unwrap(), Layout::SingleLine)
+                .trailing_commas(TrailingCommas::Multiline)
+                .final_newline(true)
+                .line_ending(LineEnding::Crlf)
+                .parallel(1)
+        }),
+    ];
+
+    let styles = [
+        FormattingStyle::Plain,
+        FormattingStyle::Delimited,
+        FormattingStyle::Compact,
+        FormattingStyle::Classic,
+    ];
+    for style in styles {
+        let indents = [
+            IndentType::Spaces(indent_type::Spaces::new(2)),
+            IndentType::Spaces(indent_type::Spaces::new(4)),
+            IndentType::Tabs(indent_type::Tabs::new()),
+        ];
+        for indent in indents {
+            for (knob, apply) in knobs {
+                let formatter =
+                    apply(FormatOptions::builder().indent(indent.clone()).style(style)).build();
+                if let Err(violation) = formatter.verify_idempotent(input) {
+                    panic!(
+                        "{violation} ({knob})\nfirst:\n{}\nsecond:\n{}",
+                        violation.first, violation.second
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_idempotency_violation_reports_first_differing_line() {
+    let violation = crate::format::IdempotencyViolation {
+        first: "a: 1\nb: 2\nc: 3".to_string(),
+        second: "a: 1\nb:  2\nc: 3".to_string(),
+    };
+    assert_eq!(violation.first_differing_line(), 1);
+    assert_eq!(
+        violation.to_string(),
+        "formatting is not idempotent: output changed on line 2 when formatted a second time"
+    );
+}
+
+#[test]
+fn test_format_reflow_comments() {
+    let input = r#"
+# This comment is far too long to fit on a single line once the width is limited
+key: value # trailing comments are left alone even when they are long
+list:
+  # - a list item whose text is long enough to need wrapping
+  - 1
+"#;
+    let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+    let formatter =
+        crate::format::Formatter::new(FormatOptions::new(indent, FormattingStyle::Plain, &[]))
+            .reflow_comments(40);
+    let output = formatter.verify_idempotent(input).unwrap();
+    insta::assert_snapshot!(output, @r"
+    # This comment is far too long to fit on
+    # a single line once the width is
+    # limited
+    key: value # trailing comments are left alone even when they are long
+    list:
+      # - a list item whose text is long
+      #   enough to need wrapping
+      - 1
+    ");
+}
+
+#[test]
+fn test_format_blank_lines() {
+    use crate::format::{BlankLines, Formatter};
+
+    let input = "name: kson\n\n\n\n# Server settings\nserver: {\n\n  host: localhost\n  port: 8080\n}\nclient: cli";
+    let options = || {
+        let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+        FormatOptions::new(indent, FormattingStyle::Plain, &[])
+    };
+
+    let normalized = Formatter::new(options()).format(input);
+    insta::assert_snapshot!(normalized, @r"
+    name: kson
+    # Server settings
+    server:
+      host: localhost
+      port: 8080
+    client: cli
+    ");
+
+    let preserved = Formatter::new(options())
+        .blank_lines(BlankLines::Preserve)
+        .verify_idempotent(input)
+        .unwrap();
+    insta::assert_snapshot!(preserved, @r"
+    name: kson
+
+
+
+    # Server settings
+    server:
+      host: localhost
+      port: 8080
+    client: cli
+    ");
+
+    let collapsed = Formatter::new(options())
+        .blank_lines(BlankLines::Collapse(1))
+        .format(input);
+    insta::assert_snapshot!(collapsed, @r"
+    name: kson
+
+    # Server settings
+    server:
+      host: localhost
+      port: 8080
+    client: cli
+    ");
+}
+
+#[test]
+fn test_format_float_format() {
+    use crate::format::{FloatFormat, Formatter};
+    use crate::value::Value;
+
+    assert_eq!(FloatFormat::Preserve.render(1.5), None);
+    assert_eq!(
+        FloatFormat::Shortest.render(0.1 + 0.2).unwrap(),
+        "0.30000000000000004"
+    );
+    assert_eq!(FloatFormat::Shortest.render(2500.0).unwrap(), "2500.0");
+    assert_eq!(FloatFormat::Fixed(2).render(0.1 + 0.2).unwrap(), "0.30");
+    assert_eq!(FloatFormat::Fixed(0).render(2.0).unwrap(), "2.0");
+    assert_eq!(FloatFormat::Fixed(2).render(f64::NAN), None);
+
+    let input = "ratio: 1.50\nbig: 2.5E3\ncount: 3\nsmall: [0.000001, 0.126]";
+    let options = || {
+        let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+        FormatOptions::new(indent, FormattingStyle::Plain, &[])
+    };
+    let shortest = Formatter::new(options())
+        .float_format(FloatFormat::Shortest)
+        .verify_idempotent(input)
+        .unwrap();
+    insta::assert_snapshot!(shortest, @r"
+    ratio: 1.5
+    big: 2500.0
+    count: 3
+    small:
+      - 1e-6
+      - 0.126
+    ");
+    let fixed = Formatter::new(options())
+        .float_format(FloatFormat::Fixed(2))
+        .verify_idempotent(input)
+        .unwrap();
+    insta::assert_snapshot!(fixed, @r"
+    ratio: 1.50
+    big: 2500.00
+    count: 3
+    small:
+      - 0.00
+      - 0.13
+    ");
+
+    let value = Value::Array(vec![Value::Decimal(0.1 + 0.2), Value::Integer(1)]);
+    assert_eq!(
+        value.to_json_with_floats(FloatFormat::Fixed(3)),
+        "[\n  0.300,\n  1\n]"
+    );
+    assert_eq!(
+        value.to_json_with_floats(FloatFormat::Preserve),
+        value.to_json()
+    );
+}
+
+#[test]
+fn test_format_sort_keys() {
+    use crate::format::{Formatter, KeyOrder};
+
+    let input = "# Service name\nname: api\nlimits: { memory: 512, cpu: 2 }\nZone: eu\nalias: Api";
+    let formatter = || {
+        let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+        Formatter::new(FormatOptions::new(indent, FormattingStyle::Plain, &[]))
+    };
+    let alphabetical = formatter()
+        .sort_keys(KeyOrder::Alphabetical)
+        .verify_idempotent(input)
+        .unwrap();
+    insta::assert_snapshot!(alphabetical, @r"
+    Zone: eu
+    alias: Api
+    limits:
+      cpu: 2
+      memory: 512
+      .
+    # Service name
+    name: api
+    ");
+    let case_insensitive = formatter()
+        .sort_keys(KeyOrder::AlphabeticalCaseInsensitive)
+        .verify_idempotent(input)
+        .unwrap();
+    insta::assert_snapshot!(case_insensitive, @r"
+    alias: Api
+    limits:
+      cpu: 2
+      memory: 512
+      .
+    # Service name
+    name: api
+    Zone: eu
+    ");
+    assert_eq!(
+        formatter().sort_keys(KeyOrder::Preserve).format(input),
+        formatter().format(input)
+    );
+}
+
+#[test]
+fn test_format_line_ending() {
+    use crate::format::LineEnding;
+
+    let lf = "name: api\nserver: { port: 80 }\n";
+    let crlf = lf.replace('\n', "\r\n");
+    let format = |line_ending: LineEnding, input: &str| {
+        FormatOptions::builder()
+            .line_ending(line_ending)
+            .build()
+            .verify_idempotent(input)
+            .unwrap()
+    };
+    let normalized = format(LineEnding::Lf, &crlf);
+    insta::assert_snapshot!(normalized, @r"
+    name: api
+    server:
+      port: 80
+    ");
+    assert!(!normalized.contains('\r'));
+    assert_eq!(format(LineEnding::Lf, lf), normalized);
+    assert_eq!(
+        format(LineEnding::Crlf, lf),
+        normalized.replace('\n', "\r\n")
+    );
+    assert_eq!(
+        format(LineEnding::Preserve, &crlf),
+        format(LineEnding::Crlf, lf)
+    );
+    assert_eq!(format(LineEnding::Preserve, lf), normalized);
+}
+
+#[test]
+fn test_format_final_newline_and_trailing_commas() {
+    use crate::format::{LineEnding, TrailingCommas};
+
+    let input = "name: api\nports: [80, 443]\nempty: {}\n\n\n";
+    let formatter = FormatOptions::builder().final_newline(true).build();
+    assert_eq!(
+        formatter.verify_idempotent(input).unwrap(),
+        "name: api\nports:\n  - 80\n  - 443\nempty: {}\n"
+    );
+    assert_eq!(
+        formatter.line_ending(LineEnding::Crlf).format("name: api"),
+        "name: api\r\n"
+    );
+    assert_eq!(
+        FormatOptions::builder()
+            .final_newline(true)
+            .build()
+            .format(""),
+        ""
+    );
+
+    let classic = FormatOptions::builder()
+        .style(FormattingStyle::Classic)
+        .trailing_commas(TrailingCommas::Multiline)
+        .build()
+        .verify_idempotent(input)
+        .unwrap();
+    insta::assert_snapshot!(classic, @r#"
+    {
+      "name": "api",
+      "ports": [
+        80,
+        443,
+      ],
+      "empty": {},
+    }
+    "#);
+    // Only classic output separates items with commas
+    let plain = FormatOptions::builder()
+        .trailing_commas(TrailingCommas::Multiline)
+        .build();
+    assert_eq!(
+        plain.format(input),
+        FormatOptions::builder().build().format(input)
+    );
+}
+
+#[test]
+fn test_parse_lenient_numbers() {
+    use crate::error::Severity;
+    use crate::value::Value;
+
+    let document = "population: 1,234,567
+budget: -1_000_000.50
+pi: 3.141_592
+pair: [1,234]
+name: 'a_1_000'
+id: v1_000 # or 2_000
+";
+    let parsed = Value::parse_lenient(document).unwrap();
+    assert_eq!(
+        parsed.value,
+        Value::parse(
+            "population: 1234567
+budget: -1000000.50
+pi: 3.141592
+pair: [1, 234]
+name: 'a_1_000'
+id: v1_000"
+        )
+        .unwrap()
+    );
+    let warnings: Vec<&str> = parsed
+        .warnings
+        .errors()
+        .iter()
+        .map(|warning| {
+            assert_eq!(warning.severity(), Severity::Warning);
+            &document[warning.span()]
+        })
+        .collect();
+    assert_eq!(warnings, ["1,234,567", "1_000_000.50", "3.141_592"]);
+
+    let plain = Value::parse_lenient("ratio: 1.5").unwrap();
+    assert_eq!(plain.value, crate::kson!({ "ratio": 1.5 }));
+    assert!(plain.warnings.is_empty());
+    assert!(Value::parse_lenient("ratio: [1_0").is_err());
+}
+
+#[test]
+fn test_parse_integer_overflow() {
+    use crate::numbers::{BigInteger, IntegerOverflow};
+    use crate::value::Value;
+
+    let document =
+        "big: 92233720368547758070\nsmall: -92233720368547758070\nfits: [9223372036854775807]";
+    assert!(Value::parse(document).is_err());
+    assert!(Value::parse_with_overflow(document, IntegerOverflow::Error).is_err());
+    assert_eq!(
+        Value::parse_with_overflow(document, IntegerOverflow::Saturate).unwrap(),
+        crate::kson!({ "big": i64::MAX, "small": i64::MIN, "fits": [i64::MAX] })
+    );
+    assert_eq!(
+        Value::parse_with_overflow(document, IntegerOverflow::Float).unwrap(),
+        crate::kson!({ "big": 9.223372036854776e19, "small": -9.223372036854776e19, "fits": [i64::MAX] })
+    );
+    #[cfg(feature = "bigdecimal")]
+    {
+        let value = Value::parse_with_overflow(
+            "big: 0092233720368547758070, 'key': '92233720368547758070'",
+            IntegerOverflow::BigDecimal,
+        )
+        .unwrap();
+        let big = BigInteger::parse("92233720368547758070").unwrap();
+        assert_eq!(value["big"], Value::BigInteger(big.clone()));
+        // Strings of digits in the document stay strings
+        assert_eq!(
+            value["key"],
+            Value::String("92233720368547758070".to_string())
+        );
+        assert_eq!(value["big"].as_big_decimal(), value["key"].as_big_decimal());
+        assert!(value["big"].as_big_decimal().is_some());
+        assert_eq!(
+            value.to_json(),
+            "{\n  \"big\": 92233720368547758070,\n  \"key\": \"92233720368547758070\"\n}"
+        );
+        assert_eq!(Value::from_cache_bytes(&value.to_cache_bytes()), Ok(value));
+        assert_eq!(
+            Value::parse_with_overflow("[-92233720368547758070]", IntegerOverflow::BigDecimal)
+                .unwrap()[0],
+            Value::BigInteger(BigInteger::parse("-092233720368547758070").unwrap())
+        );
+    }
+
+    let parse = |integer: &str| BigInteger::parse(integer);
+    assert_eq!(parse("9223372036854775807"), None);
+    assert_eq!(parse("1e30"), None);
+    assert_eq!(
+        parse("-0092233720368547758070").unwrap().as_str(),
+        "-92233720368547758070"
+    );
+    assert!(parse("-100000000000000000000") < parse("-92233720368547758070"));
+    assert!(parse("-92233720368547758070") < parse("9223372036854775808"));
+    assert!(parse("9223372036854775808") < parse("92233720368547758070"));
+    assert!(Value::Integer(i64::MAX) < Value::BigInteger(parse("-9223372036854775809").unwrap()));
+}
+
+#[test]
+fn test_pointer_glob() {
+    use crate::pointer::{PointerError, PointerGlob};
+
+    let glob = PointerGlob::parse("/matrix/include/*").unwrap();
+    assert!(glob.matches(&["matrix", "include", "0"]));
+    assert!(!glob.matches(&["matrix", "include"]));
+    assert!(!glob.matches(&["matrix", "include", "0", "os"]));
+
+    let glob = PointerGlob::parse("/**/email").unwrap();
+    assert!(glob.matches(&["email"]));
+    assert!(glob.matches(&["users", "3", "email"]));
+    assert!(!glob.matches(&["users", "3", "name"]));
+
+    let glob = PointerGlob::parse("/users/*admin?/a~1b").unwrap();
+    assert!(glob.matches(&["users", "superadmins", "a/b"]));
+    assert!(!glob.matches(&["users", "admin", "a/b"]));
+
+    let glob = PointerGlob::parse(r"/\*\*").unwrap();
+    assert!(glob.matches(&["**"]));
+    assert!(!glob.matches(&["other"]));
+
+    assert!(PointerGlob::parse("").unwrap().matches::<&str>(&[]));
+    assert_eq!(
+        PointerGlob::parse("users"),
+        Err(PointerError::BadStart('u'))
+    );
+    assert_eq!(
+        PointerGlob::parse("/a~2"),
+        Err(PointerError::InvalidEscape('2'))
+    );
+    assert_eq!(
+        PointerGlob::parse(r"/a\"),
+        Err(PointerError::IncompleteEscape)
+    );
+}
+
+#[test]
+fn test_format_layout_overrides() {
+    use crate::format::{Formatter, Layout};
+    use crate::pointer::PointerGlob;
+
+    let input = r#"
+matrix: {
+  include: [{ os: linux, arch: x64 }, { os: macos, arch: arm64 }]
+  exclude: [{ os: windows, arch: x86 }]
+}
+dependencies: { serde: "1.0", uuid: "1.10" }
+# kson-format: single-line
+tags: [fast, "with spaces"]
+"#;
+    let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+    let formatter = Formatter::new(FormatOptions::new(indent, FormattingStyle::Plain, &[]))
+        .layout(PointerGlob::parse("/**").unwrap(), Layout::SingleLine)
+        .layout(PointerGlob::parse("/matrix").unwrap(), Layout::Expanded)
+        .layout(
+            PointerGlob::parse("/matrix/exclude").unwrap(),
+            Layout::Expanded,
+        )
+        .layout(
+            PointerGlob::parse("/dependencies").unwrap(),
+            Layout::Expanded,
+        );
+
+    let output = formatter.verify_idempotent(input).unwrap();
+    insta::assert_snapshot!(output, @r#"
+    matrix:
+      include: [{ os: linux, arch: x64 }, { os: macos, arch: arm64 }]
+      exclude:
+        - { os: windows, arch: x86 }
+    dependencies:
+      serde: "1.0"
+      uuid: "1.10"
+    # kson-format: single-line
+    tags: [fast, "with spaces"]
+    "#);
+}
+
+#[test]
+fn test_format_max_width() {
+    use crate::format::Layout;
+    use crate::pointer::PointerGlob;
+
+    let input =
+        "include: [{ os: linux, arch: x64 }, { os: macos, arch: arm64 }]\ntags: [fast, small]";
+    let single_line = || PointerGlob::parse("/**").unwrap();
+    let unlimited = FormatOptions::builder()
+        .build()
+        .layout(single_line(), Layout::SingleLine)
+        .verify_idempotent(input)
+        .unwrap();
+    insta::assert_snapshot!(unlimited, @r"
+    include: [{ os: linux, arch: x64 }, { os: macos, arch: arm64 }]
+    tags: [fast, small]
+    ");
+
+    let limited = FormatOptions::builder()
+        .indent(IndentType::Spaces(indent_type::Spaces::new(2)))
+        .style(FormattingStyle::Plain)
+        .max_width(40)
+        .layout(single_line(), Layout::SingleLine)
+        .build()
+        .verify_idempotent(input)
+        .unwrap();
+    insta::assert_snapshot!(limited, @r"
+    include:
+      - { os: linux, arch: x64 }
+      - { os: macos, arch: arm64 }
+    tags: [fast, small]
+    ");
+}
+
+#[test]
+fn test_format_options_builder() {
+    use crate::format::{BlankLines, FloatFormat, Formatter, KeyOrder, Layout, QuoteStyle};
+    use crate::pointer::PointerGlob;
+
+    let input =
+        "# a comment that is long enough to be reflowed\nzone: 'b c'\n\nratio: 1.50\ntags: [x, y]";
+    let built = FormatOptions::builder()
+        .quote_style(QuoteStyle::Double)
+        .float_format(FloatFormat::Shortest)
+        .sort_keys(KeyOrder::Alphabetical)
+        .blank_lines(BlankLines::Preserve)
+        .reflow_comments(32)
+        .layout(PointerGlob::parse("/tags").unwrap(), Layout::SingleLine)
+        .parallel(1)
+        .build();
+    let chained = Formatter::new(FormatOptions::builder().options())
+        .quote_style(QuoteStyle::Double)
+        .float_format(FloatFormat::Shortest)
+        .sort_keys(KeyOrder::Alphabetical)
+        .blank_lines(BlankLines::Preserve)
+        .reflow_comments(32)
+        .layout(PointerGlob::parse("/tags").unwrap(), Layout::SingleLine)
+        .parallel(1);
+    let formatted = built.verify_idempotent(input).unwrap();
+    assert_eq!(formatted, chained.format(input));
+    assert!(formatted.contains("zone: \"b c\""));
+    assert!(formatted.contains("ratio: 1.5\n"));
+    assert!(formatted.contains("tags: [x, y]"));
+    assert!(formatted.contains("# a comment that is long enough\n"));
+    let position = |key: &str| formatted.find(key).unwrap();
+    assert!(position("ratio:") < position("tags:") && position("tags:") < position("zone:"));
+}
+
+#[test]
+fn test_format_quote_style() {
+    use crate::format::{Formatter, QuoteStyle};
+
+    let input = r#"plain: value
+spaces: 'hello world'
+apostrophe: "it's"
+quoted: 'say "hi"'"#;
+    let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+    let options = FormatOptions::new(indent, FormattingStyle::Plain, &[]);
+
+    let single = Formatter::new(options.clone())
+        .verify_idempotent(input)
+        .unwrap();
+    insta::assert_snapshot!(single, @r#"
+    plain: value
+    spaces: 'hello world'
+    apostrophe: "it's"
+    quoted: 'say "hi"'
+    "#);
+
+    let double = Formatter::new(options)
+        .quote_style(QuoteStyle::Double)
+        .verify_idempotent(input)
+        .unwrap();
+    insta::assert_snapshot!(double, @r#"
+    plain: value
+    spaces: "hello world"
+    apostrophe: "it's"
+    quoted: 'say "hi"'
+    "#);
+
+    // Rendering values goes through the same formatter
+    let formatter = FormatOptions::builder()
+        .quote_style(QuoteStyle::Double)
+        .build();
+    let root = Kson::analyze(input, None).kson_value().unwrap();
+    assert_eq!(root.to_kson_with(&formatter), double);
+}
+
+#[test]
+fn test_cancellation() {
+    use crate::cancel::{CancellationToken, Cancelled};
+    use crate::format::Formatter;
+
+    let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+    let formatter =
+        Formatter::new(FormatOptions::new(indent, FormattingStyle::Plain, &[])).reflow_comments(40);
+    let input = "# a comment\nkey: [1, 2]";
+
+    let token = CancellationToken::new();
+    let clone = token.clone();
+    assert_eq!(
+        formatter.format_cancellable(input, &token),
+        Ok(formatter.format(input))
+    );
+
+    let json = transpile_options::Json::new(false);
+    assert_eq!(
+        Kson::try_to_json_cancellable(input, json.clone(), &token).unwrap(),
+        Kson::try_to_json(input, json.clone())
+    );
+
+    clone.cancel();
+    assert!(token.is_cancelled());
+    assert_eq!(formatter.format_cancellable(input, &token), Err(Cancelled));
+    assert!(Kson::try_to_json_cancellable(input, json, &token).is_err());
+
+    let token = CancellationToken::new();
+    let expired = token.with_deadline(std::time::Instant::now());
+    assert_eq!(
+        formatter.format_cancellable(input, &expired),
+        Err(Cancelled)
+    );
+    assert!(!token.is_cancelled());
+    let lenient = token.with_timeout(std::time::Duration::from_secs(3600));
+    assert_eq!(
+        formatter.format_cancellable(input, &lenient),
+        Ok(formatter.format(input))
+    );
+    // The earliest deadline wins, and cancelling the original token cancels the derived ones
+    assert_eq!(
+        expired.with_timeout(std::time::Duration::MAX).deadline(),
+        expired.deadline()
+    );
+    token.cancel();
+    assert!(lenient.is_cancelled());
+}
+
+#[test]
+fn test_cancellation_run() {
+    use crate::cancel::{CancellationToken, Cancelled};
+    use crate::diagnostics::DiagnosticLimit;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+
+    let token = CancellationToken::new();
+    assert_eq!(token.run(|| 42), Ok(42));
+    let clone = token.clone();
+    assert_eq!(token.run(|| 42), Ok(42));
+
+    clone.cancel();
+    let ran = Arc::new(AtomicBool::new(false));
+    let flag = ran.clone();
+    assert_eq!(
+        token.run(move || flag.store(true, Ordering::Relaxed)),
+        Err(Cancelled)
+    );
+    assert!(!ran.load(Ordering::Relaxed));
+
+    // Cancelling while the operation runs discards its result
+    let token = CancellationToken::new();
+    let clone = token.clone();
+    assert_eq!(token.run(move || clone.cancel()), Err(Cancelled));
+
+    // Operations overrunning the deadline, or running when the token is cancelled, are given up on
+    // without waiting for them to complete
+    let start = Instant::now();
+    let token = CancellationToken::new().with_timeout(Duration::from_millis(50));
+    assert_eq!(
+        token.run(|| std::thread::sleep(Duration::from_secs(10))),
+        Err(Cancelled)
+    );
+    let token = CancellationToken::new();
+    let canceller = {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            token.cancel();
+        })
+    };
+    assert_eq!(
+        token.run(|| std::thread::sleep(Duration::from_secs(10))),
+        Err(Cancelled)
+    );
+    canceller.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // Panics reach the caller
+    let token = CancellationToken::new().with_timeout(Duration::from_secs(3600));
+    let panicked = std::panic::catch_unwind(|| token.run(|| panic!("boom")));
+    assert!(panicked.is_err());
+
+    // Cancelled tokens fail the operations taking them before they call into kson-lib
+    token.cancel();
+    assert_eq!(
+        crate::value::Value::parse_cancellable("key: value", &token),
+        Err(Cancelled)
+    );
+    assert_eq!(
+        Kson::try_check_cancellable("key: value", None, DiagnosticLimit::All, &token),
+        Err(Cancelled)
+    );
+    assert!(matches!(
+        crate::schema::validate_cancellable("type: object", "{}", DiagnosticLimit::All, &token),
+        Err(Cancelled)
+    ));
+}
+
+#[test]
+fn test_kson_macro() {
+    use crate::value::{Map, Value};
+
+    let port = 8080;
+    let script = String::from("echo hi\n");
+    let value = crate::kson!({
+        name: "api",
+        "log-level": "info",
+        (format!("port_{}", 1)): port + 1,
+        ratio: 0.5,
+        flags: [true, false, null, [], {}, -1, port],
+        query: %sql "select 1\n",
+        steps: [%(script), %sh "ls\n"],
+        nested: { empty: [] }
+    });
+    assert_eq!(
+        value,
+        Value::Object(Map::from_iter([
+            ("name", Value::from("api")),
+            ("log-level", Value::from("info")),
+            ("port_1", Value::Integer(8081)),
+            ("ratio", Value::Decimal(0.5)),
+            (
+                "flags",
+                Value::Array(vec![
+                    Value::Bool(true),
+                    Value::Bool(false),
+                    Value::Null,
+                    Value::Array(vec![]),
+                    Value::Object(Map::new()),
+                    Value::Integer(-1),
+                    Value::Integer(8080),
+                ]),
+            ),
+            (
+                "query",
+                Value::Embed {
+                    tag: Some("sql".to_string()),
+                    content: "select 1\n".to_string(),
+                },
+            ),
+            (
+                "steps",
+                Value::Array(vec![
+                    Value::Embed {
+                        tag: None,
+                        content: "echo hi\n".to_string(),
+                    },
+                    Value::Embed {
+                        tag: Some("sh".to_string()),
+                        content: "ls\n".to_string(),
+                    },
+                ]),
+            ),
+            (
+                "nested",
+                Value::Object(Map::from_iter([("empty", Value::Array(vec![]))])),
+            ),
+        ]))
+    );
+    assert_eq!(crate::kson!(null), Value::Null);
+    assert_eq!(
+        crate::kson!([1, "two",]),
+        Value::Array(vec![Value::Integer(1), Value::from("two")])
+    );
+}
+
+#[test]
+fn test_kson_macro_comments() {
+    use crate::comments::Comments;
+    use crate::path::KsonPath;
+
+    let (value, comments) = crate::kson_with_comments!(
+        /// The service
+        {
+            /// Where to listen
+            ///
+            /// (any free port)
+            port: 8080, //! the default
+            hosts: [
+                /// The primary host
+                "a",
+                { name: "b" }, //! fallback
+            ],
+            script: %sh "echo hi\n", //! run first
+        }
+    );
+    let commented = crate::kson!({
+        /// Where to listen
+        port: 8080, //! the default
+        hosts: ["a", { name: "b" }],
+        script: %sh "echo hi\n",
+    });
+    assert_eq!(value, commented);
+
+    let leading = |lines: &[&str]| Comments {
+        leading: lines.iter().map(|line| line.to_string()).collect(),
+        trailing: None,
+    };
+    let trailing = |text: &str| Comments {
+        leading: Vec::new(),
+        trailing: Some(text.to_string()),
+    };
+    assert_eq!(
+        comments.into_iter().collect::<Vec<_>>(),
+        [
+            (KsonPath::root(), leading(&["The service"])),
+            (
+                KsonPath::root().key("hosts").index(0),
+                leading(&["The primary host"])
+            ),
+            (KsonPath::root().key("hosts").index(1), trailing("fallback")),
+            (
+                KsonPath::root().key("port"),
+                Comments {
+                    leading: vec![
+                        "Where to listen".to_string(),
+                        String::new(),
+                        "(any free port)".to_string()
+                    ],
+                    trailing: Some("the default".to_string()),
+                }
+            ),
+            (KsonPath::root().key("script"), trailing("run first")),
+        ]
+    );
+}
+
+#[test]
+fn test_kson_document() {
+    use crate::document::{EditError, KsonDocument};
+    use crate::path::KsonPath;
+    use crate::value::Value;
+
+    let mut document = KsonDocument::parse(
+        r#"# The service
+name: "api"   # quoted on purpose
+server:
+  # Where to listen
+  port: 80
+hosts:
+  - a
+  - b
+"#,
+    )
+    .unwrap();
+    let server = KsonPath::root().key("server");
+    document
+        .set(&server.clone().key("port"), Value::Integer(8080))
+        .unwrap();
+    document
+        .set(&KsonPath::root().key("name"), Value::from("web"))
+        .unwrap();
+    document
+        .set(&KsonPath::root().key("hosts").index(1), Value::from("c"))
+        .unwrap();
+    document
+        .set(&server.clone().key("tls").key("enabled"), Value::Bool(true))
+        .unwrap();
+    insta::assert_snapshot!(document.text(), @r#"
+    # The service
+    name: "web"   # quoted on purpose
+    server:
+      # Where to listen
+      port: 8080
+      tls: {
+        enabled: true
+      }
+    hosts:
+      - a
+      - c
+    "#);
+    assert_eq!(
+        document.get(&server.clone().key("tls").key("enabled")),
+        Some(Value::Bool(true))
+    );
+
+    let port = server.key("port");
+    assert_eq!(
+        document.set(&port.clone().key("number"), Value::Null),
+        Err(EditError::NotFound(port))
+    );
+    let hosts = KsonPath::root().key("hosts");
+    assert_eq!(
+        document.set(&hosts.clone().index(5), Value::Null),
+        Err(EditError::NotFound(hosts))
+    );
+}
+
+#[test]
+fn test_comments() {
+    use std::collections::BTreeMap;
+
+    use crate::comments::Comments;
+    use crate::format::Formatter;
+    use crate::path::KsonPath;
+    use crate::value::Value;
+
+    let document = r#"# The service
+name: api # short for the service
+server:
+  # Where to listen
+  port: 80
+  hosts:
+    - a # primary
+    - b
+"#;
+    let commented = Kson::comments(document).unwrap();
+    let server = KsonPath::root().key("server");
+    let port = commented.at(&server.clone().key("port")).unwrap();
+    assert_eq!(port.comments().leading, ["Where to listen"]);
+    assert_eq!(port.value().start().line(), 4);
+    let keys: Vec<_> = commented
+        .children()
+        .map(|(segment, _)| segment.to_string())
+        .collect();
+    assert_eq!(keys, ["name", "server"]);
+    assert!(commented.comments().is_empty());
+    let comments = commented.comments_by_path();
+    assert_eq!(
+        comments,
+        BTreeMap::from([
+            (
+                KsonPath::root().key("name"),
+                Comments {
+                    leading: vec!["The service".to_string()],
+                    trailing: Some("short for the service".to_string()),
+                }
+            ),
+            (
+                server.clone().key("port"),
+                Comments {
+                    leading: vec!["Where to listen".to_string()],
+                    trailing: None,
+                }
+            ),
+            (
+                server.clone().key("hosts").index(0),
+                Comments {
+                    leading: vec![],
+                    trailing: Some("primary".to_string()),
+                }
+            ),
+        ])
+    );
+
+    let mut value = Value::parse(document).unwrap();
+    if let Value::Object(properties) = &mut value {
+        properties.insert("server", crate::kson!({ port: 8080, hosts: ["a", "b"] }));
+    }
+    let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+    let formatter = Formatter::new(FormatOptions::new(indent, FormattingStyle::Plain, &[]));
+    insta::assert_snapshot!(value.to_kson_with_comments(&formatter, &comments), @r"
+    # The service
+    name: api # short for the service
+    server:
+      # Where to listen
+      port: 8080
+      hosts:
+        - a # primary
+        - b
+    ");
+    assert!(Kson::comments("key: [").is_err());
+}
+
+#[test]
+fn test_kson_value_spans() {
+    use crate::line_index::LineIndex;
+    use crate::path::KsonPath;
+    use crate::span::{LineColumn, Span};
+
+    let source = "name: \"é\"\nlist: [1, 22]";
+    let value = Kson::analyze(source, None).kson_value().unwrap();
+    let spans = value.spans(source);
+    let position = |line, column, offset| LineColumn {
+        line,
+        column,
+        offset,
+    };
+    let name = spans[&KsonPath::root().key("name")];
+    assert_eq!(
+        name,
+        Span {
+            start: position(0, 6, 6),
+            end: position(0, 9, 10),
+        }
+    );
+    assert_eq!(&source[name.range()], "\"é\"");
+    let element = spans[&KsonPath::root().key("list").index(1)];
+    assert_eq!(
+        (element.start, element.end),
+        (position(1, 10, 21), position(1, 12, 23))
+    );
+    assert!(element.contains(22) && !element.contains(23));
+    assert_eq!(spans.len(), 5);
+    let index = LineIndex::new(source);
+    assert_eq!(value.span(&index), spans[&KsonPath::root()]);
+    assert_eq!(value.span(&index).range(), 0..source.len());
+}
+
+#[test]
+fn test_value_constructors() {
+    use crate::value::Value;
+
+    let value = Value::object([
+        ("name", Value::string("api")),
+        ("ports", Value::list([Value::from(80), Value::from(443)])),
+        ("query", Value::embed(Some("sql"), "select 1\n")),
+    ]);
+    assert_eq!(
+        value,
+        crate::kson!({ name: "api", ports: [80, 443], query: %sql "select 1\n" })
+    );
+
+    let indent = IndentType::Spaces(indent_type::Spaces::new(4));
+    let options = FormatOptions::new(indent, FormattingStyle::Plain, &[]);
+    let parsed = Kson::analyze("# kept out\nname: api\nports: [80, 443]", None)
+        .kson_value()
+        .unwrap();
+    insta::assert_snapshot!(parsed.to_kson(&options), @r"
+    name: api
+    ports:
+        - 80
+        - 443
+    ");
+}
+
+#[test]
+fn test_value_index() {
+    use crate::value::Value;
+
+    let value = crate::kson!({ server: { port: 8080, hosts: ["a", "b"] } });
+    assert_eq!(value["server"]["port"], Value::Integer(8080));
+    assert_eq!(value["server"]["hosts"][1], Value::from("b"));
+    assert_eq!(value["server"]["hosts"][2], Value::Null);
+    assert_eq!(value["missing"]["port"], Value::Null);
+    assert_eq!(value["server"][0], Value::Null);
+    assert_eq!(value["server"]["port"]["nested"], Value::Null);
+}
+
+#[test]
+fn test_kson_value_accessors() {
+    let value = Kson::analyze(
+        "name: kson\nversion: 3\nratio: 0.5\nstable: false\ntags: [fast, small]\nquery: %sql\n  select 1\n  %%",
+        None,
+    )
+    .kson_value()
+    .unwrap();
+
+    assert_eq!(
+        value.get("name").and_then(|name| name.as_string()),
+        Some("kson".to_string())
+    );
+    let version = value.get("version").unwrap();
+    assert_eq!(version.as_i64(), Some(3));
+    assert_eq!(version.as_f64(), Some(3.0));
+    assert_eq!(version.as_string(), None);
+    assert_eq!(value.get("ratio").unwrap().as_f64(), Some(0.5));
+    assert_eq!(value.get("ratio").unwrap().as_i64(), None);
+    assert_eq!(value.get("stable").unwrap().as_bool(), Some(false));
+    assert_eq!(value.get("query").unwrap().as_string(), None);
+    assert!(value.get("missing").is_none());
+
+    let tags = value.get("tags").unwrap();
+    assert_eq!(tags.as_list().map(|tags| tags.len()), Some(2));
+    assert_eq!(
+        tags.get_index(1).unwrap().as_string(),
+        Some("small".to_string())
+    );
+    assert!(tags.get_index(2).is_none());
+    assert!(tags.get("0").is_none());
+    assert!(tags.as_object().is_none());
+
+    let keys: Vec<String> = value
+        .as_object()
+        .unwrap()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(
+        keys,
+        ["name", "version", "ratio", "stable", "tags", "query"]
+    );
+}
+
+#[test]
+fn test_kson_value_pointer() {
+    use crate::path::{KsonPath, LookupError, PathSegment};
+    use crate::pointer::PointerError;
+
+    let value = Kson::analyze(
+        "servers: [{ host: 'a.example', port: 80 }]\n'a/b': { '~': 1 }",
+        None,
+    )
+    .kson_value()
+    .unwrap();
+    assert_eq!(
+        value
+            .pointer("/servers/0/host")
+            .and_then(|host| host.as_string()),
+        Some("a.example".to_string())
+    );
+    assert_eq!(
+        value.pointer("/a~1b/~0").and_then(|value| value.as_i64()),
+        Some(1)
+    );
+    let path = KsonPath::root().key("servers").index(0).key("port");
+    assert_eq!(
+        value.get_path(&path).and_then(|port| port.as_i64()),
+        Some(80)
+    );
+    assert!(value.pointer("/servers/1").is_none());
+
+    let servers = KsonPath::root().key("servers");
+    assert_eq!(
+        value.try_pointer("/servers/3/host").unwrap_err(),
+        LookupError::Missing {
+            parent: servers.clone(),
+            segment: PathSegment::Index(3),
+        }
+    );
+    let port = servers.index(0).key("port");
+    let error = value.try_pointer("/servers/0/port/number").unwrap_err();
+    assert_eq!(
+        error,
+        LookupError::NotAContainer {
+            parent: port,
+            segment: PathSegment::Key("number".to_string()),
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "can't look `number` up in the value at `/servers/0/port`, which is neither an object nor an array"
+    );
+    assert_eq!(
+        value.try_pointer("servers").unwrap_err(),
+        LookupError::Pointer(PointerError::BadStart('s'))
+    );
+}
+
+#[test]
+fn test_value_retain_recursive() {
+    use crate::path::KsonPath;
+    use crate::value::{Map, Value};
+
+    let mut value = Value::Object(Map::from_iter([
+        ("name", Value::from("kson")),
+        ("license", Value::Null),
+        (
+            "_meta",
+            Value::Object(Map::from_iter([("generated", Value::Bool(true))])),
+        ),
+        (
+            "items",
+            Value::Array(vec![
+                Value::Integer(1),
+                Value::Null,
+                Value::Object(Map::from_iter([
+                    ("_meta", Value::Null),
+                    ("id", Value::Integer(3)),
+                ])),
+            ]),
+        ),
+    ]));
+
+    let mut visited = Vec::new();
+    value.retain_recursive(|path, value| {
+        visited.push(path.to_string());
+        let is_meta =
+            matches!(path.last(), Some(crate::path::PathSegment::Key(key)) if key.starts_with('_'));
+        !is_meta && *value != Value::Null
+    });
+
+    assert_eq!(
+        visited,
+        [
+            "/name",
+            "/license",
+            "/_meta",
+            "/items",
+            "/items/0",
+            "/items/1",
+            "/items/2",
+            "/items/2/_meta",
+            "/items/2/id"
+        ]
+    );
+    assert_eq!(
+        value,
+        Value::Object(Map::from_iter([
+            ("name", Value::from("kson")),
+            (
+                "items",
+                Value::Array(vec![
+                    Value::Integer(1),
+                    Value::Object(Map::from_iter([("id", Value::Integer(3))])),
+                ]),
+            ),
+        ]))
+    );
+    assert_eq!(KsonPath::root().key("a/b").index(0).to_string(), "/a~1b/0");
+}
+
+#[test]
+fn test_value_filter_map_recursive() {
+    use crate::value::{Map, Value};
+
+    let value = Value::Object(Map::from_iter([
+        (
+            "empty_after_pruning",
+            Value::Object(Map::from_iter([("a", Value::Null)])),
+        ),
+        ("count", Value::Integer(2)),
+        ("nested", Value::Array(vec![Value::Null, Value::Integer(5)])),
+    ]));
+
+    let result = value.filter_map_recursive(|path, value| match value {
+        Value::Null => None,
+        Value::Object(ref map) if map.is_empty() && !path.is_root() => None,
+        Value::Integer(i) => Some(Value::Integer(i * 10)),
+        other => Some(other),
+    });
+    assert_eq!(
+        result,
+        Some(Value::Object(Map::from_iter([
+            ("count", Value::Integer(20)),
+            ("nested", Value::Array(vec![Value::Integer(50)])),
+        ])))
+    );
+
+    assert_eq!(Value::Null.filter_map_recursive(|_, _| None), None);
+}
+
+#[test]
+fn test_kson_value_retain_recursive() {
+    let analysis = Kson::analyze(
+        r#"
+z: 1
+a:
+  secret: hunter2
+  b: null
+embed: %sql
+  select 1
+  %%
+"#,
+        None,
+    );
+    let value = analysis.kson_value().unwrap();
+    let pruned = value.retain_recursive(|path, value| {
+        path.last()
+            .is_none_or(|segment| segment.to_string() != "secret")
+            && *value != crate::value::Value::Null
+    });
+
+    insta::assert_snapshot!(pruned.to_json(), @r#"
+    {
+      "z": 1,
+      "a": {},
+      "embed": "select 1\n"
+    }
+    "#);
+    insta::assert_snapshot!(pruned.to_kson(), @r#"
+    z: 1
+    a: {}
+    embed: %sql
+      select 1
+      %%
+    "#);
+}
+
+#[test]
+fn test_value_ordering_and_hashing() {
+    use crate::value::{Map, Value};
+    use std::collections::{BTreeSet, HashSet};
+
+    let object = |entries: &[(&str, i64)]| -> Value {
+        entries
+            .iter()
+            .map(|(key, value)| (*key, Value::Integer(*value)))
+            .collect::<Map>()
+            .into()
+    };
+    let values = vec![
+        object(&[("b", 1), ("a", 2)]),
+        Value::from("text"),
+        Value::Decimal(f64::NAN),
+        object(&[("b", 1), ("a", 2)]),
+        Value::Integer(1),
+        Value::Decimal(1.0),
+        Value::Null,
+        object(&[("a", 2), ("b", 1)]),
+        Value::Decimal(f64::NAN),
+        Value::Array(vec![Value::Null]),
+    ];
+
+    let unique: HashSet<&Value> = values.iter().collect();
+    assert_eq!(unique.len(), 8);
+
+    let sorted: Vec<Value> = values
+        .iter()
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    assert_eq!(
+        sorted,
+        [
+            Value::Null,
+            Value::Integer(1),
+            Value::Decimal(1.0),
+            Value::Decimal(f64::NAN),
+            Value::from("text"),
+            Value::Array(vec![Value::Null]),
+            object(&[("a", 2), ("b", 1)]),
+            object(&[("b", 1), ("a", 2)]),
+        ]
+    );
+    assert_ne!(Value::Decimal(0.0), Value::Decimal(-0.0));
+}
+
+#[test]
+fn test_value_copy_on_write() {
+    use crate::path::KsonPath;
+    use crate::value::{Map, Value};
+
+    let section = |name: &str| -> Value {
+        [("name", Value::from(name)), ("replicas", Value::Integer(1))]
+            .into_iter()
+            .collect::<Map>()
+            .into()
+    };
+    let base: Value = [("api", section("api")), ("worker", section("worker"))]
+        .into_iter()
+        .collect::<Map>()
+        .into();
+
+    let mut layered = base.clone();
+    let replicas = KsonPath::root().key("api").key("replicas");
+    *layered.get_path_mut(&replicas).unwrap() = Value::Integer(3);
+
+    assert_eq!(base.get_path(&replicas), Some(&Value::Integer(1)));
+    assert_eq!(layered.get_path(&replicas), Some(&Value::Integer(3)));
+    let worker = |value: &Value| match value.get_path(&KsonPath::root().key("worker")) {
+        Some(Value::Object(worker)) => worker.clone(),
+        _ => panic!("expected a worker object"),
+    };
+    // Only the path to the modified value was copied
+    assert!(worker(&base).ptr_eq(&worker(&layered)));
+    assert_ne!(base, layered);
+}
+
+#[test]
+fn test_value_merge() {
+    use crate::kson;
+    use crate::merge::{ListMerge, MergeStrategy, ObjectMerge};
+
+    let defaults = kson!({
+        "server": { "host": "localhost", "port": 80, "tls": { "enabled": false } },
+        "plugins": ["auth"],
+        "debug": true
+    });
+    let overrides = kson!({
+        "server": { "port": 8080, "host": null, "tls": { "enabled": true } },
+        "plugins": ["metrics"],
+        "name": { "short": "api", "long": null }
+    });
+
+    let mut merged = defaults.clone();
+    merged.merge(overrides.clone(), MergeStrategy::default());
+    assert_eq!(
+        merged,
+        kson!({
+            "server": { "host": null, "port": 8080, "tls": { "enabled": true } },
+            "plugins": ["metrics"],
+            "debug": true,
+            "name": { "short": "api", "long": null }
+        })
+    );
+
+    let mut merged = defaults.clone();
+    merged.merge(
+        overrides.clone(),
+        MergeStrategy::default()
+            .lists(ListMerge::Append)
+            .null_deletes(true),
+    );
+    assert_eq!(
+        merged,
+        kson!({
+            "server": { "port": 8080, "tls": { "enabled": true } },
+            "plugins": ["auth", "metrics"],
+            "debug": true,
+            "name": { "short": "api" }
+        })
+    );
+
+    let mut merged = defaults.clone();
+    merged.merge(
+        overrides.clone(),
+        MergeStrategy::default().objects(ObjectMerge::Replace),
+    );
+    assert_eq!(merged, overrides);
+
+    // Values of different types are replaced
+    let mut merged = kson!({ "plugins": ["auth"] });
+    merged.merge(
+        kson!({ "plugins": "all" }),
+        MergeStrategy::default().lists(ListMerge::Append),
+    );
+    assert_eq!(merged, kson!({ "plugins": "all" }));
+}
+
+#[test]
+fn test_value_merge_patch() {
+    use crate::value::Value;
+
+    // The examples of RFC 7396
+    let examples = [
+        ("{a: b}", "{a: c}", "{a: c}"),
+        ("{a: b}", "{b: c}", "{a: b, b: c}"),
+        ("{a: b}", "{a: null}", "{}"),
+        ("{a: b, b: c}", "{a: null}", "{b: c}"),
+        ("{a: [b]}", "{a: c}", "{a: c}"),
+        ("{a: c}", "{a: [b]}", "{a: [b]}"),
+        ("{a: {b: c}}", "{a: {b: d, c: null}}", "{a: {b: d}}"),
+        ("{a: [{b: c}]}", "{a: [1]}", "{a: [1]}"),
+        ("[a, b]", "[c, d]", "[c, d]"),
+        ("{a: b}", "[c]", "[c]"),
+        ("{a: foo}", "null", "null"),
+        ("{a: foo}", "bar", "bar"),
+        ("{e: null}", "{a: 1}", "{e: null, a: 1}"),
+        ("[1, 2]", "{a: b, c: null}", "{a: b}"),
+        ("{}", "{a: {bb: {ccc: null}}}", "{a: {bb: {}}}"),
+    ];
+    for (target, patch, expected) in examples {
+        let mut value = Value::parse(target).unwrap();
+        value.merge_patch(Value::parse(patch).unwrap());
+        assert_eq!(value, Value::parse(expected).unwrap(), "{target} + {patch}");
+    }
+}
+
+#[test]
+fn test_merge_layers() {
+    use crate::layers::{Layer, Shape, merge_layers};
+    use crate::merge::MergeStrategy;
+
+    let defaults = Layer::parse(
+        "defaults.kson",
+        "server: {\n  host: localhost\n  port: 80\n}\ntags: [web]",
+    )
+    .unwrap();
+    let staging = Layer::parse("staging.kson", "server: { port: 8080 }\ntags: null").unwrap();
+    let production = Layer::parse(
+        "production.kson",
+        "server: { host: { name: api }, port: [8443] }\ntags: { team: web }",
+    )
+    .unwrap();
+
+    assert_eq!(
+        merge_layers(
+            &[defaults.clone(), staging.clone()],
+            MergeStrategy::default()
+        )
+        .unwrap(),
+        crate::kson!({ "server": { "host": "localhost", "port": 8080 }, "tags": null })
+    );
+
+    let conflicts =
+        merge_layers(&[defaults, staging, production], MergeStrategy::default()).unwrap_err();
+    let conflicts: Vec<String> = conflicts
+        .iter()
+        .map(|conflict| {
+            assert_eq!(conflict.later.source, "production.kson");
+            assert_eq!(conflict.earlier.shape, Shape::Scalar);
+            conflict.to_string()
+        })
+        .collect();
+    assert_eq!(
+        conflicts,
+        [
+            "`/server/host` is a scalar in defaults.kson at line 2, column 9, but an object in production.kson at line 1, column 17",
+            "`/server/port` is a scalar in staging.kson at line 1, column 17, but an array in production.kson at line 1, column 38",
+        ]
+    );
+}
+
+#[test]
+fn test_validate_layers() {
+    use crate::layers::{Layer, trace_layers};
+    use crate::merge::MergeStrategy;
+    use crate::path::KsonPath;
+    use crate::schema::Schema;
+
+    let Ok(schema) = Schema::parse(
+        r#"
+        type: object
+        properties:
+          server:
+            type: object
+            properties:
+              host:
+                type: string
+                .
+              port:
+                type: integer
+      "#,
+    ) else {
+        panic!("the schema should parse")
+    };
+
+    let layers = [
+        Layer::parse(
+            "defaults.kson",
+            "server: {\n  host: localhost\n  port: 80\n}",
+        )
+        .unwrap(),
+        Layer::parse("production.kson", "server: { port: 'https' }").unwrap(),
+    ];
+    let merged = trace_layers(&layers, MergeStrategy::default()).unwrap();
+    assert_eq!(
+        merged
+            .origin(&KsonPath::parse("/server/host").unwrap())
+            .unwrap()
+            .to_string(),
+        "defaults.kson at line 2, column 9"
+    );
+
+    let violations = merged.validate(&schema);
+    assert_eq!(violations.len(), 1);
+    let violation = &violations[0];
+    assert!(matches!(violation.severity, MessageSeverity::Error));
+    assert_eq!(violation.path.to_string(), "/server/port");
+    assert_eq!(
+        violation.location.as_ref().unwrap().to_string(),
+        "production.kson at line 1, column 17"
+    );
+
+    let valid = [layers[0].clone()];
+    let merged = trace_layers(&valid, MergeStrategy::default()).unwrap();
+    assert!(merged.validate(&schema).is_empty());
+}
+
+#[test]
+fn test_merge3() {
+    use crate::Kson;
+    use crate::kson;
+    use crate::merge::Conflict;
+    use crate::path::KsonPath;
+    use crate::value::Value;
+
+    let base = kson!({
+        "replicas": 1,
+        "image": "api:1.0",
+        "env": { "LOG": "info", "REGION": "eu" },
+        "ports": [80],
+        "debug": false
+    });
+    let ours = kson!({
+        "replicas": 3,
+        "image": "api:1.0",
+        "env": { "LOG": "debug", "REGION": "eu" },
+        "ports": [80, 443],
+        "owner": "ops"
+    });
+    let theirs = kson!({
+        "replicas": 1,
+        "image": "api:1.1",
+        "env": { "LOG": "info", "REGION": "eu", "TZ": "UTC" },
+        "ports": [80],
+        "debug": false,
+        "owner": "ops"
+    });
+    assert_eq!(
+        Value::merge3(&base, &ours, &theirs),
+        Ok(kson!({
+            "replicas": 3,
+            "image": "api:1.1",
+            "env": { "LOG": "debug", "REGION": "eu", "TZ": "UTC" },
+            "ports": [80, 443],
+            "owner": "ops"
+        }))
+    );
+
+    let theirs = kson!({
+        "replicas": 2,
+        "image": "api:1.0",
+        "env": { "LOG": "warn", "REGION": "eu" },
+        "ports": [8080],
+        "owner": "dev"
+    });
+    let conflicts = Value::merge3(&base, &ours, &theirs).unwrap_err();
+    let paths: Vec<String> = conflicts
+        .iter()
+        .map(|conflict| conflict.path.to_string())
+        .collect();
+    assert_eq!(paths, ["/replicas", "/env/LOG", "/ports", "/owner"]);
+    assert_eq!(
+        conflicts[3],
+        Conflict {
+            path: KsonPath::root().key("owner"),
+            base: None,
+            ours: Some("ops".into()),
+            theirs: Some("dev".into()),
+        }
+    );
+    assert_eq!(
+        conflicts[0].to_string(),
+        "conflicting changes at `/replicas`: ours changed it to 3, theirs to 2"
+    );
+
+    // Deleting a value the other side changed conflicts
+    let conflicts = Value::merge3(&kson!({ "a": 1 }), &kson!({}), &kson!({ "a": 2 })).unwrap_err();
+    assert_eq!(conflicts[0].ours, None);
+
+    let parse = |document: &str| Kson::analyze(document, None).kson_value().unwrap();
+    assert_eq!(
+        Kson::merge3(
+            &parse("a: 1\nb: 1"),
+            &parse("a: 2\nb: 1"),
+            &parse("a: 1\nb: 2")
+        ),
+        Ok(kson!({ "a": 2, "b": 2 }))
+    );
+}
+
+#[test]
+fn test_merge_driver() {
+    use crate::merge::{MergeDriverError, MergeDriverOutcome, merge_driver};
+
+    let dir = TempDir::new("merge-driver");
+    let (base, ours, theirs) = (dir.join("base"), dir.join("ours"), dir.join("theirs"));
+    let formatter = FormatOptions::builder().build();
+    let write = |base_text: &str, ours_text: &str, theirs_text: &str| {
+        std::fs::write(&base, base_text).unwrap();
+        std::fs::write(&ours, ours_text).unwrap();
+        std::fs::write(&theirs, theirs_text).unwrap();
+    };
+
+    write("a: 1\nb: 1", "a: 2\nb: 1", "a: 1\nb: 2");
+    let outcome = merge_driver(&base, &ours, &theirs, &formatter).unwrap();
+    assert_eq!(
+        (&outcome, outcome.exit_code()),
+        (&MergeDriverOutcome::Merged, 0)
+    );
+    assert_eq!(std::fs::read_to_string(&ours).unwrap(), "a: 2\nb: 2");
+
+    // The file of the side with the merged document is kept as written
+    write("a: 1", "a: 1", "# changed\na: 2");
+    merge_driver(&base, &ours, &theirs, &formatter).unwrap();
+    assert_eq!(std::fs::read_to_string(&ours).unwrap(), "# changed\na: 2");
+
+    write("a: 1", "a: 2", "a: 3");
+    let outcome = merge_driver(&base, &ours, &theirs, &formatter).unwrap();
+    assert!(matches!(&outcome, MergeDriverOutcome::Conflicts(conflicts) if conflicts.len() == 1));
+    assert_eq!(outcome.exit_code(), 1);
+    assert_eq!(std::fs::read_to_string(&ours).unwrap(), "a: 2");
+
+    write("a: 1", "a: [", "a: 3");
+    assert!(matches!(
+        merge_driver(&base, &ours, &theirs, &formatter),
+        Err(MergeDriverError::Parse { path, .. }) if path == ours
+    ));
+}
+
+#[test]
+fn test_patch() {
+    use crate::kson;
+    use crate::patch::{Operation, Patch, PatchError};
+    use crate::path::{KsonPath, LookupError, PathSegment};
+    use crate::roundtrip::ValueGenerator;
+    use crate::value::{Value, values_equal};
+
+    let patch = Patch::parse(
+        r#"
+        - op: test
+          path: /server/port
+          value: 80.0
+        - op: replace
+          path: /server/port
+          value: 8080
+        - op: add
+          path: /plugins/-
+          value: metrics
+        - op: add
+          path: /plugins/0
+          value: tracing
+        - op: copy
+          from: /server
+          path: /backup
+        - op: move
+          from: /server/host
+          path: /host
+        - op: remove
+          path: /debug
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        patch.operations[5],
+        Operation::Move {
+            from: KsonPath::root().key("server").key("host"),
+            path: KsonPath::root().key("host"),
+        }
+    );
+    assert_eq!(Patch::from_value(&patch.to_value()), Ok(patch.clone()));
+
+    let original = kson!({
+        "server": { "host": "localhost", "port": 80 },
+        "plugins": ["auth"],
+        "debug": true
+    });
+    let mut value = original.clone();
+    value.apply_patch(&patch).unwrap();
+    assert_eq!(
+        value,
+        kson!({
+            "server": { "port": 8080 },
+            "plugins": ["tracing", "auth", "metrics"],
+            "backup": { "host": "localhost", "port": 8080 },
+            "host": "localhost"
+        })
+    );
+
+    // A failing operation leaves the value unchanged
+    let mut value = original.clone();
+    let failing = Patch::new(vec![
+        Operation::Remove {
+            path: KsonPath::root().key("debug"),
+        },
+        Operation::Test {
+            path: KsonPath::root().key("plugins").index(0),
+            value: "metrics".into(),
+        },
+    ]);
+    assert_eq!(
+        value.apply_patch(&failing),
+        Err(PatchError::TestFailed {
+            operation: 1,
+            path: KsonPath::root().key("plugins").index(0),
+        })
+    );
+    assert_eq!(value, original);
+    let missing = Patch::new(vec![Operation::Replace {
+        path: KsonPath::root().key("plugins").index(3),
+        value: Value::Null,
+    }]);
+    assert_eq!(
+        value.apply_patch(&missing),
+        Err(PatchError::Lookup {
+            operation: 0,
+            error: LookupError::Missing {
+                parent: KsonPath::root().key("plugins"),
+                segment: PathSegment::Index(3),
+            },
+        })
+    );
+    let into_itself = Patch::new(vec![Operation::Move {
+        from: KsonPath::root().key("server"),
+        path: KsonPath::root().key("server").key("nested"),
+    }]);
+    assert!(matches!(
+        value.apply_patch(&into_itself),
+        Err(PatchError::Invalid { operation: 0, .. })
+    ));
+    assert!(matches!(
+        Patch::parse("- op: frobnicate\n  path: /a"),
+        Err(PatchError::Invalid { operation: 0, .. })
+    ));
+
+    let values: Vec<Value> = ValueGenerator::new(11).take(40).collect();
+    for (from, to) in values.iter().zip(values.iter().skip(1)) {
+        let patch = Patch::diff(from, to);
+        let mut patched = from.clone();
+        patched.apply_patch(&patch).unwrap();
+        assert!(values_equal(&patched, to), "{patch:#?}");
+        assert_eq!(Patch::diff(to, to), Patch::default());
+    }
+}
+
+#[test]
+fn test_cache_bytes_round_trip() {
+    use crate::cache::{CACHE_FORMAT_VERSION, CacheError};
+    use crate::depth::MaxDepth;
+    use crate::roundtrip::ValueGenerator;
+    use crate::value::Value;
+
+    for value in ValueGenerator::new(3).take(50) {
+        let bytes = value.to_cache_bytes();
+        assert_eq!(Value::from_cache_bytes(&bytes), Ok(value));
+    }
+
+    let bytes = Value::from("cached").to_cache_bytes();
+    assert_eq!(
+        Value::from_cache_bytes(&bytes[..bytes.len() - 1]),
+        Err(CacheError::UnexpectedEnd)
+    );
+    let mut outdated = bytes.clone();
+    outdated[4] = CACHE_FORMAT_VERSION + 1;
+    assert_eq!(
+        Value::from_cache_bytes(&outdated),
+        Err(CacheError::UnsupportedVersion(CACHE_FORMAT_VERSION + 1))
+    );
+    assert_eq!(
+        Value::from_cache_bytes(b"key: value"),
+        Err(CacheError::NotACacheEntry)
+    );
+
+    // Objects with a repeated key don't come from the encoder
+    let mut duplicated = Value::Object(
+        [("a", Value::Null), ("b", Value::Null)]
+            .into_iter()
+            .collect(),
+    )
+    .to_cache_bytes();
+    let b = duplicated.iter().rposition(|&byte| byte == b'b').unwrap();
+    duplicated[b] = b'a';
+    assert_eq!(
+        Value::from_cache_bytes(&duplicated),
+        Err(CacheError::Corrupted { offset: b - 1 })
+    );
+
+    // Arrays nested 100 000 levels deep are rejected rather than overflowing the stack
+    let null = Value::Null.to_cache_bytes();
+    let mut deep = null[..null.len() - 1].to_vec();
+    for _ in 0..100_000 {
+        deep.extend([8, 1]);
+    }
+    deep.push(0);
+    let Err(CacheError::TooDeep(error)) = Value::from_cache_bytes(&deep) else {
+        panic!("expected the value to be too deep");
+    };
+    assert_eq!(error.max_depth, MaxDepth::DEFAULT);
+    assert_eq!(error.path.len(), 129);
+
+    let nested = Value::Array(vec![Value::Array(vec![Value::Null])]);
+    let bytes = nested.to_cache_bytes();
+    assert_eq!(
+        Value::from_cache_bytes_with(&bytes, MaxDepth::new(2)),
+        Ok(nested)
+    );
+    assert!(matches!(
+        Value::from_cache_bytes_with(&bytes, MaxDepth::new(1)),
+        Err(CacheError::TooDeep(error)) if error.path.to_string() == "/0/0"
+    ));
+}
+
+#[test]
+fn test_canonicalize() {
+    let canonical = Kson::canonicalize(
+        "# Scaled up\nreplicas: 3\nimage: 'app:1.4.2'\nports: [{ port: 80, name: http }]",
+    )
+    .unwrap();
+    assert_eq!(
+        canonical,
+        Kson::canonicalize("image: 'app:1.4.2'\nports: [{ name: http, port: 80 }]\nreplicas: 3")
+            .unwrap()
+    );
+    insta::assert_snapshot!(Kson::canonicalize("b: 2, a: 1").unwrap(), @r"
+    a: 1
+    b: 2
+    ");
+    assert_eq!(
+        Kson::canonicalize("{ports: [{name: http, port: 80}], image: 'app:1.4.2', replicas: 3}")
+            .unwrap(),
+        canonical
+    );
+    assert_ne!(
+        Kson::canonicalize("[2, 1]").unwrap(),
+        Kson::canonicalize("[1, 2]").unwrap()
+    );
+    assert!(Kson::canonicalize("key: [1, 2").is_err());
+
+    #[cfg(feature = "digest")]
+    {
+        let digest = Kson::digest("replicas: 3\nimage: 'app:1.4.2'").unwrap();
+        assert_eq!(
+            Kson::digest("image: 'app:1.4.2', replicas: 3").unwrap(),
+            digest
+        );
+        assert_eq!(
+            digest,
+            crate::digest::Digest::of("image: 'app:1.4.2'\nreplicas: 3")
+        );
+        assert_ne!(
+            Kson::digest("replicas: 3.0\nimage: 'app:1.4.2'").unwrap(),
+            digest
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "store")]
+fn test_document_store() {
+    use crate::store::{Digest, DocumentStore, StoreError};
+    use crate::value::Value;
+
+    let root = TempDir::new("store");
+    let store = DocumentStore::open(&root).unwrap();
+
+    let digest = store
+        .put("replicas: 3\nimage: 'app:1.4.2' # pinned")
+        .unwrap();
+    assert_eq!(
+        store.put("image: 'app:1.4.2', replicas: 3").unwrap(),
+        digest
+    );
+    assert!(store.contains(&digest));
+    assert_eq!(
+        store.get(&digest).unwrap(),
+        Value::parse("image: 'app:1.4.2'\nreplicas: 3").unwrap()
+    );
+    assert_eq!(digest.to_string().parse::<Digest>(), Ok(digest));
+
+    let other = store.put("replicas: 4").unwrap();
+    assert_ne!(other, digest);
+    std::fs::write(store.path_of(&other), "replicas: 5").unwrap();
+    assert!(matches!(store.get(&other), Err(StoreError::Corrupted(_))));
+    assert!(matches!(
+        store.get(&Digest::of("missing")),
+        Err(StoreError::NotFound(_))
+    ));
+}
+
+#[test]
+#[cfg(feature = "workspace")]
+fn test_file_discovery() {
+    use crate::workspace::FileDiscovery;
+
+    let root = TempDir::new("discovery");
+    for dir in ["config", "generated", "vendor", ".hidden"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    for file in [
+        "app.kson",
+        "notes.txt",
+        "config/db.kson",
+        "config/db.KSON",
+        "generated/out.kson",
+        "vendor/lib.kson",
+        ".hidden/secret.kson",
+    ] {
+        std::fs::write(root.join(file), "key: value").unwrap();
+    }
+    std::fs::write(root.join(".gitignore"), "generated/\n").unwrap();
+    std::fs::write(root.join(".ksonignore"), "vendor/\n").unwrap();
+
+    let relative = |paths: Vec<std::path::PathBuf>| -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    };
+    assert_eq!(
+        relative(FileDiscovery::new(&root).discover().unwrap()),
+        ["app.kson", "config/db.KSON", "config/db.kson"]
+    );
+    assert_eq!(
+        relative(
+            FileDiscovery::new(&root)
+                .respect_ignore_files(false)
+                .discover()
+                .unwrap()
+        ),
+        [
+            "app.kson",
+            "config/db.KSON",
+            "config/db.kson",
+            "generated/out.kson",
+            "vendor/lib.kson"
+        ]
+    );
+    assert_eq!(
+        relative(
+            FileDiscovery::new(&root)
+                .extensions(["txt"])
+                .discover()
+                .unwrap()
+        ),
+        ["notes.txt"]
+    );
+    // Explicitly requested files are never ignored
+    let ignored = root.join("vendor/lib.kson");
+    assert_eq!(FileDiscovery::new(&ignored).discover().unwrap(), [ignored]);
+}
+
+#[test]
+#[cfg(feature = "workspace")]
+fn test_project_config() {
+    use crate::config::{ConfigError, Indent, ProjectConfig, RuleLevel};
+    use crate::format::{FloatFormat, KeyOrder, LineEnding, QuoteStyle, TrailingCommas};
+    use crate::value::Value;
+
+    let root = TempDir::new("config");
+    let nested = root.join("services/api");
+    std::fs::create_dir_all(&nested).unwrap();
+    assert!(
+        ProjectConfig::discover(&nested)
+            .unwrap()
+            .is_none_or(|config| config.path().unwrap().parent() != Some(&*root))
+    );
+
+    std::fs::write(
+        root.join("kson.toml"),
+        "[format]\nindent = 4\nquotes = \"double\"\n\n[lint]\nsecrets = \"error\"\n\n[schemas]\n\"configs/*.kson\" = \"schemas/service.kson\"\n",
+    )
+    .unwrap();
+    let config = ProjectConfig::discover(&nested).unwrap().unwrap();
+    assert_eq!(config.path(), Some(root.join("kson.toml").as_path()));
+    assert_eq!(config.root(), &*root);
+    assert_eq!(config.format().indent, Some(Indent::Spaces(4)));
+    assert_eq!(config.format().quotes, Some(QuoteStyle::Double));
+    assert_eq!(config.rule("secrets").unwrap().level, RuleLevel::Error);
+    assert_eq!(
+        config.schema_for(&root.join("configs/api.kson")),
+        Some(root.join("schemas/service.kson").as_path())
+    );
+    assert_eq!(
+        config.schema_for(std::path::Path::new("configs/nested/api.kson")),
+        None
+    );
+
+    std::fs::write(
+        root.join(".kson.kson"),
+        concat!(
+            "format: { indent: tabs, floats: 3, sort_keys: alphabetical, max_width: 100, ",
+            "line_ending: crlf, final_newline: true, trailing_commas: multiline }"
+        ),
+    )
+    .unwrap();
+    assert!(matches!(
+        ProjectConfig::discover(&nested),
+        Err(ConfigError::Conflicting(_))
+    ));
+    std::fs::remove_file(root.join("kson.toml")).unwrap();
+    let config = ProjectConfig::discover(&nested).unwrap().unwrap();
+    assert_eq!(config.format().indent, Some(Indent::Tabs));
+    assert_eq!(config.format().floats, Some(FloatFormat::Fixed(3)));
+    assert_eq!(config.format().sort_keys, Some(KeyOrder::Alphabetical));
+    assert_eq!(config.format().max_width, Some(100));
+    assert_eq!(config.format().line_ending, Some(LineEnding::Crlf));
+    assert_eq!(config.format().final_newline, Some(true));
+    assert_eq!(
+        config.format().trailing_commas,
+        Some(TrailingCommas::Multiline)
+    );
+
+    let config = ProjectConfig::from_value(
+        &root,
+        &Value::parse(
+            "lint: { unused: { level: warning, ignore: ['/metadata/**'] }, secrets: off }",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let unused = config.rule("unused").unwrap();
+    assert_eq!(unused.level, RuleLevel::Warning);
+    assert!(unused.options.contains_key("ignore"));
+    assert!(config.rule("secrets").unwrap().level.severity().is_none());
+
+    let error = ProjectConfig::from_value(&root, &Value::parse("format: { indent: -1 }").unwrap())
+        .err()
+        .unwrap();
+    assert!(
+        matches!(error, ConfigError::InvalidSetting { setting, .. } if setting == "format.indent")
+    );
+}
+
+#[test]
+#[cfg(feature = "workspace")]
+fn test_schema_associations() {
+    use crate::config::{FileGlob, SchemaAssociations};
+    use std::path::Path;
+
+    let glob = |pattern: &str| FileGlob::parse(pattern).unwrap();
+    assert!(glob("configs/*.kson").matches(Path::new("configs/api.kson")));
+    assert!(!glob("configs/*.kson").matches(Path::new("configs/prod/api.kson")));
+    assert!(glob("configs/**/*.kson").matches(Path::new("configs/prod/api.kson")));
+    assert!(glob("configs/**/*.kson").matches(Path::new("./configs/api.kson")));
+    assert!(glob("*.kson").matches(Path::new("deep/down/api.kson")));
+    assert!(glob("/*.kson").matches(Path::new("api.kson")));
+    assert!(!glob("/*.kson").matches(Path::new("deep/api.kson")));
+    assert!(glob("v?/~draft.kson").matches(Path::new("v1/~draft.kson")));
+
+    let associations = SchemaAssociations::new()
+        .associate(glob("configs/**/*.kson"), "schemas/service.kson")
+        .associate(glob("configs/legacy/*.kson"), "schemas/legacy.kson");
+    assert_eq!(
+        associations.schema_for(Path::new("configs/prod/api.kson")),
+        Some(Path::new("schemas/service.kson"))
+    );
+    assert_eq!(
+        associations.schema_for(Path::new("configs/legacy/api.kson")),
+        Some(Path::new("schemas/legacy.kson"))
+    );
+    assert_eq!(associations.schema_for(Path::new("other/api.kson")), None);
+}
+
+#[test]
+#[cfg(feature = "workspace")]
+fn test_validate_workspace() {
+    use crate::config::ProjectConfig;
+
+    let root = TempDir::new("workspace");
+    for dir in ["configs", "schemas"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    let files = [
+        (
+            ".kson.kson",
+            "schemas: { 'configs/*.kson': 'schemas/service.kson' }",
+        ),
+        (
+            "schemas/service.kson",
+            "type: object\nproperties: { port: { type: integer } }\nrequired: [port]",
+        ),
+        ("configs/api.kson", "port: 8080"),
+        ("configs/worker.kson", "port: eighty"),
+        ("notes.kson", "key: [1, 2"),
+    ];
+    for (file, text) in files {
+        std::fs::write(root.join(file), text).unwrap();
+    }
+
+    let config = ProjectConfig::discover(&root).unwrap().unwrap();
+    let problems = Kson::validate_workspace(&root, &config).unwrap();
+    let problems: Vec<(String, bool)> = problems
+        .iter()
+        .map(|(path, errors)| {
+            let path = path.strip_prefix(&root).unwrap();
+            (
+                path.to_string_lossy().replace('\\', "/"),
+                errors.has_errors(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        problems,
+        [
+            ("configs/api.kson".to_string(), false),
+            ("configs/worker.kson".to_string(), true),
+            ("notes.kson".to_string(), true),
+            ("schemas/service.kson".to_string(), false),
+        ]
+    );
+
+    let reports = std::sync::Mutex::new(Vec::new());
+    let token = crate::cancel::CancellationToken::new();
+    Kson::validate_workspace_with(&root, &config, &token, &|progress| {
+        assert_eq!(progress.total, 4);
+        reports
+            .lock()
+            .unwrap()
+            .push((progress.done, progress.file.to_path_buf()));
+    })
+    .unwrap();
+    let mut reports = reports.into_inner().unwrap();
+    reports.sort();
+    assert_eq!(
+        reports.iter().map(|(done, _)| *done).collect::<Vec<_>>(),
+        [1, 2, 3, 4]
+    );
+    let mut files: Vec<_> = reports.into_iter().map(|(_, file)| file).collect();
+    files.sort();
+    files.dedup();
+    assert_eq!(files.len(), 4);
+
+    token.cancel();
+    let error = Kson::validate_workspace_cancellable(&root, &config, &token).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::Interrupted);
+}
+
+#[test]
+#[cfg(feature = "workspace")]
+fn test_format_and_convert_workspace() {
+    use crate::cancel::CancellationToken;
+    use crate::config::ProjectConfig;
+    use crate::workspace::{FormatMode, FormatOutcome};
+
+    let root = TempDir::new("format");
+    let files = [
+        (".kson.kson", "format: { indent: 4 }"),
+        ("formatted.kson", "key: value"),
+        ("messy.kson", "{nested: {key:   value}}"),
+        ("broken.kson", "key: [1, 2"),
+    ];
+    for (file, text) in files {
+        std::fs::write(root.join(file), text).unwrap();
+    }
+    let config = ProjectConfig::discover(&root).unwrap().unwrap();
+    let outcome = |outcomes: &std::collections::BTreeMap<std::path::PathBuf, FormatOutcome>,
+                   file: &str| outcomes[&root.join(file)].clone();
+
+    let checked = Kson::format_workspace(&root, &config, FormatMode::Check).unwrap();
+    assert_eq!(checked.len(), 3);
+    assert_eq!(
+        outcome(&checked, "formatted.kson"),
+        FormatOutcome::Unchanged
+    );
+    assert_eq!(outcome(&checked, "messy.kson"), FormatOutcome::Reformatted);
+    assert!(matches!(
+        outcome(&checked, "broken.kson"),
+        FormatOutcome::Invalid(errors) if errors.has_errors()
+    ));
+    let messy = std::fs::read_to_string(root.join("messy.kson")).unwrap();
+    assert_eq!(messy, "{nested: {key:   value}}");
+
+    let reports = std::sync::Mutex::new(Vec::new());
+    let token = CancellationToken::new();
+    let written =
+        Kson::format_workspace_with(&root, &config, FormatMode::Write, &token, &|progress| {
+            reports
+                .lock()
+                .unwrap()
+                .push((progress.done, progress.total));
+        })
+        .unwrap();
+    assert_eq!(outcome(&written, "messy.kson"), FormatOutcome::Reformatted);
+    let mut reports = reports.into_inner().unwrap();
+    reports.sort();
+    assert_eq!(reports, [(1, 3), (2, 3), (3, 3)]);
+    let messy = std::fs::read_to_string(root.join("messy.kson")).unwrap();
+    assert_eq!(messy, config.format().formatter().format(&messy));
+    assert!(messy.contains("\n    key: value"));
+    let rechecked = Kson::format_workspace(&root, &config, FormatMode::Check).unwrap();
+    assert_eq!(outcome(&rechecked, "messy.kson"), FormatOutcome::Unchanged);
+
+    let options = TranspileOptions::Json(transpile_options::Json::new(false));
+    let done = std::sync::atomic::AtomicUsize::new(0);
+    let converted = Kson::convert_workspace_with(&root, &options, &token, &|_| {
+        done.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    })
+    .unwrap();
+    assert_eq!(done.into_inner(), 3);
+    let json: serde_json::Value =
+        serde_json::from_str(converted[&root.join("messy.kson")].as_ref().unwrap()).unwrap();
+    let expected: serde_json::Value =
+        serde_json::from_str(r#"{"nested":{"key":"value"}}"#).unwrap();
+    assert_eq!(json, expected);
+    assert!(converted[&root.join("broken.kson")].is_err());
+
+    token.cancel();
+    let error =
+        Kson::format_workspace_cancellable(&root, &config, FormatMode::Write, &token).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::Interrupted);
+    let error = Kson::convert_workspace_cancellable(&root, &options, &token).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::Interrupted);
+}
+
+#[test]
+#[cfg(feature = "workspace")]
+fn test_check_references() {
+    use crate::config::ProjectConfig;
+    use crate::references::{BrokenReferenceKind, Reference};
+    use std::path::Path;
+
+    let keys = ["include".to_string(), "$ref".to_string()];
+    let references = Reference::extract(
+        Path::new("services/api.kson"),
+        "include: '../common.kson'\nport: { '$ref': 'defaults.kson#/port' }\nlocal: { '$ref': '#/port' }\nremote: { '$ref': 'https://example.com/s.kson' }",
+        &keys,
+    );
+    let targets: Vec<_> = references
+        .iter()
+        .map(|reference| (reference.target.as_deref(), reference.pointer.as_deref()))
+        .collect();
+    assert_eq!(
+        targets,
+        [
+            (Some(Path::new("common.kson")), None),
+            (Some(Path::new("services/defaults.kson")), Some("/port")),
+            (None, Some("/port")),
+        ]
+    );
+    assert_eq!(references[1].path.to_string(), "/port/$ref");
+
+    let root = TempDir::new("references");
+    std::fs::create_dir_all(root.join("services")).unwrap();
+    let files = [
+        (".kson.kson", "references: { keys: [include, '$ref'] }"),
+        ("common.kson", "port: 80"),
+        (
+            "services/api.kson",
+            "include: '../common.kson'\nport: { '$ref': '../common.kson#/port' }\nhost: { '$ref': '../common.kson#/host' }",
+        ),
+        (
+            "services/worker.kson",
+            "include: 'missing.kson'\nport: { '$ref': '#/ports/0' }",
+        ),
+    ];
+    for (file, text) in files {
+        std::fs::write(root.join(file), text).unwrap();
+    }
+
+    let config = ProjectConfig::discover(&root).unwrap().unwrap();
+    let broken = Kson::check_references(&root, &config).unwrap();
+    let broken: Vec<_> = broken
+        .iter()
+        .map(|broken| {
+            (
+                broken.reference.text.as_str(),
+                broken.kind,
+                broken.reference.start.line(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        broken,
+        [
+            ("../common.kson#/host", BrokenReferenceKind::MissingValue, 2),
+            ("missing.kson", BrokenReferenceKind::MissingDocument, 0),
+            ("#/ports/0", BrokenReferenceKind::MissingValue, 1),
+        ]
+    );
+}
+
+#[test]
+#[cfg(feature = "workspace")]
+fn test_workspace_index() {
+    use crate::config::ProjectConfig;
+    use crate::index::{FileChange, WorkspaceIndex};
+
+    let root = TempDir::new("index");
+    std::fs::create_dir_all(root.join("schemas")).unwrap();
+    let files = [
+        (
+            ".kson.kson",
+            "schemas: { 'api.kson': 'schemas/api.kson' }\nreferences: { keys: [include] }",
+        ),
+        (
+            "schemas/api.kson",
+            "type: object\nproperties: { port: { type: integer } }",
+        ),
+        ("api.kson", "include: 'common.kson#/defaults'\nport: 80"),
+        ("common.kson", "defaults: { replicas: 2 }"),
+    ];
+    for (file, text) in files {
+        std::fs::write(root.join(file), text).unwrap();
+    }
+
+    let config = ProjectConfig::discover(&root).unwrap().unwrap();
+    let mut index = WorkspaceIndex::build(&root, config).unwrap();
+    let api = root.join("api.kson");
+    let common = root.join("common.kson");
+    let schema = root.join("schemas/api.kson");
+    assert_eq!(index.documents().count(), 3);
+    assert!(index.document(&api).unwrap().diagnostics().is_empty());
+    assert!(index.document(&api).unwrap().broken_references().is_empty());
+    assert_eq!(index.dependents(&common), [api.as_path()]);
+
+    // Editing the referenced document breaks the reference of its dependent
+    let affected = index
+        .apply(FileChange::Edited(
+            common.clone(),
+            "settings: {}".to_string(),
+        ))
+        .unwrap();
+    assert_eq!(affected, [api.clone(), common.clone()]);
+    assert_eq!(index.document(&api).unwrap().broken_references().len(), 1);
+
+    // Changing the schema revalidates the documents associated with it
+    std::fs::write(
+        &schema,
+        "type: object\nproperties: { port: { type: string } }",
+    )
+    .unwrap();
+    let affected = index.apply(FileChange::Written(schema.clone())).unwrap();
+    assert_eq!(affected, [api.clone(), schema.clone()]);
+    assert!(index.document(&api).unwrap().diagnostics().has_errors());
+
+    // New documents are indexed, deleted ones are dropped
+    std::fs::write(root.join("worker.kson"), "include: 'common.kson'").unwrap();
+    index
+        .apply(FileChange::Written(root.join("worker.kson")))
+        .unwrap();
+    assert_eq!(index.dependents(&common).len(), 2);
+    std::fs::remove_file(&common).unwrap();
+    let affected = index.apply(FileChange::Deleted(common.clone())).unwrap();
+    assert_eq!(
+        affected,
+        [api.clone(), common.clone(), root.join("worker.kson")]
+    );
+    assert!(index.document(&common).is_none());
+    assert_eq!(
+        index
+            .document(&root.join("worker.kson"))
+            .unwrap()
+            .broken_references()[0]
+            .kind,
+        crate::references::BrokenReferenceKind::MissingDocument
+    );
+}
+
+#[test]
+#[cfg(feature = "workspace")]
+fn test_workspace_symbols() {
+    use crate::config::ProjectConfig;
+    use crate::index::WorkspaceIndex;
+
+    let root = TempDir::new("symbols");
+    std::fs::write(
+        root.join("api.kson"),
+        "server: { port: 80, db_password: secret }\nports: [80]",
+    )
+    .unwrap();
+    std::fs::write(root.join("worker.kson"), "services: [{ port: 81 }]").unwrap();
+    let index = WorkspaceIndex::build(&root, ProjectConfig::new(&root)).unwrap();
+
+    let symbols = |query: &str| -> Vec<(String, String)> {
+        index
+            .workspace_symbols(query)
+            .into_iter()
+            .map(|(file, symbol)| {
+                let file = file.file_name().unwrap().to_string_lossy().into_owned();
+                (file, symbol.path.to_string())
+            })
+            .collect()
+    };
+    let pair = |file: &str, path: &str| (file.to_string(), path.to_string());
+    assert_eq!(
+        symbols("PORT"),
+        [
+            pair("api.kson", "/server/port"),
+            pair("worker.kson", "/services/0/port"),
+            pair("api.kson", "/ports"),
+        ]
+    );
+    assert_eq!(symbols("dbpw"), [pair("api.kson", "/server/db_password")]);
+    assert_eq!(
+        symbols("services/0"),
+        [pair("worker.kson", "/services/0/port")]
+    );
+    assert_eq!(symbols("").len(), 6);
+
+    let symbol = &index.workspace_symbols("db_password")[0].1;
+    assert_eq!((symbol.start.line(), symbol.start.column()), (0, 20));
+}
+
+#[test]
+#[cfg(feature = "workspace")]
+fn test_find_references() {
+    use crate::config::ProjectConfig;
+    use crate::index::{ReferenceQuery, WorkspaceIndex};
+    use crate::path::KsonPath;
+
+    let root = TempDir::new("find-references");
+    let files = [
+        (".kson.kson", "references: { keys: [include] }"),
+        ("common.kson", "defaults: { port: 80, host: localhost }"),
+        (
+            "api.kson",
+            "port: { include: 'common.kson#/defaults/port' }\nall: { include: 'common.kson' }",
+        ),
+        (
+            "worker.kson",
+            "server: { port: 81, include: 'common.kson#/defaults' }",
+        ),
+    ];
+    for (file, text) in files {
+        std::fs::write(root.join(file), text).unwrap();
+    }
+    let config = ProjectConfig::discover(&root).unwrap().unwrap();
+    let index = WorkspaceIndex::build(&root, config).unwrap();
+
+    let found = |query: ReferenceQuery| -> Vec<(String, String)> {
+        index
+            .find_references(&query)
+            .into_iter()
+            .map(|usage| {
+                let file = usage
+                    .file
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned();
+                (file, usage.path.to_string())
+            })
+            .collect()
+    };
+    let pair = |file: &str, path: &str| (file.to_string(), path.to_string());
+    assert_eq!(
+        found(ReferenceQuery::Key("port".to_string())),
+        [
+            pair("api.kson", "/port"),
+            pair("common.kson", "/defaults/port"),
+            pair("worker.kson", "/server/port"),
+        ]
+    );
+    assert_eq!(
+        found(ReferenceQuery::Path("/*/port".parse().unwrap())),
+        [
+            pair("common.kson", "/defaults/port"),
+            pair("worker.kson", "/server/port")
+        ]
+    );
+    assert_eq!(
+        found(ReferenceQuery::Definition {
+            file: root.join("common.kson"),
+            path: KsonPath::parse("/defaults/port").unwrap(),
+        }),
+        [pair("api.kson", "/port/include")]
+    );
+    assert_eq!(
+        found(ReferenceQuery::Definition {
+            file: root.join("common.kson"),
+            path: KsonPath::parse("/defaults").unwrap(),
+        }),
+        [
+            pair("api.kson", "/port/include"),
+            pair("worker.kson", "/server/include")
+        ]
+    );
+
+    let searched = |query: &str| -> Vec<(String, String)> {
+        index
+            .search(&query.parse().unwrap())
+            .into_iter()
+            .map(|usage| {
+                let file = usage
+                    .file
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned();
+                (file, usage.path.to_string())
+            })
+            .collect()
+    };
+    assert_eq!(
+        searched("$.**.port"),
+        [
+            pair("api.kson", "/port"),
+            pair("common.kson", "/defaults/port"),
+            pair("worker.kson", "/server/port"),
+        ]
+    );
+    assert_eq!(
+        searched("$[?(@.port == 80)]"),
+        [pair("common.kson", "/defaults")]
+    );
+    let usage = &index.search(&"server.port".parse().unwrap())[0];
+    assert_eq!(
+        (usage.start.line(), usage.start.column(), usage.end.column()),
+        (0, 16, 18)
+    );
+}
+
+#[test]
+fn test_value_rewrite() {
+    use crate::value::{Map, Value};
+
+    let mut value = Value::Object(Map::from_iter([
+        ("host", Value::from("${HOST}")),
+        (
+            "database",
+            Value::Object(Map::from_iter([
+                ("user", Value::from("admin")),
+                ("password", Value::from("${DB_PASSWORD}")),
+            ])),
+        ),
+        (
+            "ports",
+            Value::Array(vec![Value::Integer(80), Value::from("${PORT}")]),
+        ),
+    ]));
+
+    let secrets = [
+        ("HOST", "db.internal"),
+        ("DB_PASSWORD", "hunter2"),
+        ("PORT", "8080"),
+    ];
+    let mut visited = Vec::new();
+    value.rewrite(|path, value| {
+        visited.push(path.to_string());
+        let Value::String(text) = value else {
+            return None;
+        };
+        let name = text.strip_prefix("${")?.strip_suffix('}')?;
+        let (_, secret) = secrets.iter().find(|(key, _)| *key == name)?;
+        Some(Value::from(*secret))
+    });
+
+    assert_eq!(
+        value,
+        Value::Object(Map::from_iter([
+            ("host", Value::from("db.internal")),
+            (
+                "database",
+                Value::Object(Map::from_iter([
+                    ("user", Value::from("admin")),
+                    ("password", Value::from("hunter2")),
+                ])),
+            ),
+            (
+                "ports",
+                Value::Array(vec![Value::Integer(80), Value::from("8080")])
+            ),
+        ]))
+    );
+    assert_eq!(
+        visited,
+        [
+            "",
+            "/host",
+            "/database",
+            "/database/user",
+            "/database/password",
+            "/ports",
+            "/ports/0",
+            "/ports/1"
+        ]
+    );
+
+    // Replacements are not traversed
+    let mut value = Value::Array(vec![Value::Null]);
+    value.rewrite(|path, _| {
+        path.is_root()
+            .then(|| Value::Array(vec![Value::Null, Value::Null]))
+    });
+    assert_eq!(value, Value::Array(vec![Value::Null, Value::Null]));
+}
+
+#[test]
+fn test_value_query() {
+    use crate::query::{PathQuery, QueryError, QueryErrorKind};
+    use crate::value::{Map, Value};
+
+    let server = |name: &str, enabled: bool, port: i64| {
+        Value::Object(Map::from_iter([
+            ("name", Value::from(name)),
+            ("enabled", Value::Bool(enabled)),
+            ("port", Value::Integer(port)),
+        ]))
+    };
+    let value = Value::Object(Map::from_iter([
+        (
+            "servers",
+            Value::Array(vec![
+                server("alpha", true, 80),
+                server("beta", false, 8080),
+                server("gamma", true, 8080),
+            ]),
+        ),
+        (
+            "backup",
+            Value::Object(Map::from_iter([("name", Value::from("delta"))])),
+        ),
+        ("odd key.with[chars]", Value::Null),
+    ]));
+
+    let query = |query: &str| -> Vec<String> {
+        let query = PathQuery::parse(query).unwrap();
+        value
+            .query(&query)
+            .into_iter()
+            .map(|(path, value)| format!("{path} = {value:?}"))
+            .collect()
+    };
+
+    assert_eq!(
+        query("servers[1].name"),
+        ["/servers/1/name = String(\"beta\")"]
+    );
+    assert_eq!(
+        query("$.servers.2.port"),
+        ["/servers/2/port = Integer(8080)"]
+    );
+    assert_eq!(
+        query("servers[?(@.enabled == true)].name"),
+        [
+            "/servers/0/name = String(\"alpha\")",
+            "/servers/2/name = String(\"gamma\")"
+        ]
+    );
+    assert_eq!(
+        query("servers[?(@.port == 8080.0)].name"),
+        [
+            "/servers/1/name = String(\"beta\")",
+            "/servers/2/name = String(\"gamma\")"
+        ]
+    );
+    assert_eq!(
+        query("$.servers[?( @['name'] != 'beta' )].port"),
+        [
+            "/servers/0/port = Integer(80)",
+            "/servers/2/port = Integer(8080)"
+        ]
+    );
+    assert_eq!(
+        query("**.name"),
+        [
+            "/servers/0/name = String(\"alpha\")",
+            "/servers/1/name = String(\"beta\")",
+            "/servers/2/name = String(\"gamma\")",
+            "/backup/name = String(\"delta\")"
+        ]
+    );
+    assert_eq!(query("*.name"), ["/backup/name = String(\"delta\")"]);
+    assert_eq!(
+        query("['odd key.with[chars]']"),
+        ["/odd key.with[chars] = Null"]
+    );
+    assert_eq!(query("servers[7]"), Vec::<String>::new());
+    assert_eq!(query("$").len(), 1);
+
+    assert_eq!(
+        PathQuery::parse("servers[?(@.port == nope)]"),
+        Err(QueryError {
+            kind: QueryErrorKind::InvalidLiteral("nope".to_string()),
+            offset: 20
+        })
+    );
+    assert_eq!(
+        PathQuery::parse("servers..name").unwrap_err().kind,
+        QueryErrorKind::EmptyKey
+    );
+    assert_eq!(
+        PathQuery::parse("servers['name").unwrap_err().kind,
+        QueryErrorKind::UnexpectedEnd
+    );
+}
+
+#[test]
+fn test_path_escaping_round_trip() {
+    use crate::path::{KsonPath, PathSegment};
+    use crate::pointer::PointerError;
+    use crate::query::PathQuery;
+    use crate::value::{Map, Value};
+
+    let keys = [
+        "plain",
+        "with/slash",
+        "with~tilde",
+        "~1",
+        "dotted.key",
+        "[bracketed]",
+        "it's \"quoted\"",
+        "back\\slash",
+        "ünïcödé 🦀",
+        "$schema",
+        "*",
+        "**",
+        "007",
+        "",
+    ];
+    for key in keys {
+        let path = KsonPath::root().key(key).index(3).key("x");
+        let pointer = path.to_string();
+        assert_eq!(KsonPath::parse(&pointer), Ok(path.clone()), "{pointer}");
+        assert_eq!(pointer.parse::<KsonPath>().unwrap().to_string(), pointer);
+
+        let value = Value::Object(Map::from_iter([(
+            key,
+            Value::Array(vec![
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Object(Map::from_iter([("x", Value::from(key))])),
+            ]),
+        )]));
+        assert_eq!(
+            value.pointer(&pointer),
+            Some(&Value::from(key)),
+            "{pointer}"
+        );
+
+        let query = PathQuery::parse(&path.to_query()).unwrap();
+        let matches = value.query(&query);
+        assert_eq!(matches.len(), 1, "{}", path.to_query());
+        assert_eq!(matches[0].0, path);
+    }
+
+    insta::assert_snapshot!(
+        KsonPath::root().key("a/b").key("c~d").index(0).key("e.f").key("it's"),
+        @"/a~1b/c~0d/0/e.f/it's"
+    );
+    insta::assert_snapshot!(
+        KsonPath::root().key("a/b").key("c~d").index(0).key("e.f").key("it's").to_query(),
+        @r"$.a/b.c~d[0]['e.f']['it\'s']"
+    );
+
+    // Canonical array indices are parsed as indices, but still resolve object keys
+    assert_eq!(
+        KsonPath::parse("/0/10/01").unwrap().segments(),
+        [
+            PathSegment::Index(0),
+            PathSegment::Index(10),
+            PathSegment::Key("01".to_string())
+        ]
+    );
+    let value = Value::Object(Map::from_iter([("0", Value::Bool(true))]));
+    assert_eq!(value.pointer("/0"), Some(&Value::Bool(true)));
+    assert_eq!(value.pointer(""), Some(&value));
+
+    assert_eq!(KsonPath::parse("a/b"), Err(PointerError::BadStart('a')));
+    assert_eq!(KsonPath::parse("/a~"), Err(PointerError::IncompleteEscape));
+    assert_eq!(
+        KsonPath::parse("/a~2"),
+        Err(PointerError::InvalidEscape('2'))
+    );
+}
+
+#[test]
+fn test_value_metrics() {
+    use crate::metrics::{DocumentMetrics, NodeCounts};
+    use crate::value::{Map, Value};
+
+    let value = Value::Object(Map::from_iter([
+        ("name", Value::from("kson")),
+        (
+            "servers",
+            Value::Array(vec![
+                Value::Object(Map::from_iter([
+                    ("name", Value::from("α")),
+                    ("port", Value::Integer(80)),
+                ])),
+                Value::Object(Map::from_iter([
+                    ("name", Value::Null),
+                    ("load", Value::Decimal(0.5)),
+                    ("up", Value::Bool(true)),
+                ])),
+            ]),
+        ),
+        (
+            "query",
+            Value::Embed {
+                tag: Some("sql".to_string()),
+                content: "select 1\n".to_string(),
+            },
+        ),
+    ]));
+
+    let metrics = value.metrics();
+    assert_eq!(
+        metrics,
+        DocumentMetrics {
+            depth: 3,
+            nodes: NodeCounts {
+                objects: 3,
+                arrays: 1,
+                strings: 2,
+                integers: 1,
+                decimals: 1,
+                booleans: 1,
+                nulls: 1,
+                embeds: 1,
+            },
+            string_bytes: 4 + 2 + 9,
+            keys: [
+                ("load", 1),
+                ("name", 3),
+                ("port", 1),
+                ("query", 1),
+                ("servers", 1),
+                ("up", 1)
+            ]
+            .into_iter()
+            .map(|(key, count)| (key.to_string(), count))
+            .collect(),
+        }
+    );
+    assert_eq!(metrics.nodes.total(), 11);
+    assert_eq!(Value::Integer(1).metrics().depth, 0);
+    assert_eq!(value.depth(), 3);
+
+    let mut deep = Value::Null;
+    for _ in 0..1000 {
+        deep = Value::Array(vec![deep]);
+    }
+    assert_eq!(deep.depth(), 1000);
+}
+
+#[test]
+fn test_max_depth() {
+    use crate::depth::MaxDepth;
+    use crate::value::{Map, Value};
+
+    let value = Value::Object(Map::from_iter([
+        ("name", Value::from("kson")),
+        (
+            "servers",
+            Value::Array(vec![Value::Object(Map::from_iter([(
+                "ports",
+                Value::Array(vec![Value::Integer(80)]),
+            )]))]),
+        ),
+    ]));
+    assert_eq!(value.depth(), 4);
+    assert_eq!(MaxDepth::default().get(), 128);
+    assert_eq!(MaxDepth::new(4).check(&value), Ok(()));
+    let error = MaxDepth::new(3).check(&value).unwrap_err();
+    assert_eq!(error.path.to_string(), "/servers/0/ports/0");
+    assert_eq!(
+        error.to_string(),
+        "the value at `/servers/0/ports/0` is nested deeper than the limit of 3 levels"
+    );
+    assert!(MaxDepth::new(0).check(&Value::Integer(1)).is_ok());
+
+    let mut deep = Value::Null;
+    for _ in 0..1000 {
+        deep = Value::Array(vec![deep]);
+    }
+    assert_eq!(MaxDepth::DEFAULT.check(&deep).unwrap_err().path.len(), 129);
+}
+
+#[test]
+fn test_deeply_nested_values() {
+    use crate::merge::MergeStrategy;
+    use crate::patch::Patch;
+    use crate::value::{Map, Value, values_equal};
+    use std::collections::HashSet;
+
+    // Far deeper than the stack allows recursing, but values built in code can get there
+    let depth = 100_000;
+    let nested = |leaf: i64| {
+        let (mut array, mut object) = (Value::Integer(leaf), Value::Integer(leaf));
+        for _ in 0..depth {
+            array = Value::Array(vec![array]);
+            object = Value::Object(Map::from_iter([("a", object)]));
+        }
+        (array, object)
+    };
+    let (array, object) = nested(1);
+    let (other_array, other_object) = nested(2);
+
+    assert_eq!(array.depth(), depth);
+    assert_eq!(object.metrics().depth, depth);
+    assert_eq!(array.clone(), array);
+    assert_eq!(object, nested(1).1);
+    assert!(array < other_array && object < other_object);
+    assert!(values_equal(&object, &nested(1).1));
+    assert!(!values_equal(&array, &other_array));
+    assert_eq!(HashSet::from([array.clone(), nested(1).0]).len(), 1);
+    assert_eq!(object.canonical(), object);
+    assert_eq!(array.to_inline_kson().len(), 2 * depth + 1);
+    assert!(Patch::diff(&object, &nested(1).1).operations.is_empty());
+
+    let mut merged = object.clone();
+    merged.merge(other_object.clone(), MergeStrategy::default());
+    assert_eq!(merged, other_object);
+    assert_eq!(
+        Value::merge3(&object, &object, &other_object),
+        Ok(other_object)
+    );
+
+    let mut rewritten = array.clone();
+    rewritten.rewrite(|_, value| (*value == Value::Integer(1)).then(|| Value::Integer(2)));
+    assert_eq!(rewritten, other_array);
+    rewritten.retain_recursive(|_, value| *value != Value::Integer(2));
+    assert_eq!(rewritten.depth(), depth - 1);
+    let filtered = array.clone().filter_map_recursive(|_, value| Some(value));
+    assert_eq!(filtered.as_ref(), Some(&array));
+}
+
+#[test]
+fn test_wide_objects_equal() {
+    use crate::value::{Map, Value, values_equal};
+
+    // Too many properties to look each one up in the other object
+    let width = 100_000;
+    let wide = |keys: &mut dyn Iterator<Item = usize>, last: Value| {
+        let mut properties: Vec<_> = keys
+            .map(|key| (format!("key{key}"), Value::Integer(key as i64)))
+            .collect();
+        properties.push(("last".to_string(), last));
+        Value::Object(Map::from_entries(properties))
+    };
+    let object = wide(&mut (0..width), Value::Integer(1));
+    assert!(values_equal(
+        &object,
+        &wide(&mut (0..width).rev(), Value::Decimal(1.0))
+    ));
+    assert!(!values_equal(
+        &object,
+        &wide(&mut (0..width).rev(), Value::Integer(2))
+    ));
+    assert!(!values_equal(
+        &object,
+        &wide(&mut (1..=width), Value::Integer(1))
+    ));
+}
+
+#[test]
+fn test_kson_metrics() {
+    let metrics = Kson::metrics("a: { b: [1, 2] }\nc: 'xyz'").unwrap();
+    assert_eq!(metrics.depth, 3);
+    assert_eq!(metrics.nodes.total(), 6);
+    assert_eq!(metrics.string_bytes, 3);
+
+    let errors = Kson::metrics("key: [1, 2").unwrap_err();
+    assert!(!errors.is_empty());
+
+    // kson-lib rejects pathological nesting instead of building a tree too deep to walk
+    let deep = "[".repeat(100_000) + &"]".repeat(100_000);
+    assert!(Kson::metrics(&deep).is_err());
+}
+
+#[test]
+fn test_validate_enum_suggestions() {
+    use crate::schema::SchemaDiagnosticKind;
+    use crate::value::Value;
+
+    let schema = r#"
+        properties: {
+          level: { enum: [debug, info, warning, error] }
+          retries: { enum: [1, 2, 3] }
+        }
+    "#;
+    let document = r#"
+        level: warnin
+        retries: 3.0
+    "#;
+
+    let diagnostics = crate::schema::validate_enums(schema, document);
+    assert_eq!(diagnostics.len(), 1);
+    let diagnostic = &diagnostics[0];
+    assert_eq!(diagnostic.path.to_string(), "/level");
+    assert_eq!(diagnostic.start.line(), 1);
+    insta::assert_snapshot!(diagnostic.message, @"Value `warnin` is not one of the allowed values: `debug`, `info`, `warning`, `error`. Did you mean `warning`?");
+    assert_eq!(
+        diagnostic.kind,
+        SchemaDiagnosticKind::NotInEnum {
+            allowed: ["debug", "info", "warning", "error"]
+                .map(Value::from)
+                .to_vec(),
+            suggestions: vec![Value::from("warning")],
+        }
+    );
+}
+
+#[test]
+fn test_explain_compositions() {
+    use crate::schema::{CompositionKeyword, SchemaDiagnosticKind};
+
+    let schema = r#"
+        properties: {
+          port: { anyOf: [{ type: integer }, { '$ref': '#/definitions/named_port' }] }
+          mode: { oneOf: [{ type: string }, { enum: [fast, slow] }] }
+          tls: {
+            if: { properties: { enabled: { const: true } } }
+            then: { required: [cert] }
+          }
+        }
+        definitions: { named_port: { enum: [http, https] } }
+    "#;
+    let document = r#"
+        port: ftp
+        mode: fast
+        tls: { enabled: true }
+    "#;
+
+    let diagnostics = crate::schema::explain_compositions(schema, document);
+    let summary: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| format!("{}: {}", diagnostic.path, diagnostic.message))
+        .collect();
+    insta::assert_snapshot!(summary.join("\n"), @r#"
+    /port: Value matches none of the `anyOf` alternatives:
+    - #1 (`#/properties/port/anyOf/0`): Expected one of: integer, but got: string
+    - #2 (`#/properties/port/anyOf/1`): Value must be one of: "http", "https"
+    /mode: Value matches 2 of the `oneOf` alternatives (#1, #2), but must match exactly one
+    /tls: Value matches the `if` condition but fails `then`:
+    - then (`#/properties/tls/then`): Missing required properties: cert
+    "#);
+
+    let SchemaDiagnosticKind::CompositionFailed {
+        keyword, failures, ..
+    } = &diagnostics[0].kind
+    else {
+        panic!("expected a composition failure")
+    };
+    assert_eq!(*keyword, CompositionKeyword::AnyOf);
+    assert_eq!(failures.len(), 2);
+    assert_eq!(failures[1].index, Some(1));
+}
+
+#[test]
+fn test_schema_coverage() {
+    use crate::value::Value;
+
+    let schema = "{
+        properties: { protocol: { enum: [tcp, udp] }, port: { type: integer } },
+        oneOf: [{ required: [port] }, { required: [socket] }]
+    }";
+    let documents = [
+        "{ protocol: tcp, port: 80 }",
+        "{ protocol: tcp, socket: '/run/app.sock' }",
+        "key: [",
+    ];
+    let coverage = Kson::schema_coverage(schema, &documents).unwrap();
+    assert_eq!(coverage.documents, 2);
+    let counts: Vec<(String, String, usize)> = coverage
+        .constraints
+        .iter()
+        .map(|constraint| {
+            (
+                constraint.schema_path.to_string(),
+                constraint.kind.to_string(),
+                constraint.documents,
+            )
+        })
+        .collect();
+    let count = |path: &str, kind: &str, documents| (path.to_string(), kind.to_string(), documents);
+    assert_eq!(
+        counts,
+        [
+            count("/properties/protocol", "property `protocol`", 2),
+            count("/properties/protocol/enum/0", "enum value `tcp`", 2),
+            count("/properties/protocol/enum/1", "enum value `udp`", 0),
+            count("/properties/port", "property `port`", 1),
+            count("/oneOf/0", "`oneOf` branch #1", 1),
+            count("/oneOf/1", "`oneOf` branch #2", 1),
+        ]
+    );
+    assert_eq!(coverage.covered(), 5);
+    assert_eq!(coverage.fraction(), 5.0 / 6.0);
+
+    let report = coverage.to_value();
+    assert_eq!(report.pointer("/total"), Some(&Value::Integer(6)));
+    assert_eq!(
+        report.pointer("/constraints/2/value"),
+        Some(&Value::from("udp"))
+    );
+    let html = coverage.to_html();
+    assert!(html.contains("5 of 6 constraints (83.3%) exercised by 2 documents"));
+    assert!(html.contains("<tr class=\"uncovered\"><td>enum value `udp`</td>"));
+
+    assert!(Kson::schema_coverage("{", &documents).is_err());
+}
+
+#[test]
+fn test_validate_unique_items() {
+    use crate::schema::SchemaDiagnosticKind;
+
+    let schema = r#"
+        properties: {
+          servers: { type: array, uniqueItems: true }
+          tags: { uniqueItems: false }
+        }
+    "#;
+    let document = r#"
+        servers:
+          - { host: a, port: 80 }
+          - { host: b, port: 80 }
+          - { port: 80.0, host: a }
+          - { host: b, port: 80 }
+        tags: [x, x]
+    "#;
+
+    let diagnostics = crate::schema::validate_unique_items(schema, document);
+    let summary: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            format!(
+                "{} (line {}): {}",
+                diagnostic.path,
+                diagnostic.start.line() + 1,
+                diagnostic.message
+            )
+        })
+        .collect();
+    insta::assert_snapshot!(summary.join("\n"), @r"
+    /servers/2 (line 5): Item 2 is a duplicate of item 0, but items must be unique
+    /servers/3 (line 6): Item 3 is a duplicate of item 1, but items must be unique
+    ");
+
+    let SchemaDiagnosticKind::DuplicateItem {
+        first_path,
+        duplicate,
+        ..
+    } = &diagnostics[0].kind
+    else {
+        panic!("expected a duplicate item")
+    };
+    assert_eq!(first_path.to_string(), "/servers/0");
+    assert_eq!(*duplicate, 2);
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_remote_schema_resolver() {
+    use crate::remote::{FetchError, RemoteSchemaError, RemoteSchemaResolver};
+    use std::cell::Cell;
+
+    let fetches = Cell::new(0);
+    let fetcher = |url: &str| -> std::result::Result<String, FetchError> {
+        fetches.set(fetches.get() + 1);
+        match url {
+            "https://schemas.example.com/common.kson" => Ok(r#"
+                '$id': 'https://schemas.example.com/common.kson'
+                definitions: {
+                  port: { type: integer, maximum: 65535 }
+                  host: { '$ref': 'hosts.kson#/definitions/host' }
+                }
+            "#
+            .to_string()),
+            "https://schemas.example.com/hosts.kson" => {
+                Ok("definitions: { host: { type: string } }".to_string())
+            }
+            _ => Err(format!("unexpected fetch of {url}").into()),
+        }
+    };
+    let resolver =
+        RemoteSchemaResolver::with_fetcher(fetcher).allow("https://schemas.example.com/");
+
+    let schema = r#"
+        properties: {
+          port: { '$ref': 'https://schemas.example.com/common.kson#/definitions/port' }
+          host: { '$ref': 'https://schemas.example.com/common.kson#/definitions/host' }
+        }
+    "#;
+    let validator = resolver.parse_schema(schema).unwrap();
+    assert!(
+        validator
+            .validate("port: 8080\nhost: localhost", None)
+            .is_empty()
+    );
+    assert!(!validator.validate("port: 70000\nhost: 1", None).is_empty());
+
+    // Documents are cached across calls
+    resolver.bundle(schema).unwrap();
+    assert_eq!(fetches.get(), 2);
+
+    let disallowed = r#"'$ref': 'https://elsewhere.example.com/schema.kson'"#;
+    assert!(matches!(
+        resolver.bundle(disallowed),
+        Err(RemoteSchemaError::NotAllowed(url)) if url == "https://elsewhere.example.com/schema.kson"
+    ));
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_remote_schema_allowlist() {
+    use crate::remote::{FetchError, RemoteSchemaResolver};
+
+    let fetcher = |url: &str| -> std::result::Result<String, FetchError> {
+        Err(format!("unexpected fetch of {url}").into())
+    };
+    let resolver = RemoteSchemaResolver::with_fetcher(fetcher)
+        .allow("https://host.example.com/org/schemas/")
+        .allow("https://schemas.example.com")
+        .allow("not a url");
+
+    assert!(resolver.is_allowed("https://host.example.com/org/schemas/common.kson"));
+    assert!(resolver.is_allowed("https://host.example.com:443/org/schemas/a/b.kson"));
+    assert!(resolver.is_allowed("https://host.example.com/org/other/../schemas/common.kson"));
+    assert!(resolver.is_allowed("https://schemas.example.com/any.kson"));
+    for url in [
+        "https://host.example.com/org/schemas/../../other-org/x.json",
+        "https://host.example.com/org/schemas-evil/x.json",
+        "https://host.example.com:8443/org/schemas/x.json",
+        "http://host.example.com/org/schemas/x.json",
+        "https://schemas.example.com.evil.net/x.json",
+        "https://schemas.example.com@evil.net/x.json",
+        "https://user@schemas.example.com/x.json",
+        "not a url",
+    ] {
+        assert!(!resolver.is_allowed(url), "{url} is allowed");
+    }
+}
+
+#[test]
+fn test_validate_required_fixes() {
+    use crate::schema::{SchemaDiagnosticKind, TextEdit};
+
+    let schema = r#"
+        required: [name, replicas]
+        properties: {
+          name: { type: string }
+          replicas: { type: integer, default: 3 }
+          server: { '$ref': '#/definitions/server' }
+        }
+        definitions: {
+          server: {
+            required: [host, port]
+            properties: { host: { type: string }, port: { enum: [80, 443] } }
+          }
+        }
+    "#;
+    let fixes = |document: &str| -> Vec<(String, TextEdit)> {
+        crate::schema::validate_required(schema, document)
+            .into_iter()
+            .map(|diagnostic| match diagnostic.kind {
+                SchemaDiagnosticKind::MissingProperties { fix, .. } => {
+                    (diagnostic.path.to_string(), fix)
+                }
+                kind => panic!("unexpected diagnostic kind: {kind:?}"),
+            })
+            .collect()
+    };
+
+    let document = "server: { host: localhost }\nname: api";
+    let diagnostics = crate::schema::validate_required(schema, document);
+    assert_eq!(diagnostics.len(), 2);
+    insta::assert_snapshot!(diagnostics[0].message, @"Missing required properties: `replicas`");
+    let fixes_inline = fixes(document);
+    assert_eq!(fixes_inline[0].0, "");
+    assert_eq!(
+        fixes_inline[0].1.apply(document),
+        "server: { host: localhost }\nname: api\n'replicas': 3"
+    );
+    assert_eq!(fixes_inline[1].0, "/server");
+    assert_eq!(
+        fixes_inline[1].1.apply(document),
+        "server: { host: localhost, 'port': 80 }\nname: api"
+    );
+
+    // Properties can't be appended after a nested object without braces, which would take them in
+    let document = "name: api\nserver:\n  host: localhost\n";
+    let fixes_indented = fixes(document);
+    assert_eq!(
+        fixes_indented[0].1,
+        TextEdit {
+            start: (0, 0),
+            end: (0, 0),
+            new_text: "'replicas': 3\n".to_string(),
+        }
+    );
+    assert_eq!(
+        fixes_indented[1].1.apply(document),
+        "name: api\nserver:\n  host: localhost\n  'port': 80\n"
+    );
+    for (_, fix) in fixes_indented {
+        assert!(
+            Kson::analyze(&fix.apply(document), None)
+                .errors()
+                .is_empty()
+        );
+    }
+}
+
+#[test]
+fn test_validate_closed_world() {
+    use crate::pointer::PointerGlob;
+    use crate::schema::SchemaDiagnosticKind;
+
+    let schema = r#"
+        properties: {
+          name: { type: string }
+          server: { '$ref': '#/definitions/server' }
+          metadata: { type: object }
+          labels: { additionalProperties: { type: string } }
+        }
+        anyOf: [{ properties: { replicas: { type: integer } } }]
+        definitions: {
+          server: { properties: { host: { type: string }, port: { type: integer } } }
+        }
+    "#;
+    let document = r#"
+        name: api
+        replicas: 2
+        server: { host: localhost, prot: 8080 }
+        metadata: { anything: goes }
+        labels: { team: core }
+        x-owner: ops
+        debug: true
+    "#;
+
+    let diagnostics = crate::schema::validate_closed_world(schema, document, &[]);
+    let paths: Vec<String> = diagnostics.iter().map(|d| d.path.to_string()).collect();
+    assert_eq!(paths, ["/x-owner", "/debug", "/server/prot"]);
+    let typo = &diagnostics[2];
+    assert!(matches!(typo.severity, MessageSeverity::Error));
+    assert_eq!(typo.start.line(), 3);
+    insta::assert_snapshot!(typo.message, @"Property `prot` is not declared in the schema. Did you mean `port`?");
+    assert_eq!(
+        typo.kind,
+        SchemaDiagnosticKind::UndeclaredProperty {
+            declared: vec!["host".to_string(), "port".to_string()],
+            suggestions: vec!["port".to_string()],
+        }
+    );
+
+    let exceptions = [PointerGlob::parse("/x-*").unwrap()];
+    let diagnostics = crate::schema::validate_closed_world(schema, document, &exceptions);
+    let paths: Vec<String> = diagnostics.iter().map(|d| d.path.to_string()).collect();
+    assert_eq!(paths, ["/debug", "/server/prot"]);
+}
+
+#[test]
+fn test_diagnostic_limit() {
+    use crate::diagnostics::DiagnosticLimit;
+
+    let schema = r#"
+        properties: {
+          level: { enum: [debug, info] }
+          tags: { uniqueItems: true }
+        }
+    "#;
+    let document = "level: trace\ntags: [a, a, b, b]";
+    let all = crate::schema::validate(schema, document, DiagnosticLimit::All);
+    assert_eq!(all.len(), 3);
+    let first = crate::schema::validate(schema, document, DiagnosticLimit::FailFast);
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].message, all[0].message);
+    assert_eq!(
+        crate::schema::validate(schema, document, DiagnosticLimit::AtMost(2)).len(),
+        2
+    );
+
+    let Ok(validator) =
+        Kson::parse_schema("properties: { a: { type: string }, b: { type: string } }")
+    else {
+        panic!("expected a valid schema")
+    };
+    let validator = validator.schema_validator();
+    assert_eq!(
+        Kson::check("a: 1\nb: 2", Some(&validator), DiagnosticLimit::All).len(),
+        2
+    );
+    assert_eq!(
+        Kson::check("a: 1\nb: 2", Some(&validator), DiagnosticLimit::FailFast).len(),
+        1
+    );
+    // Validation is skipped for documents that fail to parse
+    let errors = Kson::check("a: [1, 2", Some(&validator), DiagnosticLimit::All);
+    assert!(
+        errors
+            .iter()
+            .all(|error| matches!(error.severity(), MessageSeverity::Error))
+    );
+    assert_eq!(DiagnosticLimit::default().max(), None);
+}
+
+#[test]
+fn test_kson_value_ref_borrows_from_input() {
+    use crate::borrowed::KsonValueRef;
+    use std::borrow::Cow;
+
+    let input = r#"
+name: kson
+'quoted key': 'plain quoted'
+escaped: "tab\there"
+list: [one, 2, 3.5, null]
+'it\'s': escaped key
+"#;
+    let value = KsonValueRef::parse(input).unwrap();
+    let KsonValueRef::Object(properties) = &value else {
+        panic!("expected object")
+    };
+    let keys: Vec<&str> = properties.iter().map(|(key, _)| key.as_ref()).collect();
+    assert_eq!(keys, ["name", "quoted key", "escaped", "list", "it's"]);
+    let borrowed: Vec<bool> = properties
+        .iter()
+        .map(|(key, _)| matches!(key, Cow::Borrowed(_)))
+        .collect();
+    assert_eq!(borrowed, [true, true, true, true, false]);
+    // Borrowed strings are slices of the input itself
+    let Some(KsonValueRef::String(Cow::Borrowed(name))) = value.get("name") else {
+        panic!("expected a borrowed string")
+    };
+    assert!(input.as_bytes().as_ptr_range().contains(&name.as_ptr()));
+
+    assert!(value.get("name").unwrap().is_borrowed());
+    assert_eq!(
+        value.get("quoted key").unwrap().as_str(),
+        Some("plain quoted")
+    );
+    assert!(value.get("quoted key").unwrap().is_borrowed());
+    assert_eq!(value.get("escaped").unwrap().as_str(), Some("tab\there"));
+    assert!(!value.get("escaped").unwrap().is_borrowed());
+
+    let owned: KsonValueRef<'static> = value.clone().into_owned();
+    assert_eq!(owned, value);
+    insta::assert_snapshot!(crate::value::Value::from(value).to_json(), @r#"
+    {
+      "name": "kson",
+      "quoted key": "plain quoted",
+      "escaped": "tab\there",
+      "list": [
+        "one",
+        2,
+        3.5,
+        null
+      ],
+      "it's": "escaped key"
+    }
+    "#);
+
+    assert!(KsonValueRef::parse("key: [1, 2").is_err());
+}
+
+#[test]
+fn test_lazy_document() {
+    use crate::lazy::LazyDocument;
+    use crate::path::KsonPath;
+    use crate::query::PathQuery;
+    use crate::value::Value;
+
+    let document = LazyDocument::parse(
+        r#"
+servers:
+  - name: alpha
+    enabled: true
+    tags: [a, b]
+  - name: beta
+    enabled: false
+settings: { retries: 3 }
+"#,
+    )
+    .unwrap();
+
+    assert!(!document.is_materialized(&KsonPath::root()));
+    assert_eq!(
+        document.pointer("/settings/retries"),
+        Some(Value::Integer(3))
+    );
+    assert!(document.is_materialized(&KsonPath::root().key("settings").key("retries")));
+    assert!(!document.is_materialized(&KsonPath::root().key("servers")));
+
+    let query = PathQuery::parse("servers[?(@.enabled == true)].name").unwrap();
+    let matches = document.query(&query);
+    assert_eq!(
+        matches,
+        [(
+            KsonPath::root().key("servers").index(0).key("name"),
+            Value::from("alpha")
+        )]
+    );
+    assert!(!document.is_materialized(&KsonPath::root().key("servers").index(1)));
+
+    // Descendants of materialized values are served from the cache
+    document.pointer("/servers/0").unwrap();
+    assert!(
+        document.is_materialized(
+            &KsonPath::root()
+                .key("servers")
+                .index(0)
+                .key("tags")
+                .index(1)
+        )
+    );
+    assert_eq!(
+        document.pointer("/servers/0/tags/1"),
+        Some(Value::from("b"))
+    );
+    assert_eq!(document.pointer("/servers/5"), None);
+}
+
+#[test]
+fn test_shared_document() {
+    use crate::path::KsonPath;
+    use crate::shared::SharedDocument;
+    use crate::value::Value;
+
+    let document = SharedDocument::parse(
+        r#"
+servers:
+  - name: alpha
+    tags: [a, b]
+  - name: beta
+settings: { retries: 3, 'a/b~c': escaped }
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(document.pointer(""), Some(document.value()));
+    assert_eq!(
+        document.pointer("/settings/retries"),
+        Some(&Value::Integer(3))
+    );
+    assert_eq!(
+        document.pointer("/settings/a~1b~0c"),
+        Some(&Value::from("escaped"))
+    );
+    assert_eq!(
+        document.get_path(
+            &KsonPath::root()
+                .key("servers")
+                .index(0)
+                .key("tags")
+                .index(1)
+        ),
+        Some(&Value::from("b"))
+    );
+    assert_eq!(document.pointer("/servers/2"), None);
+    assert_eq!(document.pointer("/settings/retries/0"), None);
+
+    let shared = document.clone();
+    assert!(shared.ptr_eq(&document));
+    let name = std::thread::spawn(move || shared.pointer("/servers/1/name").cloned())
+        .join()
+        .unwrap();
+    assert_eq!(name, Some(Value::from("beta")));
+    assert_eq!(SharedDocument::new(document.value().clone()), document);
+}
+
+#[test]
+fn test_shared_document_typed_getters() {
+    use crate::path::KsonPath;
+    use crate::shared::{Path, SharedDocument};
+
+    let flags = SharedDocument::parse(
+        "checkout: { new_flow: true, rollout: 25, ratio: 0.5, owner: payments, teams: [web] }",
+    )
+    .unwrap();
+    let path = |pointer: &str| Path::parse(pointer).unwrap();
+
+    assert_eq!(flags.get_bool(&path("/checkout/new_flow")), Some(true));
+    assert_eq!(flags.get_i64(&path("/checkout/rollout")), Some(25));
+    assert_eq!(flags.get_f64(&path("/checkout/rollout")), Some(25.0));
+    assert_eq!(flags.get_f64(&path("/checkout/ratio")), Some(0.5));
+    assert_eq!(flags.get_str(&path("/checkout/owner")), Some("payments"));
+    assert_eq!(flags.get_str(&path("/checkout/teams")), None);
+    assert_eq!(flags.get_str(&path("/checkout/teams/0")), Some("web"));
+    assert_eq!(flags.get_i64(&path("/checkout/ratio")), None);
+    assert_eq!(flags.get_bool(&path("/checkout/missing")), None);
+
+    let compiled = Path::from(&KsonPath::root().key("checkout").key("owner"));
+    assert_eq!(compiled, path("/checkout/owner"));
+    assert_eq!(compiled.to_string(), "/checkout/owner");
+    assert_eq!(flags.get(&compiled), flags.pointer("/checkout/owner"));
+    assert!(Path::parse("checkout").is_err());
+}
+
+#[test]
+fn test_format_parallel_matches_serial() {
+    use crate::format::Formatter;
+
+    let plain = r#"
+# The first section
+name: kson
+nested: { list: [1, 2.5, { deep: true }], inner: { key: value } }
+
+# About the script
+script: $sh
+  echo "hello"
+  $$
+tags: [a, b]
+last: { x: 1 } # trailing
+"#;
+    let json = r#"{
+  "name": "kson",
+  "nested": { "list": [1, 2.5, { "deep": true }], "inner": { "key": "value" } },
+  "tags": ["a", "b"],
+  "last": { "x": 1 }
+}
+"#;
+    let styles = [
+        FormattingStyle::Plain,
+        FormattingStyle::Delimited,
+        FormattingStyle::Compact,
+        FormattingStyle::Classic,
+    ];
+    for input in [plain, json] {
+        for style in styles {
+            let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+            let options = FormatOptions::new(indent, style, &[]);
+            assert_eq!(
+                Formatter::new(options.clone()).parallel(0).format(input),
+                Kson::format(input, options),
+            );
+        }
+    }
+}
+
+#[test]
+fn test_differential_against_serde_json() {
+    use crate::differential::{Divergence, compare};
+
+    let agreeing = [
+        r#"{"a": [1, -2.5e3, true, null], "b": {"c": "é\n"}}"#,
+        "[]",
+        "9007199254740993",
+        r#""😀""#,
+    ];
+    for input in agreeing {
+        assert_eq!(compare(input.as_bytes()), None, "{input}");
+    }
+    assert_eq!(compare(b"[1, 2,]"), Some(Divergence::Accepted));
+    assert_eq!(compare(b"{"), None);
+}
+
+#[test]
+fn test_differential_compare_dir() {
+    use crate::differential::{Divergence, SuiteOptions, compare_dir};
+
+    let dir = TempDir::new("differential");
+    let files = [
+        ("n_trailing_comma.json", "[1, 2,]"),
+        ("n_unclosed.json", "{"),
+        ("y_array.json", "[1, 2]"),
+        ("n_trailing_comma_object.json", "{\"a\": 1,}"),
+    ];
+    for (name, contents) in files {
+        std::fs::write(dir.join(name), contents).unwrap();
+    }
+
+    let all = compare_dir(&dir, &SuiteOptions::default().threads(2)).unwrap();
+    assert_eq!(
+        all,
+        [
+            ("n_trailing_comma.json".to_string(), Divergence::Accepted),
+            (
+                "n_trailing_comma_object.json".to_string(),
+                Divergence::Accepted
+            ),
+        ]
+    );
+    let filtered = SuiteOptions::default()
+        .filter("_array")
+        .filter("unclosed")
+        .timings(true);
+    assert_eq!(compare_dir(&dir, &filtered).unwrap(), []);
+}
+
+/// Runs the differential harness over the files of JSONTestSuite's `test_parsing` directory, which the
+/// Gradle build checks out (the location can be overridden with `KSON_JSON_TEST_SUITE`, and the test is
+/// skipped without it, see [`suite_dir`](crate::differential::suite_dir)). `y_` files are valid JSON,
+/// `n_` files invalid JSON (some of which KSON accepts as extensions) and `i_` files are up to the
+/// parser. Set `KSON_SUITE_FILTER` to run only some of the files, see
+/// [`SuiteOptions::from_env`](crate::differential::SuiteOptions::from_env).
+#[test]
+fn test_differential_json_test_suite() {
+    use crate::differential::{Divergence, SuiteOptions, compare_dir, suite_dir};
+
+    // The same exceptions as `JsonTestSuiteEditList` on the Kotlin side
+    const ACCEPTED_FOR_SUPERSET: &[&str] = &[
+        "n_array_1_true_without_comma.json",
+        "n_array_colon_instead_of_comma.json",
+        "n_array_comma_and_number.json",
+        "n_array_extra_comma.json",
+        "n_array_inner_array_no_comma.json",
+        "n_array_missing_value.json",
+        "n_array_number_and_comma.json",
+        "n_incomplete_false.json",
+        "n_incomplete_null.json",
+        "n_incomplete_true.json",
+        "n_number_-01.json",
+        "n_number_1_000.json",
+        "n_number_Inf.json",
+        "n_number_minus_space_1.json",
+        "n_number_NaN.json",
+        "n_number_with_leading_zero.json",
+        "n_object_bad_value.json",
+        "n_number_infinity.json",
+        "n_number_neg_int_starting_with_zero.json",
+        "n_object_lone_continuation_byte_in_key_and_trailing_comma.json",
+        "n_object_trailing_comma.json",
+        "n_object_unquoted_key.json",
+        "n_object_with_trailing_garbage.json",
+        "n_string_accentuated_char_no_quotes.json",
+        "n_structure_ascii-unicode-identifier.json",
+        "n_structure_unicode-identifier.json",
+        "n_string_single_string_no_double_quotes.json",
+        "n_string_unescaped_newline.json",
+        "n_structure_capitalized_True.json",
+        "n_structure_trailing_#.json",
+        "n_object_key_with_single_quotes.json",
+        "n_object_single_quote.json",
+        "n_string_single_quote.json",
+        "n_string_unescaped_tab.json",
+    ];
+    const NEEDS_INVESTIGATION: &[&str] = &[
+        "y_object_duplicated_key.json",
+        "y_object_duplicated_key_and_value.json",
+    ];
+
+    let Some(dir) = suite_dir(
+        "KSON_JSON_TEST_SUITE",
+        "../../buildSrc/support/jsonsuite/JSONTestSuite/test_parsing",
+    ) else {
+        return;
+    };
+
+    let unexpected: Vec<_> = compare_dir(&dir, &SuiteOptions::from_env())
+        .unwrap()
+        .into_iter()
+        .filter(|(name, divergence)| {
+            if NEEDS_INVESTIGATION.contains(&name.as_str()) {
+                return false;
+            }
+            match divergence {
+                Divergence::Accepted => {
+                    !name.starts_with("i_") && !ACCEPTED_FOR_SUPERSET.contains(&name.as_str())
+                }
+                Divergence::Rejected(_) => !name.starts_with("i_"),
+                Divergence::Mismatch { .. } => true,
+            }
+        })
+        .collect();
+    assert!(unexpected.is_empty(), "{unexpected:#?}");
+}
+
+/// Runs the differential harness over the fuzzing corpora listed in `KSON_FUZZ_CORPUS` (separated like
+/// `PATH`), if any. KSON extensions are expected there, so only rejected JSON and mismatches fail.
+#[test]
+fn test_differential_fuzz_corpus() {
+    use crate::differential::{Divergence, SuiteOptions, compare_dir};
+
+    let Some(corpora) = std::env::var_os("KSON_FUZZ_CORPUS") else {
+        return;
+    };
+    for dir in std::env::split_paths(&corpora) {
+        let unexpected: Vec<_> = compare_dir(&dir, &SuiteOptions::from_env())
+            .unwrap()
+            .into_iter()
+            .filter(|(_, divergence)| !matches!(divergence, Divergence::Accepted))
+            .collect();
+        assert!(unexpected.is_empty(), "{}: {unexpected:#?}", dir.display());
+    }
+}
+
+#[test]
+fn test_value_generator_is_deterministic() {
+    use crate::roundtrip::ValueGenerator;
+    use crate::value::Value;
+
+    let first: Vec<_> = ValueGenerator::new(7).take(20).collect();
+    let second: Vec<_> = ValueGenerator::new(7).take(20).collect();
+    assert_eq!(first, second);
+    assert_ne!(first, ValueGenerator::new(8).take(20).collect::<Vec<_>>());
+
+    let flat = ValueGenerator::new(7).max_depth(0).take(50);
+    assert!(
+        flat.into_iter()
+            .all(|value| !matches!(value, Value::Array(_) | Value::Object(_)))
+    );
+}
+
+#[test]
+fn test_round_trips() {
+    use crate::roundtrip::{RoundTripError, ValueGenerator, assert_round_trips, check_round_trips};
+
+    for value in ValueGenerator::new(42).take(200) {
+        assert_round_trips(&value.to_kson());
+    }
+    assert_round_trips("# a comment\nkey: $sh\n  echo hi\n  $$\n");
+    assert!(matches!(
+        check_round_trips("key: [1, 2"),
+        Err(RoundTripError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_document_recipes() {
+    use crate::roundtrip::{DocumentRecipe, Syntax, ValueGenerator, assert_round_trips};
+    use crate::value::Value;
+
+    let syntaxes = [
+        Syntax::Inline,
+        Syntax::Plain,
+        Syntax::Delimited,
+        Syntax::Compact,
+        Syntax::Classic,
+    ];
+    for (value, syntax) in ValueGenerator::new(7)
+        .zip(syntaxes.into_iter().cycle())
+        .take(50)
+    {
+        let recipe = DocumentRecipe { value, syntax };
+        let document = recipe.render();
+        assert_eq!(Value::parse(&document).unwrap(), recipe.value, "{document}");
+        assert_round_trips(&document);
+    }
+
+    let recipe = DocumentRecipe {
+        value: crate::kson!({ "it's": [1, 2.5, null] }),
+        syntax: Syntax::Inline,
+    };
+    assert_eq!(recipe.render(), r#"{"it's": [1, 2.5, null]}"#);
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn test_arbitrary_document_recipes() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    use crate::roundtrip::DocumentRecipe;
+    use crate::value::Value;
+
+    for seed in 0..50u8 {
+        let input: Vec<u8> = (0..200u8)
+            .map(|byte| byte.wrapping_mul(31).wrapping_add(seed))
+            .collect();
+        let recipe = DocumentRecipe::arbitrary(&mut Unstructured::new(&input)).unwrap();
+        let document = recipe.render();
+        assert_eq!(Value::parse(&document).unwrap(), recipe.value, "{document}");
+    }
+}
+
+#[test]
+fn test_mutations() {
+    use crate::mutation::{Expectation, MutationKind, mutate, write_corpus};
+    use crate::value::Value;
+
+    let document = "server: { host: 'example.com', ports: [80, 443] }";
+    let mutants = mutate(document);
+    for mutant in &mutants {
+        assert_eq!(mutant.check(), Ok(()), "{}: {}", mutant.name, mutant.input);
+    }
+
+    let flipped = &mutants[mutants
+        .iter()
+        .position(|mutant| mutant.kind == MutationKind::FlipQuotes)
+        .unwrap()];
+    assert_eq!(flipped.name, "flip-quotes-0");
+    assert_eq!(
+        flipped.input,
+        r#"server: { host: "example.com", ports: [80, 443] }"#
+    );
+    assert_eq!(
+        flipped.expected,
+        Expectation::Accept(Value::parse(document).unwrap())
+    );
+    let closers: Vec<&str> = mutants
+        .iter()
+        .filter(|mutant| mutant.kind == MutationKind::MismatchCloser)
+        .map(|mutant| mutant.input.as_str())
+        .collect();
+    assert_eq!(
+        closers,
+        [
+            "server: { host: 'example.com', ports: [80, 443} }",
+            "server: { host: 'example.com', ports: [80, 443] ]",
+        ]
+    );
+    assert!(
+        mutants
+            .iter()
+            .any(|mutant| mutant.kind == MutationKind::Unicode && mutant.input.contains('😀'))
+    );
+    assert!(
+        mutants
+            .iter()
+            .filter(|mutant| mutant.kind == MutationKind::Truncate)
+            .all(|mutant| document.starts_with(&mutant.input))
+    );
+    assert!(mutate("server: {").is_empty());
+
+    let root = TempDir::new("mutation");
+    let cases = root.join("cases");
+    std::fs::create_dir_all(&cases).unwrap();
+    std::fs::write(cases.join("server.kson"), document).unwrap();
+    std::fs::write(cases.join("broken.kson"), "server: {").unwrap();
+    let out = root.join("mutants");
+    assert_eq!(write_corpus(&cases, &out).unwrap(), mutants.len());
+    assert_eq!(
+        std::fs::read_to_string(out.join("y_server_flip-quotes-0.kson")).unwrap(),
+        flipped.input
+    );
+    assert!(out.join("n_server_truncate-0.kson").is_file());
+}
+
+#[cfg(feature = "jsonschema")]
+#[test]
+fn test_jsonschema_backend() {
+    use crate::jsonschema_backend::{JsonSchemaBackend, JsonSchemaError};
+    use crate::path::KsonPath;
+    use crate::schema::SchemaDiagnosticKind;
+
+    let backend = JsonSchemaBackend::parse(
+        r#"
+        "$schema": "https://json-schema.org/draft/2020-12/schema"
+        type: object
+        properties:
+          ports:
+            type: array
+            prefixItems: [{ type: integer }]
+        "#,
+    )
+    .unwrap();
+
+    let document = Kson::analyze("ports: [web, 8080]", None)
+        .kson_value()
+        .unwrap();
+    let diagnostics = backend.validate(&document);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].path, KsonPath::root().key("ports").index(0));
+    assert_eq!(diagnostics[0].start.column(), 8);
+    assert_eq!(
+        diagnostics[0].kind,
+        SchemaDiagnosticKind::Violation {
+            schema_path: KsonPath::parse("/properties/ports/prefixItems/0/type").unwrap()
+        }
+    );
+    assert!(!backend.is_valid(&document.to_value()));
+    let valid = Kson::analyze("ports: [8080, web]", None)
+        .kson_value()
+        .unwrap();
+    assert!(backend.is_valid(&valid.to_value()));
+
+    assert!(matches!(
+        JsonSchemaBackend::parse("type: [object"),
+        Err(JsonSchemaError::Parse(_))
+    ));
+    assert!(matches!(
+        JsonSchemaBackend::parse("type: 12"),
+        Err(JsonSchemaError::InvalidSchema(_))
+    ));
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_serde_json_conversions() {
+    use crate::depth::MaxDepth;
+    use crate::value::Value;
+
+    let json: serde_json::Value = serde_json::from_str(
+        r#"{"name": "kson", "size": 18446744073709551615, "tags": [1, 2.5, null]}"#,
+    )
+    .unwrap();
+    let value = Value::from(&json);
+    assert_eq!(value.pointer("/name"), Some(&Value::from("kson")));
+    assert_eq!(
+        value.pointer("/size"),
+        Some(&Value::Decimal(u64::MAX as f64))
+    );
+    assert_eq!(value.pointer("/tags/1"), Some(&Value::Decimal(2.5)));
+
+    let document = Kson::analyze(
+        "name: kson\nquery: %sql\n  select 1\n  %%\ntags: [1, 2.5, null]",
+        None,
+    )
+    .kson_value()
+    .unwrap();
+    let json = serde_json::Value::try_from(&document).unwrap();
+    assert_eq!(
+        json.get("query"),
+        Some(&serde_json::Value::from("select 1\n"))
+    );
+    assert_eq!(
+        Value::from(json).pointer("/tags"),
+        document.to_value().pointer("/tags")
+    );
+
+    // Values built in code may hold decimals that JSON can't represent
+    assert_eq!(
+        serde_json::Value::from(&Value::Array(vec![Value::Decimal(f64::NAN)])),
+        serde_json::Value::Array(vec![serde_json::Value::Null])
+    );
+
+    let mut nested = serde_json::Value::from("leaf");
+    for key in ["b", "a"] {
+        nested = serde_json::Value::Object(serde_json::Map::from_iter([(key.to_string(), nested)]));
+        nested = serde_json::Value::Array(vec![nested]);
+    }
+    assert_eq!(Value::from(&nested).depth(), 4);
+    assert_eq!(serde_json::Value::from(&Value::from(&nested)), nested);
+    assert!(Value::from_json_with(&nested, MaxDepth::new(4)).is_ok());
+    let error = Value::from_json_with(&nested, MaxDepth::new(3)).unwrap_err();
+    assert_eq!(error.path.to_string(), "/0/a/0/b");
+}
+
+#[test]
+fn test_kson_errors() {
+    use crate::error::{KsonErrors, Severity};
+    use crate::value::Value;
+
+    let errors = Value::parse("key: [1, 2").unwrap_err();
+    assert_eq!(errors.source(), "key: [1, 2");
+    assert!(errors.has_errors());
+    assert_eq!(errors.errors()[0].severity(), Severity::Error);
+    assert!(errors.errors()[0].span().end <= errors.source().len());
+    assert_eq!(
+        Value::parse("key: 1").unwrap(),
+        Value::Object([("key", Value::Integer(1))].into_iter().collect())
+    );
+
+    let diagnostics = crate::schema::validate_enums("enum: [red, green]", "gren");
+    let errors = KsonErrors::from_schema_diagnostics("gren", &diagnostics);
+    assert!(!errors.has_errors());
+    assert_eq!(errors.errors()[0].span(), 0..4);
+    assert_eq!(
+        errors.errors()[0].help(),
+        Some(r#"allowed values: "red", "green""#)
+    );
+
+    #[cfg(feature = "miette")]
+    {
+        use miette::Diagnostic;
+        assert!(errors.source_code().is_some());
+        assert_eq!(errors.severity(), Some(miette::Severity::Warning));
+        assert_eq!(errors.labels().unwrap().count(), 1);
+    }
+}
+
+#[test]
+fn test_kson_errors_context() {
+    use crate::value::Value;
+
+    let errors = Value::parse("name: kson\nports: [80, 443")
+        .unwrap_err()
+        .with_source_name("deploy.kson")
+        .with_context("loading the deployment configuration")
+        .with_context("starting the server");
+    assert_eq!(errors.source_name(), Some("deploy.kson"));
+    assert_eq!(
+        errors.context(),
+        [
+            "loading the deployment configuration",
+            "starting the server"
+        ]
+    );
+    let message = errors.to_string();
+    assert!(
+        message.starts_with("starting the server: loading the deployment configuration: "),
+        "{message}"
+    );
+    assert!(message.contains("deploy.kson"), "{message}");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_transcode() {
+    use crate::de::{TranscodeError, transcode};
+
+    let input = r#"
+        name: kson
+        ports: [80, 443]
+        ratio: 0.5
+        script: %sh
+          echo hello
+          %%
+        extra: null
+    "#;
+    let mut json = Vec::new();
+    transcode(input, &mut serde_json::Serializer::new(&mut json)).unwrap();
+    assert_eq!(
+        String::from_utf8(json).unwrap(),
+        r#"{"name":"kson","ports":[80,443],"ratio":0.5,"script":"echo hello\n","extra":null}"#
+    );
+
+    let mut json = Vec::new();
+    let error = transcode("ports: [80", &mut serde_json::Serializer::new(&mut json)).unwrap_err();
+    assert!(matches!(error, TranscodeError::Parse(errors) if errors.has_errors()));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_from_str() {
+    use crate::de::from_str;
+    use std::collections::BTreeMap;
+
+    let ports: BTreeMap<String, Vec<u16>> = from_str("http: [80, 8080]\nhttps: [443]").unwrap();
+    assert_eq!(ports["http"], [80, 8080]);
+    assert_eq!(from_str::<Option<String>>("null").unwrap(), None);
+    assert_eq!(
+        from_str::<std::result::Result<u8, String>>("Err: failed").unwrap(),
+        Err("failed".to_string())
+    );
+
+    // Errors point at the offending value
+    let error = from_str::<BTreeMap<String, u8>>("a: 1\nb: 300").unwrap_err();
+    assert_eq!((error.line(), error.column()), (Some(1), Some(3)));
+    assert!(error.to_string().ends_with(" at line 2, column 4"));
+    let error = from_str::<std::result::Result<u8, String>>("a: 1\nb: 2").unwrap_err();
+    assert_eq!((error.line(), error.column()), (Some(0), Some(0)));
+
+    let error = from_str::<Vec<u8>>("[1, 2").unwrap_err();
+    assert!(error.line().is_some());
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_from_kson_value() {
+    use crate::Kson;
+    use crate::de::from_kson_value;
+    use serde::Deserialize;
+    use serde::de::IntoDeserializer;
+    use std::collections::BTreeMap;
+
+    let analysis = Kson::analyze(
+        "name: api\nlimits: { cpu: 2, memory: 512 }\nports: [80, x]",
+        None,
+    );
+    let root = analysis.kson_value().unwrap();
+    let limits: BTreeMap<String, u16> = from_kson_value(root.pointer("/limits").unwrap()).unwrap();
+    assert_eq!(
+        limits,
+        BTreeMap::from([("cpu".into(), 2), ("memory".into(), 512)])
+    );
+    let name = String::deserialize(root.pointer("/name").unwrap().into_deserializer()).unwrap();
+    assert_eq!(name, "api");
+
+    // Errors still point at the offending value in the document
+    let error = from_kson_value::<Vec<u16>>(root.pointer("/ports").unwrap()).unwrap_err();
+    assert_eq!((error.line(), error.column()), (Some(2), Some(12)));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serialize() {
+    use crate::depth::MaxDepth;
+    use crate::format::QuoteStyle;
+    use crate::ser::{to_string, to_string_with, to_value, to_value_with};
+    use crate::value::{Map, Value};
+    use serde::ser::{SerializeStruct, Serializer};
+
+    struct Server {
+        host: &'static str,
+        ports: Vec<u16>,
+        tls: Option<bool>,
+    }
+
+    impl serde::Serialize for Server {
+        fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            let mut server = serializer.serialize_struct("Server", 3)?;
+            server.serialize_field("host", self.host)?;
+            server.serialize_field("ports", &self.ports)?;
+            server.serialize_field("tls", &self.tls)?;
+            server.end()
+        }
+    }
+
+    let server = Server {
+        host: "localhost",
+        ports: vec![80, 443],
+        tls: None,
+    };
+    assert_eq!(
+        to_value(&server).unwrap(),
+        Value::Object(Map::from_iter([
+            ("host", Value::from("localhost")),
+            (
+                "ports",
+                Value::Array(vec![Value::Integer(80), Value::Integer(443)])
+            ),
+            ("tls", Value::Null),
+        ]))
+    );
+    let map = std::collections::BTreeMap::from([(1, 'a'), (2, 'b')]);
+    assert_eq!(
+        to_value(&map).unwrap(),
+        Value::Object(Map::from_iter([
+            ("1", Value::from("a")),
+            ("2", Value::from("b"))
+        ]))
+    );
+    assert!(to_value(&u64::MAX).is_err());
+
+    let nested = vec![vec![vec![1]]];
+    assert_eq!(to_value_with(&nested, MaxDepth::new(3)).unwrap().depth(), 3);
+    assert_eq!(
+        to_value_with(&nested, MaxDepth::new(2))
+            .unwrap_err()
+            .message(),
+        "the value at `/0/0/0` is nested deeper than the limit of 2 levels"
+    );
+
+    let options = FormatOptions::new(
+        IndentType::Spaces(indent_type::Spaces::new(2)),
+        FormattingStyle::Plain,
+        &[],
+    );
+    let kson = to_string(&server, &options).unwrap();
+    assert_eq!(kson, "host: localhost\nports:\n  - 80\n  - 443\ntls: null");
+    assert_eq!(
+        Kson::analyze(&kson, None).kson_value().unwrap().to_value(),
+        to_value(&server).unwrap()
+    );
+
+    let formatter = FormatOptions::builder()
+        .quote_style(QuoteStyle::Double)
+        .build();
+    assert_eq!(
+        to_string_with(&vec!["local host", "it's"], &formatter).unwrap(),
+        "- \"local host\"\n- \"it's\""
+    );
+}
+
+#[test]
+fn test_catch_internal_errors() {
+    use crate::error::{KsonErrors, catch_internal_errors};
+
+    assert_eq!(catch_internal_errors(|| 42), Ok(42));
+    let error = catch_internal_errors(|| -> i32 { panic!("broken invariant: {}", 7) }).unwrap_err();
+    assert_eq!(error.message(), "broken invariant: 7");
+    let error = catch_internal_errors(|| std::panic::panic_any(3)).unwrap_err();
+    assert_eq!(error.message(), "unknown panic");
+
+    let errors = KsonErrors::from_internal_error("a: 1", error.clone());
+    assert_eq!(errors.internal_error(), Some(&error));
+    assert!(errors.has_errors());
+    assert_eq!(errors.to_string(), "internal error in kson: unknown panic");
+    assert_eq!(errors.errors()[0].span(), 0..0);
+    assert_eq!(
+        KsonErrors::from_messages("a: 1", &[]).internal_error(),
+        None
+    );
+}
+
+#[test]
+fn test_entry_points_without_panics() {
+    assert_eq!(
+        Kson::try_format("a:1", FormatOptions::builder().options()).unwrap(),
+        "a: 1"
+    );
+    assert!(
+        !Kson::try_analyze("a: [1", None)
+            .unwrap()
+            .errors()
+            .is_empty()
+    );
+    assert_eq!(
+        Kson::try_to_json("a: 1", transpile_options::Json::new(false)).unwrap(),
+        "{\n  \"a\": 1\n}"
+    );
+    let errors = Kson::try_to_yaml("a: [1", transpile_options::Yaml::new(false)).unwrap_err();
+    assert!(errors.has_errors() && errors.internal_error().is_none());
+}
+
+/// The generated enums decode Kotlin enums by ordinal and encode them by name, so a variant added or
+/// reordered on one side only shows up as a mismatch between the two
+#[test]
+fn test_enums_match_kotlin() {
+    let styles = [
+        (FormattingStyle::Plain, "PLAIN"),
+        (FormattingStyle::Delimited, "DELIMITED"),
+        (FormattingStyle::Compact, "COMPACT"),
+        (FormattingStyle::Classic, "CLASSIC"),
+    ];
+    for (ordinal, (style, name)) in styles.into_iter().enumerate() {
+        assert_eq!(style as usize, ordinal);
+        assert_eq!(style.name(), name);
+        let indent = IndentType::Spaces(indent_type::Spaces::new(2));
+        let options = FormatOptions::new(indent, style, &[]);
+        assert_eq!(options.formatting_style() as usize, ordinal);
+    }
+
+    assert_eq!(MessageSeverity::Error.name(), "ERROR");
+    assert_eq!(MessageSeverity::Warning.name(), "WARNING");
+    let errors = Kson::analyze("[1, 2", None).errors();
+    assert!(!errors.is_empty());
+    assert!(
+        errors
+            .iter()
+            .all(|error| matches!(error.severity(), MessageSeverity::Error))
+    );
+
+    let types = [
+        ("o", KsonValueType::Object, "OBJECT"),
+        ("a", KsonValueType::Array, "ARRAY"),
+        ("s", KsonValueType::String, "STRING"),
+        ("i", KsonValueType::Integer, "INTEGER"),
+        ("d", KsonValueType::Decimal, "DECIMAL"),
+        ("b", KsonValueType::Boolean, "BOOLEAN"),
+        ("n", KsonValueType::Null, "NULL"),
+        ("e", KsonValueType::Embed, "EMBED"),
+    ];
+    let document =
+        "o: {}\na: []\ns: text\ni: 1\nd: 1.5\nb: true\nn: null\ne: %\n  embedded\n  %%\n";
+    let Some(KsonValue::KsonObject(object)) = Kson::analyze(document, None).kson_value() else {
+        panic!("expected object");
+    };
+    let properties = object.properties();
+    for (ordinal, (key, value_type, name)) in types.into_iter().enumerate() {
+        assert_eq!(value_type as usize, ordinal);
+        assert_eq!(value_type.name(), name);
+        assert_eq!(properties[key].type_() as usize, ordinal, "{key}");
+    }
+
+    // Every token type, in declaration order
+    let token_types = [
+        "CURLY_BRACE_L",
+        "CURLY_BRACE_R",
+        "SQUARE_BRACKET_L",
+        "SQUARE_BRACKET_R",
+        "ANGLE_BRACKET_L",
+        "ANGLE_BRACKET_R",
+        "COLON",
+        "DOT",
+        "END_DASH",
+        "COMMA",
+        "COMMENT",
+        "EMBED_OPEN_DELIM",
+        "EMBED_CLOSE_DELIM",
+        "EMBED_TAG",
+        "EMBED_PREAMBLE_NEWLINE",
+        "EMBED_CONTENT",
+        "FALSE",
+        "UNQUOTED_STRING",
+        "ILLEGAL_CHAR",
+        "LIST_DASH",
+        "NULL",
+        "NUMBER",
+        "STRING_OPEN_QUOTE",
+        "STRING_CLOSE_QUOTE",
+        "STRING_CONTENT",
+        "TRUE",
+        "WHITESPACE",
+        "EOF",
+    ];
+    let document = "# comment\na: { b: [1, 'x'] }\nc:\n  - true\n  - false\n  =\nd: null\ne: %sql\n  select\n  %%\n";
+    for token in Kson::analyze(document, None).tokens() {
+        let token_type = token.token_type();
+        assert_eq!(
+            token_types[token_type as usize],
+            token_type.name(),
+            "{}",
+            token.text()
+        );
+    }
+}
+
+#[test]
+fn test_embed_dialects() {
+    use crate::dialect::{Dialect, DialectIssue, EmbedDialects};
+
+    let mut dialects = EmbedDialects::with_builtins();
+    dialects.register(
+        "SQL",
+        Dialect::new("SQL")
+            .line_comment("--")
+            .block_comment("/*", "*/")
+            .validator(|content| {
+                content
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| !line.trim_end().ends_with(';'))
+                    .map(|(line, text)| DialectIssue::new("missing `;`", line, text.len()))
+                    .collect()
+            }),
+    );
+    let sql = dialects.get("sql").unwrap();
+    assert_eq!(sql.line_comment_prefix(), Some("--"));
+    assert_eq!(sql.block_comment_delimiters(), Some(("/*", "*/")));
+    assert!(dialects.get("yaml").is_none());
+
+    let document = r#"
+        queries: [
+          %sql
+          select 1;
+          select 2
+          %%
+        ]
+        key: %hex
+          cafe0
+          %%
+        notes: %markdown
+          # anything goes
+          %%
+    "#;
+    let diagnostics = dialects.validate(document);
+    assert_eq!(diagnostics.len(), 2, "{diagnostics:?}");
+    assert_eq!(diagnostics[0].tag, "sql");
+    assert_eq!(diagnostics[0].path.to_string(), "/queries/0");
+    assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 8));
+    assert_eq!(diagnostics[1].path.to_string(), "/key");
+    assert_eq!(diagnostics[1].start.line(), 7);
+}
+
+#[test]
+fn test_embed_parse_nested() {
+    use crate::embed::NestedDocumentError;
+
+    let document = r#"
+        service: api
+        overrides: %kson
+          replicas: 3
+          limits: { cpu: 2 }
+          %%
+        broken: %json
+          { "a": }
+          %%
+        script: %sh
+          echo hi
+          %%
+        inline: %kson
+          x: 1
+          y: 2%%
+    "#;
+    let Some(KsonValue::KsonObject(object)) = Kson::analyze(document, None).kson_value() else {
+        panic!("expected object");
+    };
+    let properties = object.properties();
+    let embed = |key: &str| match &properties[key] {
+        KsonValue::KsonEmbed(embed) => embed.clone(),
+        _ => panic!("expected embed"),
+    };
+
+    let nested = embed("overrides").parse_nested(document).unwrap();
+    let KsonValue::KsonObject(overrides) = nested.value() else {
+        panic!("expected object");
+    };
+    let replicas = &overrides.properties()["replicas"];
+    assert_eq!(replicas.to_value(), crate::value::Value::Integer(3));
+    // `3` is on the fourth line of the outer document, after `replicas: `
+    assert_eq!(nested.outer_position(&replicas.start()), (3, 20));
+
+    let Err(NestedDocumentError::Parse(errors)) = embed("broken").parse_nested(document) else {
+        panic!("expected a parse error");
+    };
+    assert!(!errors.is_empty());
+    assert_eq!(errors[0].line, 7);
+
+    assert_eq!(
+        embed("script").parse_nested(document).unwrap_err(),
+        NestedDocumentError::UnsupportedTag(Some("sh".to_string()))
+    );
+
+    // The indentation comes from the content when the closing delimiter ends its last line
+    let nested = embed("inline").parse_nested(document).unwrap();
+    let y = nested.value().pointer("/y").unwrap();
+    assert_eq!(nested.outer_position(&y.start()), (14, 13));
+}
+
+#[test]
+fn test_template_render() {
+    use crate::template::{TemplateErrorKind, render};
+    use crate::value::{Map, Value};
+
+    let object = |entries: Vec<(&str, Value)>| Value::Object(entries.into_iter().collect::<Map>());
+    let context = object(vec![(
+        "release",
+        object(vec![
+            ("version", Value::String("1.4.2".to_string())),
+            ("replicas", Value::Integer(3)),
+            (
+                "regions",
+                Value::Array(vec![Value::String("eu".to_string())]),
+            ),
+        ]),
+    )]);
+
+    let template = object(vec![
+        (
+            "image",
+            Value::String("registry/app:{{release.version}}".to_string()),
+        ),
+        (
+            "replicas",
+            Value::String("{{ release.replicas }}".to_string()),
+        ),
+        ("regions", Value::String("{{release.regions}}".to_string())),
+        ("first", Value::String("{{release.regions.0}}".to_string())),
+        (
+            "script",
+            Value::Embed {
+                tag: Some("sh".to_string()),
+                content: "deploy --replicas {{release.replicas}}\n".to_string(),
+            },
+        ),
+    ]);
+    let rendered = render(&template, &context).unwrap();
+    assert_eq!(
+        rendered,
+        object(vec![
+            ("image", Value::String("registry/app:1.4.2".to_string())),
+            ("replicas", Value::Integer(3)),
+            (
+                "regions",
+                Value::Array(vec![Value::String("eu".to_string())])
+            ),
+            ("first", Value::String("eu".to_string())),
+            (
+                "script",
+                Value::Embed {
+                    tag: Some("sh".to_string()),
+                    content: "deploy --replicas 3\n".to_string(),
+                },
+            ),
+        ])
+    );
+
+    let template = Value::Array(vec![
+        Value::String("{{release.missing}}".to_string()),
+        Value::String("regions: {{release.regions}}".to_string()),
+        Value::String("{{release.version".to_string()),
+    ]);
+    let errors = render(&template, &context).unwrap_err();
+    let errors: Vec<_> = errors
+        .iter()
+        .map(|error| {
+            (
+                error.path.to_string(),
+                error.placeholder.as_str(),
+                error.kind,
+            )
+        })
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            (
+                "/0".to_string(),
+                "release.missing",
+                TemplateErrorKind::Unresolved
+            ),
+            (
+                "/1".to_string(),
+                "release.regions",
+                TemplateErrorKind::NotScalar
+            ),
+            (
+                "/2".to_string(),
+                "release.version",
+                TemplateErrorKind::Unterminated
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_resolve_secrets() {
+    use crate::secrets::{
+        AsyncSecretResolver, SecretError, SecretReference, resolve_secrets, resolve_secrets_async,
+    };
+    use crate::value::{Map, Value};
+
+    let resolver = |reference: &SecretReference| -> std::result::Result<String, SecretError> {
+        match (reference.provider.as_str(), reference.path.as_str()) {
+            ("vault", "kv/db/password") => Ok("hunter2".to_string()),
+            _ => Err(format!("no secret at `{}`", reference.path).into()),
+        }
+    };
+    let document = |password: &str, token: &str| {
+        Value::Object(
+            [
+                ("user", Value::String("admin".to_string())),
+                ("password", Value::String(password.to_string())),
+                (
+                    "tokens",
+                    Value::Array(vec![Value::String(token.to_string())]),
+                ),
+            ]
+            .into_iter()
+            .collect::<Map>(),
+        )
+    };
+
+    let value = document(
+        "secret://vault/kv/db/password",
+        "secret://vault/kv/db/password",
+    );
+    assert_eq!(
+        resolve_secrets(&value, &resolver).unwrap(),
+        document("hunter2", "hunter2")
+    );
+
+    let value = document("secret://vault/kv/db/password", "secret://env/API_TOKEN");
+    let unresolved = resolve_secrets(&value, &resolver).unwrap_err();
+    assert_eq!(unresolved.len(), 1);
+    assert_eq!(unresolved[0].path.to_string(), "/tokens/0");
+    assert_eq!(unresolved[0].reference.provider, "env");
+    assert_eq!(unresolved[0].error.to_string(), "no secret at `API_TOKEN`");
+
+    // A resolver that completes immediately, polled without an executor
+    struct Ready;
+    impl AsyncSecretResolver for Ready {
+        async fn resolve(
+            &self,
+            reference: &SecretReference,
+        ) -> std::result::Result<String, SecretError> {
+            Ok(reference.path.to_uppercase())
+        }
+    }
+    let value = document("secret://vault/a", "secret://vault/b");
+    let future = std::pin::pin!(resolve_secrets_async(&value, &Ready));
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    let std::task::Poll::Ready(resolved) = future.poll(&mut context) else {
+        panic!("expected the resolution to complete");
+    };
+    assert_eq!(resolved.unwrap(), document("A", "B"));
+}
+
+#[test]
+#[cfg(feature = "workspace")]
+fn test_key_usage() {
+    use crate::config::ProjectConfig;
+    use crate::index::WorkspaceIndex;
+
+    let root = TempDir::new("key-usage");
+    std::fs::create_dir_all(root.join("schemas")).unwrap();
+    let files = [
+        (
+            ".kson.kson",
+            "schemas: { 'services/*.kson': 'schemas/service.kson', 'jobs/*.kson': 'schemas/job.kson' }",
+        ),
+        (
+            "schemas/service.kson",
+            "type: object\nproperties: {\n  port: { type: integer }\n  legacy_mode: { type: boolean }\n  tls: { anyOf: [{ properties: { cert: { type: string } } }, { properties: { acme: { type: boolean } } }] }\n}",
+        ),
+        (
+            "schemas/job.kson",
+            "properties: { schedule: { type: string } }",
+        ),
+        ("services/api.kson", "port: 80\ntls: { acme: true }"),
+        ("services/worker.kson", "port: 81\nprot: 82"),
+    ];
+    for dir in ["services", "jobs"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    for (file, text) in files {
+        std::fs::write(root.join(file), text).unwrap();
+    }
+    let config = ProjectConfig::discover(&root).unwrap().unwrap();
+    let index = WorkspaceIndex::build(&root, config).unwrap();
+
+    let usage = index.key_usage(&[]);
+    let unused: Vec<_> = usage
+        .unused_properties
+        .iter()
+        .map(|property| {
+            let schema = property.schema.file_name().unwrap().to_string_lossy();
+            (schema.into_owned(), property.schema_path.to_string())
+        })
+        .collect();
+    let pair = |file: &str, path: &str| (file.to_string(), path.to_string());
+    assert_eq!(
+        unused,
+        [
+            pair("job.kson", "/properties/schedule"),
+            pair("service.kson", "/properties/legacy_mode"),
+            pair("service.kson", "/properties/tls/anyOf/0/properties/cert"),
+        ]
+    );
+    assert_eq!(usage.unused_properties[1].name, "legacy_mode");
+    assert_eq!(usage.unused_properties[1].start.line(), 3);
+
+    let undeclared: Vec<_> = usage
+        .undeclared_keys
+        .iter()
+        .map(|(file, diagnostic)| {
+            let file = file.file_name().unwrap().to_string_lossy();
+            (file.into_owned(), diagnostic.path.to_string())
+        })
+        .collect();
+    assert_eq!(undeclared, [pair("worker.kson", "/prot")]);
+    let exceptions = ["/prot".parse().unwrap()];
+    assert!(index.key_usage(&exceptions).undeclared_keys.is_empty());
+}
@@ -0,0 +1,306 @@
+//! Interpretation of human-friendly durations (`"2h15m"`) and byte sizes (`"512MiB"`).
+//!
+//! Configuration files constantly encode these as strings. Nothing here is applied implicitly: the
+//! parsed document keeps them as plain strings, and callers opt in through [`KsonValue::as_duration`],
+//! [`KsonValue::as_byte_size`] or the functions in this module.
+
+use std::time::Duration;
+
+use crate::{KsonValue, kson_value};
+
+/// The error returned when a duration or byte size string cannot be interpreted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnitError {
+    /// The input was empty (or contained only whitespace)
+    Empty,
+    /// A component did not start with a valid number
+    InvalidNumber(String),
+    /// A number was not followed by a unit, in a context where one is required
+    MissingUnit(String),
+    /// The unit is not one we know about
+    UnknownUnit(String),
+    /// The value does not fit in the target type
+    Overflow,
+}
+
+impl std::fmt::Display for UnitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnitError::Empty => write!(f, "expected a value, found an empty string"),
+            UnitError::InvalidNumber(text) => write!(f, "invalid number in `{text}`"),
+            UnitError::MissingUnit(text) => write!(f, "missing unit after `{text}`"),
+            UnitError::UnknownUnit(unit) => write!(f, "unknown unit `{unit}`"),
+            UnitError::Overflow => write!(f, "value is too large"),
+        }
+    }
+}
+
+impl std::error::Error for UnitError {}
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Duration units, with their length in nanoseconds
+const DURATION_UNITS: &[(&str, u128)] = &[
+    ("ns", 1),
+    ("us", 1_000),
+    ("µs", 1_000),
+    ("ms", 1_000_000),
+    ("s", NANOS_PER_SECOND),
+    ("m", 60 * NANOS_PER_SECOND),
+    ("h", 60 * 60 * NANOS_PER_SECOND),
+    ("d", 24 * 60 * 60 * NANOS_PER_SECOND),
+    ("w", 7 * 24 * 60 * 60 * NANOS_PER_SECOND),
+];
+
+/// Byte size units (matched case-insensitively), with their size in bytes
+const BYTE_SIZE_UNITS: &[(&str, u128)] = &[
+    ("b", 1),
+    ("k", 1_000),
+    ("kb", 1_000),
+    ("kib", 1 << 10),
+    ("m", 1_000_000),
+    ("mb", 1_000_000),
+    ("mib", 1 << 20),
+    ("g", 1_000_000_000),
+    ("gb", 1_000_000_000),
+    ("gib", 1 << 30),
+    ("t", 1_000_000_000_000),
+    ("tb", 1_000_000_000_000),
+    ("tib", 1 << 40),
+    ("p", 1_000_000_000_000_000),
+    ("pb", 1_000_000_000_000_000),
+    ("pib", 1 << 50),
+];
+
+/// Parses a duration made of one or more `<number><unit>` components, like `"30s"`, `"1.5h"` or
+/// `"2h 15m"`. Supported units are `ns`, `us` (or `µs`), `ms`, `s`, `m`, `h`, `d` and `w`. A bare `"0"`
+/// is accepted as the zero duration.
+pub fn parse_duration(input: &str) -> Result<Duration, UnitError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(UnitError::Empty);
+    }
+    if input == "0" {
+        return Ok(Duration::ZERO);
+    }
+
+    let mut rest = input;
+    let mut total_nanos: u128 = 0;
+    while !rest.is_empty() {
+        let (number, after_number) = split_number(rest)?;
+        let after_number = after_number.trim_start();
+        let unit_len = after_number
+            .find(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace())
+            .unwrap_or(after_number.len());
+        let (unit, after_unit) = after_number.split_at(unit_len);
+        if unit.is_empty() {
+            return Err(UnitError::MissingUnit(number.to_string()));
+        }
+
+        let (_, unit_nanos) = DURATION_UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .ok_or_else(|| UnitError::UnknownUnit(unit.to_string()))?;
+        let nanos = scale(number, *unit_nanos)?;
+        total_nanos = total_nanos.checked_add(nanos).ok_or(UnitError::Overflow)?;
+        rest = after_unit.trim_start();
+    }
+
+    let seconds = u64::try_from(total_nanos / NANOS_PER_SECOND).map_err(|_| UnitError::Overflow)?;
+    Ok(Duration::new(
+        seconds,
+        (total_nanos % NANOS_PER_SECOND) as u32,
+    ))
+}
+
+/// Parses a byte size like `"512MiB"`, `"1.5 GB"` or `"4096"` into a number of bytes. Decimal (`kB`,
+/// `MB`, ...) and binary (`KiB`, `MiB`, ...) units are supported, matched case-insensitively. A number
+/// without unit is a number of bytes, and fractional results are rounded down to whole bytes.
+pub fn parse_byte_size(input: &str) -> Result<u64, UnitError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(UnitError::Empty);
+    }
+
+    let (number, unit) = split_number(input)?;
+    let unit = unit.trim_start();
+    let unit_bytes = if unit.is_empty() {
+        1
+    } else {
+        let lowercase_unit = unit.to_lowercase();
+        let (_, unit_bytes) = BYTE_SIZE_UNITS
+            .iter()
+            .find(|(name, _)| *name == lowercase_unit)
+            .ok_or_else(|| UnitError::UnknownUnit(unit.to_string()))?;
+        *unit_bytes
+    };
+
+    u64::try_from(scale(number, unit_bytes)?).map_err(|_| UnitError::Overflow)
+}
+
+/// Renders a duration in the format accepted by [`parse_duration`], using the largest units possible
+/// (e.g. `"2h15m"`)
+pub fn format_duration(duration: Duration) -> String {
+    let mut nanos = duration.as_nanos();
+    if nanos == 0 {
+        return "0s".to_string();
+    }
+
+    let mut output = String::new();
+    for (name, unit_nanos) in DURATION_UNITS.iter().rev() {
+        // `µs` is an alias of `us`, and weeks are a surprising unit for most readers
+        if *name == "µs" || *name == "w" {
+            continue;
+        }
+        let count = nanos / unit_nanos;
+        if count > 0 {
+            output.push_str(&format!("{count}{name}"));
+            nanos %= unit_nanos;
+        }
+    }
+    output
+}
+
+/// Renders a byte size in the format accepted by [`parse_byte_size`], using the largest binary unit
+/// that represents it exactly (e.g. `"512MiB"`)
+pub fn format_byte_size(bytes: u64) -> String {
+    const BINARY_UNITS: &[(&str, u32)] = &[
+        ("PiB", 50),
+        ("TiB", 40),
+        ("GiB", 30),
+        ("MiB", 20),
+        ("KiB", 10),
+    ];
+    if bytes != 0 {
+        for (name, shift) in BINARY_UNITS {
+            if bytes.is_multiple_of(1 << shift) {
+                return format!("{}{name}", bytes >> shift);
+            }
+        }
+    }
+    format!("{bytes}B")
+}
+
+/// Splits the leading number (digits with an optional fraction) off the input
+fn split_number(input: &str) -> Result<(&str, &str), UnitError> {
+    let number_len = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (number, rest) = input.split_at(number_len);
+
+    let is_valid = !number.is_empty()
+        && number.matches('.').count() <= 1
+        && !number.starts_with('.')
+        && !number.ends_with('.');
+    if is_valid {
+        Ok((number, rest))
+    } else {
+        Err(UnitError::InvalidNumber(input.to_string()))
+    }
+}
+
+/// Multiplies a decimal number (as text) by the given unit without going through floating point,
+/// rounding down the result
+fn scale(number: &str, unit: u128) -> Result<u128, UnitError> {
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let overflow = |_| UnitError::Overflow;
+
+    let whole: u128 = whole.parse().map_err(overflow)?;
+    let mut result = whole.checked_mul(unit).ok_or(UnitError::Overflow)?;
+    if !fraction.is_empty() {
+        let denominator = 10u128
+            .checked_pow(fraction.len() as u32)
+            .ok_or(UnitError::Overflow)?;
+        let numerator: u128 = fraction.parse().map_err(overflow)?;
+        let fraction_value = numerator.checked_mul(unit).ok_or(UnitError::Overflow)? / denominator;
+        result = result
+            .checked_add(fraction_value)
+            .ok_or(UnitError::Overflow)?;
+    }
+    Ok(result)
+}
+
+impl KsonValue {
+    /// Interprets this value as a duration, if it is a string accepted by [`parse_duration`]
+    pub fn as_duration(&self) -> Option<Duration> {
+        match self {
+            KsonValue::KsonString(string) => parse_duration(&string.value()).ok(),
+            _ => None,
+        }
+    }
+
+    /// Interprets this value as a number of bytes, if it is a string accepted by [`parse_byte_size`] or a
+    /// non-negative integer
+    pub fn as_byte_size(&self) -> Option<u64> {
+        match self {
+            KsonValue::KsonString(string) => parse_byte_size(&string.value()).ok(),
+            KsonValue::KsonNumber(kson_value::KsonNumber::Integer(integer)) => {
+                u64::try_from(integer.value()).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Serde adapters for fields holding durations and byte sizes, to be used as
+/// `#[serde(with = "kson_rs::units::serde::duration")]`
+#[cfg(feature = "serde")]
+pub mod serde {
+    /// (De)serializes a [`std::time::Duration`] as a string like `"2h15m"`
+    pub mod duration {
+        use std::time::Duration;
+
+        use ::serde::de::Error;
+        use ::serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            duration: &Duration,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&super::super::format_duration(*duration))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Duration, D::Error> {
+            let text = String::deserialize(deserializer)?;
+            super::super::parse_duration(&text).map_err(D::Error::custom)
+        }
+    }
+
+    /// (De)serializes a number of bytes as a string like `"512MiB"`, also accepting plain integers
+    pub mod byte_size {
+        use ::serde::de::{Error, Unexpected, Visitor};
+        use ::serde::{Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(bytes: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&super::super::format_byte_size(*bytes))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+            deserializer.deserialize_any(ByteSizeVisitor)
+        }
+
+        struct ByteSizeVisitor;
+
+        impl<'de> Visitor<'de> for ByteSizeVisitor {
+            type Value = u64;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a byte size like `512MiB`, or a number of bytes")
+            }
+
+            fn visit_u64<E: Error>(self, bytes: u64) -> Result<u64, E> {
+                Ok(bytes)
+            }
+
+            fn visit_i64<E: Error>(self, bytes: i64) -> Result<u64, E> {
+                u64::try_from(bytes).map_err(|_| E::invalid_value(Unexpected::Signed(bytes), &self))
+            }
+
+            fn visit_str<E: Error>(self, text: &str) -> Result<u64, E> {
+                super::super::parse_byte_size(text).map_err(E::custom)
+            }
+        }
+    }
+}
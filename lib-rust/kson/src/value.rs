@@ -0,0 +1,1325 @@
+//! An owned, mutable representation of KSON values.
+//!
+//! [`KsonValue`] is a read-only view of a document parsed by kson-lib. To transform a document (e.g.
+//! prune or rewrite parts of it) convert it into a [`Value`] with [`KsonValue::to_value`], and turn
+//! the result back into text with [`Value::to_kson`] or [`Value::to_json`]. Documents can also be built
+//! from scratch, with constructors like [`Value::object`] or with the [`kson!`](crate::kson) macro.
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::ops::Index;
+use std::sync::Arc;
+
+use crate::format::{FloatFormat, Formatter, QuoteStyle, apply_float_format};
+use crate::numbers::BigInteger;
+use crate::path::{KsonPath, LookupError, PathSegment};
+use crate::query::QueryNode;
+use crate::{FormatOptions, KsonValue, kson_value};
+
+/// An owned KSON value.
+///
+/// Values are compared structurally, so unlike [`KsonValue`]s (which kson-lib compares by identity) they
+/// can be deduplicated in a `HashSet` or used as `BTreeMap` keys. Equality is strict: `1` and `1.0` are
+/// different values, and so are objects with the same properties in a different order (see
+/// [`values_equal`] for the looser equality of JSON Schema). Decimals are compared by their bits, so
+/// `NaN` equals itself and `0.0` differs from `-0.0`.
+///
+/// Values are totally ordered, first by type in the order of the variants below (`null` < booleans <
+/// integers < big integers < decimals < strings < embed blocks < arrays < objects), then by content:
+/// numbers numerically (decimals as [`f64::total_cmp`] does), strings by their UTF-8 bytes, embed blocks by tag
+/// (untagged first) then content, and arrays and objects lexicographically, with objects compared
+/// property by property (key, then value) in document order.
+///
+/// Values can be nested arbitrarily deep when built in code or converted from other formats, so they're
+/// cloned, compared, hashed, rendered and dropped without recursing (see [`crate::depth`]).
+#[derive(Debug)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    /// An integer out of the range of an `i64`, only read by [`Value::parse_with_overflow`]
+    BigInteger(BigInteger),
+    Decimal(f64),
+    String(String),
+    Embed {
+        tag: Option<String>,
+        content: String,
+    },
+    Array(Vec<Value>),
+    Object(Map),
+}
+
+/// The properties of an object, in document order.
+///
+/// The properties are shared between clones and copied on the first mutation of a clone, so cloning a
+/// value (e.g. to layer configurations on top of each other) doesn't copy its objects, and comparing
+/// values that share objects doesn't compare their properties.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Map {
+    entries: Arc<Vec<(String, Value)>>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        // Look the key up first, so that shared properties are only copied if it's present
+        let index = self.position(key)?;
+        Some(&mut Arc::make_mut(&mut self.entries)[index].1)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Inserts a property, returning its previous value if it was already present (in which case it
+    /// keeps its position)
+    pub fn insert(&mut self, key: impl Into<String>, value: Value) -> Option<Value> {
+        let key = key.into();
+        match self.get_mut(&key) {
+            Some(existing) => Some(std::mem::replace(existing, value)),
+            None => {
+                Arc::make_mut(&mut self.entries).push((key, value));
+                None
+            }
+        }
+    }
+
+    /// Removes a property, preserving the order of the remaining ones
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let index = self.position(key)?;
+        Some(Arc::make_mut(&mut self.entries).remove(index).1)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, &mut Value) -> bool) {
+        Arc::make_mut(&mut self.entries).retain_mut(|(key, value)| keep(key, value));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Value)> {
+        Arc::make_mut(&mut self.entries)
+            .iter_mut()
+            .map(|(k, v)| (&*k, v))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+
+    /// Whether both maps share their properties, i.e. one is an unmodified clone of the other (which
+    /// implies they're equal, without comparing them)
+    pub fn ptr_eq(&self, other: &Map) -> bool {
+        Arc::ptr_eq(&self.entries, &other.entries)
+    }
+
+    /// The value of the property at the given position
+    pub(crate) fn get_index(&self, index: usize) -> Option<&Value> {
+        self.entries.get(index).map(|(_, v)| v)
+    }
+
+    /// The property at the given position
+    pub(crate) fn get_entry(&self, index: usize) -> Option<(&String, &Value)> {
+        self.entries.get(index).map(|(k, v)| (k, v))
+    }
+
+    /// Makes a map of properties with distinct keys, without checking them
+    pub(crate) fn from_entries(entries: Vec<(String, Value)>) -> Self {
+        Map {
+            entries: Arc::new(entries),
+        }
+    }
+
+    /// The properties sorted by key
+    fn sorted_by_key(&self) -> Vec<(&String, &Value)> {
+        let mut properties: Vec<_> = self.iter().collect();
+        properties.sort_unstable_by_key(|(key, _)| *key);
+        properties
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.entries.iter().position(|(k, _)| k == key)
+    }
+}
+
+impl IntoIterator for Map {
+    type Item = (String, Value);
+    type IntoIter = std::vec::IntoIter<(String, Value)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Arc::unwrap_or_clone(self.entries).into_iter()
+    }
+}
+
+impl<K: Into<String>> FromIterator<(K, Value)> for Map {
+    fn from_iter<T: IntoIterator<Item = (K, Value)>>(iter: T) -> Self {
+        let mut map = Map::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+impl Value {
+    /// Walks this value depth-first, without recursing
+    pub(crate) fn walk(&self) -> Walk<'_> {
+        Walk {
+            root: Some(self),
+            open: Vec::new(),
+        }
+    }
+
+    /// Clones a value that isn't an array, sharing the properties of objects
+    fn clone_shallow(&self) -> Value {
+        match self {
+            Value::Null => Value::Null,
+            Value::Bool(boolean) => Value::Bool(*boolean),
+            Value::Integer(integer) => Value::Integer(*integer),
+            Value::BigInteger(integer) => Value::BigInteger(integer.clone()),
+            Value::Decimal(decimal) => Value::Decimal(*decimal),
+            Value::String(string) => Value::String(string.clone()),
+            Value::Embed { tag, content } => Value::Embed {
+                tag: tag.clone(),
+                content: content.clone(),
+            },
+            Value::Array(elements) => Value::Array(elements.clone()),
+            Value::Object(map) => Value::Object(map.clone()),
+        }
+    }
+
+    /// Moves the children of this value to `pending`, unless they're shared with another object
+    fn take_children(&mut self, pending: &mut Vec<Value>) {
+        match self {
+            Value::Array(elements) => pending.append(elements),
+            Value::Object(map) => {
+                if let Some(entries) = Arc::get_mut(&mut map.entries) {
+                    pending.extend(entries.drain(..).map(|(_, value)| value));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The position of the variant in the total order of values
+    fn type_rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Integer(_) => 2,
+            Value::BigInteger(_) => 3,
+            Value::Decimal(_) => 4,
+            Value::String(_) => 5,
+            Value::Embed { .. } => 6,
+            Value::Array(_) => 7,
+            Value::Object(_) => 8,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Both values are walked in lockstep, comparing each pair of values before their children
+        let (mut walk, mut other_walk) = (self.walk(), other.walk());
+        loop {
+            match (walk.next(), other_walk.next()) {
+                (
+                    Some(Step::Enter { key, value, .. }),
+                    Some(Step::Enter {
+                        key: other_key,
+                        value: other_value,
+                        ..
+                    }),
+                ) => {
+                    let ordering = key
+                        .cmp(&other_key)
+                        .then_with(|| shallow_cmp(value, other_value));
+                    if ordering != Ordering::Equal {
+                        return ordering;
+                    }
+                    if let (Value::Object(a), Value::Object(b)) = (value, other_value)
+                        && a.ptr_eq(b)
+                    {
+                        walk.skip_children();
+                        other_walk.skip_children();
+                    }
+                }
+                // The array or object that runs out of children first is a prefix of the other
+                (Some(Step::Leave { .. }), Some(Step::Enter { .. })) => return Ordering::Less,
+                (Some(Step::Enter { .. }), Some(Step::Leave { .. })) => return Ordering::Greater,
+                (Some(_), Some(_)) => {}
+                _ => return Ordering::Equal,
+            }
+        }
+    }
+}
+
+/// Compares values without their children, i.e. by type and by content for scalars
+fn shallow_cmp(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+        (Value::BigInteger(a), Value::BigInteger(b)) => a.cmp(b),
+        (Value::Decimal(a), Value::Decimal(b)) => a.total_cmp(b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (
+            Value::Embed { tag, content },
+            Value::Embed {
+                tag: other_tag,
+                content: other_content,
+            },
+        ) => (tag, content).cmp(&(other_tag, other_content)),
+        _ => a.type_rank().cmp(&b.type_rank()),
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for step in self.walk() {
+            let Step::Enter { key, value, .. } = step else {
+                continue;
+            };
+            key.hash(state);
+            value.type_rank().hash(state);
+            match value {
+                Value::Null => {}
+                Value::Bool(boolean) => boolean.hash(state),
+                Value::Integer(integer) => integer.hash(state),
+                Value::BigInteger(integer) => integer.hash(state),
+                // Consistent with equality, which compares decimals by their bits
+                Value::Decimal(decimal) => decimal.to_bits().hash(state),
+                Value::String(string) => string.hash(state),
+                Value::Embed { tag, content } => (tag, content).hash(state),
+                Value::Array(elements) => elements.len().hash(state),
+                Value::Object(map) => map.len().hash(state),
+            }
+        }
+    }
+}
+
+impl Clone for Value {
+    fn clone(&self) -> Self {
+        // Objects share their properties, so only arrays need their children copied
+        let Value::Array(_) = self else {
+            return self.clone_shallow();
+        };
+        let mut builder = Builder::default();
+        let mut walk = self.walk();
+        while let Some(step) = walk.next() {
+            let built = match step {
+                Step::Enter {
+                    key,
+                    value: Value::Array(elements),
+                    ..
+                } => {
+                    builder.open_array(key.map(str::to_string), elements.len());
+                    None
+                }
+                Step::Enter { key, value, .. } => {
+                    walk.skip_children();
+                    builder.push(key.map(str::to_string), value.clone_shallow())
+                }
+                Step::Leave {
+                    value: Value::Array(_),
+                    ..
+                } => builder.close(),
+                Step::Leave { .. } => None,
+            };
+            if let Some(value) = built {
+                return value;
+            }
+        }
+        unreachable!("the walk ends by leaving the root")
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        // Dropping nested values would recurse, so their children are moved out to be dropped one by one,
+        // once they have no children left themselves
+        let mut pending = Vec::new();
+        self.take_children(&mut pending);
+        while let Some(mut value) = pending.pop() {
+            value.take_children(&mut pending);
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Decimal(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::Array(value)
+    }
+}
+
+impl From<Map> for Value {
+    fn from(value: Map) -> Self {
+        Value::Object(value)
+    }
+}
+
+/// The value returned when indexing a value that has no such property or element
+static NULL: Value = Value::Null;
+
+/// Returns the value of the property, or `null` if this isn't an object or doesn't have it, so that
+/// lookups can be chained like `value["server"]["port"]` without panicking
+impl Index<&str> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        match self {
+            Value::Object(properties) => properties.get(key).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+/// Returns the element at the index, or `null` if this isn't an array or is too short
+impl Index<usize> for Value {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        match self {
+            Value::Array(elements) => elements.get(index).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+impl From<&KsonValue> for Value {
+    fn from(value: &KsonValue) -> Self {
+        match value {
+            KsonValue::KsonNull(_) => Value::Null,
+            KsonValue::KsonBoolean(boolean) => Value::Bool(boolean.value()),
+            KsonValue::KsonNumber(kson_value::KsonNumber::Integer(integer)) => {
+                Value::Integer(integer.value())
+            }
+            KsonValue::KsonNumber(kson_value::KsonNumber::Decimal(decimal)) => {
+                Value::Decimal(decimal.value())
+            }
+            KsonValue::KsonString(string) => Value::String(string.value()),
+            KsonValue::KsonEmbed(embed) => Value::Embed {
+                tag: embed.tag(),
+                content: embed.content(),
+            },
+            KsonValue::KsonArray(array) => {
+                Value::Array(array.elements().iter().map(Value::from).collect())
+            }
+            KsonValue::KsonObject(object) => {
+                let mut properties = object.properties();
+                Value::Object(
+                    sorted_property_keys(object)
+                        .into_iter()
+                        .filter_map(|(name, _)| {
+                            let value = properties.remove(&name)?;
+                            Some((name, Value::from(&value)))
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+impl KsonValue {
+    /// Converts this value into an owned [`Value`], keeping object properties in document order
+    pub fn to_value(&self) -> Value {
+        Value::from(self)
+    }
+
+    /// Returns a copy of this value pruned with [`Value::retain_recursive`]
+    pub fn retain_recursive(&self, keep: impl FnMut(&KsonPath, &Value) -> bool) -> Value {
+        let mut value = self.to_value();
+        value.retain_recursive(keep);
+        value
+    }
+
+    /// Returns a copy of this value transformed with [`Value::filter_map_recursive`]
+    pub fn filter_map_recursive(
+        &self,
+        f: impl FnMut(&KsonPath, Value) -> Option<Value>,
+    ) -> Option<Value> {
+        self.to_value().filter_map_recursive(f)
+    }
+
+    /// Returns a copy of this value rewritten with [`Value::rewrite`]
+    pub fn rewrite(&self, f: impl FnMut(&KsonPath, &Value) -> Option<Value>) -> Value {
+        let mut value = self.to_value();
+        value.rewrite(f);
+        value
+    }
+
+    /// Renders this value as KSON, formatted with the given options. Comments of the document this value
+    /// was parsed from are not kept (format the document itself with [`Kson::format`](crate::Kson::format)
+    /// to keep them).
+    pub fn to_kson(&self, options: &FormatOptions) -> String {
+        self.to_kson_with(&Formatter::new(options.clone()))
+    }
+
+    /// Renders this value as KSON like [`KsonValue::to_kson`] does, formatted with the given formatter
+    pub fn to_kson_with(&self, formatter: &Formatter) -> String {
+        self.to_value().to_kson_with(formatter)
+    }
+}
+
+/// Typed accessors, returning `None` when the value is of another type. Strings and nested values live
+/// in kson-lib, so they are returned owned rather than borrowed.
+impl KsonValue {
+    /// Returns the string, if this is a string (embed blocks are not strings, see [`KsonValue::KsonEmbed`])
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            KsonValue::KsonString(string) => Some(string.value()),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            KsonValue::KsonNumber(kson_value::KsonNumber::Integer(integer)) => {
+                Some(integer.value())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the number, if this is a decimal or an integer (which may lose precision beyond 2^53)
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            KsonValue::KsonNumber(kson_value::KsonNumber::Decimal(decimal)) => {
+                Some(decimal.value())
+            }
+            KsonValue::KsonNumber(kson_value::KsonNumber::Integer(integer)) => {
+                Some(integer.value() as f64)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            KsonValue::KsonBoolean(boolean) => Some(boolean.value()),
+            _ => None,
+        }
+    }
+
+    /// Returns the properties in document order, if this is an object
+    pub fn as_object(&self) -> Option<Vec<(String, KsonValue)>> {
+        let KsonValue::KsonObject(object) = self else {
+            return None;
+        };
+        let mut properties = object.properties();
+        Some(
+            sorted_property_keys(object)
+                .into_iter()
+                .filter_map(|(name, _)| {
+                    let value = properties.remove(&name)?;
+                    Some((name, value))
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the elements, if this is an array
+    pub fn as_list(&self) -> Option<Vec<KsonValue>> {
+        match self {
+            KsonValue::KsonArray(array) => Some(array.elements()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the given property, if this is an object that has it
+    pub fn get(&self, key: &str) -> Option<KsonValue> {
+        match self {
+            KsonValue::KsonObject(object) => object.properties().remove(key),
+            _ => None,
+        }
+    }
+
+    /// Returns the element at the given index, if this is an array that long
+    pub fn get_index(&self, index: usize) -> Option<KsonValue> {
+        match self {
+            KsonValue::KsonArray(array) => {
+                let mut elements = array.elements();
+                (index < elements.len()).then(|| elements.swap_remove(index))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the value at the given path, if there is one. Like [`Value::get_path`], indices also
+    /// look up object properties named after them, and keys that are numbers array elements.
+    pub fn get_path(&self, path: &KsonPath) -> Option<KsonValue> {
+        self.try_get_path(path).ok()
+    }
+
+    /// Returns the value at the given JSON Pointer (e.g. `/servers/0/host`, see [`crate::path`] for its
+    /// escapes), or `None` if there is none or the pointer is malformed
+    pub fn pointer(&self, pointer: &str) -> Option<KsonValue> {
+        self.try_pointer(pointer).ok()
+    }
+
+    /// Returns the value at the given path, or which of its segments couldn't be followed
+    pub fn try_get_path(&self, path: &KsonPath) -> Result<KsonValue, LookupError> {
+        let mut current = self.clone();
+        let mut parent = KsonPath::root();
+        for segment in path.segments() {
+            let is_container =
+                matches!(current, KsonValue::KsonObject(_) | KsonValue::KsonArray(_));
+            current = match current.child(segment) {
+                Some((_, child)) => child,
+                None if is_container => {
+                    return Err(LookupError::Missing {
+                        parent,
+                        segment: segment.clone(),
+                    });
+                }
+                None => {
+                    return Err(LookupError::NotAContainer {
+                        parent,
+                        segment: segment.clone(),
+                    });
+                }
+            };
+            parent.push(segment.clone());
+        }
+        Ok(current)
+    }
+
+    /// Returns the value at the given JSON Pointer, or why the pointer couldn't be followed
+    pub fn try_pointer(&self, pointer: &str) -> Result<KsonValue, LookupError> {
+        self.try_get_path(&KsonPath::parse(pointer)?)
+    }
+}
+
+/// Returns the property keys of the object in document order
+pub(crate) fn sorted_property_keys(
+    object: &kson_value::KsonObject,
+) -> Vec<(String, kson_value::KsonString)> {
+    let mut keys: Vec<_> = object
+        .property_keys()
+        .into_iter()
+        .map(|(name, key)| {
+            let start = key.start();
+            ((start.line(), start.column()), name, key)
+        })
+        .collect();
+    keys.sort_by_key(|(position, _, _)| *position);
+    keys.into_iter().map(|(_, name, key)| (name, key)).collect()
+}
+
+impl Value {
+    /// Builds an object from its properties, in order. A repeated key keeps its first position and its
+    /// last value.
+    pub fn object<K: Into<String>>(properties: impl IntoIterator<Item = (K, Value)>) -> Value {
+        Value::Object(properties.into_iter().collect())
+    }
+
+    pub fn list(elements: impl IntoIterator<Item = Value>) -> Value {
+        Value::Array(elements.into_iter().collect())
+    }
+
+    pub fn string(string: impl Into<String>) -> Value {
+        Value::String(string.into())
+    }
+
+    /// Builds an embed block, e.g. `Value::embed(Some("sql"), "select 1\n")`
+    pub fn embed(tag: Option<&str>, content: impl Into<String>) -> Value {
+        Value::Embed {
+            tag: tag.map(str::to_string),
+            content: content.into(),
+        }
+    }
+
+    /// Returns the value at the given path, if there is one
+    pub fn get_path(&self, path: &KsonPath) -> Option<&Value> {
+        path.segments()
+            .iter()
+            .try_fold(self, |value, segment| match (value, segment) {
+                (Value::Object(map), PathSegment::Key(key)) => map.get(key),
+                (Value::Object(map), PathSegment::Index(index)) => map.get(&index.to_string()),
+                (Value::Array(elements), PathSegment::Index(index)) => elements.get(*index),
+                (Value::Array(elements), PathSegment::Key(key)) => {
+                    elements.get(key.parse::<usize>().ok()?)
+                }
+                _ => None,
+            })
+    }
+
+    /// Returns the value at the given JSON Pointer, or `None` if there is none or the pointer is malformed
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        self.get_path(&KsonPath::parse(pointer).ok()?)
+    }
+
+    pub fn get_path_mut(&mut self, path: &KsonPath) -> Option<&mut Value> {
+        path.segments()
+            .iter()
+            .try_fold(self, |value, segment| match (value, segment) {
+                (Value::Object(map), PathSegment::Key(key)) => map.get_mut(key),
+                (Value::Object(map), PathSegment::Index(index)) => map.get_mut(&index.to_string()),
+                (Value::Array(elements), PathSegment::Index(index)) => elements.get_mut(*index),
+                (Value::Array(elements), PathSegment::Key(key)) => {
+                    elements.get_mut(key.parse::<usize>().ok()?)
+                }
+                _ => None,
+            })
+    }
+
+    /// Removes every array element and object property (along with its whole subtree) for which `keep`
+    /// returns `false`, e.g. to strip all `null` values or internal `_meta` keys.
+    ///
+    /// The document is traversed top-down: a value is only visited if its parent was kept, and indices in
+    /// the paths passed to `keep` are those of the original document. The root itself is always kept.
+    pub fn retain_recursive(&mut self, mut keep: impl FnMut(&KsonPath, &Value) -> bool) {
+        let value = std::mem::replace(self, Value::Null);
+        *self = rebuild(
+            value,
+            |path, value| {
+                if path.is_root() || keep(path, &value) {
+                    Visit::Children(value)
+                } else {
+                    Visit::Skip(None)
+                }
+            },
+            |_, value| Some(value),
+        )
+        .unwrap_or(Value::Null);
+    }
+
+    /// Transforms the value bottom-up: `f` is called on every value after its children have been
+    /// transformed, and may replace it or remove it (by returning `None`) from its parent. Returns the
+    /// transformed root, or `None` if it was removed.
+    ///
+    /// Because children are handled first, `f` sees the result of pruning them, which makes it possible
+    /// to e.g. drop objects that became empty.
+    pub fn filter_map_recursive(
+        self,
+        mut f: impl FnMut(&KsonPath, Value) -> Option<Value>,
+    ) -> Option<Value> {
+        rebuild(
+            self,
+            |_, value| Visit::Children(value),
+            |path, value| f(path, value),
+        )
+    }
+
+    /// Replaces the values for which `f` returns a replacement, leaving everything else untouched, e.g. to
+    /// fill in template placeholders or inject secrets.
+    ///
+    /// The document is traversed top-down, starting at the root: once a value is replaced, neither the
+    /// replacement nor the original children are visited.
+    pub fn rewrite(&mut self, mut f: impl FnMut(&KsonPath, &Value) -> Option<Value>) {
+        let value = std::mem::replace(self, Value::Null);
+        *self = rebuild(
+            value,
+            |path, value| match f(path, &value) {
+                Some(replacement) => Visit::Skip(Some(replacement)),
+                None => Visit::Children(value),
+            },
+            |_, value| Some(value),
+        )
+        .unwrap_or(Value::Null);
+    }
+
+    /// The length of the longest path from this value to one it contains, e.g. 0 for a scalar or an empty
+    /// object, and 2 for `{ a: [1] }`. Computed without recursing, like [`MaxDepth::check`] which checks
+    /// it against a limit.
+    ///
+    /// [`MaxDepth::check`]: crate::depth::MaxDepth::check
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut pending = vec![(self, 0)];
+        while let Some((value, level)) = pending.pop() {
+            depth = depth.max(level);
+            match value {
+                Value::Array(elements) => {
+                    pending.extend(elements.iter().map(|element| (element, level + 1)))
+                }
+                Value::Object(map) => pending.extend(map.values().map(|value| (value, level + 1))),
+                _ => {}
+            }
+        }
+        depth
+    }
+
+    /// Renders this value as KSON, formatted with the default [`FormatOptions`]
+    pub fn to_kson(&self) -> String {
+        self.to_kson_with(&FormatOptions::builder().build())
+    }
+
+    /// Renders this value as KSON, formatted with the given formatter
+    pub fn to_kson_with(&self, formatter: &Formatter) -> String {
+        let mut kson = String::new();
+        write_kson(self, &mut kson);
+        formatter.format(&kson)
+    }
+
+    /// Renders this value as KSON on a single line (except for embed blocks), without formatting it
+    pub(crate) fn to_inline_kson(&self) -> String {
+        let mut kson = String::new();
+        write_kson(self, &mut kson);
+        kson
+    }
+
+    /// Renders this value as pretty-printed JSON. Embed blocks become strings holding their content, and
+    /// non-finite decimals (which JSON can't represent) become `null`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        write_json(self, &mut json);
+        json
+    }
+
+    /// Renders this value as pretty-printed JSON like [`Value::to_json`], with the decimals written as
+    /// `floats` says. Values don't keep the text of their decimals, so [`FloatFormat::Preserve`] writes
+    /// them like [`FloatFormat::Shortest`] does.
+    pub fn to_json_with_floats(&self, floats: FloatFormat) -> String {
+        apply_float_format(&self.to_json(), floats)
+    }
+}
+
+/// Compares values the way JSON Schema does: like `==`, except that integers and decimals with the same
+/// value are equal, and embed blocks are compared by content as strings
+pub fn values_equal(a: &Value, b: &Value) -> bool {
+    let mut pending = vec![(a, b)];
+    while let Some((a, b)) = pending.pop() {
+        let equal = match (a, b) {
+            (Value::Integer(i), Value::Decimal(d)) | (Value::Decimal(d), Value::Integer(i)) => {
+                *i as f64 == *d
+            }
+            (Value::Embed { content, .. }, Value::String(string))
+            | (Value::String(string), Value::Embed { content, .. })
+            | (
+                Value::Embed { content, .. },
+                Value::Embed {
+                    content: string, ..
+                },
+            ) => content == string,
+            (Value::Array(a), Value::Array(b)) => {
+                pending.extend(a.iter().zip(b));
+                a.len() == b.len()
+            }
+            (Value::Object(a), Value::Object(b)) => {
+                // Pair the properties up by sorting them by key, since looking each key of one object up
+                // in the other would be quadratic
+                a.ptr_eq(b)
+                    || a.len() == b.len()
+                        && a.sorted_by_key().into_iter().zip(b.sorted_by_key()).all(
+                            |((a_key, a), (b_key, b))| {
+                                pending.push((a, b));
+                                a_key == b_key
+                            },
+                        )
+            }
+            (Value::Array(_) | Value::Object(_), _) | (_, Value::Array(_) | Value::Object(_)) => {
+                false
+            }
+            _ => a == b,
+        };
+        if !equal {
+            return false;
+        }
+    }
+    true
+}
+
+/// Writes the value as delimited KSON, to be pretty-printed by the formatter
+fn write_kson(value: &Value, out: &mut String) {
+    for step in value.walk() {
+        match step {
+            Step::Enter {
+                depth,
+                position,
+                key,
+                value,
+            } => {
+                if depth > 0 && position > 0 {
+                    out.push_str(", ");
+                }
+                if let Some(key) = key {
+                    write_quoted(key, QuoteStyle::default().delimiter_for(key), out);
+                    out.push_str(": ");
+                }
+                match value {
+                    Value::Array(_) => out.push('['),
+                    Value::Object(_) => out.push('{'),
+                    scalar => write_scalar(scalar, out),
+                }
+            }
+            Step::Leave {
+                value: Value::Array(_),
+                ..
+            } => out.push(']'),
+            Step::Leave {
+                value: Value::Object(_),
+                ..
+            } => out.push('}'),
+            Step::Leave { .. } => {}
+        }
+    }
+}
+
+fn write_json(value: &Value, out: &mut String) {
+    let indent = |out: &mut String, depth: usize| out.push_str(&"  ".repeat(depth));
+    for step in value.walk() {
+        match step {
+            Step::Enter {
+                depth,
+                position,
+                key,
+                value,
+            } => {
+                if depth > 0 {
+                    out.push_str(if position > 0 { ",\n" } else { "\n" });
+                    indent(out, depth);
+                }
+                if let Some(key) = key {
+                    write_quoted(key, '"', out);
+                    out.push_str(": ");
+                }
+                match value {
+                    Value::String(string)
+                    | Value::Embed {
+                        content: string, ..
+                    } => write_quoted(string, '"', out),
+                    Value::Array(_) => out.push('['),
+                    Value::Object(_) => out.push('{'),
+                    scalar => write_scalar(scalar, out),
+                }
+            }
+            Step::Leave { depth, value } => {
+                let close = match value {
+                    Value::Array(elements) => (!elements.is_empty(), ']'),
+                    Value::Object(map) => (!map.is_empty(), '}'),
+                    _ => continue,
+                };
+                if close.0 {
+                    out.push('\n');
+                    indent(out, depth);
+                }
+                out.push(close.1);
+            }
+        }
+    }
+}
+
+/// Writes a value that isn't an array or an object as KSON
+fn write_scalar(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(boolean) => out.push_str(if *boolean { "true" } else { "false" }),
+        Value::Integer(integer) => out.push_str(&integer.to_string()),
+        Value::BigInteger(integer) => out.push_str(integer.as_str()),
+        Value::Decimal(decimal) => write_decimal(*decimal, out),
+        Value::String(string) => {
+            write_quoted(string, QuoteStyle::default().delimiter_for(string), out)
+        }
+        Value::Embed { tag, content } => write_embed(tag.as_deref(), content, out),
+        Value::Array(_) | Value::Object(_) => {}
+    }
+}
+
+fn write_decimal(decimal: f64, out: &mut String) {
+    if decimal.is_finite() {
+        // The `Debug` representation always includes a fraction or exponent (e.g. `1.0`), so the number
+        // stays a decimal when parsed back
+        out.push_str(&format!("{decimal:?}"));
+    } else {
+        out.push_str("null");
+    }
+}
+
+/// Writes a string delimited by `quote`, using the escapes shared by KSON and JSON
+pub(crate) fn write_quoted(string: &str, quote: char, out: &mut String) {
+    out.push(quote);
+    for c in string.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0C}' => out.push_str("\\f"),
+            c if c == quote => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push(quote);
+}
+
+/// Writes an embed block, picking the delimiter that needs the least escaping
+fn write_embed(tag: Option<&str>, content: &str, out: &mut String) {
+    let percent_count = count_embed_delimiters(content, '%');
+    let delimiter = if count_embed_delimiters(content, '$') < percent_count {
+        '$'
+    } else {
+        '%'
+    };
+
+    out.push(delimiter);
+    out.push_str(tag.unwrap_or_default());
+    out.push('\n');
+    out.push_str(&escape_embed_content(content, delimiter));
+    out.push(delimiter);
+    out.push(delimiter);
+}
+
+/// Finds the occurrences of the closing delimiter in embed content, including already-escaped ones
+/// (`%%`, `%\%`, `%\\%`, ...), returning the byte ranges they cover along with any trailing backslashes
+fn embed_delimiter_occurrences(content: &str, delimiter: char) -> Vec<std::ops::Range<usize>> {
+    let bytes = content.as_bytes();
+    let delimiter = delimiter as u8;
+    let skip_backslashes = |mut index: usize| {
+        while bytes.get(index) == Some(&b'\\') {
+            index += 1;
+        }
+        index
+    };
+
+    let mut occurrences = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == delimiter {
+            let second = skip_backslashes(index + 1);
+            if bytes.get(second) == Some(&delimiter) {
+                let end = skip_backslashes(second + 1);
+                occurrences.push(index..end);
+                index = end;
+                continue;
+            }
+        }
+        index += 1;
+    }
+    occurrences
+}
+
+fn count_embed_delimiters(content: &str, delimiter: char) -> usize {
+    embed_delimiter_occurrences(content, delimiter).len()
+}
+
+/// Escapes the closing delimiter in embed content by adding a backslash after its first character (see
+/// the escaping rules of embed blocks)
+fn escape_embed_content(content: &str, delimiter: char) -> String {
+    let mut escaped = String::with_capacity(content.len());
+    let mut last = 0;
+    for occurrence in embed_delimiter_occurrences(content, delimiter) {
+        escaped.push_str(&content[last..occurrence.start + 1]);
+        escaped.push('\\');
+        escaped.push_str(&content[occurrence.start + 1..occurrence.end]);
+        last = occurrence.end;
+    }
+    escaped.push_str(&content[last..]);
+    escaped
+}
+
+/// A step of a depth-first walk over a value (see [`Value::walk`])
+pub(crate) enum Step<'a> {
+    /// Entering a value: the root at depth 0, or the child at `position` in the innermost value entered
+    /// and not left yet, with its `key` if that's an object
+    Enter {
+        depth: usize,
+        position: usize,
+        key: Option<&'a str>,
+        value: &'a Value,
+    },
+    /// Leaving a value, after the steps of its children
+    Leave { depth: usize, value: &'a Value },
+}
+
+impl Step<'_> {
+    /// The segment leading to the value entered from its parent (`None` for the root, or when leaving)
+    pub(crate) fn segment(&self) -> Option<PathSegment> {
+        match self {
+            Step::Enter { depth: 0, .. } | Step::Leave { .. } => None,
+            Step::Enter { key: Some(key), .. } => Some(PathSegment::Key(key.to_string())),
+            Step::Enter { position, .. } => Some(PathSegment::Index(*position)),
+        }
+    }
+}
+
+/// Walks a value depth-first, keeping the values being walked through on the heap rather than recursing,
+/// so that values of any depth can be walked
+pub(crate) struct Walk<'a> {
+    root: Option<&'a Value>,
+    /// The values entered and not left yet, with the position of the next child to enter
+    open: Vec<(&'a Value, usize)>,
+}
+
+impl Walk<'_> {
+    /// Skips the children of the value entered last, so that leaving it is the next step
+    pub(crate) fn skip_children(&mut self) {
+        if let Some((_, next)) = self.open.last_mut() {
+            *next = usize::MAX;
+        }
+    }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = Step<'a>;
+
+    fn next(&mut self) -> Option<Step<'a>> {
+        if let Some(root) = self.root.take() {
+            self.open.push((root, 0));
+            return Some(Step::Enter {
+                depth: 0,
+                position: 0,
+                key: None,
+                value: root,
+            });
+        }
+
+        let depth = self.open.len().checked_sub(1)?;
+        let (value, next) = self.open.last_mut()?;
+        let position = *next;
+        let child = match *value {
+            Value::Array(elements) => elements.get(position).map(|element| (None, element)),
+            Value::Object(map) => map
+                .get_entry(position)
+                .map(|(key, property)| (Some(key.as_str()), property)),
+            _ => None,
+        };
+        match child {
+            Some((key, child)) => {
+                *next += 1;
+                self.open.push((child, 0));
+                Some(Step::Enter {
+                    depth: depth + 1,
+                    position,
+                    key,
+                    value: child,
+                })
+            }
+            None => {
+                let (value, _) = self.open.pop()?;
+                Some(Step::Leave { depth, value })
+            }
+        }
+    }
+}
+
+/// Builds a value bottom-up without recursing: arrays and objects are opened, filled with their children
+/// (whose `key` is only used in objects), then closed
+#[derive(Default)]
+pub(crate) struct Builder {
+    open: Vec<(Option<String>, Children)>,
+    sort_keys: bool,
+}
+
+enum Children {
+    Elements(Vec<Value>),
+    Properties(Vec<(String, Value)>),
+}
+
+impl Builder {
+    /// A builder that sorts the properties of objects by key when closing them
+    pub(crate) fn sorting_keys() -> Self {
+        Builder {
+            open: Vec::new(),
+            sort_keys: true,
+        }
+    }
+
+    pub(crate) fn open_array(&mut self, key: Option<String>, capacity: usize) {
+        self.open
+            .push((key, Children::Elements(Vec::with_capacity(capacity))));
+    }
+
+    /// Opens an object, whose properties must have distinct keys
+    pub(crate) fn open_object(&mut self, key: Option<String>, capacity: usize) {
+        self.open
+            .push((key, Children::Properties(Vec::with_capacity(capacity))));
+    }
+
+    /// Adds a value to the innermost open array or object, or returns it if there's none (i.e. it's the
+    /// root)
+    pub(crate) fn push(&mut self, key: Option<String>, value: Value) -> Option<Value> {
+        match self.open.last_mut() {
+            None => Some(value),
+            Some((_, Children::Elements(elements))) => {
+                elements.push(value);
+                None
+            }
+            Some((_, Children::Properties(properties))) => {
+                properties.push((key.unwrap_or_default(), value));
+                None
+            }
+        }
+    }
+
+    /// Closes the innermost open array or object, returning it if it's the root
+    pub(crate) fn close(&mut self) -> Option<Value> {
+        let (key, children) = self.open.pop()?;
+        let value = match children {
+            Children::Elements(elements) => Value::Array(elements),
+            Children::Properties(mut properties) => {
+                if self.sort_keys {
+                    properties.sort_by(|(a, _), (b, _)| a.cmp(b));
+                }
+                Value::Object(Map::from_entries(properties))
+            }
+        };
+        self.push(key, value)
+    }
+}
+
+/// What to do with a value entered while rebuilding a document
+enum Visit {
+    /// Rebuild its children, then leave it
+    Children(Value),
+    /// Use this instead of the value (removing it if `None`), without visiting its children
+    Skip(Option<Value>),
+}
+
+/// Rebuilds a value by moving its children out and back in, without recursing. `enter` is called
+/// top-down on each value with its path, and `leave` bottom-up once its children are rebuilt, returning
+/// the value to put in its parent (if any). Indices in paths are those of the original value.
+fn rebuild(
+    value: Value,
+    mut enter: impl FnMut(&KsonPath, Value) -> Visit,
+    mut leave: impl FnMut(&KsonPath, Value) -> Option<Value>,
+) -> Option<Value> {
+    /// An array or object being rebuilt, with its children left to visit and the rebuilt ones
+    enum Frame {
+        Elements {
+            pending: std::iter::Enumerate<std::vec::IntoIter<Value>>,
+            rebuilt: Vec<Value>,
+        },
+        Properties {
+            pending: std::vec::IntoIter<(String, Value)>,
+            key: String,
+            rebuilt: Vec<(String, Value)>,
+        },
+    }
+
+    let mut path = KsonPath::root();
+    let mut frames = Vec::new();
+    let mut entering = Some(value);
+    loop {
+        let rebuilt = match entering.take() {
+            Some(value) => match enter(&path, value) {
+                Visit::Children(mut value) => match &mut value {
+                    Value::Array(elements) => {
+                        frames.push(Frame::Elements {
+                            rebuilt: Vec::with_capacity(elements.len()),
+                            pending: std::mem::take(elements).into_iter().enumerate(),
+                        });
+                        continue;
+                    }
+                    Value::Object(map) => {
+                        frames.push(Frame::Properties {
+                            rebuilt: Vec::with_capacity(map.len()),
+                            pending: std::mem::take(map).into_iter(),
+                            key: String::new(),
+                        });
+                        continue;
+                    }
+                    _ => leave(&path, value),
+                },
+                Visit::Skip(replacement) => replacement,
+            },
+            None => {
+                let next = match frames.last_mut() {
+                    Some(Frame::Elements { pending, .. }) => pending
+                        .next()
+                        .map(|(index, element)| (PathSegment::Index(index), element)),
+                    Some(Frame::Properties { pending, key, .. }) => {
+                        pending.next().map(|(next_key, property)| {
+                            *key = next_key.clone();
+                            (PathSegment::Key(next_key), property)
+                        })
+                    }
+                    None => unreachable!("the root is returned once rebuilt"),
+                };
+                if let Some((segment, child)) = next {
+                    path.push(segment);
+                    entering = Some(child);
+                    continue;
+                }
+                let value = match frames.pop() {
+                    Some(Frame::Elements { rebuilt, .. }) => Value::Array(rebuilt),
+                    Some(Frame::Properties { rebuilt, .. }) => {
+                        Value::Object(Map::from_entries(rebuilt))
+                    }
+                    None => unreachable!("the root is returned once rebuilt"),
+                };
+                leave(&path, value)
+            }
+        };
+
+        // The value at `path` is rebuilt: put it back in its parent, unless it's the root
+        path.pop();
+        match (frames.last_mut(), rebuilt) {
+            (None, rebuilt) => return rebuilt,
+            (Some(Frame::Elements { rebuilt, .. }), Some(value)) => rebuilt.push(value),
+            (Some(Frame::Properties { rebuilt, key, .. }), Some(value)) => {
+                rebuilt.push((std::mem::take(key), value))
+            }
+            (Some(_), None) => {}
+        }
+    }
+}
@@ -0,0 +1,426 @@
+//! Discovery, validation, formatting and conversion of the KSON documents of a directory tree, for tools
+//! working on whole projects.
+//!
+//! Like git, discovery skips the files ignored by `.gitignore` files (and `.git/info/exclude`), as well
+//! as by `.ksonignore` files, which use the same syntax and only apply to KSON tools. Hidden files and
+//! directories are always skipped:
+//!
+//! ```no_run
+//! use kson_rs::workspace::FileDiscovery;
+//!
+//! for path in FileDiscovery::new(".").discover().unwrap() {
+//!     println!("{}", path.display());
+//! }
+//! ```
+//!
+//! [`Kson::validate_workspace`] validates all these documents against the schemas the
+//! [project configuration](crate::config) associates them with, e.g. for a CI check:
+//!
+//! ```no_run
+//! use kson_rs::Kson;
+//! use kson_rs::config::ProjectConfig;
+//!
+//! let root = std::env::current_dir().unwrap();
+//! let config = ProjectConfig::discover(&root)
+//!     .unwrap()
+//!     .unwrap_or_else(|| ProjectConfig::new(&root));
+//! let problems = Kson::validate_workspace(&root, &config).unwrap();
+//! for errors in problems.values().filter(|errors| !errors.is_empty()) {
+//!     eprintln!("{errors}");
+//! }
+//! ```
+//!
+//! [`Kson::format_workspace`] formats them with the [format settings](crate::config::FormatSettings) of
+//! the configuration, and [`Kson::convert_workspace`] converts them to JSON or YAML. Like validation,
+//! both process the documents on several threads and have variants taking a
+//! [`CancellationToken`] and reporting their [progress](crate::progress).
+
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::cancel::CancellationToken;
+use crate::config::ProjectConfig;
+use crate::diagnostics::DiagnosticLimit;
+use crate::error::KsonErrors;
+use crate::progress::Progress;
+use crate::schema::Schema;
+use crate::{Kson, TranspileOptions};
+
+/// The name of the ignore files only followed by KSON tools
+pub const KSON_IGNORE_FILENAME: &str = ".ksonignore";
+
+/// What [`Kson::format_workspace`] does with the documents that aren't formatted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FormatMode {
+    /// Rewrite them formatted
+    #[default]
+    Write,
+    /// Leave them as they are, e.g. for a CI check that all documents are formatted
+    Check,
+}
+
+/// What [`Kson::format_workspace`] found formatting a document
+#[derive(Clone, Debug, PartialEq)]
+pub enum FormatOutcome {
+    /// The document was already formatted
+    Unchanged,
+    /// The document wasn't formatted, and was rewritten unless checking
+    Reformatted,
+    /// The document has syntax errors, so it was left as it was
+    Invalid(KsonErrors),
+}
+
+/// Finds the documents under a directory (see the [module documentation](self))
+#[derive(Clone, Debug)]
+pub struct FileDiscovery {
+    root: PathBuf,
+    extensions: Vec<String>,
+    respect_ignore_files: bool,
+}
+
+impl FileDiscovery {
+    /// Discovers the `.kson` files under `root`, which may also be a single file
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            extensions: vec!["kson".to_string()],
+            respect_ignore_files: true,
+        }
+    }
+
+    /// Sets the extensions of the files to discover (without the leading dot), replacing `kson`
+    pub fn extensions<S: Into<String>>(mut self, extensions: impl IntoIterator<Item = S>) -> Self {
+        self.extensions = extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Whether to skip the files excluded by ignore files (the default), which tools turn off for their
+    /// `--no-ignore` flag
+    pub fn respect_ignore_files(mut self, respect: bool) -> Self {
+        self.respect_ignore_files = respect;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Walks the directory tree, returning the paths of the matching files sorted by path. A root that
+    /// is a file is returned as is, even if it's ignored or has another extension, since it was asked for
+    /// explicitly.
+    pub fn discover(&self) -> std::io::Result<Vec<PathBuf>> {
+        if self.root.is_file() {
+            return Ok(vec![self.root.clone()]);
+        }
+
+        let respect = self.respect_ignore_files;
+        let mut builder = ignore::WalkBuilder::new(&self.root);
+        builder
+            .hidden(true)
+            .git_ignore(respect)
+            .git_exclude(respect)
+            // Tests don't depend on the global ignore file of the machine running them
+            .git_global(respect && !cfg!(test))
+            .ignore(false)
+            .parents(respect)
+            // Ignore files apply to projects that aren't git repositories as well
+            .require_git(false)
+            .sort_by_file_name(|a, b| a.cmp(b));
+        if respect {
+            builder.add_custom_ignore_filename(KSON_IGNORE_FILENAME);
+        }
+
+        let mut paths = Vec::new();
+        for entry in builder.build() {
+            let entry = entry.map_err(std::io::Error::other)?;
+            if entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+                && self.has_extension(entry.path())
+            {
+                paths.push(entry.into_path());
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Whether the file has one of the extensions to discover
+    pub(crate) fn has_extension(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                self.extensions
+                    .iter()
+                    .any(|expected| expected.eq_ignore_ascii_case(extension))
+            })
+    }
+}
+
+impl Kson {
+    /// Validates the documents under `root` (as found by [`FileDiscovery`]) on several threads, returning
+    /// the problems found in each of them, named after their path. Every document is in the map, including
+    /// the ones without problems.
+    ///
+    /// Documents are checked like with [`Kson::try_check`], formats included, against the schema the
+    /// configuration associates them with (see [`ProjectConfig::schema_for`]), if any. A schema that fails
+    /// to parse is reported in the map under its own path (replacing its problems as a document, which
+    /// they include), and the documents associated with it are only checked for syntax errors.
+    pub fn validate_workspace(
+        root: impl AsRef<Path>,
+        config: &ProjectConfig,
+    ) -> std::io::Result<BTreeMap<PathBuf, KsonErrors>> {
+        Kson::validate_workspace_cancellable(root, config, &CancellationToken::new())
+    }
+
+    /// Like [`Kson::validate_workspace`], but checks the token before each schema and checks the documents
+    /// like [`Kson::try_check_cancellable`], failing with an error of kind
+    /// [`std::io::ErrorKind::Interrupted`] once it's cancelled (see [`Cancelled`](crate::cancel::Cancelled))
+    pub fn validate_workspace_cancellable(
+        root: impl AsRef<Path>,
+        config: &ProjectConfig,
+        token: &CancellationToken,
+    ) -> std::io::Result<BTreeMap<PathBuf, KsonErrors>> {
+        Kson::validate_workspace_with(root, config, token, &|_| {})
+    }
+
+    /// Like [`Kson::validate_workspace_cancellable`], but also reports its progress to `progress` every
+    /// time a document is done (see the [`progress`](crate::progress) module). Schemas are parsed before
+    /// the documents are checked and aren't reported.
+    pub fn validate_workspace_with(
+        root: impl AsRef<Path>,
+        config: &ProjectConfig,
+        token: &CancellationToken,
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> std::io::Result<BTreeMap<PathBuf, KsonErrors>> {
+        token.check()?;
+        let files = FileDiscovery::new(root.as_ref()).discover()?;
+
+        let mut schemas: HashMap<&Path, Option<Schema>> = HashMap::new();
+        let mut invalid_schemas = Vec::new();
+        for file in &files {
+            let Some(path) = config.schema_for(file) else {
+                continue;
+            };
+            if schemas.contains_key(path) {
+                continue;
+            }
+            token.check()?;
+            let text = read(path)?;
+            let schema = match Schema::parse(&text) {
+                Ok(schema) => Some(schema),
+                Err(messages) => {
+                    let errors = KsonErrors::from_messages(&text, &messages)
+                        .with_source_name(path.display().to_string());
+                    invalid_schemas.push((path.to_path_buf(), errors));
+                    None
+                }
+            };
+            schemas.insert(path, schema);
+        }
+
+        let results = process_files(&files, token, progress, |file| {
+            let schema = config
+                .schema_for(file)
+                .and_then(|path| schemas.get(path)?.as_ref());
+            check_file(file, schema, token)
+        })?;
+
+        let mut problems: BTreeMap<_, _> = results.into_iter().collect();
+        problems.extend(invalid_schemas);
+        Ok(problems)
+    }
+
+    /// Formats the documents under `root` (as found by [`FileDiscovery`]) on several threads with the
+    /// [format settings](crate::config::FormatSettings) of the configuration, returning what was found
+    /// in each of them, named after their path. Documents with syntax errors are left as they are.
+    pub fn format_workspace(
+        root: impl AsRef<Path>,
+        config: &ProjectConfig,
+        mode: FormatMode,
+    ) -> std::io::Result<BTreeMap<PathBuf, FormatOutcome>> {
+        Kson::format_workspace_cancellable(root, config, mode, &CancellationToken::new())
+    }
+
+    /// Like [`Kson::format_workspace`], but checks the token before each document and between the passes
+    /// of the formatter, failing with an error of kind [`std::io::ErrorKind::Interrupted`] once it's
+    /// cancelled. Documents done by then stay rewritten.
+    pub fn format_workspace_cancellable(
+        root: impl AsRef<Path>,
+        config: &ProjectConfig,
+        mode: FormatMode,
+        token: &CancellationToken,
+    ) -> std::io::Result<BTreeMap<PathBuf, FormatOutcome>> {
+        Kson::format_workspace_with(root, config, mode, token, &|_| {})
+    }
+
+    /// Like [`Kson::format_workspace_cancellable`], but also reports its progress to `progress` every
+    /// time a document is done (see the [`progress`](crate::progress) module)
+    pub fn format_workspace_with(
+        root: impl AsRef<Path>,
+        config: &ProjectConfig,
+        mode: FormatMode,
+        token: &CancellationToken,
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> std::io::Result<BTreeMap<PathBuf, FormatOutcome>> {
+        token.check()?;
+        let files = FileDiscovery::new(root.as_ref()).discover()?;
+        let formatter = config.format().formatter();
+        let results = process_files(&files, token, progress, |file| {
+            let document = read(file)?;
+            if let Err(errors) =
+                Kson::try_check_cancellable(&document, None, DiagnosticLimit::All, token)?
+                && errors.has_errors()
+            {
+                let errors = errors.with_source_name(file.display().to_string());
+                return Ok(FormatOutcome::Invalid(errors));
+            }
+            let formatted = formatter.format_cancellable(&document, token)?;
+            if formatted == document {
+                return Ok(FormatOutcome::Unchanged);
+            }
+            if mode == FormatMode::Write {
+                write(file, &formatted)?;
+            }
+            Ok(FormatOutcome::Reformatted)
+        })?;
+        Ok(results.into_iter().collect())
+    }
+
+    /// Converts the documents under `root` (as found by [`FileDiscovery`]) on several threads to JSON or
+    /// YAML, as [`Kson::try_to_json`] and [`Kson::try_to_yaml`] do, returning the output or the errors of
+    /// each of them, named after their path. Nothing is written, so that callers choose where the outputs
+    /// go.
+    pub fn convert_workspace(
+        root: impl AsRef<Path>,
+        options: &TranspileOptions,
+    ) -> std::io::Result<BTreeMap<PathBuf, Result<String, KsonErrors>>> {
+        Kson::convert_workspace_cancellable(root, options, &CancellationToken::new())
+    }
+
+    /// Like [`Kson::convert_workspace`], but converts the documents like
+    /// [`Kson::try_to_json_cancellable`] and [`Kson::try_to_yaml_cancellable`], failing with an error of
+    /// kind [`std::io::ErrorKind::Interrupted`] once the token is cancelled
+    pub fn convert_workspace_cancellable(
+        root: impl AsRef<Path>,
+        options: &TranspileOptions,
+        token: &CancellationToken,
+    ) -> std::io::Result<BTreeMap<PathBuf, Result<String, KsonErrors>>> {
+        Kson::convert_workspace_with(root, options, token, &|_| {})
+    }
+
+    /// Like [`Kson::convert_workspace_cancellable`], but also reports its progress to `progress` every
+    /// time a document is done (see the [`progress`](crate::progress) module)
+    pub fn convert_workspace_with(
+        root: impl AsRef<Path>,
+        options: &TranspileOptions,
+        token: &CancellationToken,
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> std::io::Result<BTreeMap<PathBuf, Result<String, KsonErrors>>> {
+        token.check()?;
+        let files = FileDiscovery::new(root.as_ref()).discover()?;
+        let results = process_files(&files, token, progress, |file| {
+            let document = read(file)?;
+            let output = match options {
+                TranspileOptions::Json(options) => {
+                    Kson::try_to_json_cancellable(&document, options.clone(), token)?
+                }
+                TranspileOptions::Yaml(options) => {
+                    Kson::try_to_yaml_cancellable(&document, options.clone(), token)?
+                }
+            };
+            Ok(output.map_err(|errors| errors.with_source_name(file.display().to_string())))
+        })?;
+        Ok(results.into_iter().collect())
+    }
+}
+
+/// Processes the files on several threads, reporting the progress every time a file is done, and
+/// returns the results by file. Fails with the first error, or once the token is cancelled.
+fn process_files<T: Send>(
+    files: &[PathBuf],
+    token: &CancellationToken,
+    progress: &(dyn Fn(Progress) + Sync),
+    process: impl Fn(&Path) -> std::io::Result<T> + Sync,
+) -> std::io::Result<Vec<(PathBuf, T)>> {
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let threads = std::thread::available_parallelism()
+        .map_or(1, NonZero::get)
+        .min(files.len());
+    let results: Vec<Vec<_>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    // Each thread takes the next file until there are none left, which balances the
+                    // load when some files are much larger than others
+                    while let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        if let Err(cancelled) = token.check() {
+                            results.push((file, Err(cancelled.into())));
+                            break;
+                        }
+                        results.push((file, process(file)));
+                        progress(Progress {
+                            done: done.fetch_add(1, Ordering::Relaxed) + 1,
+                            total: files.len(),
+                            file,
+                        });
+                    }
+                    results
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    results
+        .into_iter()
+        .flatten()
+        .map(|(file, result)| Ok((file.clone(), result?)))
+        .collect()
+}
+
+fn check_file(
+    path: &Path,
+    schema: Option<&Schema>,
+    token: &CancellationToken,
+) -> std::io::Result<KsonErrors> {
+    let document = read(path)?;
+    let checked = Kson::try_check_cancellable(&document, schema, DiagnosticLimit::All, token)?;
+    Ok(named_errors(path, &document, checked))
+}
+
+/// Checks the document at `path` against its schema, like [`Kson::validate_workspace`] does
+pub(crate) fn check_document(path: &Path, document: &str, schema: Option<&Schema>) -> KsonErrors {
+    named_errors(
+        path,
+        document,
+        Kson::try_check(document, schema, DiagnosticLimit::All),
+    )
+}
+
+/// The errors of a check, none if it passed, named after the path of the document
+fn named_errors(path: &Path, document: &str, checked: Result<(), KsonErrors>) -> KsonErrors {
+    let errors = match checked {
+        Ok(()) => KsonErrors::from_messages(document, &[]),
+        Err(errors) => errors,
+    };
+    errors.with_source_name(path.display().to_string())
+}
+
+/// Reads the file, naming it in the error if that fails
+pub(crate) fn read(path: &Path) -> std::io::Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|error| std::io::Error::new(error.kind(), format!("{}: {error}", path.display())))
+}
+
+/// Writes the file, naming it in the error if that fails
+fn write(path: &Path, text: &str) -> std::io::Result<()> {
+    std::fs::write(path, text)
+        .map_err(|error| std::io::Error::new(error.kind(), format!("{}: {error}", path.display())))
+}